@@ -2,6 +2,9 @@ use std::time::Duration;
 use tetra::{Context, graphics::{Color, DrawParams, Rectangle, Texture, animation::Animation}};
 use crate::{GC, V2};
 
+pub const WATER_SPLASH_FRAMES: usize = 5;
+pub const WATER_SPLASH_FRAME_LENGTH: f32 = 0.125;
+
 pub struct AnimatedSprite {
     pub anim: Animation,
     pub translation: Option<(V2, f32)>,
@@ -42,11 +45,10 @@ impl AnimatedSprite {
     }
 }
 
-pub fn build_water_splash_sprite(ctx: &mut Context, game: GC, pos: V2)
-    -> tetra::Result<AnimatedSprite> {
-    let tex = game.borrow_mut().assets.load_texture(
-            ctx, "Water Splash.png".to_owned(), true)?;
-    Ok(AnimatedSprite::new(
-            tex, 5, 15.0, 15.0, 0.125, false,
+// Effects are purely visual, headless simulations go without
+pub fn build_water_splash_sprite(game: GC, pos: V2) -> Option<AnimatedSprite> {
+    let tex = game.borrow().assets.get_texture("Water Splash.png")?;
+    Some(AnimatedSprite::new(
+            tex, WATER_SPLASH_FRAMES, 15.0, 15.0, WATER_SPLASH_FRAME_LENGTH, false,
         Some((pos, 0.0))))
 }
@@ -1,15 +1,30 @@
-use std::{collections::HashMap, iter::FromIterator, path::{Path, PathBuf}};
-use tetra::{Context, graphics::{Texture, text::Font}};
+use std::{collections::HashMap, fs::File, io::Read, iter::FromIterator, path::{Path, PathBuf}};
+use tetra::{Context, TetraError, graphics::{Texture, text::Font}};
+use crate::{Sprite, SpriteOrigin, V2};
 
 pub const ASSETS_ROOT_PATH: &str = "assets";
 pub const TEXTURES_PATH: &str = "textures";
+// Everything the simulation spawns mid-match. These are loaded up front, so entities can be
+// created without a context, and headless simulations only need their sizes.
+pub const SIMULATION_TEXTURES: [&str; 18] = [
+    "Caravel.png", "Galleon.png", "Schooner.png", "Cannon.png", "Shoot Cannon.png",
+    "Cannon Ball.png", "Water Splash.png", "Destroyed Caravel.png", "Harbour.png",
+    "Island 1.png", "Island 2.png", "Island 3.png", "Island 4.png", "Reef.png",
+    "UI/Ammo Upgrade Mod.png", "UI/Cannon Reload Upgrade Mod.png", "UI/Cannon Range Upgrade Mod.png",
+    "UI/X.png"
+];
 
-pub struct Assets {
+pub struct Fonts {
     pub small_font: Font,
     pub font: Font,
     pub header_font: Font,
-    pub header2_font: Font,
-    cached_textures: HashMap<String, Texture>
+    pub header2_font: Font
+}
+
+pub struct Assets {
+    fonts: Option<Fonts>, // Headless assets neither have fonts nor textures
+    cached_textures: HashMap<String, Texture>,
+    texture_sizes: HashMap<String, V2>
 }
 
 impl Assets {
@@ -18,16 +33,63 @@ impl Assets {
         let green_tex = Texture::from_rgba(ctx, 1, 1, &[51, 204, 51, 255])?;
         let red_tex = Texture::from_rgba(ctx, 1, 1, &[204, 0, 0, 255])?;
 
-        Ok(Assets {
-            small_font: Font::vector(ctx, font_path.clone(), 17.0)?,
-            font: Font::vector(ctx, font_path.clone(), 20.0)?,
-            header_font: Font::vector(ctx, font_path.clone(), 35.0)?,
-            header2_font: Font::vector(ctx, font_path, 60.0)?,
+        let mut assets = Assets {
+            fonts: Some(Fonts {
+                small_font: Font::vector(ctx, font_path.clone(), 17.0)?,
+                font: Font::vector(ctx, font_path.clone(), 20.0)?,
+                header_font: Font::vector(ctx, font_path.clone(), 35.0)?,
+                header2_font: Font::vector(ctx, font_path, 60.0)?
+            }),
             cached_textures: HashMap::from_iter([
-                ("Green".to_owned(), green_tex), ("Red".to_owned(), red_tex)])
+                ("Green".to_owned(), green_tex), ("Red".to_owned(), red_tex)]),
+            texture_sizes: HashMap::new()
+        };
+        for name in SIMULATION_TEXTURES.iter() {
+            assets.load_texture(ctx, (*name).to_owned(), true)?;
+        }
+        Ok(assets)
+    }
+
+    // Only reads the dimensions of the simulation textures from their PNG headers,
+    // which is all colliders are built from
+    pub fn headless() -> tetra::Result<Assets> {
+        let mut texture_sizes = HashMap::new();
+        for name in SIMULATION_TEXTURES.iter() {
+            let path = Self::get_full_texture_path((*name).to_owned());
+            let size = read_png_size(&path).map_err(|reason| TetraError::FailedToLoadAsset {
+                reason, path
+            })?;
+            texture_sizes.insert((*name).to_owned(), size);
+        }
+        Ok(Assets {
+            fonts: None, cached_textures: HashMap::new(), texture_sizes
         })
     }
 
+    pub fn is_headless(&self) -> bool {
+        self.fonts.is_none()
+    }
+
+    pub fn get_fonts(&self) -> Option<&Fonts> {
+        self.fonts.as_ref()
+    }
+
+    pub fn small_font(&self) -> Font {
+        self.expect_fonts().small_font.clone()
+    }
+
+    pub fn font(&self) -> Font {
+        self.expect_fonts().font.clone()
+    }
+
+    pub fn header_font(&self) -> Font {
+        self.expect_fonts().header_font.clone()
+    }
+
+    pub fn header2_font(&self) -> Font {
+        self.expect_fonts().header2_font.clone()
+    }
+
     pub fn load_texture(&mut self, ctx: &mut Context, name: String, cache: bool)
         -> tetra::Result<Texture> {
         if cache {
@@ -38,6 +100,8 @@ impl Assets {
 
         let texture = Texture::new(ctx, Self::get_full_texture_path(name.clone()))?;
         if cache {
+            self.texture_sizes.insert(name.clone(),
+                V2::new(texture.width() as f32, texture.height() as f32));
             self.cache_texture(name, texture.clone());
         }
         Ok(texture)
@@ -47,6 +111,21 @@ impl Assets {
         return self.cached_textures[&name].clone()
     }
 
+    // Simulation textures only, None if headless
+    pub fn get_texture(&self, name: &str) -> Option<Texture> {
+        self.cached_textures.get(name).cloned()
+    }
+
+    pub fn load_sprite(&self, name: &str, origin: SpriteOrigin) -> Sprite {
+        let size = *self.texture_sizes.get(name)
+            .unwrap_or_else(|| panic!("{} is not a simulation texture", name));
+        Sprite::new(self.get_texture(name), size, origin, None)
+    }
+
+    fn expect_fonts(&self) -> &Fonts {
+        self.fonts.as_ref().expect("Headless assets have no fonts")
+    }
+
     fn cache_texture(&mut self, name: String, texture: Texture) {
         self.cached_textures.insert(name, texture);
     }
@@ -59,3 +138,15 @@ impl Assets {
         Path::new(ASSETS_ROOT_PATH).join(TEXTURES_PATH).join(texture_name)
     }
 }
+
+fn read_png_size(path: &Path) -> std::io::Result<V2> {
+    // Signature (8 bytes), IHDR length and type (8 bytes), then width and height
+    let mut header = [0u8; 24];
+    File::open(path)?.read_exact(&mut header)?;
+    if &header[1..4] != b"PNG" || &header[12..16] != b"IHDR" {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not a PNG file"))
+    }
+    let width = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+    let height = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+    Ok(V2::new(width as f32, height as f32))
+}
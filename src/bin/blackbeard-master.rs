@@ -0,0 +1,40 @@
+use blackbeard::{get_version, master_server::{DEFAULT_MASTER_SERVER_PORT, MasterServer}};
+
+fn main() {
+    println!("Blackbeard Master Server {} - (c) 2021, Niklas Vaudt", get_version());
+    let port = match process_params() {
+        Ok(port) => port,
+        Err(e) => {
+            println!("{}", e);
+            print_usage();
+            std::process::exit(1)
+        }
+    };
+
+    let result = MasterServer::bind(port).and_then(|mut master_server| master_server.run());
+    if let Err(e) = result {
+        println!("Master server encountered an error: {}", e);
+        std::process::exit(1)
+    }
+}
+
+fn process_params() -> Result<u16, String> {
+    let mut port = DEFAULT_MASTER_SERVER_PORT;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            print_usage();
+            std::process::exit(0)
+        }
+        let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--port" => port = value.parse().or(Err(format!("Invalid value {} for {}", value, arg)))?,
+            _ => return Err(format!("Unknown parameter {}", arg))
+        }
+    }
+    Ok(port)
+}
+
+fn print_usage() {
+    println!("Usage: blackbeard-master [--port {}]", DEFAULT_MASTER_SERVER_PORT);
+}
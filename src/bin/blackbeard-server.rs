@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+use blackbeard::{BbResult, dedicated_server::DedicatedServer, game_settings::{GameMode, GameSettings, Weather}, get_version, net_settings::{DEFAULT_MAX_SPECTATORS, NetSettings}};
+
+pub const DEFAULT_SERVER_PORT: u16 = 22081;
+pub const DEFAULT_MAX_PLAYERS: usize = 4;
+pub const DEFAULT_MIN_PLAYERS: usize = 2;
+
+fn main() {
+    println!("Blackbeard Dedicated Server {} - (c) 2021, Niklas Vaudt", get_version());
+    let params = match process_params() {
+        Ok(params) => params,
+        Err(e) => {
+            println!("{}", e);
+            print_usage();
+            std::process::exit(1)
+        }
+    };
+
+    if let Err(e) = run(params) {
+        println!("Dedicated server encountered an error: {}", e);
+        std::process::exit(1)
+    }
+}
+
+fn run(params: ServerParams) -> BbResult {
+    let mut net_settings = NetSettings::dedicated(params.max_players, params.max_spectators);
+    net_settings.password = params.password;
+    net_settings.set_allow_list(&params.allow_list);
+    if let Some(lobby_name) = params.lobby_name {
+        net_settings.lobby_name = lobby_name;
+    }
+    net_settings.master_server = params.master_server;
+    net_settings.relay = params.relay;
+    if let Some(chat_filter_path) = params.chat_filter_path {
+        net_settings.chat_filter_path = Some(chat_filter_path);
+    }
+    let mut server = DedicatedServer::host(params.port, net_settings, params.min_players,
+        params.settings, params.fill_bots)?;
+    let result = server.run();
+    server.shutdown()?;
+    result
+}
+
+struct ServerParams {
+    port: u16,
+    max_players: usize,
+    max_spectators: usize,
+    min_players: usize,
+    settings: GameSettings,
+    fill_bots: bool,
+    password: Option<String>,
+    allow_list: String,
+    chat_filter_path: Option<PathBuf>, // Falls back to the one next to the settings file
+    lobby_name: Option<String>, // Shown in the LAN and online match lists
+    master_server: Option<String>, // Unlisted by default
+    relay: Option<String>
+}
+
+fn process_params() -> Result<ServerParams, String> {
+    let mut params = ServerParams {
+        port: DEFAULT_SERVER_PORT, max_players: DEFAULT_MAX_PLAYERS,
+        max_spectators: DEFAULT_MAX_SPECTATORS, min_players: DEFAULT_MIN_PLAYERS, settings: GameSettings::default(),
+        fill_bots: false, password: None, allow_list: String::new(),
+        chat_filter_path: None, lobby_name: None, master_server: None,
+        relay: None
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            print_usage();
+            std::process::exit(0)
+        }
+        let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--port" => params.port = parse_number(&arg, &value)?,
+            "--max-players" => params.max_players = parse_number(&arg, &value)?,
+            "--max-spectators" => params.max_spectators = parse_number(&arg, &value)?,
+            "--min-players" => params.min_players = parse_number(&arg, &value)?,
+            "--mode" => params.settings.mode = parse_mode(&value)?,
+            "--weather" => params.settings.weather = parse_weather(&value)?,
+            "--bots" => params.fill_bots = parse_bots(&value)?,
+            "--password" => params.password = Some(value),
+            "--invite" => params.allow_list = value,
+            "--chat-filter" => params.chat_filter_path = Some(value.into()),
+            "--name" => params.lobby_name = Some(value),
+            "--master" => params.master_server = Some(value),
+            "--relay" => params.relay = Some(value),
+            _ => return Err(format!("Unknown parameter {}", arg))
+        }
+    }
+    if params.max_players == 0 || params.min_players > params.max_players {
+        return Err(format!("Invalid player limits: min {}, max {}",
+            params.min_players, params.max_players))
+    }
+    Ok(params)
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().or(Err(format!("Invalid value {} for {}", value, arg)))
+}
+
+fn parse_mode(value: &str) -> Result<GameMode, String> {
+    let mut split = value.splitn(2, ':');
+    let mode = split.next().unwrap_or_default().to_lowercase();
+    let goal = split.next();
+    match mode.as_str() {
+        "raid" => Ok(GameMode::Raid(match goal {
+            Some(goal) => parse_number("--mode raid", goal)?,
+            None => 500
+        })),
+        "deathmatch" => Ok(GameMode::Deathmatch(match goal {
+            Some(goal) => parse_number("--mode deathmatch", goal)?,
+            None => 10
+        })),
+        _ => Err(format!("Unknown game mode {}", value))
+    }
+}
+
+fn parse_weather(value: &str) -> Result<Weather, String> {
+    match value.to_lowercase().as_str() {
+        "sunny" => Ok(Weather::Sunny),
+        "windy" => Ok(Weather::Windy),
+        "rainy" => Ok(Weather::Rainy),
+        "stormy" => Ok(Weather::Stormy),
+        _ => Err(format!("Unknown weather {}", value))
+    }
+}
+
+fn parse_bots(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "fill" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Unknown bots option {}", value))
+    }
+}
+
+fn print_usage() {
+    println!("Usage: blackbeard-server [--port {}] [--max-players {}] [--max-spectators {}] [--min-players {}] \
+        [--mode raid:<escudos>|deathmatch:<sinkings>] [--weather sunny|windy|rainy|stormy] [--bots fill|off] \
+        [--password <password>] [--invite <name>,<name>,...] [--chat-filter <file>] \
+        [--name <lobby name>] [--master <address>] \
+        [--relay <address>]",
+        DEFAULT_SERVER_PORT, DEFAULT_MAX_PLAYERS, DEFAULT_MAX_SPECTATORS, DEFAULT_MIN_PLAYERS);
+}
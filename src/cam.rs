@@ -1,18 +1,22 @@
-use tetra::{Context, State, graphics::Camera, input::{Key, is_key_down, is_mouse_scrolled_down, is_mouse_scrolled_up}, math::Clamp, window::{get_height, get_width}};
-use crate::{UNIT_FRAMERATE_TIMESTEP, V2};
+use tetra::{Context, graphics::Camera, input::{is_key_down, is_mouse_scrolled_down, is_mouse_scrolled_up}, math::Clamp, window::{get_height, get_width}};
+use crate::{Settings, UNIT_FRAMERATE_TIMESTEP, V2};
 
 pub const CAM_ZOOM_RATE: f32 = 2.0;
+pub const CAM_MIN_ZOOM: f32 = 0.1;
+pub const CAM_MAX_ZOOM: f32 = 1.5;
 
 pub struct Cam {
-    pub instance: Camera,
-    pub movement_speed: f32
+    pub instance: Camera
 }
 
 impl Cam {
-    pub fn setup(ctx: &mut Context, movement_speed: f32) -> Cam {
+    pub fn setup(ctx: &mut Context) -> Cam {
+        Self::with_size(V2::new(get_width(ctx) as f32, get_height(ctx) as f32))
+    }
+
+    pub fn with_size(size: V2) -> Cam {
         Cam {
-            instance: Camera::new(get_width(ctx) as f32, get_height(ctx) as f32),
-            movement_speed
+            instance: Camera::new(size.x, size.y)
         }
     }
 
@@ -33,30 +37,33 @@ impl Cam {
     }
 }
 
-impl State for Cam {
-    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        if is_key_down(ctx, Key::W) {
-            self.instance.position.y -= self.movement_speed * UNIT_FRAMERATE_TIMESTEP;
+impl Cam {
+    // Speed, zoom limits and keys come from the player's settings
+    pub fn update(&mut self, ctx: &mut Context, settings: &Settings) -> tetra::Result {
+        let keys = &settings.key_bindings;
+        let speed = settings.cam_speed * UNIT_FRAMERATE_TIMESTEP;
+        if is_key_down(ctx, keys.cam_up) {
+            self.instance.position.y -= speed;
         }
-        if is_key_down(ctx, Key::S) {
-            self.instance.position.y += self.movement_speed * UNIT_FRAMERATE_TIMESTEP;
+        if is_key_down(ctx, keys.cam_down) {
+            self.instance.position.y += speed;
         }
-        if is_key_down(ctx, Key::A) {
-            self.instance.position.x -= self.movement_speed * UNIT_FRAMERATE_TIMESTEP;
+        if is_key_down(ctx, keys.cam_left) {
+            self.instance.position.x -= speed;
         }
-        if is_key_down(ctx, Key::D) {
-            self.instance.position.x += self.movement_speed * UNIT_FRAMERATE_TIMESTEP;
+        if is_key_down(ctx, keys.cam_right) {
+            self.instance.position.x += speed;
         }
 
+        let (min_zoom, max_zoom) = (V2::one() * settings.cam_min_zoom,
+            V2::one() * settings.cam_max_zoom);
         if is_mouse_scrolled_up(ctx) {
             self.instance.scale += CAM_ZOOM_RATE * UNIT_FRAMERATE_TIMESTEP;
-            self.instance.scale = self.instance.scale.clamped(V2::new(0.1, 0.1),
-                V2::one() * 1.5);
+            self.instance.scale = self.instance.scale.clamped(min_zoom, max_zoom);
         }
         else if is_mouse_scrolled_down(ctx) {
             self.instance.scale -= CAM_ZOOM_RATE * UNIT_FRAMERATE_TIMESTEP;
-            self.instance.scale = self.instance.scale.clamped(V2::new(0.1, 0.1),
-                V2::one() * 1.5);
+            self.instance.scale = self.instance.scale.clamped(min_zoom, max_zoom);
         }
 
         self.instance.update();
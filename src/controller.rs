@@ -1,7 +1,7 @@
 use std::{time::Instant};
 use indexmap::IndexMap;
-use tetra::{Context, Event, State, input::{Key, MouseButton}, time::{Timestep, set_timestep}};
-use crate::{BbResult, DiagnosticState, GC, Player, Rcc, Sprite, SpriteOrigin, SyncStateShipData, TransformResult, V2, entity::GameState, input_pool::STEP_PHASE_TIME_SECS, packet::{InputState, InputStep, Packet}, playback_buffer::{PlaybackBuffer, StepPhase}, ship_mod::ShipModType, sync_checker::{SYNC_STATE_GEN_INTERVAL, SyncState}, world::World, wrap_rcc};
+use tetra::{Context, Event, input::MouseButton};
+use crate::{BbResult, DiagnosticState, GC, Player, Rcc, Sprite, SpriteOrigin, SyncStateShipData, TransformResult, V2, entity::GameState, game_settings::GameSettings, input_pool::STEP_PHASE_TIME_SECS, match_result::MatchResult, packet::{InputState, InputStep, Packet}, playback_buffer::{PlaybackBuffer, StepPhase}, replay::Replay, rollback::{PendingSync, PredictedGen, Prediction, Rollback, SimulationSnapshot}, ship_ai::{ShipAI, ShipSighting}, ship_mod::ShipModType, state_digest::{DigestHistory, StateDigest}, sync_checker::{SYNC_STATE_GEN_INTERVAL, SyncState}, world::World, wrap_rcc};
 
 pub const MAX_INPUT_STEP_BLOCK_TIME: f32 = 20.0;
 pub const DEFAULT_SIMULATION_TIMESTEP: f64 = 60.0;
@@ -9,6 +9,7 @@ pub const MAX_ACCELERATED_TIMESTEP: f64 = DEFAULT_SIMULATION_TIMESTEP * 6.0; //
 
 pub struct Controller {
     pub players: IndexMap<u16, Rcc<Player>>,
+    pub computers: IndexMap<u16, ShipAI>, // Players whose input is generated by the simulation
     pub local_player: Option<Rcc<Player>>,
     pub catch_input: bool,
     pub input_buffer: PlaybackBuffer,
@@ -17,20 +18,36 @@ pub struct Controller {
     blocking_time: Instant,
     target_x: Sprite,
     curr_target_pos: Option<V2>,
+    settings: GameSettings,
+    match_result: Option<MatchResult>,
+    last_sync_state: Option<SyncState>,
+    pub recording: Option<Replay>,
+    pub playback_speed: Option<f64>, // Fixed speed factor during replays, instead of adapting to the buffer
+    pub digest_history: DigestHistory,
+    // Simulation speed chosen at the last step, applied by whoever owns the context
+    next_timestep: Option<f64>,
+    // Runs ahead of the received steps by predicting them, online only
+    rollback: Option<Rollback>,
     game: GC
 }
 
 impl Controller {
-    pub fn new(ctx: &mut Context, game: GC) -> tetra::Result<Controller> {
-        let target_x = game.borrow_mut().assets.load_texture(
-            ctx, "UI/X.png".to_owned(), false)?;
+    pub fn new(settings: GameSettings, game: GC) -> tetra::Result<Controller> {
+        let target_x = game.borrow().assets.load_sprite("UI/X.png", SpriteOrigin::Centre);
+        let rollback = {
+            let game_ref = game.borrow();
+            let is_player = game_ref.network.as_ref().is_some_and(|n| !n.client.is_spectator());
+            (game_ref.settings.rollback && is_player).then(Rollback::new)
+        };
         let mut controller = Controller {
-            players: IndexMap::new(), local_player: None, catch_input: true,
+            players: IndexMap::new(), computers: IndexMap::new(), local_player: None, catch_input: true,
             input_buffer: PlaybackBuffer::new(),
             curr_input_state: InputState::default(),
             curr_gen: 0, blocking_time: Instant::now(),
-            target_x: Sprite::new(target_x, SpriteOrigin::Centre, None),
-            curr_target_pos: None, game
+            target_x,
+            curr_target_pos: None, settings, match_result: None, last_sync_state: None,
+            recording: None, playback_speed: None, digest_history: DigestHistory::new(),
+            next_timestep: None, rollback, game
         };
         controller.send_curr_state().convert()?; // Notify server we are finished loading
         Ok(controller)
@@ -43,7 +60,13 @@ impl Controller {
         player_ref
     }
 
+    pub fn add_computer_player(&mut self, player: Player, ai: ShipAI) -> Rcc<Player> {
+        self.computers.insert(player.id.n, ai);
+        self.add_player(player)
+    }
+
     pub fn remove_player(&mut self, id: u16) -> Option<Rcc<Player>> {
+        self.computers.shift_remove(&id);
         self.players.remove(&id)
     }
 
@@ -64,7 +87,8 @@ impl Controller {
         // The simulation is ready for the next frame, if...
         match self.input_buffer.get_curr_phase() {
             // ...the current phase is imminent or over and the next step is ready
-            StepPhase::Over | StepPhase::Imminent => self.input_buffer.get_buffer_size() > 0,
+            StepPhase::Over | StepPhase::Imminent => self.input_buffer.get_buffer_size() > 0
+                || self.rollback.as_ref().is_some_and(|r| r.can_predict()),
             // ...or the phase is still going on
             StepPhase::Running => true
         }
@@ -86,7 +110,109 @@ impl Controller {
         self.curr_gen
     }
 
-    fn adjust_simulation(&mut self, ctx: &mut Context) {
+    pub fn get_settings(&self) -> GameSettings {
+        self.settings
+    }
+
+    pub fn get_confirmed_gen(&self) -> u64 {
+        match self.rollback.as_ref() {
+            Some(rollback) => self.curr_gen - rollback.get_predicted_gens() as u64,
+            None => self.curr_gen
+        }
+    }
+
+    // A result reached with predicted steps may still be rolled back
+    pub fn get_match_result(&self) -> Option<&MatchResult> {
+        self.match_result.as_ref().filter(|r| r.gen <= self.get_confirmed_gen())
+    }
+
+    // Headless simulations have no network to decide on it
+    pub fn enable_rollback(&mut self) {
+        self.rollback = Some(Rollback::new());
+    }
+
+    pub fn get_rollback(&self) -> Option<&Rollback> {
+        self.rollback.as_ref()
+    }
+
+    pub fn set_resimulating(&mut self, resimulating: bool) {
+        if let Some(rollback) = self.rollback.as_mut() {
+            rollback.resimulating = resimulating;
+        }
+    }
+
+    pub fn save_state(&self, world: &World) -> SimulationSnapshot {
+        let game_ref = self.game.borrow();
+        SimulationSnapshot {
+            gen: self.curr_gen,
+            frames: self.input_buffer.curr_frames,
+            frame_index: self.input_buffer.get_curr_frame_index(),
+            players: self.players.clone(),
+            computers: self.computers.clone(),
+            match_result: self.match_result.clone(),
+            world: world.save_state(),
+            physics: game_ref.physics.save_state(),
+            economy: game_ref.economy,
+            weather: game_ref.weather.clone()
+        }
+    }
+
+    pub fn restore_state(&mut self, snapshot: &SimulationSnapshot, world: &mut World) {
+        self.curr_gen = snapshot.gen;
+        self.input_buffer.rewind(snapshot.frames, snapshot.frame_index);
+        self.players = snapshot.players.clone();
+        self.computers = snapshot.computers.clone();
+        self.match_result = snapshot.match_result.clone();
+        {
+            let mut game_ref = self.game.borrow_mut();
+            game_ref.physics.restore_state(&snapshot.physics);
+            game_ref.economy = snapshot.economy;
+            game_ref.weather = snapshot.weather.clone();
+            game_ref.world.discard_events_after(snapshot.gen);
+        }
+        world.restore_state(&snapshot.world);
+    }
+
+    // Confirms predicted steps as they arrive. On a misprediction, returns the snapshot to
+    // simulate again from.
+    pub fn reconcile_predictions(&mut self) -> BbResult<Option<SimulationSnapshot>> {
+        loop {
+            let step = match self.input_buffer.get_buffered_steps().next() {
+                Some(step) => step.clone(),
+                None => break
+            };
+            let prediction = match self.rollback.as_mut() {
+                Some(rollback) => rollback.check_prediction(&step),
+                None => None
+            };
+            match prediction {
+                Some(Prediction::Correct(predicted_gen)) => {
+                    self.input_buffer.skip_step();
+                    self.confirm_step(step, predicted_gen.digest, predicted_gen.sync)?;
+                },
+                Some(Prediction::Incorrect(predicted_gen)) => {
+                    println!("Gen {} was mispredicted. Rolling back...", step.gen);
+                    return Ok(Some(predicted_gen.snapshot))
+                },
+                None => break
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_last_sync_state(&self) -> Option<SyncState> {
+        self.last_sync_state
+    }
+
+    pub fn take_next_timestep(&mut self) -> Option<f64> {
+        self.next_timestep.take()
+    }
+
+    fn adjust_simulation(&mut self) {
+        if let Some(speed) = self.playback_speed {
+            self.next_timestep = Some(DEFAULT_SIMULATION_TIMESTEP * speed);
+            return
+        }
         let buffered_steps = self.input_buffer.get_buffer_size();
         let optimal_buffer_size = self.input_buffer.estimate_optimal_buffer_size();
         let timestep = {
@@ -106,37 +232,138 @@ impl Controller {
                 DEFAULT_SIMULATION_TIMESTEP
             }
         };
-        set_timestep(ctx, Timestep::Fixed(timestep));
+        self.next_timestep = Some(timestep);
     }
 
-    fn update_step(&mut self, ctx: &mut Context, world: &mut World) -> tetra::Result {
-        if self.input_buffer.get_curr_phase() == StepPhase::Over {
-            if let Some(next_step) = self.input_buffer.get_next_step() {
-                self.apply_step(ctx, next_step, world)?;
-                self.send_curr_state().convert()?;
-                self.check_sync_state().convert()?;
-                self.adjust_simulation(ctx);
-            }
+    fn update_step(&mut self, world: &mut World) -> tetra::Result {
+        if self.input_buffer.get_curr_phase() != StepPhase::Over {
+            return Ok(())
         }
+        if let Some(next_step) = self.input_buffer.get_next_step() {
+            self.apply_step(next_step.clone(), world)?;
+            let digest = self.gen_digest(world);
+            let sync = self.gen_sync_state();
+            self.confirm_step(next_step, digest, sync).convert()?;
+        } else if self.rollback.as_ref().is_some_and(|r| r.can_predict()) {
+            let snapshot = self.save_state(world);
+            let local_id = self.local_player.as_ref().map(|p| p.borrow().id.n);
+            let step = self.rollback.as_ref().unwrap().predict_step(self.curr_gen + 1, local_id);
+            self.input_buffer.restart_phase();
+            self.apply_step(step.clone(), world)?;
+            let predicted_gen = PredictedGen {
+                step, snapshot, digest: self.gen_digest(world), sync: self.gen_sync_state()
+            };
+            self.rollback.as_mut().unwrap().add_prediction(predicted_gen);
+        } else {
+            return Ok(())
+        }
+        self.send_curr_state().convert()?;
+        self.adjust_simulation();
         Ok(())
     }
 
-    fn apply_step(&mut self, ctx: &mut Context, step: InputStep, world: &mut World) -> tetra::Result {
-        self.curr_gen += 1;
+    // Only called once a step is known to be final, predicted or not
+    fn confirm_step(&mut self, step: InputStep, digest: Option<StateDigest>,
+        sync: Option<PendingSync>) -> BbResult {
         self.blocking_time = Instant::now();
+        if let Some(rollback) = self.rollback.as_mut() {
+            rollback.confirm_step(&step);
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            recording.add_step(step);
+        }
+        if let Some(digest) = digest {
+            self.digest_history.add_digest(digest);
+        }
+        match sync {
+            Some(sync) => self.commit_sync_state(sync),
+            None => Ok(())
+        }
+    }
+
+    // Only online matches can be asked for digests
+    fn gen_digest(&self, world: &World) -> Option<StateDigest> {
+        self.game.borrow().network.as_ref().map(|_| world.gen_digest(self.curr_gen))
+    }
+
+    fn apply_step(&mut self, step: InputStep, world: &mut World) -> tetra::Result {
+        self.curr_gen += 1;
         assert!(self.curr_gen == step.gen);
 
+        self.update_weather()?;
         for (sender, state) in step.states.into_iter() {
-            self.apply_state(ctx, sender, state, world)?;
+            self.apply_state(sender, state, world)?;
+        }
+        for (id, state) in self.gen_computer_states(world).into_iter() {
+            self.apply_state(id, state, world)?;
         }
+        self.check_match_result();
         Ok(())
     }
 
-    fn apply_state(&mut self, ctx: &mut Context, sender: u16, state: InputState, world: &mut World)
+    // All computer players decide on the same snapshot, so their order does not matter
+    fn gen_computer_states(&mut self, world: &World) -> Vec<(u16, InputState)> {
+        if self.computers.is_empty() {
+            return vec![]
+        }
+        let ships = self.players.values().map(|p| {
+            let p_ref = p.borrow();
+            let pos = p_ref.possessed_ship.borrow().transform.get_translation().0;
+            ShipSighting {
+                id: p_ref.id.n, pos
+            }
+        }).collect::<Vec<_>>();
+        let harbours = world.get_harbour_positions();
+        let players = &self.players;
+        self.computers.iter_mut()
+            .filter_map(|(id, ai)| players.get(id).map(|p| {
+                let p_ref = p.borrow();
+                let ship_ref = p_ref.possessed_ship.borrow();
+                (*id, ai.gen_input_state(*id, &ship_ref, &ships, &harbours))
+            }))
+            .collect()
+    }
+
+    fn update_weather(&mut self) -> tetra::Result {
+        let storm_damage = {
+            let mut game_ref = self.game.borrow_mut();
+            game_ref.weather.update(self.curr_gen);
+            let wind = game_ref.weather.wind;
+            game_ref.physics.wind = wind;
+            game_ref.weather.get_storm_damage(self.curr_gen)
+        };
+        if let Some(damage) = storm_damage {
+            for player in self.players.values() {
+                let player_ref = player.borrow();
+                let mut ship_ref = player_ref.possessed_ship.borrow_mut();
+                // Storms batter the hull but never sink a ship. Harbours give shelter.
+                if ship_ref.status.is_in_harbour || ship_ref.data.curr_health <= 1 {
+                    continue
+                }
+                let remaining_health = ship_ref.data.curr_health
+                    - damage.min(ship_ref.data.curr_health - 1);
+                ship_ref.set_health(remaining_health);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_match_result(&mut self) {
+        if self.match_result.is_some() {
+            return
+        }
+        // Standings are frozen at the generation the goal was first reached
+        if let Some(result) = MatchResult::check(self.curr_gen, self.settings.mode, &self.players) {
+            println!("{:?} won the match at gen {}.", result.get_winner().id, result.gen);
+            self.match_result = Some(result);
+        }
+    }
+
+    fn apply_state(&mut self, sender: u16, state: InputState, world: &mut World)
         -> tetra::Result {
         if let Some(player) = self.players.get(&sender) {
             let disconnect = state.disconnect;
-            player.borrow_mut().apply_state(ctx, state, world)?;
+            player.borrow_mut().apply_state(state, world)?;
             if disconnect {
                 self.remove_player(sender);
             }
@@ -147,46 +374,68 @@ impl Controller {
     }
 
     fn send_curr_state(&mut self) -> BbResult {
-        self.game.borrow_mut().network.as_mut().unwrap().send_input(
-            self.curr_input_state.clone())?;
+        // Steps that are simulated again were sent for the first time around
+        if let Some(rollback) = self.rollback.as_mut() {
+            if rollback.resimulating {
+                return Ok(())
+            }
+            rollback.add_sent_state(self.curr_gen, self.curr_input_state.clone());
+        }
+        // Replays run without network, all input comes from the recorded steps.
+        // Spectators don't own a ship, so they have nothing to send.
+        if let Some(network) = self.game.borrow_mut().network.as_mut()
+            .filter(|n| !n.client.is_spectator()) {
+            network.send_input(self.curr_input_state.clone())?;
+        }
         self.curr_input_state = InputState::default();
         Ok(())
     }
 
-    fn check_sync_state(&mut self) -> BbResult {
-        if self.curr_gen % SYNC_STATE_GEN_INTERVAL == 0 && self.curr_gen > 0 {
-            let player_ships = self.players
-                .values()
-                .map(|p| p.borrow().possessed_ship.clone())
-                .collect::<Vec<_>>();
-            let state = SyncState::gen_from_ships(self.curr_gen, player_ships.clone());
-            {
-                let ship_data = self.players.values().map(|p| {
-                    let p_ref = p.borrow();
-                    let ship_ref = p_ref.possessed_ship.borrow_mut();
-                    let translation = ship_ref.transform.get_translation();
-                    SyncStateShipData::new(p_ref.id.n,
-                        translation.0, translation.1, ship_ref.data.curr_health, ship_ref.treasury.balance)
-                }).collect();
-
-                let mut game_ref = self.game.borrow_mut();
-                game_ref.diagnostics.add_state(DiagnosticState::new_sync_state(
-                    self.curr_gen, self.input_buffer.curr_frames, state, ship_data));
-                game_ref.network.as_mut().unwrap().send_packet(Packet::Sync {
-                    state
-                })
-            }
-        } else {
-            Ok(())
+    fn gen_sync_state(&self) -> Option<PendingSync> {
+        if self.curr_gen % SYNC_STATE_GEN_INTERVAL != 0 || self.curr_gen == 0 {
+            return None
+        }
+        let player_ships = self.players
+            .values()
+            .map(|p| p.borrow().possessed_ship.clone())
+            .collect::<Vec<_>>();
+        let state = SyncState::gen_from_ships(self.curr_gen, player_ships);
+        let ship_data = self.players.values().map(|p| {
+            let p_ref = p.borrow();
+            let ship_ref = p_ref.possessed_ship.borrow();
+            let translation = ship_ref.transform.get_translation();
+            SyncStateShipData::new(p_ref.id.n,
+                translation.0, translation.1, ship_ref.data.curr_health, ship_ref.treasury.balance)
+        }).collect();
+        Some(PendingSync {
+            state, ship_data, frames: self.input_buffer.curr_frames
+        })
+    }
+
+    fn commit_sync_state(&mut self, sync: PendingSync) -> BbResult {
+        let state = sync.state;
+        self.last_sync_state = Some(state);
+        if let Some(recording) = self.recording.as_mut() {
+            recording.add_sync_state(state);
+        }
+
+        let mut game_ref = self.game.borrow_mut();
+        game_ref.diagnostics.add_state(DiagnosticState::new_sync_state(
+            state.t, sync.frames, state, sync.ship_data));
+        match game_ref.network.as_mut().filter(|n| !n.client.is_spectator()) {
+            Some(network) => network.send_packet(Packet::Sync {
+                state
+            }),
+            None => Ok(())
         }
     }
 }
 
 impl GameState for Controller {
-    fn update(&mut self, ctx: &mut Context, world: &mut World) -> tetra::Result {
+    fn update(&mut self, world: &mut World) -> tetra::Result {
         // First update step, to start checking at frame zero?
-        self.update_step(ctx, world)?;
-        self.input_buffer.update(ctx)?;
+        self.update_step(world)?;
+        self.input_buffer.update();
         self.game.borrow_mut().simulation_settings.update(
             self.curr_gen, self.input_buffer.curr_frames);
         Ok(())
@@ -218,29 +467,27 @@ impl GameState for Controller {
                     self.curr_input_state.r = false;
                 },
                 Event::KeyPressed { key } => {
-                    match key {
-                        Key::R => {
-                            let mouse_pos = Some({
-                                let game_ref = self.game.borrow();
-                                game_ref.cam.get_mouse_pos(ctx)
-                            });
-                            self.curr_target_pos = mouse_pos.clone();
-                            self.curr_input_state.mouse_pos = mouse_pos;
-                            self.curr_input_state.r = true;
-                            self.curr_input_state.rmb = false;
-                        },
-                        Key::Space => {
-                            self.curr_input_state.q = true;
-                            self.curr_input_state.e = true;
-                        },
-                        Key::Q => self.curr_input_state.q = true,
-                        Key::E => self.curr_input_state.e = true,
-                        Key::Tab => {
-                            let curr_pos = local_player.borrow()
-                                .possessed_ship.borrow().transform.get_translation().0;
-                            self.game.borrow_mut().cam.centre_on(curr_pos);
-                        }
-                        _ => ()
+                    let keys = self.game.borrow().settings.key_bindings;
+                    if key == keys.rotate {
+                        let mouse_pos = Some({
+                            let game_ref = self.game.borrow();
+                            game_ref.cam.get_mouse_pos(ctx)
+                        });
+                        self.curr_target_pos = mouse_pos.clone();
+                        self.curr_input_state.mouse_pos = mouse_pos;
+                        self.curr_input_state.r = true;
+                        self.curr_input_state.rmb = false;
+                    } else if key == keys.shoot_all {
+                        self.curr_input_state.q = true;
+                        self.curr_input_state.e = true;
+                    } else if key == keys.shoot_bowside {
+                        self.curr_input_state.q = true;
+                    } else if key == keys.shoot_portside {
+                        self.curr_input_state.e = true;
+                    } else if key == keys.centre_cam {
+                        let curr_pos = local_player.borrow()
+                            .possessed_ship.borrow().transform.get_translation().0;
+                        self.game.borrow_mut().cam.centre_on(curr_pos);
                     }
                 },
                 _ => ()
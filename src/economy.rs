@@ -2,6 +2,7 @@ pub const BASE_SHIP_ESCUDO_BALANCE: u32 = 80;
 pub const BASE_ESCUDO_PAYOUT: u32 = 50;
 pub const NETWORTH_PAYOUT_PERCENTAGE: f32 = 0.4;
 
+#[derive(Clone, Copy)]
 pub struct Deposit {
     pub balance: u32,
     pub networth: u32
@@ -33,6 +34,7 @@ impl Deposit {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Economy {
     pub escudos_in_circulation: u32,
     pub produced_escudos: u32,
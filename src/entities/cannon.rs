@@ -1,6 +1,6 @@
 use rapier2d::{data::Index, na::Vector2};
 use tetra::{Context, State, graphics::text::Text};
-use crate::{AnimatedSprite, CANNON_BALL_COLL_GROUP, EMPTY_COLL_GROUP, GC, MASS_FORCE_SCALE, Rcc, Sprite, SpriteOrigin, Timer, Transform, V2, build_water_splash_sprite, conv_vec, entity::{Entity, EntityType, GameState}, get_angle, polar_to_cartesian, ship::Ship, ship_mod::Attribute, world::World};
+use crate::{AnimatedSprite, CANNON_BALL_COLL_GROUP, EMPTY_COLL_GROUP, GC, MASS_FORCE_SCALE, Rcc, Sprite, SpriteOrigin, Timer, Transform, V2, WATER_SPLASH_FRAMES, WATER_SPLASH_FRAME_LENGTH, build_water_splash_sprite, conv_vec, entity::{Entity, EntitySnapshot, EntityType, GameState}, get_angle, polar_to_cartesian, ship::Ship, ship_mod::Attribute, state_digest::{DigestField, EntityDigest}, world::World};
 
 pub const POWER_FORCE_FACTOR: f32 = 35.0 * MASS_FORCE_SCALE;
 pub const POWER_DROP_THRESHOLD: f32 = 4.0 * POWER_FORCE_FACTOR / MASS_FORCE_SCALE;
@@ -13,6 +13,15 @@ pub enum CannonSide {
     Stern
 }
 
+#[derive(Clone)]
+pub struct CannonSnapshot {
+    dmg: Attribute<u16>,
+    reload: Timer,
+    reload_time: Attribute<f32>,
+    shooting_power: Attribute<f32>,
+    ship_translation: (V2, f32)
+}
+
 pub struct Cannon {
     pub translation: (V2, f32),
     pub relative_rot: f32,
@@ -24,24 +33,22 @@ pub struct Cannon {
     pub ship_translation: (V2, f32),
     ship_index: Index,
     cannon_sprite: Sprite,
-    shoot_effect: AnimatedSprite,
-    reload_label: Text,
+    shoot_effect: Option<AnimatedSprite>,
+    reload_label: Option<Text>,
     shoot: bool,
     game: GC
 }
 
 impl Cannon {
-    pub fn new(ctx: &mut Context, relative_pos: V2, relative_rot: f32, dmg: u16,
+    pub fn new(relative_pos: V2, relative_rot: f32, dmg: u16,
         side: CannonSide, reload_time: f32, shooting_power: f32, ship_index: Index, game: GC) -> tetra::Result<Cannon> {
-        let mut game_ref = game.borrow_mut();
-        let cannon_tex = game_ref.assets.load_texture(ctx, "Cannon.png".to_owned(), true)?;
-        let shoot_tex = game_ref.assets.load_texture(ctx, "Shoot Cannon.png".to_owned(), true)?;
-        let reload_label = Text::new("*", game_ref.assets.font.clone());
+        let game_ref = game.borrow();
+        let cannon_sprite = game_ref.assets.load_sprite("Cannon.png", SpriteOrigin::Centre);
+        let shoot_effect = game_ref.assets.get_texture("Shoot Cannon.png")
+            .map(|tex| AnimatedSprite::new(tex, 5, 15.0, 15.0, 0.2, false, None));
+        let reload_label = game_ref.assets.get_fonts()
+            .map(|fonts| Text::new("*", fonts.font.clone()));
         std::mem::drop(game_ref);
-
-        let cannon_sprite = Sprite::new(cannon_tex, SpriteOrigin::Centre, None);
-        let shoot_effect = AnimatedSprite::new(game.borrow_mut().assets.load_texture(
-            ctx, "Shoot Cannon.png".to_owned(), true)?, 5, 15.0, 15.0, 0.2, false, None);
         
         Ok(Cannon {
             translation: (relative_pos, get_angle(relative_pos)), relative_rot,
@@ -53,7 +60,7 @@ impl Cannon {
         })
     }
 
-    pub fn shoot(&mut self, ctx: &mut Context, world: &mut World)
+    pub fn shoot(&mut self, world: &mut World)
         -> tetra::Result<Option<Rcc<CannonBall>>> {
         if !self.can_shoot() {
             return Ok(None);
@@ -62,9 +69,11 @@ impl Cannon {
         let curr_translation = self.get_world_translation();
         let facing_dir = polar_to_cartesian(1.0, curr_translation.1);
         let starting_pos = curr_translation.0 + facing_dir;
-        let cannon_ball = CannonBall::new(ctx, self.dmg.total(), self.shooting_power.total(),
+        let shooting_power = self.shooting_power.total()
+            * self.game.borrow().weather.get_cannon_range_factor(); // Rain shortens range
+        let cannon_ball = CannonBall::new(self.dmg.total(), shooting_power,
             self.ship_index, starting_pos, facing_dir, self.game.clone())?;
-        let cannon_ball = world.add_cannon_ball(ctx, cannon_ball);
+        let cannon_ball = world.add_cannon_ball(cannon_ball);
 
         // Shoot effect
         self.reload.reset();
@@ -88,6 +97,25 @@ impl Cannon {
         self.ship_translation = ship_translation;
     }
 
+    pub fn update(&mut self) {
+        self.reload.update();
+    }
+
+    pub fn save_state(&self) -> CannonSnapshot {
+        CannonSnapshot {
+            dmg: self.dmg, reload: self.reload, reload_time: self.reload_time,
+            shooting_power: self.shooting_power, ship_translation: self.ship_translation
+        }
+    }
+
+    pub fn restore_state(&mut self, snapshot: &CannonSnapshot) {
+        self.dmg = snapshot.dmg;
+        self.reload = snapshot.reload;
+        self.reload_time = snapshot.reload_time;
+        self.shooting_power = snapshot.shooting_power;
+        self.ship_translation = snapshot.ship_translation;
+    }
+
     pub fn get_world_translation(&self) -> (V2, f32) {
         (self.ship_translation.0 + polar_to_cartesian(self.translation.0.magnitude(),
             self.translation.1 + self.ship_translation.1), self.relative_rot + self.ship_translation.1)
@@ -95,31 +123,36 @@ impl Cannon {
 }
 
 impl State for Cannon {
-    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.reload.update(ctx);
-        Ok(())
-    }
-
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
         let curr_translation = self.get_world_translation();
         self.cannon_sprite.draw2(ctx, curr_translation);
-        if self.shoot && !self.shoot_effect.is_finished() {
-            self.shoot_effect.draw(ctx, curr_translation);
+        if let Some(shoot_effect) = self.shoot_effect.as_mut() {
+            if self.shoot && !shoot_effect.is_finished() {
+                shoot_effect.draw(ctx, curr_translation);
+            }
         }
-        if !self.can_shoot() {
-            self.reload_label.draw(ctx, curr_translation.0);
+        let can_shoot = self.can_shoot();
+        if let Some(reload_label) = self.reload_label.as_mut().filter(|_| !can_shoot) {
+            reload_label.draw(ctx, curr_translation.0);
         }
         Ok(())
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum CannonBallState {
     Travelling,
     Hit,
     Miss
 }
 
+#[derive(Clone)]
+pub struct CannonBallSnapshot {
+    state: CannonBallState,
+    miss_timer: Option<Timer>,
+    destroy: bool
+}
+
 pub struct CannonBall {
     pub dmg: u16,
     pub shooter_index: Index,
@@ -127,19 +160,20 @@ pub struct CannonBall {
     pub transform: Transform,
     sprite: Sprite,
     miss_effect: Option<AnimatedSprite>,
+    // The lifetime of a missed ball must not depend on drawing its effect
+    miss_timer: Option<Timer>,
     destroy: bool,
     game: GC
 }
 
 impl CannonBall {
-    pub fn new(ctx: &mut Context, dmg: u16, shooting_power: f32, shooter_index: Index,
+    pub fn new(dmg: u16, shooting_power: f32, shooter_index: Index,
         starting_pos: V2, dir: V2, game: GC) -> tetra::Result<CannonBall> {
         let mut game_ref = game.borrow_mut();
-        let sprite = Sprite::new(game_ref.assets.load_texture(ctx,
-            "Cannon Ball.png".to_owned(), true)?, SpriteOrigin::Centre, None);
+        let sprite = game_ref.assets.load_sprite("Cannon Ball.png", SpriteOrigin::Centre);
         
         let physics_handle = game_ref.physics.build_cannon_ball(
-            sprite.texture.width() as f32, 0.1);
+            sprite.get_size().x, 0.1);
         std::mem::drop(game_ref);
 
         let mut transform = Transform::new(physics_handle, game.clone());
@@ -154,20 +188,20 @@ impl CannonBall {
 
         Ok(CannonBall {
             dmg, shooter_index, transform, state: CannonBallState::Travelling,
-            sprite, miss_effect: None, destroy: false, game
+            sprite, miss_effect: None, miss_timer: None, destroy: false, game
         })
     }
 
-    fn check_trajectory(&mut self, ctx: &mut Context) -> tetra::Result {
+    fn check_trajectory(&mut self) -> tetra::Result {
         if self.transform.get_lin_velocity().magnitude() <= POWER_DROP_THRESHOLD
             && self.state == CannonBallState::Travelling {
-            self.on_drop(ctx)
+            self.on_drop()
         } else {
             Ok(())
         }
     }
 
-    fn on_hit_ship(&mut self, ctx: &mut Context, ship: Rcc<Ship>, world: &mut World)
+    fn on_hit_ship(&mut self, ship: Rcc<Ship>, world: &mut World)
         -> tetra::Result {
         // Issue: When two cannon balls hit a ship in immediate succession, and the first
         // sinks the ship, the second still applies damage right afterward. This results
@@ -175,10 +209,10 @@ impl CannonBall {
         // Even potential desync issues?
         self.state = CannonBallState::Hit;
         self.destroy = true;
-        ship.borrow_mut().take_cannon_ball_hit(ctx, self.dmg, self.shooter_index, world)       
+        ship.borrow_mut().take_cannon_ball_hit(self.dmg, self.shooter_index, world)       
     }
 
-    fn miss(&mut self, _ctx: &mut Context, miss_effect: AnimatedSprite) -> tetra::Result {
+    fn miss(&mut self, miss_effect: Option<AnimatedSprite>) -> tetra::Result {
         self.state = CannonBallState::Miss;
         let mut game_ref = self.game.borrow_mut();
         let rb = game_ref.physics.get_rb_mut(
@@ -186,26 +220,28 @@ impl CannonBall {
         rb.set_linvel(Vector2::new(0.0, 0.0), true);
         game_ref.physics.set_coll_group(self.transform.handle.1,
             CANNON_BALL_COLL_GROUP, EMPTY_COLL_GROUP);
-        self.miss_effect = Some(miss_effect);
+        self.miss_effect = miss_effect;
+        self.miss_timer = Some(Timer::start(
+            (WATER_SPLASH_FRAMES - 1) as f32 * WATER_SPLASH_FRAME_LENGTH));
         Ok(())
     }
 
-    fn on_drop(&mut self, ctx: &mut Context) -> tetra::Result {
-        let water_splash_effect = build_water_splash_sprite(ctx, self.game.clone(),
-            self.transform.get_translation().0)?;
-        self.miss(ctx, water_splash_effect)
+    fn on_drop(&mut self) -> tetra::Result {
+        let water_splash_effect = build_water_splash_sprite(self.game.clone(),
+            self.transform.get_translation().0);
+        self.miss(water_splash_effect)
     }
 
-    fn on_hit_object(&mut self, ctx: &mut Context) -> tetra::Result {
+    fn on_hit_object(&mut self) -> tetra::Result {
         let curr_pos = self.transform.get_translation().0;
-        let water_splash_effect = build_water_splash_sprite(ctx, self.game.clone(),
-            curr_pos)?; // Add different effect
-        self.miss(ctx, water_splash_effect)
+        let water_splash_effect = build_water_splash_sprite(self.game.clone(),
+            curr_pos); // Add different effect
+        self.miss(water_splash_effect)
     }
 
-    fn check_miss_lifetime(&mut self, _: &mut Context, _world: &mut World) -> tetra::Result {
-        if let Some(miss_effect) = self.miss_effect.as_ref() {
-            if miss_effect.is_finished() {
+    fn check_miss_lifetime(&mut self, _world: &mut World) -> tetra::Result {
+        if let Some(miss_timer) = self.miss_timer.as_mut() {
+            if miss_timer.run() {
                 self.destroy();
             }
         }
@@ -238,34 +274,61 @@ impl Entity for CannonBall {
         self.destroy = true;
     }
 
-    fn collide_with_ship(&mut self, ctx: &mut Context, other: Rcc<Ship>,
-        world: &mut World) -> tetra::Result {
+    fn digest(&self, digest: &mut EntityDigest) {
+        digest.add(DigestField::Damage, self.dmg);
+        digest.add(DigestField::State, match self.state {
+            CannonBallState::Travelling => 0u16,
+            CannonBallState::Hit => 1,
+            CannonBallState::Miss => 2
+        });
+        digest.add(DigestField::Shooter, self.shooter_index);
+        digest.add(DigestField::Destroy, self.destroy);
+    }
+
+    fn save_state(&self) -> Option<EntitySnapshot> {
+        Some(EntitySnapshot::CannonBall(CannonBallSnapshot {
+            state: self.state, miss_timer: self.miss_timer, destroy: self.destroy
+        }))
+    }
+
+    fn restore_state(&mut self, snapshot: &EntitySnapshot) {
+        if let EntitySnapshot::CannonBall(snapshot) = snapshot {
+            self.state = snapshot.state;
+            self.miss_timer = snapshot.miss_timer;
+            self.destroy = snapshot.destroy;
+            if self.state == CannonBallState::Travelling {
+                self.miss_effect = None;
+            }
+        }
+    }
+
+    fn collide_with_ship(&mut self, other: Rcc<Ship>, world: &mut World) -> tetra::Result {
         let entity_ref = other.borrow_mut();
         if entity_ref.get_index() == self.shooter_index { // Ignore if hitting own ship
             Ok(())
         } else {
             std::mem::drop(entity_ref);
-            self.on_hit_ship(ctx, other, world)
+            self.on_hit_ship(other, world)
         }
     }
 
-    fn collide_with_entity(&mut self, ctx: &mut Context, _other: Rcc<dyn Entity>,
-        _world: &mut World) -> tetra::Result {
-        self.on_hit_object(ctx)
+    fn collide_with_entity(&mut self, _other: Rcc<dyn Entity>, _world: &mut World)
+        -> tetra::Result {
+        self.on_hit_object()
     }
 }
 
 impl GameState for CannonBall {
-    fn update(&mut self, ctx: &mut Context, world: &mut World) -> tetra::Result {
-        self.check_trajectory(ctx)?;
-        self.check_miss_lifetime(ctx, world)
+    fn update(&mut self, world: &mut World) -> tetra::Result {
+        self.check_trajectory()?;
+        self.check_miss_lifetime(world)
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
         if let Some(miss_effect) = self.miss_effect.as_mut() {
             miss_effect.draw(ctx, self.transform.get_translation());
         }
-        else {
+        else if self.state == CannonBallState::Travelling {
             self.sprite.draw2(ctx, self.transform.get_translation());
         }
         Ok(())
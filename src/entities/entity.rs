@@ -1,8 +1,8 @@
 use rapier2d::data::Index;
 use tetra::{Context, Event};
-use crate::{Rcc, Transform, ship::Ship, world::World};
+use crate::{CannonBallSnapshot, Rcc, Transform, ship::{Ship, ShipSnapshot}, state_digest::EntityDigest, world::World};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EntityType {
     Ship = 0,
     Object = 1,
@@ -31,8 +31,17 @@ impl EntityType {
     }
 }
 
+// Simulated state an entity keeps outside of the physics, for rolling back the world
+#[derive(Clone)]
+pub enum EntitySnapshot {
+    Ship(ShipSnapshot),
+    CannonBall(CannonBallSnapshot),
+    Destroy(bool)
+}
+
+// Updating is pure simulation and never touches the context, so worlds can run headless
 pub trait GameState {
-    fn update(&mut self, ctx: &mut Context, world: &mut World) -> tetra::Result {
+    fn update(&mut self, world: &mut World) -> tetra::Result {
         Ok(())
     }
 
@@ -57,25 +66,36 @@ pub trait Entity : GameState {
     fn destroy(&mut self) {
     }
 
-    fn collide_with_ship(&mut self, ctx: &mut Context, other: Rcc<Ship>,
-        world: &mut World) -> tetra::Result {
+    fn collide_with_ship(&mut self, other: Rcc<Ship>, world: &mut World) -> tetra::Result {
         Ok(())
     }
 
-    fn collide_with_entity(&mut self, ctx: &mut Context, other: Rcc<dyn Entity>,
-        world: &mut World) -> tetra::Result {
+    fn collide_with_entity(&mut self, other: Rcc<dyn Entity>, world: &mut World)
+        -> tetra::Result {
         Ok(())
     }
-    fn collide_with_neutral(&mut self, ctx: &mut Context) -> tetra::Result {
+    fn collide_with_neutral(&mut self) -> tetra::Result {
         Ok(())
     }
 
-    fn intersect_with_entity(&mut self, ctx: &mut Context, state: bool,
-        other: Rcc<dyn Entity>) -> tetra::Result {
+    fn intersect_with_entity(&mut self, state: bool, other: Rcc<dyn Entity>)
+        -> tetra::Result {
         Ok(())
     }
 
     fn get_index(&self) -> Index {
         self.get_transform().get_index()
     }
+
+    // Adds simulated state beyond the transform, which the world already covers
+    fn digest(&self, digest: &mut EntityDigest) {
+    }
+
+    // Entities without any state beyond their transform have nothing to save
+    fn save_state(&self) -> Option<EntitySnapshot> {
+        None
+    }
+
+    fn restore_state(&mut self, snapshot: &EntitySnapshot) {
+    }
 }
@@ -7,30 +7,31 @@ pub struct Harbour {
     pub transform: Transform,
     pub zone_handle: ColliderHandle,
     pub sprite: Sprite,
-    name_label: Text,
+    name: String,
+    name_label: Option<Text>,
     game: GC
 }
 
 impl Harbour {
-    pub fn new(ctx: &mut Context, name: String, pos: V2, rot: f32, game: GC)
+    pub fn new(name: String, pos: V2, rot: f32, game: GC)
         -> tetra::Result<Harbour> {
         let mut game_ref = game.borrow_mut();
-        let texture = game_ref.assets.load_texture(ctx, "Harbour.png".to_owned(), true)?;
-        let sprite = Sprite::new(texture, SpriteOrigin::Centre, None);
+        let sprite = game_ref.assets.load_sprite("Harbour.png", SpriteOrigin::Centre);
         let sprite_size = sprite.get_size();
         let handle = game_ref.physics.build_harbour_collider(
             sprite_size.x * 0.5, sprite_size.y * 0.5);
         // TODO: Adjust properly around actual harbour zone
         let zone_handle = game_ref.physics.build_harbour_zone(pos, rot,
             sprite_size.x * 1.085, sprite_size.y * 1.2);
-        let name_label = Text::new(name.clone(), game_ref.assets.header_font.clone());
+        let name_label = game_ref.assets.get_fonts()
+            .map(|fonts| Text::new(name.clone(), fonts.header_font.clone()));
         std::mem::drop(game_ref);
 
         let mut transform = Transform::new(handle, game.clone());
         transform.set_pos(pos, rot);
         Ok(Harbour {
             transform, zone_handle,
-            sprite, name_label, game
+            sprite, name, name_label, game
         })
     }
 }
@@ -41,7 +42,7 @@ impl Entity for Harbour {
     }
     
     fn get_name(&self) -> String {
-        self.name_label.content().to_owned()
+        self.name.clone()
     }
 
     fn get_transform(&self) -> &Transform {
@@ -57,7 +58,9 @@ impl GameState for Harbour {
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
         let translation = self.transform.get_translation();
         self.sprite.draw2(ctx, translation);
-        self.name_label.draw(ctx, translation.0 - V2::new(90.0, 15.0));
+        if let Some(name_label) = self.name_label.as_mut() {
+            name_label.draw(ctx, translation.0 - V2::new(90.0, 15.0));
+        }
         Ok(())
     }
 }
@@ -1,5 +1,5 @@
 use tetra::{Context};
-use crate::{ANY_COLL_GROUP, CANNON_BALL_COLL_GROUP, GC, SMALL_SHIP_COLL_GROUP, Sprite, SpriteOrigin, Transform, V2, entity::{Entity, EntityType, GameState}};
+use crate::{ANY_COLL_GROUP, CANNON_BALL_COLL_GROUP, GC, SMALL_SHIP_COLL_GROUP, Sprite, SpriteOrigin, Transform, V2, entity::{Entity, EntitySnapshot, EntityType, GameState}};
 
 // Reefs only pose hazard to big ships (with greater keel depth), hence providing
 // a way of escape for smaller ships
@@ -20,7 +20,7 @@ pub struct Object {
 }
 
 impl Object {
-    pub fn build_island(ctx: &mut Context, game: GC, pos: V2, rot: f32, island_type: u32)
+    pub fn build_island(game: GC, pos: V2, rot: f32, island_type: u32)
         -> tetra::Result<Object> {
         let island_tex = match island_type {
             1 => "Island 1.png",
@@ -29,36 +29,35 @@ impl Object {
             4 => "Island 4.png",
             n @ _ => panic!("Island type {} doesn't exist", n)
         };
-        Self::build_object(ctx, ObjectType::Island, game, island_tex.to_owned(),
+        Self::build_object(ObjectType::Island, game, island_tex.to_owned(),
             pos, rot, ANY_COLL_GROUP)
     }
 
-    pub fn build_ship_wreck(ctx: &mut Context, game: GC, pos: V2, rot: f32)
+    pub fn build_ship_wreck(game: GC, pos: V2, rot: f32)
         -> tetra::Result<Object> {
         // IDEA: Add timer that removes wreck after some time to avoid cluttering?
         // Only for practical reasons; the idea of 'ship graveyards' forming sounds
         // strangely appealing too.
         // Regardless, shipwrecks should take up less space and visual focus.
         // Add a transparent gradient for submerging effect.
-        Self::build_object(ctx, ObjectType::Shipwreck, game, "Destroyed Caravel.png".to_owned(),
+        Self::build_object(ObjectType::Shipwreck, game, "Destroyed Caravel.png".to_owned(),
             pos, rot, ANY_COLL_GROUP)
     }
 
-    pub fn build_reef(ctx: &mut Context, game: GC, pos: V2, rot: f32)
+    pub fn build_reef(game: GC, pos: V2, rot: f32)
         -> tetra::Result<Object> {
-        Self::build_object(ctx, ObjectType::Reef, game.clone(), "Reef.png".to_owned(),
+        Self::build_object(ObjectType::Reef, game.clone(), "Reef.png".to_owned(),
             pos, rot, ANY_COLL_GROUP ^ SMALL_SHIP_COLL_GROUP ^ CANNON_BALL_COLL_GROUP)
     }
 
-    fn build_object(ctx: &mut Context, obj_type: ObjectType, game: GC, tex_name: String,
+    fn build_object(obj_type: ObjectType, game: GC, tex_name: String,
         pos: V2, rot: f32, filter_groups: u32)
         -> tetra::Result<Object> {
         let mut game_ref = game.borrow_mut();
-        let sprite = Sprite::new(game_ref.assets.load_texture(
-            ctx, tex_name.to_owned(), true)?, SpriteOrigin::Centre, None);
+        let sprite = game_ref.assets.load_sprite(&tex_name, SpriteOrigin::Centre);
+        let sprite_size = sprite.get_size();
         let handle = game_ref.physics.build_object_collider(
-            sprite.texture.width() as f32 * 0.4, sprite.texture.height() as f32 * 0.4,
-            obj_type, filter_groups);
+            sprite_size.x * 0.4, sprite_size.y * 0.4, obj_type, filter_groups);
         std::mem::drop(game_ref);
 
         let mut transform = Transform::new(handle, game.clone());
@@ -93,6 +92,16 @@ impl Entity for Object {
     fn destroy(&mut self) {
         self.destroy = true;
     }
+
+    fn save_state(&self) -> Option<EntitySnapshot> {
+        Some(EntitySnapshot::Destroy(self.destroy))
+    }
+
+    fn restore_state(&mut self, snapshot: &EntitySnapshot) {
+        if let EntitySnapshot::Destroy(destroy) = snapshot {
+            self.destroy = *destroy;
+        }
+    }
 }
 
 impl GameState for Object {
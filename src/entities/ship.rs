@@ -1,7 +1,7 @@
 use std::{f32::consts::PI};
 use rapier2d::{data::Index};
 use tetra::{Context, State, graphics::{Color}};
-use crate::{Cannon, CannonSide, GC, MASS_FORCE_SCALE, Rcc, Sprite, SpriteOrigin, StateEvent, Timer, Transform, V2, WorldEvent, conv_vec, disassemble_iso, economy::{Deposit}, entity::{Entity, EntityType, GameState}, get_angle, health_bar::HealthBar, log_state_event, pi_to_pi2_range, polar_to_cartesian, ship_data::{DamageResult, ShipAttributes, ShipData, ShipID, ShipType}, ship_mod::{ShipMod, ShipModType}, ship_status::ShipStatus, vec_distance, world::World};
+use crate::{Cannon, CannonSide, CannonSnapshot, GC, MASS_FORCE_SCALE, Rcc, Sprite, SpriteOrigin, StateEvent, Timer, Transform, V2, WorldEvent, add_world_event, conv_vec, disassemble_iso, economy::{Deposit}, entity::{Entity, EntitySnapshot, EntityType, GameState}, get_angle, health_bar::HealthBar, log_state_event, pi_to_pi2_range, polar_to_cartesian, ship_data::{DamageResult, ShipAttributes, ShipData, ShipID, ShipType}, ship_mod::{ShipMod, ShipModType}, ship_status::ShipStatus, state_digest::{DigestField, EntityDigest}, vec_distance, weather::calc_wind_thrust_factor, world::World};
 
 pub const BASE_STUN_LENGTH: f32 = 0.5;
 pub const MAX_SHIP_DEFENSE: u16 = 100;
@@ -16,6 +16,18 @@ const ESCUDO_RAM_STEAL_PERCENTAGE: f32 = 0.15;
 const ESCUDO_SHOOT_STEAL_PERCENTAGE: f32 = 0.1;
 const ESCUDO_ACCIDENT_LOSS_PERCENTAGE: f32 = 0.1;
 
+#[derive(Clone)]
+pub struct ShipSnapshot {
+    curr_health: u16,
+    spawn_pos: Option<V2>,
+    sinkings: u16,
+    destroy: bool,
+    status: ShipStatus,
+    treasury: Deposit,
+    cannons: Vec<CannonSnapshot>,
+    mod_count: usize // Mods only ever get added, and their effects are part of the cannons
+}
+
 pub struct Ship {
     pub data: ShipData,
     pub status: ShipStatus,
@@ -24,38 +36,37 @@ pub struct Ship {
     pub treasury: Deposit,
     pub mods: Vec<Box<dyn ShipMod>>,
     sprite: Sprite,
-    health_bar: HealthBar,
+    health_bar: Option<HealthBar>,
 }
 
 impl Ship {
-    pub fn caravel(ctx: &mut Context, game: GC, controller: ShipID, spawn: V2,
+    pub fn caravel(game: GC, controller: ShipID, spawn: V2,
         respawn: bool) -> tetra::Result<Ship> {
-        Self::new(ctx, ShipType::Caravel, controller, "Caravel.png",
+        Self::new(ShipType::Caravel, controller, "Caravel.png",
             ShipAttributes::caravel(), spawn, respawn, 4, 1.0, V2::new(10.0, 0.0), 1.0, game)
     }
 
-    pub fn galleon(ctx: &mut Context, game: GC, controller: ShipID, spawn: V2,
+    pub fn galleon(game: GC, controller: ShipID, spawn: V2,
         respawn: bool) -> tetra::Result<Ship> {
-        Self::new(ctx, ShipType::Galleon, controller, "Galleon.png",
+        Self::new(ShipType::Galleon, controller, "Galleon.png",
         ShipAttributes::galleon(), spawn, respawn, 5, 1.2, V2::new(15.0, 0.0), 1.0, game)
     }
 
-    pub fn schooner(ctx: &mut Context, game: GC, controller: ShipID, spawn: V2,
+    pub fn schooner(game: GC, controller: ShipID, spawn: V2,
         respawn: bool) -> tetra::Result<Ship> {
-        Self::new(ctx, ShipType::Schooner, controller, "Schooner.png",
+        Self::new(ShipType::Schooner, controller, "Schooner.png",
         ShipAttributes::schooner(), spawn, respawn, 3, 0.9, V2::new(0.0, 15.0), 1.25, game)
     }
 
-    fn new(ctx: &mut Context, ship_type: ShipType, controller: ShipID,
+    fn new(ship_type: ShipType, controller: ShipID,
         ship_texture: &str, attr: ShipAttributes, spawn_pos: V2,
         respawn: bool, cannons_per_side: u32, cannon_power: f32, cannon_pos: V2,
         mass: f32, game: GC) -> tetra::Result<Ship> {
         let mut game_ref = game.borrow_mut();
-        let sprite = Sprite::new(game_ref.assets.load_texture(
-            ctx, ship_texture.to_owned(), true)?, SpriteOrigin::Centre, None);
+        let sprite = game_ref.assets.load_sprite(ship_texture, SpriteOrigin::Centre);
+        let sprite_size = sprite.get_size();
         let handle = game_ref.physics.build_ship_collider(
-            sprite.texture.width() as f32 * 0.5,
-            sprite.texture.height() as f32 * 0.5, mass, ship_type);
+            sprite_size.x * 0.5, sprite_size.y * 0.5, mass, ship_type);
         let stun_length = attr.get_stun_length();
         game_ref.economy.add_deposit();
         std::mem::drop(game_ref);
@@ -72,7 +83,7 @@ impl Ship {
         let mut bow_pos = V2::new(48.0, -50.0) + cannon_pos;
         let bow_rot = PI * 1.5;
         for _ in 0..cannons_per_side {
-            cannons.push(Cannon::new(ctx, bow_pos, bow_rot, attr.cannon_damage,
+            cannons.push(Cannon::new(bow_pos, bow_rot, attr.cannon_damage,
                 CannonSide::Bowside, attr.cannon_reload_time, cannon_power, index,
                 game.clone())?);
             bow_pos -= V2::new(45.0, 0.0);
@@ -80,7 +91,7 @@ impl Ship {
         let mut port_pos = V2::new(48.0, 50.0) + V2::new(cannon_pos.x, -cannon_pos.y);
         let port_rot = PI / 2.0;
         for _ in 0..cannons_per_side {
-            cannons.push(Cannon::new(ctx, port_pos, port_rot, attr.cannon_damage,
+            cannons.push(Cannon::new(port_pos, port_rot, attr.cannon_damage,
                 CannonSide::Portside, attr.cannon_reload_time, cannon_power, index,
                 game.clone())?);
             port_pos -= V2::new(45.0, 0.0);
@@ -89,15 +100,15 @@ impl Ship {
         Ok(Ship {
             data: ShipData {
                 curr_health: attr.health, ship_type, id: controller.clone(),
-                attr, spawn_pos, destroy: false, game: game.clone()
+                attr, spawn_pos, sinkings: 0, destroy: false, game: game.clone()
             },
             status: ShipStatus {
                 target_pos: None, rotate_only: false,
                 is_in_harbour: false, stun: Timer::new(stun_length)
             }, cannons,
             transform, treasury: Deposit::default(), mods: Vec::new(), sprite,
-            health_bar: HealthBar::new(ctx, controller.to_string(), Color::WHITE /* Customise for local player? */,
-            attr.health, game.clone())?
+            health_bar: HealthBar::new(controller.to_string(), Color::WHITE /* Customise for local player? */,
+            attr.health, game.clone())
         })
     }
 
@@ -121,10 +132,12 @@ impl Ship {
 
     pub fn set_health(&mut self, val: u16) {
         self.data.set_health(val);
-        self.health_bar.set_info(val);
+        if let Some(health_bar) = self.health_bar.as_mut() {
+            health_bar.set_info(val);
+        }
     }
 
-    pub fn take_damage(&mut self, ctx: &mut Context, mut damage: u16, world: &mut World)
+    pub fn take_damage(&mut self, mut damage: u16, world: &mut World)
         -> tetra::Result<DamageResult> {
         if damage <= 0 {
             return Ok(DamageResult::Empty)
@@ -137,15 +150,15 @@ impl Ship {
         self.set_health(remaining_health);
 
         match self.data.is_sunk() {
-            true => self.sink(ctx, world).and(Ok(DamageResult::Sink)),
+            true => self.sink(world).and(Ok(DamageResult::Sink)),
             false => Ok(DamageResult::Hit(damage))
         }
     }
 
-    pub fn sink(&mut self, ctx: &mut Context, world: &mut World) -> tetra::Result {
+    pub fn sink(&mut self, world: &mut World) -> tetra::Result {
         println!("{} has been sunk!", self.get_name());
         let (pos, rot) = self.transform.get_translation();
-        world.add_ship_wreck(ctx, pos, rot)?;
+        world.add_ship_wreck(pos, rot)?;
 
         if let Some(spawn) = self.data.spawn_pos { // Respawn
             self.reset();
@@ -179,21 +192,22 @@ impl Ship {
         self.repair();
     }
 
-    pub fn take_cannon_ball_hit(&mut self, ctx: &mut Context, dmg: u16,
+    pub fn take_cannon_ball_hit(&mut self, dmg: u16,
         shooter_index: Index, world: &mut World) -> tetra::Result {
         let shooter = world.get_ship(shooter_index).unwrap(); // Bold unwrap but what else...
         let mut shooter_ref = shooter.borrow_mut();
         log_state_event(self.data.game.clone(), StateEvent::ShipCannonBallCollision(
                 shooter_ref.data.id.clone(), self.data.id.clone(), dmg));
 
-        match self.take_damage(ctx, dmg, world)? {
+        match self.take_damage(dmg, world)? {
             DamageResult::Sink => {
                 let forfeited_escudos = (self.treasury.balance as f32 * ESCUDO_SHOOT_STEAL_PERCENTAGE) as u32;
                 let generated_payout =  self.data.game.borrow_mut()
                     .economy.total_payout(self.treasury.networth);
                 shooter_ref.treasury.add(forfeited_escudos + generated_payout);
+                shooter_ref.data.sinkings += 1;
                 self.treasury.lose(forfeited_escudos);
-                self.data.game.borrow_mut().world.add_event(
+                add_world_event(self.data.game.clone(),
                     WorldEvent::PlayerSunkByCannon(shooter_ref.get_name(), self.get_name()));
                 Ok(())
             },
@@ -201,15 +215,14 @@ impl Ship {
         }
     }
 
-    pub fn shoot_cannons(&mut self, ctx: &mut Context, side: Option<CannonSide>,
-        world: &mut World)
+    pub fn shoot_cannons(&mut self, side: Option<CannonSide>, world: &mut World)
         -> tetra::Result {
         let cannons: Vec<_> = match side {
             Some(side) => self.cannons.iter_mut().filter(|c| c.side == side).collect(),
             None => self.cannons.iter_mut().collect()
         };
         for cannon in cannons {
-            if let Some(cannonball) = cannon.shoot(ctx, world)? {
+            if let Some(cannonball) = cannon.shoot(world)? {
                 let cannonball_ref = cannonball.borrow();
                 log_state_event(self.data.game.clone(), StateEvent::ShipShootCannon(
                     self.data.id.clone(), cannonball_ref.transform.get_translation().0, cannonball_ref.dmg));
@@ -225,6 +238,7 @@ impl Ship {
         
         if let Some(target_pos) = self.status.target_pos {
             let mut game_ref = self.data.game.borrow_mut();
+            let wind = game_ref.weather.wind;
             let rb = game_ref.physics.get_rb_mut(self.transform.handle.0);
             let (pos, rot) = disassemble_iso(rb.position());
             if vec_distance(pos, target_pos) <= TARGET_POS_DIST_MARGIN {
@@ -234,7 +248,8 @@ impl Ship {
 
             if !self.status.rotate_only {
                 let mut facing_dir = polar_to_cartesian(1.0, rot);
-                facing_dir *= BASE_MOVEMENT_FORCE * self.data.attr.movement_speed;
+                let wind_factor = calc_wind_thrust_factor(facing_dir, wind);
+                facing_dir *= BASE_MOVEMENT_FORCE * self.data.attr.movement_speed * wind_factor;
                 rb.apply_impulse(conv_vec(facing_dir), true);
             }
 
@@ -280,7 +295,58 @@ impl Entity for Ship {
         self.data.destroy = true;
     }
 
-    fn collide_with_ship(&mut self, ctx: &mut Context, other: Rcc<Ship>, world: &mut World) -> tetra::Result {
+    fn digest(&self, digest: &mut EntityDigest) {
+        digest.add(DigestField::Health, self.data.curr_health);
+        digest.add(DigestField::Balance, self.treasury.balance);
+        digest.add(DigestField::Networth, self.treasury.networth);
+        digest.add(DigestField::Sinkings, self.data.sinkings);
+        digest.add(DigestField::Destroy, self.data.destroy);
+        digest.add(DigestField::StunTime, self.status.stun.curr_time);
+        if let Some(target_pos) = self.status.target_pos {
+            digest.add(DigestField::TargetX, target_pos.x);
+            digest.add(DigestField::TargetY, target_pos.y);
+        }
+        digest.add(DigestField::RotateOnly, self.status.rotate_only);
+        digest.add(DigestField::InHarbour, self.status.is_in_harbour);
+        for (i, cannon) in self.cannons.iter().enumerate() {
+            let i = i as u8;
+            digest.add(DigestField::CannonReload(i), cannon.reload.curr_time);
+            digest.add(DigestField::CannonDamage(i), cannon.dmg.total());
+            digest.add(DigestField::CannonReloadTime(i), cannon.reload_time.total());
+            digest.add(DigestField::CannonShootingPower(i), cannon.shooting_power.total());
+        }
+        digest.add(DigestField::ModCount, self.mods.len() as u16);
+        for (i, ship_mod) in self.mods.iter().enumerate() {
+            digest.add(DigestField::Mod(i as u8), ship_mod.get_type().to_num() as u16);
+        }
+    }
+
+    fn save_state(&self) -> Option<EntitySnapshot> {
+        Some(EntitySnapshot::Ship(ShipSnapshot {
+            curr_health: self.data.curr_health, spawn_pos: self.data.spawn_pos,
+            sinkings: self.data.sinkings, destroy: self.data.destroy, status: self.status,
+            treasury: self.treasury,
+            cannons: self.cannons.iter().map(|c| c.save_state()).collect(),
+            mod_count: self.mods.len()
+        }))
+    }
+
+    fn restore_state(&mut self, snapshot: &EntitySnapshot) {
+        if let EntitySnapshot::Ship(snapshot) = snapshot {
+            self.set_health(snapshot.curr_health);
+            self.data.spawn_pos = snapshot.spawn_pos;
+            self.data.sinkings = snapshot.sinkings;
+            self.data.destroy = snapshot.destroy;
+            self.status = snapshot.status;
+            self.treasury = snapshot.treasury;
+            for (cannon, cannon_snapshot) in self.cannons.iter_mut().zip(snapshot.cannons.iter()) {
+                cannon.restore_state(cannon_snapshot);
+            }
+            self.mods.truncate(snapshot.mod_count);
+        }
+    }
+
+    fn collide_with_ship(&mut self, other: Rcc<Ship>, world: &mut World) -> tetra::Result {
         // ---
         // TODO: Rewrite logic to apply ram effects to oneself instead of opponent
         // ---
@@ -291,7 +357,7 @@ impl Entity for Ship {
             self.data.id.clone(), other_ref.data.id.clone(), self.data.attr.ram_damage));
         
         other_ref.status.stun();
-        match other_ref.take_damage(ctx, self.data.attr.ram_damage, world)? {
+        match other_ref.take_damage(self.data.attr.ram_damage, world)? {
             DamageResult::Sink => {
                 let forfeited_escudos = (other_ref.treasury.balance as f32 *
                     ESCUDO_RAM_STEAL_PERCENTAGE) as u32;
@@ -303,7 +369,8 @@ impl Entity for Ship {
                 
                 other_ref.treasury.lose(forfeited_escudos);
                 self.treasury.add(forfeited_escudos + generated_payout);
-                self.data.game.borrow_mut().world.add_event(
+                self.data.sinkings += 1;
+                add_world_event(self.data.game.clone(),
                     WorldEvent::PlayerSunkByRamming(self.get_name(), other_ref.get_name()));
                 Ok(())
             },
@@ -311,7 +378,7 @@ impl Entity for Ship {
         }
     }
 
-    fn collide_with_entity(&mut self, ctx: &mut Context, other: Rcc<dyn Entity>, world: &mut World)
+    fn collide_with_entity(&mut self, other: Rcc<dyn Entity>, world: &mut World)
         -> tetra::Result {
         let other_ref = other.borrow();
         let other_entity_type = other_ref.get_type();
@@ -332,14 +399,14 @@ impl Entity for Ship {
             self.data.id.clone(), other_entity_type, damage));
 
         self.status.stun();
-        match self.take_damage(ctx, damage, world)? {
+        match self.take_damage(damage, world)? {
             DamageResult::Sink => {
                 let forfeited_escudos = (self.treasury.balance as f32 * ESCUDO_ACCIDENT_LOSS_PERCENTAGE) as u32;
                 self.data.game.borrow_mut().economy.remove(forfeited_escudos); // Lost to the sea...
                 self.treasury.lose(forfeited_escudos);
                 // println!("{} lost {} escudos after sinking their ship in an accident!",
                 //     self.get_name(), forfeited_escudos);
-                self.data.game.borrow_mut().world.add_event(
+                add_world_event(self.data.game.clone(),
                     WorldEvent::PlayerSunkByAccident(self.get_name()));
                 Ok(())
             },
@@ -347,13 +414,12 @@ impl Entity for Ship {
         }
     }
 
-    fn collide_with_neutral(&mut self, _: &mut Context)
-        -> tetra::Result {
+    fn collide_with_neutral(&mut self) -> tetra::Result {
         Ok(())
     }
 
-    fn intersect_with_entity(&mut self, _: &mut Context, state: bool,
-        other: Rcc<dyn Entity>) -> tetra::Result {
+    fn intersect_with_entity(&mut self, state: bool, other: Rcc<dyn Entity>)
+        -> tetra::Result {
         if other.borrow().get_type() == EntityType::Harbour {
             self.status.is_in_harbour = state;
         }
@@ -362,15 +428,15 @@ impl Entity for Ship {
 }
 
 impl GameState for Ship {
-    fn update(&mut self, ctx: &mut Context, world: &mut World) -> tetra::Result {
-        self.status.stun.update(ctx);
+    fn update(&mut self, world: &mut World) -> tetra::Result {
+        self.status.stun.update();
         let translation = self.transform.get_translation();
         for cannon in self.cannons.iter_mut() {
             cannon.set_ship_translation(translation);
-            cannon.update(ctx)?;
+            cannon.update();
         }
         for ship_mod in self.mods.iter_mut() {
-            ship_mod.update(ctx, world)?;
+            ship_mod.update(world)?;
         }
         self.move_to_target_pos();
         Ok(())
@@ -382,7 +448,9 @@ impl GameState for Ship {
         for cannon in self.cannons.iter_mut() {
             cannon.draw(ctx)?;
         }
-        self.health_bar.draw(ctx, translation.0);
+        if let Some(health_bar) = self.health_bar.as_mut() {
+            health_bar.draw(ctx, translation.0);
+        }
         Ok(())
     }
 }
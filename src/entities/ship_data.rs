@@ -1,6 +1,6 @@
 use core::fmt;
 use binary_stream::{BinaryStream, Serializable};
-use crate::{GC, ID, V2, ship::{BASE_STUN_LENGTH, MAX_SHIP_DEFENSE}};
+use crate::{BbResult, GC, ID, V2, decode::{Decode, DecodeStream, unknown_index}, ship::{BASE_STUN_LENGTH, MAX_SHIP_DEFENSE}};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ShipType {
@@ -19,12 +19,18 @@ impl Serializable for ShipType {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        match stream.read_buffer_single().unwrap() {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for ShipType {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
             0 => ShipType::Caravel,
             1 => ShipType::Galleon,
             2 => ShipType::Schooner,
-            n @ _ => panic!("Index {} is not assigned to any ship type", n)
-        }
+            n @ _ => return Err(unknown_index(n, "ship type"))
+        })
     }
 }
 
@@ -116,6 +122,7 @@ pub struct ShipData {
     pub id: ShipID,
     pub attr: ShipAttributes,
     pub spawn_pos: Option<V2>,
+    pub sinkings: u16, // Ships sunk by this ship
     pub destroy: bool,
     pub game: GC
 }
@@ -1,5 +1,6 @@
 use crate::{Timer, V2};
 
+#[derive(Clone, Copy)]
 pub struct ShipStatus {
     pub stun: Timer,
     pub target_pos: Option<V2>,
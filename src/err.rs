@@ -11,6 +11,7 @@ pub enum BbError {
     Laminar(ErrorKind),
     CrossbeamSender(SendError<Packet>),
     CrossbeamReceiver, // Disconnect. Empty is handled by a simple Ok(None)
+    Io(std::io::Error),
     Bb(BbErrorType)
 }
 
@@ -53,5 +54,9 @@ pub enum BbErrorType {
     NetNotConnected,
     NetInvalidSender(SocketAddr),
     NetInsufficientAuthority,
-    InvalidPlayerID(u16)
+    NetInvalidEndpoint(String),
+    NetRelayFailure(String), // Relay unreachable or session unknown
+    InvalidPlayerID(u16),
+    InvalidReplay(String),
+    InvalidEncoding(String) // Malformed packet or file contents
 }
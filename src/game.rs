@@ -1,6 +1,9 @@
-use std::{cell::RefCell , rc::Rc};
+use std::{cell::RefCell , collections::HashMap, rc::Rc};
 use tetra::{Context, State, graphics::{self, Color, text::Text}, window::{get_height, get_width}};
-use crate::{Assets, Cam, Diagnostics, Physics, Settings, V2, WorldSettings, economy::Economy, get_version, network::Network, scenes::scenes::{Scenes}, simulation_settings::SimulationSettings};
+use crate::{Assets, Cam, Diagnostics, Physics, Settings, V2, WorldSettings, economy::Economy, get_version, network::Network, scenes::scenes::{Scenes}, simulation_settings::SimulationSettings, weather::WeatherSystem};
+
+const HEADLESS_CAM_WIDTH: f32 = 1280.0;
+const HEADLESS_CAM_HEIGHT: f32 = 720.0;
 
 pub type Rcc<T> = Rc<RefCell<T>>;
 pub type GC = Rcc<GameContainer>;
@@ -18,31 +21,56 @@ pub struct GameContainer {
     pub network: Option<Network>,
     pub economy: Economy,
     pub diagnostics: Diagnostics,
-    pub simulation_settings: SimulationSettings
+    pub simulation_settings: SimulationSettings,
+    pub weather: WeatherSystem,
+    pub rejoin_tokens: HashMap<String, u64> // By endpoint, of matches the player timed out of
 }
 
 impl GameContainer {
-    pub fn new(ctx: &mut Context) -> tetra::Result<GameContainer> {
+    pub fn new(ctx: &mut Context, settings: Settings) -> tetra::Result<GameContainer> {
         Ok(GameContainer {
             assets: Assets::load(ctx)?,
             physics: Physics::setup(),
+            settings,
+            world: WorldSettings::new(),
+            cam: Cam::setup(ctx),
+            network: None,
+            economy: Economy::new(),
+            diagnostics: Diagnostics::new(),
+            simulation_settings: SimulationSettings::new(),
+            weather: WeatherSystem::calm(),
+            rejoin_tokens: HashMap::new()
+        })
+    }
+
+    // Everything a simulation needs, without a window. Used to run matches in tests and tools.
+    pub fn headless() -> tetra::Result<GameContainer> {
+        Ok(GameContainer {
+            assets: Assets::headless()?,
+            physics: Physics::setup(),
             settings: Settings::new(),
             world: WorldSettings::new(),
-            cam: Cam::setup(ctx, 800.0),
+            cam: Cam::with_size(V2::new(HEADLESS_CAM_WIDTH, HEADLESS_CAM_HEIGHT)),
             network: None,
             economy: Economy::new(),
             diagnostics: Diagnostics::new(),
-            simulation_settings: SimulationSettings::new()
+            simulation_settings: SimulationSettings::new(),
+            weather: WeatherSystem::calm(),
+            rejoin_tokens: HashMap::new()
         })
     }
+
+    pub fn update_physics(&mut self) {
+        if self.simulation_settings.run { // Dont simulate physics if simulation is halted
+            self.physics.update();
+        }
+    }
 }
 
 impl State for GameContainer {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.cam.update(ctx)?;
-        if self.simulation_settings.run { // Dont simulate physics if simulation is halted
-            self.physics.update(ctx)?;
-        }
+        self.cam.update(ctx, &self.settings)?;
+        self.update_physics();
         Ok(())
     }
 }
@@ -54,10 +82,10 @@ pub struct Game {
 }
 
 impl Game {
-    pub fn new(ctx: &mut Context) -> tetra::Result<Game> {
-        let container = wrap_rcc(GameContainer::new(ctx)?);
+    pub fn new(ctx: &mut Context, settings: Settings) -> tetra::Result<Game> {
+        let container = wrap_rcc(GameContainer::new(ctx, settings)?);
         let watermark = Text::new(format!("Blackbeard Alpha {}", get_version()),
-                container.borrow().assets.small_font.clone());
+                container.borrow().assets.small_font());
         let scenes = Scenes::setup(ctx, container.clone())?;
 
         Ok(Game {
@@ -1,15 +1,17 @@
 use binary_stream::{BinaryStream, Serializable};
+use crate::{BbResult, decode::{Decode, DecodeStream, unknown_index}};
 
 #[derive(Debug, Clone, Copy)]
 pub struct GameSettings {
     pub mode: GameMode,
-    pub weather: Weather
+    pub weather: Weather,
+    pub bots: u8 // Computer players joining the match
 }
 
 impl GameSettings {
     pub fn new(mode: GameMode, weather: Weather) -> GameSettings {
         GameSettings {
-            mode, weather
+            mode, weather, bots: 0
         }
     }
 
@@ -22,21 +24,47 @@ impl Serializable for GameSettings {
     fn to_stream(&self, stream: &mut BinaryStream) {
         self.mode.to_stream(stream);
         self.weather.to_stream(stream);
+        stream.write_buffer_single(self.bots).unwrap();
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let mode = GameMode::from_stream(stream);
-        let weather = Weather::from_stream(stream);
-        GameSettings::new(mode, weather)
+        Self::decode(stream).unwrap()
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Decode for GameSettings {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let mode = GameMode::decode(stream)?;
+        let weather = Weather::decode(stream)?;
+        let bots = stream.decode_u8()?;
+        Ok(GameSettings {
+            mode, weather, bots
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GameMode {
     Raid(u32), // First player to collect required amount of escudos wins
     Deathmatch(u16) // First player with required amount of sinkings wins
 }
 
+impl GameMode {
+    pub fn get_goal(&self) -> u32 {
+        match self {
+            GameMode::Raid(escudos_goal) => *escudos_goal,
+            GameMode::Deathmatch(sinkings_goal) => *sinkings_goal as u32
+        }
+    }
+
+    pub fn get_score(&self, networth: u32, sinkings: u16) -> u32 {
+        match self {
+            GameMode::Raid(..) => networth,
+            GameMode::Deathmatch(..) => sinkings as u32
+        }
+    }
+}
+
 impl Serializable for GameMode {
     fn to_stream(&self, stream: &mut BinaryStream) {
         match self {
@@ -52,11 +80,17 @@ impl Serializable for GameMode {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        match stream.read_buffer_single().unwrap() {
-            0 => GameMode::Raid(stream.read_u32().unwrap()),
-            1 => GameMode::Deathmatch(stream.read_u16().unwrap()),
-            n @ _ => panic!("Index {} is not assigned to any weather type", n)
-        }
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for GameMode {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
+            0 => GameMode::Raid(stream.decode_u32()?),
+            1 => GameMode::Deathmatch(stream.decode_u16()?),
+            n @ _ => return Err(unknown_index(n, "game mode"))
+        })
     }
 }
 
@@ -79,12 +113,18 @@ impl Serializable for Weather {
     }
 
     fn from_stream(stream: &mut binary_stream::BinaryStream) -> Self {
-        match stream.read_buffer_single().unwrap() {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for Weather {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
             0 => Weather::Sunny,
             1 => Weather::Windy,
             2 => Weather::Rainy,
             3 => Weather::Stormy,
-            n @ _ => panic!("Index {} is not assigned to any weather type", n)
-        }
+            n @ _ => return Err(unknown_index(n, "weather type"))
+        })
     }
 }
@@ -1,6 +1,7 @@
 use std::fmt;
 use binary_stream::{BinaryStream, Serializable};
 use tetra::graphics::Color;
+use crate::{BbResult, decode::{Decode, DecodeStream}};
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub struct ID {
@@ -27,9 +28,15 @@ impl Serializable for ID {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let name = stream.read_string().unwrap();
-        let n = stream.read_u16().unwrap();
-        ID::new(name, n)
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for ID {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let name = stream.decode_string()?;
+        let n = stream.decode_u16()?;
+        Ok(ID::new(name, n))
     }
 }
 
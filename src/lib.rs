@@ -0,0 +1,119 @@
+pub mod game;
+pub mod physics;
+pub mod assets;
+pub mod entities {
+    pub mod entity;
+    pub mod object;
+    pub mod ship;
+    pub mod ship_data;
+    pub mod ship_status;
+    pub mod cannon;
+    pub mod harbour;
+}
+pub mod ship_mod;
+pub mod controller;
+pub mod scenes {
+    pub mod scenes;
+    pub mod startup_scene;
+    pub mod menu_scene;
+    pub mod world_scene;
+    pub mod loading_scene;
+    pub mod lobby_scene;
+    pub mod connection_scene;
+    pub mod login_scene;
+    pub mod score_scene;
+}
+pub mod player;
+pub mod util;
+pub mod settings;
+pub mod world_settings;
+pub mod cam;
+pub mod transform;
+pub mod id;
+pub mod sprite;
+pub mod animated_sprite;
+pub mod ui {
+    pub mod grid;
+    pub mod ui_element;
+    pub mod ui_transform;
+    pub mod label;
+    pub mod button;
+    pub mod spritesheet;
+    pub mod image;
+    pub mod textbox;
+    pub mod chat;
+    pub mod health_bar;
+}
+pub mod net {
+    pub mod network;
+    pub mod packet;
+    pub mod peer;
+    pub mod client;
+    pub mod server;
+    pub mod playback_buffer;
+    pub mod net_controller;
+    pub mod net_settings;
+    pub mod input_pool;
+    pub mod sync_checker;
+    pub mod dedicated_server;
+    pub mod state_digest;
+    pub mod desync_investigation;
+    pub mod version;
+    pub mod decode;
+    pub mod rejoin;
+    pub mod host_migration;
+    pub mod net_conditions;
+    pub mod lobby_access;
+    pub mod moderation;
+    pub mod chat_policy;
+    pub mod discovery;
+    pub mod master_server;
+    pub mod transport;
+    pub mod relay;
+    pub mod connection_quality;
+    pub mod input_validation;
+    pub mod flood_protection;
+}
+pub mod err;
+pub mod diagnostics;
+pub mod world;
+pub mod economy;
+pub mod game_settings;
+pub mod match_result;
+pub mod weather;
+pub mod replay;
+pub mod ship_ai;
+pub mod simulation;
+pub mod rollback;
+pub mod simulation_settings;
+
+pub use game::*;
+pub use physics::*;
+pub use assets::*;
+pub use controller::*;
+pub use scenes::*;
+pub use player::*;
+pub use util::*;
+pub use settings::*;
+pub use world_settings::*;
+pub use cam::*;
+pub use transform::*;
+pub use id::*;
+pub use sprite::*;
+pub use animated_sprite::*;
+pub use cannon::*;
+pub use ui::*;
+pub use net::*;
+pub use err::*;
+pub use diagnostics::*;
+pub use world::*;
+pub use entities::*;
+
+pub const PRIMARY_VERSION: u32 = 0;
+pub const SECONDARY_VERSION: u32 = 1;
+// Bump whenever the packet layout changes
+pub const PROTOCOL_VERSION: u16 = 16;
+
+pub fn get_version() -> String {
+    format!("v{}.{}", PRIMARY_VERSION, SECONDARY_VERSION)
+}
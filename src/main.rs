@@ -1,105 +1,11 @@
-mod game;
-mod physics;
-mod assets;
-mod entities {
-    pub mod entity;
-    pub mod object;
-    pub mod ship;
-    pub mod ship_data;
-    pub mod ship_status;
-    pub mod cannon;
-    pub mod harbour;
-}
-mod ship_mod;
-mod controller;
-mod scenes {
-    pub mod scenes;
-    pub mod startup_scene;
-    pub mod menu_scene;
-    pub mod world_scene;
-    pub mod loading_scene;
-    pub mod lobby_scene;
-    pub mod connection_scene;
-    pub mod login_scene;
-}
-mod player;
-mod util;
-mod settings;
-mod world_settings;
-mod cam;
-mod transform;
-mod id;
-mod sprite;
-mod animated_sprite;
-mod ui {
-    pub mod grid;
-    pub mod ui_element;
-    pub mod ui_transform;
-    pub mod label;
-    pub mod button;
-    pub mod spritesheet;
-    pub mod image;
-    pub mod textbox;
-    pub mod chat;
-    pub mod health_bar;
-}
-mod net {
-    pub mod network;
-    pub mod packet;
-    pub mod peer;
-    pub mod client;
-    pub mod server;
-    pub mod playback_buffer;
-    pub mod net_controller;
-    pub mod net_settings;
-    pub mod input_pool;
-    pub mod sync_checker;
-}
-mod err;
-mod diagnostics;
-mod world;
-mod economy;
-mod game_settings;
-mod simulation_settings;
-
-pub use game::*;
-pub use physics::*;
-pub use assets::*;
-pub use controller::*;
-pub use scenes::*;
-pub use player::*;
-pub use util::*;
-pub use settings::*;
-pub use world_settings::*;
-pub use cam::*;
-pub use transform::*;
-pub use id::*;
-pub use sprite::*;
-pub use animated_sprite::*;
-pub use cannon::*;
-pub use ui::*;
-pub use net::*;
-pub use err::*;
-pub use diagnostics::*;
-pub use world::*;
-pub use entities::*;
-
+use blackbeard::{Game, Settings, get_version};
 use tetra::{ContextBuilder};
 use std::io::{Read, stdin};
 
-pub const DEFAULT_WINDOW_SIZE_WIDTH: i32 = 1200;
-pub const DEFAULT_WINDOW_SIZE_HEIGHT: i32 = 640;
-
-pub const PRIMARY_VERSION: u32 = 0;
-pub const SECONDARY_VERSION: u32 = 1;
-
-fn get_version() -> String {
-    format!("v{}.{}", PRIMARY_VERSION, SECONDARY_VERSION)
-}
-
 fn main() -> tetra::Result {
     println!("Blackbeard {} - (c) 2021, Niklas Vaudt", get_version());
-    let startup_params = process_params();
+    let settings = Settings::load();
+    let startup_params = process_params(&settings);
 
     if let Err(e) = ContextBuilder::new("Blackbeard", startup_params.1, startup_params.2)
         .debug_info(true)
@@ -108,7 +14,7 @@ fn main() -> tetra::Result {
         .fullscreen(startup_params.3)
         .quit_on_escape(startup_params.3)
         .build()?
-        .run(Game::new)
+        .run(|ctx| Game::new(ctx, settings))
     {
         println!("Game loop encountered an error: {}", e);
         stdin().read(&mut Vec::new()).unwrap();
@@ -119,19 +25,21 @@ fn main() -> tetra::Result {
 
 struct StartupParams(String, i32, i32, bool);
 
-fn process_params() -> StartupParams {
+// Command-line arguments override the window mode from the settings file for this run only
+fn process_params(settings: &Settings) -> StartupParams {
     let mut args: Vec<String> = std::env::args().collect();
     let startup_path = args[0].to_owned();
     if args.len() == 3 {
         let window_size_params = &args[1..3];
-        let mut x: i32 = DEFAULT_WINDOW_SIZE_WIDTH;
-        let mut y: i32 = DEFAULT_WINDOW_SIZE_HEIGHT;
+        let mut x: i32 = settings.window_width;
+        let mut y: i32 = settings.window_height;
         for (i, n) in window_size_params.into_iter()
-            .map(|s| s.parse::<i32>().unwrap()).enumerate() {
-            if i == 0 {
-                x = n;
-            } else {
-                y = n;
+            .map(|s| s.parse::<i32>()).enumerate() {
+            match (i, n) {
+                (0, Ok(n)) if n > 0 => x = n,
+                (1, Ok(n)) if n > 0 => y = n,
+                _ => println!("Invalid window size {}. Using {}x{}.",
+                    window_size_params[i], settings.window_width, settings.window_height)
             }
         }
         return StartupParams(startup_path, x, y, false)
@@ -139,11 +47,11 @@ fn process_params() -> StartupParams {
         if let Some(fs) = args.pop() {
             if fs == "fullscreen" {
                 return StartupParams(startup_path,
-                    DEFAULT_WINDOW_SIZE_WIDTH, DEFAULT_WINDOW_SIZE_HEIGHT, true)
+                    settings.window_width, settings.window_height, true)
             }
         }
     }
 
     StartupParams(startup_path,
-        DEFAULT_WINDOW_SIZE_WIDTH, DEFAULT_WINDOW_SIZE_HEIGHT, false)
+        settings.window_width, settings.window_height, settings.fullscreen)
 }
@@ -0,0 +1,48 @@
+use indexmap::IndexMap;
+use crate::{ID, Player, Rcc, game_settings::GameMode};
+
+#[derive(Debug, Clone)]
+pub struct Standing {
+    pub id: ID,
+    pub networth: u32,
+    pub sinkings: u16,
+    pub score: u32
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    pub gen: u64,
+    pub mode: GameMode,
+    pub standings: Vec<Standing> // Best player first
+}
+
+impl MatchResult {
+    // Must only be called from within the simulation step, as all clients need to
+    // agree on the generation a match was decided in.
+    pub fn check(gen: u64, mode: GameMode, players: &IndexMap<u16, Rcc<Player>>)
+        -> Option<MatchResult> {
+        let mut standings = players.values().map(|p| {
+            let p_ref = p.borrow();
+            let ship_ref = p_ref.possessed_ship.borrow();
+            Standing {
+                id: p_ref.id.clone(),
+                networth: ship_ref.treasury.networth,
+                sinkings: ship_ref.data.sinkings,
+                score: mode.get_score(ship_ref.treasury.networth, ship_ref.data.sinkings)
+            }
+        }).collect::<Vec<_>>();
+        // Ties are broken by player ID, so every client ranks players the same way
+        standings.sort_by(|a, b| b.score.cmp(&a.score).then(a.id.n.cmp(&b.id.n)));
+
+        match standings.first() {
+            Some(best) if best.score >= mode.get_goal() => Some(MatchResult {
+                gen, mode, standings
+            }),
+            _ => None
+        }
+    }
+
+    pub fn get_winner(&self) -> &Standing {
+        &self.standings[0]
+    }
+}
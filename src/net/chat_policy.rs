@@ -0,0 +1,125 @@
+use std::{collections::HashMap, fs, path::PathBuf, time::{Duration, Instant}};
+use crate::get_settings_path;
+
+pub const CHAT_FILTER_FILE_NAME: &str = "chat_filter.cfg";
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 200; // Characters
+// Each sender may burst a few messages, then one every two seconds
+const CHAT_BURST: f32 = 5.0;
+const CHAT_REFILL_RATE: f32 = 0.5;
+const VIOLATION_MUTE_DURATION: f32 = 30.0;
+pub const DEFAULT_HOST_MUTE_MINUTES: u64 = 10;
+pub const MAX_HOST_MUTE_MINUTES: u64 = 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatViolation {
+    TooLong,
+    Flooding,
+    FilteredWord
+}
+
+struct ChatSender {
+    tokens: f32,
+    last_refill_time: Instant,
+    muted_until: Option<Instant>
+}
+
+// Applied by the server before any message is passed on. Senders are tracked by name,
+// so reconnecting doesn't lift a mute.
+pub struct ChatPolicy {
+    filter: Vec<String>, // Lowercase words
+    senders: HashMap<String, ChatSender>
+}
+
+impl ChatPolicy {
+    pub fn new(filter: Vec<String>) -> ChatPolicy {
+        ChatPolicy {
+            filter: filter.into_iter().map(|word| word.to_lowercase()).collect(),
+            senders: HashMap::new()
+        }
+    }
+
+    // One word per line, lines starting with # are comments. A missing file filters nothing.
+    pub fn load(filter_path: Option<PathBuf>) -> ChatPolicy {
+        let filter = filter_path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines()
+                .map(|line| line.trim().to_owned())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .collect::<Vec<_>>())
+            .unwrap_or_default();
+        if !filter.is_empty() {
+            println!("Loaded {} filtered word(s) from {:?}.", filter.len(), filter_path.unwrap());
+        }
+        Self::new(filter)
+    }
+
+    // Returns the warning for the sender if the message must not be passed on
+    pub fn check(&mut self, name: &str, message: &str) -> Result<(), String> {
+        let now = Instant::now();
+        let sender = self.senders.entry(name.to_owned()).or_insert(ChatSender {
+            tokens: CHAT_BURST, last_refill_time: now, muted_until: None
+        });
+        sender.tokens = (sender.tokens + (now - sender.last_refill_time).as_secs_f32()
+            * CHAT_REFILL_RATE).min(CHAT_BURST);
+        sender.last_refill_time = now;
+        if let Some(muted_until) = sender.muted_until.filter(|muted_until| now < *muted_until) {
+            return Err(format!("You are muted for another {}s.", (muted_until - now).as_secs() + 1))
+        }
+
+        let violation = if message.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+            Some(ChatViolation::TooLong)
+        } else if sender.tokens < 1.0 {
+            Some(ChatViolation::Flooding)
+        } else if contains_filtered_word(&self.filter, message) {
+            Some(ChatViolation::FilteredWord)
+        } else {
+            None
+        };
+        sender.tokens = (sender.tokens - 1.0).max(0.0);
+        match violation {
+            Some(violation) => {
+                sender.muted_until = Some(now + Duration::from_secs_f32(VIOLATION_MUTE_DURATION));
+                Err(format!("Your message was dropped. {} You are muted for {}s.", match violation {
+                    ChatViolation::TooLong => format!(
+                        "Messages may be at most {} characters long.", MAX_CHAT_MESSAGE_LENGTH),
+                    ChatViolation::Flooding => "You are sending messages too quickly.".to_owned(),
+                    ChatViolation::FilteredWord => "Mind your language.".to_owned()
+                }, VIOLATION_MUTE_DURATION))
+            },
+            None => Ok(())
+        }
+    }
+
+    pub fn mute(&mut self, name: &str, duration: Duration) {
+        let now = Instant::now();
+        let sender = self.senders.entry(name.to_owned()).or_insert(ChatSender {
+            tokens: CHAT_BURST, last_refill_time: now, muted_until: None
+        });
+        sender.muted_until = Some(now + duration.min(Duration::from_secs(MAX_HOST_MUTE_MINUTES * 60)));
+    }
+
+    // Returns whether the sender was muted
+    pub fn unmute(&mut self, name: &str) -> bool {
+        self.senders.get_mut(name)
+            .and_then(|sender| sender.muted_until.take())
+            .is_some_and(|muted_until| Instant::now() < muted_until)
+    }
+
+    pub fn is_muted(&self, name: &str) -> bool {
+        self.senders.get(name)
+            .and_then(|sender| sender.muted_until)
+            .is_some_and(|muted_until| Instant::now() < muted_until)
+    }
+}
+
+// Whole words only, so that filtering "ass" leaves "assassin" alone
+fn contains_filtered_word(filter: &[String], message: &str) -> bool {
+    message.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .any(|word| filter.contains(&word.to_lowercase()))
+}
+
+// Next to the settings file
+pub fn get_chat_filter_path() -> Option<PathBuf> {
+    get_settings_path()?.parent().map(|dir| dir.join(CHAT_FILTER_FILE_NAME))
+}
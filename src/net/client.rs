@@ -1,11 +1,16 @@
-use std::{collections::HashMap, net::{SocketAddr}, thread, time::Duration};
+use std::{collections::{HashMap, VecDeque}, net::{SocketAddr}, thread, time::{Duration, Instant}};
 use laminar::SocketEvent;
 
-use crate::{BbResult, ID, PlayerParams, packet::{Packet, deserialize_packet, serialize_packet_unsigned}, peer::{DisconnectReason, Peer, is_auth_client}};
+use crate::{BbResult, ID, PlayerParams, connection_quality::ConnectionQuality, host_migration::{HostCandidate, MIGRATION_RESEND_INTERVAL, MigrationToken, MIGRATION_TIMEOUT, elect_host}, input_pool::{REDUNDANT_INPUT_STATES, STEP_PHASE_TIME_SECS}, lobby_access::gen_password_proof, net_conditions::NetConditions, packet::{GamePhase, InputState, InputStep, Packet, deserialize_packet, serialize_packet_unsigned}, peer::{DEDICATED_SERVER_ID, DisconnectReason, Peer}, rand_u64, replay::Replay, transport::{TransportSetup, parse_server_endpoint}, version::GameVersion};
+
+// Without a new step for this long, either the latest state or step got lost
+const STEP_RESEND_INTERVAL: f32 = STEP_PHASE_TIME_SECS * 5.0;
 
 pub enum ClientEvent {
     ReceivePacket(u16, Packet),
     Connect(PlayerParams, Vec<PlayerParams>),
+    Rejoin(Replay, u64), // Setup of the running match and the gen to catch up to
+    HostMigration(u16), // The host left, the elected player takes over
     Disconnect(DisconnectReason),
     Empty
 }
@@ -13,21 +18,53 @@ pub enum ClientEvent {
 pub struct Client {
     peer: Peer,
     server_addr: SocketAddr,
+    endpoint: String, // As given by the player, relayed servers share their address
     connections: HashMap<u16, ID>,
     local_id: Option<ID>,
     connected: bool,
-    name: String
+    name: String,
+    spectator: bool,
+    password: Option<String>, // Answers the challenge of password-protected lobbies
+    rejoin_token: Option<u64>, // Given by the server, takes back the slot after a timeout
+    kick_message: Option<String>,
+    host_id: Option<u16>,
+    host_candidates: Vec<HostCandidate>, // Only known during a match
+    migration_tokens: Vec<MigrationToken>, // Sent along with the candidates, for the one elected
+    migration: Option<PendingMigration>,
+    in_match: bool,
+    input_seq: u32,
+    sent_states: VecDeque<InputState>, // Latest states, repeated in every input packet
+    last_step_gen: u64, // Steps are only passed on once and in order
+    last_step_time: Instant,
+    quality_table: HashMap<u16, ConnectionQuality> // As last measured by the server
+}
+
+// Migrate packet that is resent until the new host answers. It may not have noticed yet
+// that the previous host left, and drops anything sent to it before.
+struct PendingMigration {
+    packet: Packet, // Carries the cookie once the new host sent one
+    start_time: Instant,
+    last_send_time: Instant
 }
 
 impl Client {
-    pub fn connect(server_addr: &str, name: String) -> BbResult<Client> {
+    pub fn connect(server_addr: &str, name: String, spectator: bool, password: Option<String>,
+        rejoin_token: Option<u64>, conditions: NetConditions) -> BbResult<Client> {
+        let (transport, addr) = parse_server_endpoint(server_addr)?;
         let mut client = Client {
-            peer: Peer::setup(None)?, server_addr: server_addr.parse().unwrap(),
-            connections: HashMap::new(), local_id: None, connected: false, name: name.to_owned()
+            peer: Peer::setup(transport, conditions)?, server_addr: addr, endpoint: server_addr.to_owned(),
+            connections: HashMap::new(), local_id: None, connected: false, name: name.to_owned(),
+            spectator, password, rejoin_token, kick_message: None, host_id: None, host_candidates: Vec::new(),
+            migration_tokens: Vec::new(), migration: None,
+            in_match: false, input_seq: 0, sent_states: VecDeque::new(), last_step_gen: 0,
+            last_step_time: Instant::now(), quality_table: HashMap::new()
         };
-        println!("Connecting to {}", server_addr);
+        println!("Connecting to {}{}", server_addr, match spectator {
+            true => " as spectator",
+            false => ""
+        });
         client.send_packet(Packet::Handshake {
-            name: name.clone()
+            version: GameVersion::local(), name: name.clone(), spectator, cookie: None, rejoin_token
         })?;
         Ok(client)
     }
@@ -36,10 +73,45 @@ impl Client {
         self.local_id.as_ref().and_then(|id| Some(id.clone()))
     }
 
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    pub fn get_endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    // Kept for rejoining, or given by the server once it accepted the connection
+    pub fn get_rejoin_token(&self) -> Option<u64> {
+        self.rejoin_token
+    }
+
+    pub fn get_password(&self) -> Option<String> {
+        self.password.clone()
+    }
+
+    // Kicked players also get to see the message of the host
+    pub fn describe_disconnect(&self, reason: DisconnectReason) -> String {
+        match self.kick_message.as_ref().filter(|message| !message.is_empty()) {
+            Some(message) => format!("{} ({})", reason, message),
+            None => reason.to_string()
+        }
+    }
+
     pub fn is_connected(&self) -> bool {
         self.connected
     }
 
+    // None if the host is a dedicated server
+    pub fn get_host_id(&self) -> Option<u16> {
+        self.host_id
+    }
+
+    // Whether the sender runs the server, either as the host or as a dedicated server
+    fn is_server(&self, sender: u16) -> bool {
+        sender == self.host_id.unwrap_or(DEDICATED_SERVER_ID)
+    }
+
     pub fn get_connection(&self, id: u16) -> Option<&ID> {
         self.connections.get(&id)
     }
@@ -48,8 +120,42 @@ impl Client {
         self.connections.values().map(|id| id.clone()).collect::<Vec<_>>() // Performance?
     }
 
+    // None until the server measured the connection
+    pub fn get_connection_quality(&self, id: u16) -> Option<&ConnectionQuality> {
+        self.quality_table.get(&id)
+    }
+
     pub fn send_packet(&mut self, packet: Packet) -> BbResult {
-        self.peer.send_raw_packet(serialize_packet_unsigned(packet), self.server_addr)
+        let delivery = packet.get_delivery();
+        self.peer.send_raw_packet(serialize_packet_unsigned(packet), self.server_addr, delivery)
+    }
+
+    pub fn send_input(&mut self, state: InputState) -> BbResult {
+        if self.sent_states.len() >= REDUNDANT_INPUT_STATES {
+            self.sent_states.pop_front();
+        }
+        self.sent_states.push_back(state);
+        self.input_seq += 1;
+        self.resend_input()
+    }
+
+    pub fn send_migration(&mut self, packet: Packet) -> BbResult {
+        self.migration = Some(PendingMigration {
+            packet, start_time: Instant::now(), last_send_time: Instant::now()
+        });
+        self.resend_migration()
+    }
+
+    pub fn get_migration_tokens(&self) -> Vec<MigrationToken> {
+        self.migration_tokens.clone()
+    }
+
+    // Hands the socket over to the server of the new host, which is then reached locally
+    pub fn hand_over_peer(&mut self) -> BbResult<Peer> {
+        let peer = std::mem::replace(&mut self.peer,
+            Peer::setup(TransportSetup::Direct { port: None }, NetConditions::default())?);
+        self.server_addr = SocketAddr::from(([127, 0, 0, 1], peer.get_local_port()));
+        Ok(peer)
     }
 
     pub fn disconnect(&mut self, reason: DisconnectReason) -> BbResult {
@@ -62,6 +168,23 @@ impl Client {
     }
 
     pub fn poll_received_packets(&mut self) -> BbResult<ClientEvent> {
+        if let Some(migration) = self.migration.as_mut() {
+            if migration.start_time.elapsed().as_secs_f32() >= MIGRATION_TIMEOUT {
+                println!("New host did not answer in time.");
+                self.migration = None;
+                self.connected = false;
+                return Ok(ClientEvent::Disconnect(DisconnectReason::HostShutdown))
+            } else if migration.last_send_time.elapsed().as_secs_f32() >= MIGRATION_RESEND_INTERVAL {
+                self.resend_migration()?;
+            }
+        } else if self.in_match && self.last_step_time.elapsed().as_secs_f32() >= STEP_RESEND_INTERVAL {
+            // The server drops what it already has and only resends steps after the gap
+            self.last_step_time = Instant::now();
+            self.resend_input()?;
+            self.send_packet(Packet::StepsRequest {
+                gen: self.last_step_gen
+            })?;
+        }
         if let Some(event) = self.peer.poll_received_packets()? {
             Ok(match event {
                 laminar::SocketEvent::Packet(packet) =>  {
@@ -70,17 +193,27 @@ impl Client {
                         println!("Received packet {:?} from unknown endpoint: {}. Dropping...", packet, sender_addr);
                         ClientEvent::Empty
                     } else {
-                        let (packet, sender) = deserialize_packet(packet.payload().to_vec());
-                        self.handle_server_packet(packet, sender)?
+                        match deserialize_packet(packet.payload().to_vec()) {
+                            Ok((packet, sender)) => {
+                                if !matches!(packet, Packet::HandshakeCookie { .. }) {
+                                    self.migration = None; // New host is up and running
+                                }
+                                self.handle_server_packet(packet, sender)?
+                            },
+                            Err(e) => {
+                                println!("Dropped malformed packet from server. Reason: {:?}", e);
+                                ClientEvent::Empty
+                            }
+                        }
                     }
                 },
                 SocketEvent::Connect(_) => {
                     println!("Established connection to server.");
                     ClientEvent::Empty
                 },
-                SocketEvent::Timeout(_) => {
+                SocketEvent::Timeout(addr) if addr == self.server_addr => {
                     println!("Connection to server timed out.");
-                    ClientEvent::Disconnect(DisconnectReason::Timeout)
+                    self.migrate_host(DisconnectReason::Timeout)
                 },
                 // SocketEvent::Disconnect(_) => {
                 //     println!("Disconnected from server.");
@@ -96,33 +229,125 @@ impl Client {
 
     fn handle_server_packet(&mut self, packet: Packet, sender: u16) -> BbResult<ClientEvent> {
         Ok(match &packet {
-            Packet::HandshakeReply { players } => {
+            Packet::HandshakeReply { players, spectators, host, rejoin_token } => {
                 println!("Server accepted connection attempt!");
+                self.rejoin_token = Some(*rejoin_token);
+                self.host_id = *host;
                 let id = ID::new(self.name.to_owned(), sender);
                 self.local_id = Some(id.clone());
                 self.connections.insert(id.n, id.clone());
                 players.iter().for_each(|p| {
                     self.connections.insert(p.id.n, p.id.clone());
-                    if is_auth_client(p.id.n) {
+                    if *host == Some(p.id.n) {
                         println!("Hosting player: {:?}", p.id.clone())
                     } else {
                         println!("Updating player: {:?}", p.id.clone());
                     }
                 });
+                spectators.iter().for_each(|id| {
+                    self.connections.insert(id.n, id.clone());
+                });
                 self.connected = true;
                 ClientEvent::Connect(PlayerParams::new(id), players.clone())
             },
-            Packet::PlayerConnect { name } => {
+            Packet::Rejoin { world_seed, settings, players, host, gen } => {
+                println!("Server accepted rejoin attempt! Catching up on {} gens...", gen);
+                let id = ID::new(self.name.to_owned(), sender);
+                self.local_id = Some(id.clone());
+                players.iter().for_each(|p| {
+                    self.connections.insert(p.id.n, p.id.clone());
+                });
+                self.host_id = *host;
+                self.connected = true;
+                self.start_match();
+                ClientEvent::Rejoin(Replay::new(*world_seed, players.clone(), *settings), *gen)
+            },
+            Packet::PlayerConnect { name, .. } => {
                 self.connections.insert(sender, ID::new(name.to_owned(), sender));
                 ClientEvent::ReceivePacket(sender, packet)
             },
+            Packet::HandshakeCookie { cookie } if self.local_id.is_none() => {
+                self.send_packet(Packet::Handshake {
+                    version: GameVersion::local(), name: self.name.clone(), spectator: self.spectator,
+                    cookie: Some(*cookie), rejoin_token: self.rejoin_token
+                })?;
+                ClientEvent::Empty
+            },
+            Packet::HandshakeCookie { cookie: new_cookie } if self.migration.is_some() => {
+                if let Some(Packet::Migrate { cookie, .. }) = self.migration.as_mut().map(|m| &mut m.packet) {
+                    *cookie = Some(*new_cookie);
+                }
+                self.resend_migration()?;
+                ClientEvent::Empty
+            },
+            Packet::Challenge { salt } if self.local_id.is_none() => {
+                // Without a password, the server refuses the answer and says why
+                let password = self.password.as_deref().unwrap_or_default();
+                let nonce = rand_u64();
+                self.send_packet(Packet::ChallengeAnswer {
+                    nonce, proof: gen_password_proof(*salt, nonce, password)
+                })?;
+                ClientEvent::Empty
+            },
+            Packet::Kick { reason, message } => {
+                println!("Server terminated this connection. Reason: {:?} ({}).", reason, message);
+                self.kick_message = Some(message.to_owned());
+                self.connected = false;
+                ClientEvent::Disconnect(*reason)
+            },
+            Packet::PlayerDisconnect { reason } if self.local_id.is_none() => {
+                println!("Server refused connection attempt. Reason: {}.", reason);
+                ClientEvent::Disconnect(*reason)
+            },
+            Packet::PlayerDisconnect { reason } if self.is_server(sender) => {
+                // Dedicated servers don't have a player connection for their ID
+                self.connections.remove(&sender);
+                println!("Host disconnected.");
+                self.migrate_host(*reason)
+            },
+            Packet::Ping { seq } => {
+                self.send_packet(Packet::Pong {
+                    seq: *seq
+                })?;
+                ClientEvent::Empty
+            },
+            Packet::QualityTable { entries } => {
+                self.quality_table = entries.iter().map(|entry| (entry.id, *entry)).collect();
+                ClientEvent::ReceivePacket(sender, packet)
+            },
+            Packet::HostCandidates { host, candidates, tokens } => {
+                self.host_id = *host;
+                self.host_candidates = candidates.clone();
+                self.migration_tokens = tokens.clone();
+                ClientEvent::Empty
+            },
+            Packet::Game { phase: GamePhase::World(..) } => {
+                self.start_match();
+                ClientEvent::ReceivePacket(sender, packet)
+            },
+            Packet::Game { phase: GamePhase::Score } => {
+                self.host_candidates.clear(); // Standings don't need a host
+                self.migration_tokens.clear();
+                self.in_match = false;
+                self.sent_states.clear();
+                ClientEvent::ReceivePacket(sender, packet)
+            },
+            Packet::InputStep { steps } => match self.take_new_steps(steps) {
+                Some(steps) => ClientEvent::ReceivePacket(sender, Packet::InputStep {
+                    steps
+                }),
+                None => ClientEvent::Empty
+            },
+            Packet::RejoinSteps { steps } => match self.take_new_steps(steps) {
+                Some(steps) => ClientEvent::ReceivePacket(sender, Packet::RejoinSteps {
+                    steps
+                }),
+                None => ClientEvent::Empty
+            },
             Packet::PlayerDisconnect { reason } => {
+                self.host_candidates.retain(|c| c.id != sender);
                 if let Some(player) = self.connections.remove(&sender) {
-                    if is_auth_client(sender) {
-                        println!("Host disconnected - connection terminated.");
-                        self.connected = false;
-                        ClientEvent::Disconnect(*reason)
-                    } else if player.n == self.local_id.as_ref().unwrap().n {
+                    if player.n == self.local_id.as_ref().unwrap().n {
                         println!("Server terminated this connection. Reason: {:?}.", reason);
                         self.connected = false;
                         ClientEvent::Disconnect(*reason)
@@ -137,4 +362,69 @@ impl Client {
             _ => ClientEvent::ReceivePacket(sender, packet)
         })
     }
-}
\ No newline at end of file
+
+    fn start_match(&mut self) {
+        self.in_match = true;
+        self.last_step_gen = 0;
+        self.last_step_time = Instant::now();
+    }
+
+    fn resend_input(&mut self) -> BbResult {
+        if self.sent_states.is_empty() {
+            return Ok(())
+        }
+        self.send_packet(Packet::Input {
+            seq: self.input_seq, states: self.sent_states.iter().cloned().collect()
+        })
+    }
+
+    // Steps are repeated across packets, so most arrive more than once. After more were lost
+    // in a row than were repeated, nothing is passed on until the server filled the gap.
+    fn take_new_steps(&mut self, steps: &[InputStep]) -> Option<Vec<InputStep>> {
+        let new_steps = steps.iter()
+            .filter(|step| step.gen > self.last_step_gen)
+            .cloned()
+            .collect::<Vec<_>>();
+        match (new_steps.first(), new_steps.last()) {
+            (Some(first), Some(last)) if first.gen == self.last_step_gen + 1 => {
+                self.last_step_gen = last.gen;
+                self.last_step_time = Instant::now();
+                Some(new_steps)
+            },
+            _ => None
+        }
+    }
+
+    fn resend_migration(&mut self) -> BbResult {
+        if let Some(migration) = self.migration.as_mut() {
+            migration.last_send_time = Instant::now();
+            let delivery = migration.packet.get_delivery();
+            let packet_bytes = serialize_packet_unsigned(migration.packet.clone());
+            self.peer.send_raw_packet(packet_bytes, self.server_addr, delivery)?;
+        }
+        Ok(())
+    }
+
+    // Re-points this client to the elected host, if the match can go on without the old one
+    fn migrate_host(&mut self, reason: DisconnectReason) -> ClientEvent {
+        self.quality_table.clear(); // Measured by the previous host
+        if let Some(host_id) = self.host_id.take() {
+            self.connections.remove(&host_id);
+            self.host_candidates.retain(|c| c.id != host_id);
+        }
+        match elect_host(&self.host_candidates) {
+            Some(new_host) => {
+                println!("Migrating to new host ^{} at {}...", new_host.id, new_host.addr);
+                self.host_id = Some(new_host.id);
+                self.server_addr = new_host.addr;
+                self.host_candidates.retain(|c| c.id != new_host.id);
+                ClientEvent::HostMigration(new_host.id)
+            },
+            None => {
+                println!("Connection to host terminated.");
+                self.connected = false;
+                ClientEvent::Disconnect(reason)
+            }
+        }
+    }
+}
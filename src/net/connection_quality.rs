@@ -0,0 +1,135 @@
+use std::{collections::VecDeque, fmt, time::{Duration, Instant}};
+use binary_stream::{BinaryStream, Serializable};
+use crate::{BbResult, decode::{Decode, DecodeStream}};
+
+// Every connection is pinged this often, and the table of all of them sent along
+pub const PING_INTERVAL: f32 = 1.0;
+// Pings that weren't answered by then count as lost
+pub const PING_TIMEOUT: f32 = 3.0;
+// Quality is measured over the latest pings (~20 secs)
+pub const PING_WINDOW: usize = 20;
+// Beyond any of these, a player's inputs regularly miss the step they were meant for
+pub const LAG_RTT_MS: u16 = 250;
+pub const LAG_JITTER_MS: u16 = 80;
+pub const LAG_LOSS_PERCENT: u8 = 10;
+
+// How well the server reaches a player, as sent to everyone in the quality table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionQuality {
+    pub id: u16,
+    pub rtt: u16, // Millis, averaged over the window
+    pub jitter: u16, // Millis, mean difference between consecutive RTTs
+    pub loss: u8 // Percent of the pings that went unanswered
+}
+
+impl ConnectionQuality {
+    pub fn is_lagging(&self) -> bool {
+        self.rtt >= LAG_RTT_MS || self.jitter >= LAG_JITTER_MS || self.loss >= LAG_LOSS_PERCENT
+    }
+}
+
+impl fmt::Display for ConnectionQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms ±{}ms, {}% loss", self.rtt, self.jitter, self.loss)
+    }
+}
+
+impl Serializable for ConnectionQuality {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_u16(self.id).unwrap();
+        stream.write_u16(self.rtt).unwrap();
+        stream.write_u16(self.jitter).unwrap();
+        stream.write_buffer_single(self.loss).unwrap();
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for ConnectionQuality {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(ConnectionQuality {
+            id: stream.decode_u16()?,
+            rtt: stream.decode_u16()?,
+            jitter: stream.decode_u16()?,
+            loss: stream.decode_u8()?.min(100)
+        })
+    }
+}
+
+struct SentPing {
+    seq: u32,
+    sent_at: Instant,
+    rtt: Option<Duration>
+}
+
+// Pings of a single connection. Each ping carries its own sequence number, so late pongs
+// still count and duplicates are ignored.
+pub struct QualityTracker {
+    pings: VecDeque<SentPing>,
+    next_seq: u32
+}
+
+impl QualityTracker {
+    pub fn new() -> QualityTracker {
+        QualityTracker {
+            pings: VecDeque::new(), next_seq: 0
+        }
+    }
+
+    // Returns the sequence number to send the ping with
+    pub fn send_ping(&mut self, now: Instant) -> u32 {
+        if self.pings.len() >= PING_WINDOW {
+            self.pings.pop_front();
+        }
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.pings.push_back(SentPing {
+            seq, sent_at: now, rtt: None
+        });
+        seq
+    }
+
+    // Returns false for pongs of unknown or already answered pings
+    pub fn on_pong(&mut self, seq: u32, now: Instant) -> bool {
+        match self.pings.iter_mut().find(|ping| ping.seq == seq && ping.rtt.is_none()) {
+            Some(ping) => {
+                ping.rtt = Some(now.saturating_duration_since(ping.sent_at));
+                true
+            },
+            None => false
+        }
+    }
+
+    // None until the first pong arrived. Pings still within their timeout are left out.
+    pub fn get_quality(&self, id: u16, now: Instant) -> Option<ConnectionQuality> {
+        let rtts = self.pings.iter()
+            .filter_map(|ping| ping.rtt)
+            .map(|rtt| rtt.as_secs_f32() * 1000.0)
+            .collect::<Vec<_>>();
+        if rtts.is_empty() {
+            return None
+        }
+        let lost = self.pings.iter()
+            .filter(|ping| ping.rtt.is_none()
+                && now.saturating_duration_since(ping.sent_at).as_secs_f32() >= PING_TIMEOUT)
+            .count();
+        let rtt = rtts.iter().sum::<f32>() / rtts.len() as f32;
+        let jitter = match rtts.len() {
+            1 => 0.0,
+            n => rtts.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum::<f32>() / (n - 1) as f32
+        };
+        Some(ConnectionQuality {
+            id, rtt: rtt.round().min(u16::MAX as f32) as u16,
+            jitter: jitter.round().min(u16::MAX as f32) as u16,
+            loss: (lost * 100 / (lost + rtts.len())) as u8
+        })
+    }
+}
+
+impl Default for QualityTracker {
+    fn default() -> Self {
+        QualityTracker::new()
+    }
+}
@@ -0,0 +1,137 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use binary_stream::BinaryStream;
+use crate::{BbError, BbErrorType, BbResult, V2};
+
+// Longest string any packet may carry. Names and chat messages are far shorter.
+pub const MAX_DECODED_STRING_LEN: usize = 1024;
+
+// Fallible counterpart to Serializable::from_stream, for anything that comes from the
+// network or disk. binary_stream itself panics on underflow and invalid UTF-8, so
+// from_stream implementations only forward here and are left for trusted data.
+pub trait Decode: Sized {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self>;
+}
+
+// Reads that check the remaining size of the stream before touching it
+pub trait DecodeStream {
+    fn decode_u8(&mut self) -> BbResult<u8>;
+    fn decode_bool(&mut self) -> BbResult<bool>;
+    fn decode_u16(&mut self) -> BbResult<u16>;
+    fn decode_u32(&mut self) -> BbResult<u32>;
+    fn decode_u64(&mut self) -> BbResult<u64>;
+    fn decode_f32(&mut self) -> BbResult<f32>;
+    fn decode_v2(&mut self) -> BbResult<V2>;
+    fn decode_bytes(&mut self, len: usize) -> BbResult<Vec<u8>>;
+    fn decode_string(&mut self) -> BbResult<String>;
+    fn decode_socket_addr(&mut self) -> BbResult<SocketAddr>;
+    fn decode_vec<T: Decode>(&mut self) -> BbResult<Vec<T>>;
+}
+
+impl DecodeStream for BinaryStream {
+    fn decode_u8(&mut self) -> BbResult<u8> {
+        Ok(self.decode_bytes(1)?[0])
+    }
+
+    fn decode_bool(&mut self) -> BbResult<bool> {
+        match self.decode_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            n @ _ => Err(decode_error(&format!("{} is not a boolean", n)))
+        }
+    }
+
+    fn decode_u16(&mut self) -> BbResult<u16> {
+        let bytes = self.decode_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn decode_u32(&mut self) -> BbResult<u32> {
+        let bytes = self.decode_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn decode_u64(&mut self) -> BbResult<u64> {
+        let low = self.decode_u32()? as u64;
+        let high = self.decode_u32()? as u64;
+        Ok(low | (high << 32))
+    }
+
+    fn decode_f32(&mut self) -> BbResult<f32> {
+        Ok(f32::from_bits(self.decode_u32()?))
+    }
+
+    fn decode_v2(&mut self) -> BbResult<V2> {
+        let x = self.decode_f32()?;
+        let y = self.decode_f32()?;
+        Ok(V2::new(x, y))
+    }
+
+    fn decode_bytes(&mut self, len: usize) -> BbResult<Vec<u8>> {
+        if self.size() < len {
+            return Err(decode_error(&format!("Expected {} bytes, only {} left", len, self.size())))
+        }
+        self.read_buffer(len).map_err(BbError::Io)
+    }
+
+    fn decode_string(&mut self) -> BbResult<String> {
+        let len = self.decode_u32()? as usize;
+        if len > MAX_DECODED_STRING_LEN {
+            return Err(decode_error(&format!("String of {} bytes is too long", len)))
+        }
+        String::from_utf8(self.decode_bytes(len)?)
+            .map_err(|_| decode_error("String is not valid UTF-8"))
+    }
+
+    fn decode_socket_addr(&mut self) -> BbResult<SocketAddr> {
+        let ip = match self.decode_u8()? {
+            4 => {
+                let octets = self.decode_bytes(4)?;
+                IpAddr::V4(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+            },
+            6 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&self.decode_bytes(16)?);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            },
+            n @ _ => return Err(unknown_index(n, "IP version"))
+        };
+        let port = self.decode_u16()?;
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    fn decode_vec<T: Decode>(&mut self) -> BbResult<Vec<T>> {
+        let len = self.decode_u32()? as usize;
+        // Every element takes at least a byte, which bounds the allocation
+        if len > self.size() {
+            return Err(decode_error(&format!("Vector of {} elements exceeds the stream", len)))
+        }
+        let mut vec = Vec::with_capacity(len);
+        for _ in 0..len {
+            vec.push(T::decode(self)?);
+        }
+        Ok(vec)
+    }
+}
+
+// Counterpart to decode_socket_addr: IP version, octets and port
+pub fn write_socket_addr(stream: &mut BinaryStream, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            stream.write_buffer_single(4).unwrap();
+            stream.write_buffer(&ip.octets().to_vec()).unwrap();
+        },
+        IpAddr::V6(ip) => {
+            stream.write_buffer_single(6).unwrap();
+            stream.write_buffer(&ip.octets().to_vec()).unwrap();
+        }
+    }
+    stream.write_u16(addr.port()).unwrap();
+}
+
+pub fn decode_error(reason: &str) -> BbError {
+    BbError::Bb(BbErrorType::InvalidEncoding(reason.to_owned()))
+}
+
+pub fn unknown_index(n: u8, type_name: &str) -> BbError {
+    decode_error(&format!("Index {} not assigned to any {}", n, type_name))
+}
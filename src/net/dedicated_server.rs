@@ -0,0 +1,418 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, thread, time::{Duration, Instant}};
+use crate::{BbResult, DEFAULT_SIMULATION_TIMESTEP, ID, PlayerParams, desync_investigation::DesyncInvestigation, game_settings::GameSettings, input_pool::{InputPool, REDUNDANT_INPUT_STEPS}, net_settings::NetSettings, packet::{GamePhase, InputState, Packet, serialize_packet}, peer::DisconnectReason, rand_u64, rejoin::{gen_missed_steps_packets, gen_rejoin_packets}, server::{Server, ServerEvent}, ship_data::ShipType, state_digest::StateDigestPart, sync_checker::{SyncChecker, SyncState}};
+
+pub const DEDICATED_SERVER_START_DELAY: f32 = 10.0;
+const MAX_EVENTS_PER_TICK: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedicatedServerPhase {
+    Lobby,
+    Match,
+    Score
+}
+
+// Runs the lockstep authority without a window or local player.
+// Mirrors what the LobbyScene and WorldScene do server-side, minus any tetra context.
+pub struct DedicatedServer {
+    server: Server,
+    settings: GameSettings,
+    min_players: usize,
+    fill_bots: bool, // Computer players take the slots left empty at match start
+    players: HashMap<u16, PlayerParams>,
+    phase: DedicatedServerPhase,
+    start_time: Option<Instant>,
+    input_pool: Option<InputPool>,
+    sync_checker: Option<SyncChecker>,
+    desync_investigation: DesyncInvestigation,
+    score_reports: HashSet<u16> // Players whose simulation reached the end of the match
+}
+
+impl DedicatedServer {
+    pub fn host(port: u16, net_settings: NetSettings, min_players: usize, settings: GameSettings,
+        fill_bots: bool) -> BbResult<DedicatedServer> {
+        let mut server = Server::host(port, net_settings)?;
+        server.set_game_mode(settings.mode);
+        println!("Dedicated server: {:?}. Matches start with at least {} player(s).",
+            settings, min_players);
+        Ok(DedicatedServer {
+            server, settings, min_players: min_players.max(1), fill_bots, players: HashMap::new(),
+            phase: DedicatedServerPhase::Lobby, start_time: None,
+            input_pool: None, sync_checker: None, desync_investigation: DesyncInvestigation::new(),
+            score_reports: HashSet::new()
+        })
+    }
+
+    pub fn get_phase(&self) -> DedicatedServerPhase {
+        self.phase
+    }
+
+    pub fn run(&mut self) -> BbResult {
+        let tick_duration = Duration::from_secs_f64(1.0 / DEFAULT_SIMULATION_TIMESTEP);
+        let mut next_tick = Instant::now();
+        loop {
+            self.tick()?;
+            next_tick += tick_duration;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else {
+                next_tick = now; // Fell behind, don't try to catch up with a burst of ticks
+            }
+        }
+    }
+
+    pub fn tick(&mut self) -> BbResult {
+        for _ in 0..MAX_EVENTS_PER_TICK {
+            match self.server.poll_received_packets()? {
+                ServerEvent::Empty => break,
+                event => self.handle_event(event)?
+            }
+        }
+        match self.phase {
+            DedicatedServerPhase::Lobby => self.update_lobby(),
+            DedicatedServerPhase::Match => self.update_match(),
+            DedicatedServerPhase::Score => {
+                // Players leave on their own once they've seen the standings
+                if self.server.get_player_count() == 0 {
+                    self.end_match();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn shutdown(&mut self) -> BbResult {
+        println!("Dedicated server: Shutting down...");
+        self.server.shutdown()
+    }
+
+    fn handle_event(&mut self, event: ServerEvent) -> BbResult {
+        match event {
+            ServerEvent::ReceivePacket(sender, packet) => {
+                match packet {
+                    Packet::ChatMessage { message } => self.on_receive_chat_message(sender, message),
+                    Packet::Input { states, .. } => states.into_iter()
+                        .try_for_each(|state| self.on_receive_input(sender, state)),
+                    Packet::Sync { state } => self.on_receive_sync_state(sender, state),
+                    Packet::DigestSummary { states } => self.on_receive_digest_summary(sender, states),
+                    Packet::Digest { part } => self.on_receive_digest(sender, part),
+                    Packet::Selection { mode, ship, .. } if mode => self.on_receive_ship_selection(
+                        sender, ship.unwrap()),
+                    Packet::Game { phase } => self.on_receive_game_phase(sender, phase),
+                    Packet::StepsRequest { gen } => self.on_receive_steps_request(sender, gen),
+                    Packet::Selection { .. } => {
+                        println!("^{} failed to change settings: the dedicated server decides the settings.",
+                            sender);
+                        Ok(())
+                    },
+                    _ => Ok(())
+                }
+            },
+            ServerEvent::PlayerConnect(id, addr) => self.on_receive_handshake(id, addr),
+            ServerEvent::PlayerRejoin(id, _) => self.on_receive_rejoin(id),
+            ServerEvent::PlayerDisconnect(sender, reason) => self.on_receive_disconnect(sender, reason),
+            ServerEvent::ReservationExpired(id) => {
+                if let Some(input_pool) = self.input_pool.as_mut() {
+                    input_pool.remove_player(id);
+                }
+                Ok(())
+            },
+            // Dedicated servers never take over a match, so nobody migrates to them
+            ServerEvent::PlayerMigrate(..) | ServerEvent::Empty => Ok(())
+        }
+    }
+
+    fn on_receive_handshake(&mut self, id: ID, remote_addr: SocketAddr) -> BbResult {
+        let spectator = self.server.is_spectator(id.n);
+        self.server.send_raw_unicast(serialize_packet(Packet::HandshakeReply {
+            players: self.players.values().cloned().collect(),
+            spectators: self.server.get_spectators(),
+            host: None,
+            rejoin_token: self.server.get_rejoin_token(id.n).unwrap_or_default()
+        }, id.n), remote_addr)?;
+        // Newcomers only learn the settings when they change, so tell them right away
+        self.server.send_unicast(Packet::Selection {
+            mode: false, ship: None, settings: Some(self.settings)
+        }, id.n)?;
+        // Spectators may arrive mid-match and have to catch up with it
+        if spectator && self.phase != DedicatedServerPhase::Lobby {
+            self.send_match_history(id.n)?;
+        }
+
+        self.announce_connection(&id, spectator)?;
+        if !spectator {
+            self.players.insert(id.n, PlayerParams::new(id));
+        }
+        Ok(())
+    }
+
+    fn on_receive_rejoin(&mut self, id: ID) -> BbResult {
+        let input_pool = match self.input_pool.as_mut() {
+            Some(input_pool) => input_pool,
+            None => return Ok(())
+        };
+        input_pool.resume_player(id.n);
+        let player = input_pool.get_history().players.iter().find(|p| p.id.n == id.n).cloned()
+            .unwrap_or(PlayerParams::new(id.clone()));
+        self.send_match_history(id.n)?;
+        self.announce_connection(&id, false)?;
+        self.players.insert(id.n, player);
+        Ok(())
+    }
+
+    fn send_match_history(&mut self, id: u16) -> BbResult {
+        if let Some(input_pool) = self.input_pool.as_ref() {
+            for packet in gen_rejoin_packets(input_pool.get_history(), None).into_iter() {
+                self.server.send_unicast(packet, id)?;
+            }
+        }
+        if self.phase == DedicatedServerPhase::Score {
+            self.server.send_unicast(Packet::Game {
+                phase: GamePhase::Score
+            }, id)
+        } else {
+            self.server.send_host_candidates() // The newcomer's address may have changed
+        }
+    }
+
+    fn announce_connection(&mut self, id: &ID, spectator: bool) -> BbResult {
+        let others = self.server.get_connections().map(|conn| conn.0.n)
+            .filter(|n| *n != id.n).collect::<Vec<_>>();
+        if !others.is_empty() {
+            self.server.send_multicast_group(Packet::PlayerConnect {
+                name: id.name.to_owned(), spectator
+            }, id.n, others.as_slice())?;
+        }
+        Ok(())
+    }
+
+    fn on_receive_disconnect(&mut self, sender: u16, _reason: DisconnectReason) -> BbResult {
+        self.players.remove(&sender);
+        self.score_reports.remove(&sender);
+        if let Some(input_pool) = self.input_pool.as_mut() {
+            if self.server.is_slot_reserved(sender) {
+                input_pool.suspend_player(sender);
+            } else {
+                input_pool.remove_player(sender);
+            }
+        }
+        let suspects = self.desync_investigation.remove_player(sender);
+        self.disconnect_players(suspects, DisconnectReason::Desync)?;
+        self.check_score_reports() // Those left may have reported already
+    }
+
+    fn on_receive_chat_message(&mut self, sender: u16, message: String) -> BbResult {
+        self.server.send_multicast(Packet::ChatMessage {
+            message
+        }, sender)
+    }
+
+    fn on_receive_ship_selection(&mut self, sender: u16, ship_type: ShipType) -> BbResult {
+        if self.phase != DedicatedServerPhase::Lobby {
+            return Ok(())
+        }
+        if let Some(player) = self.players.get_mut(&sender) {
+            player.ship_type = ship_type;
+        }
+        self.server.send_multicast(Packet::Selection {
+            mode: true, ship: Some(ship_type), settings: None
+        }, sender)
+    }
+
+    fn on_receive_game_phase(&mut self, sender: u16, phase: GamePhase) -> BbResult {
+        match (self.phase, &phase) {
+            // Every client detects the win in the same generation, but a single modified client
+            // must not end the match for everyone
+            (DedicatedServerPhase::Match, GamePhase::Score) => {
+                if self.players.contains_key(&sender) && self.score_reports.insert(sender) {
+                    println!("Dedicated server: ^{} reported the end of the match ({}/{}).", sender,
+                        self.score_reports.len(), self.players.len());
+                }
+                self.check_score_reports()
+            },
+            (DedicatedServerPhase::Score, GamePhase::Score) => Ok(()),
+            _ => {
+                println!("^{} failed to set {:?} phase: the dedicated server decides the phase.",
+                    sender, phase);
+                Ok(())
+            }
+        }
+    }
+
+    // The match ends once a majority of the players still connected reported it
+    fn check_score_reports(&mut self) -> BbResult {
+        if self.phase != DedicatedServerPhase::Match || self.score_reports.is_empty()
+            || self.score_reports.len() * 2 <= self.players.len() {
+            return Ok(())
+        }
+        println!("Dedicated server: Majority of players reported the end of the match.");
+        self.phase = DedicatedServerPhase::Score;
+        self.server.send_multicast(Packet::Game {
+            phase: GamePhase::Score
+        }, 0)
+    }
+
+    // Without a simulation, purchases can't be checked against the harbours
+    fn on_receive_input(&mut self, sender: u16, state: InputState) -> BbResult {
+        let violation = match self.input_pool.as_mut() {
+            Some(input_pool) => match input_pool.validate_state(sender, &state, None) {
+                Ok(()) => {
+                    input_pool.add_state(sender, state);
+                    return Ok(())
+                },
+                Err(violation) => violation
+            },
+            None => return Ok(())
+        };
+        if self.server.on_receive_invalid_input(sender, violation)? {
+            self.on_receive_disconnect(sender, DisconnectReason::InvalidInput)?;
+        }
+        Ok(())
+    }
+
+    // More steps got lost in a row than each packet repeats
+    fn on_receive_steps_request(&mut self, sender: u16, gen: u64) -> BbResult {
+        if let Some(input_pool) = self.input_pool.as_ref() {
+            for packet in gen_missed_steps_packets(input_pool.get_history(), gen).into_iter() {
+                self.server.send_unicast(packet, sender)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_receive_sync_state(&mut self, sender: u16, state: SyncState) -> BbResult {
+        if let Some(sync_checker) = self.sync_checker.as_mut() {
+            sync_checker.add_state(sender, state);
+        }
+        self.review_sync_states()
+    }
+
+    fn review_sync_states(&mut self) -> BbResult {
+        let (sync_checker, input_pool) = match (self.sync_checker.as_mut(), self.input_pool.as_ref()) {
+            (Some(sync_checker), Some(input_pool)) => (sync_checker, input_pool),
+            _ => return Ok(())
+        };
+        for review in sync_checker.take_reviews(&input_pool.get_active_players(), None).into_iter() {
+            if !review.desynced_players.is_empty() {
+                // Desynced players are only disconnected once their digests were diffed
+                let requests = self.desync_investigation.investigate(review.reference_id,
+                    review.desynced_players);
+                self.send_digest_requests(requests)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_receive_digest_summary(&mut self, sender: u16, states: Vec<SyncState>) -> BbResult {
+        let requests = self.desync_investigation.add_summary(sender, states);
+        self.send_digest_requests(requests)
+    }
+
+    fn on_receive_digest(&mut self, sender: u16, part: StateDigestPart) -> BbResult {
+        let finished_players = self.desync_investigation.add_digest_part(sender, part);
+        self.disconnect_players(finished_players, DisconnectReason::Desync)
+    }
+
+    fn send_digest_requests(&mut self, requests: Vec<(u16, Packet)>) -> BbResult {
+        for (id, packet) in requests.into_iter() {
+            self.server.send_unicast(packet, id)?;
+        }
+        Ok(())
+    }
+
+    fn disconnect_player(&mut self, id: u16, reason: DisconnectReason) -> BbResult {
+        self.server.disconnect_player(id, reason)?;
+        self.on_receive_disconnect(id, reason)
+    }
+
+    fn disconnect_players(&mut self, ids: Vec<u16>, reason: DisconnectReason) -> BbResult {
+        for id in ids.into_iter() {
+            self.disconnect_player(id, reason)?;
+        }
+        Ok(())
+    }
+
+    fn update_lobby(&mut self) -> BbResult {
+        if self.players.len() < self.min_players {
+            if self.start_time.take().is_some() {
+                println!("Dedicated server: Not enough players anymore. Match start aborted.");
+            }
+            return Ok(())
+        }
+
+        if let Some(start_time) = self.start_time.as_ref() {
+            if start_time.elapsed().as_secs_f32() >= DEDICATED_SERVER_START_DELAY {
+                self.start_match()?;
+            }
+        } else {
+            println!("Dedicated server: Starting match in {} seconds...", DEDICATED_SERVER_START_DELAY);
+            self.start_time = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    fn start_match(&mut self) -> BbResult {
+        let world_seed = rand_u64();
+        println!("Dedicated server: Starting match with {} player(s). World seed: {}.",
+            self.players.len(), world_seed);
+        self.server.set_accepting_connections(false);
+        if self.fill_bots {
+            let free_slots = self.server.get_max_players().saturating_sub(self.players.len());
+            self.settings.bots = free_slots.min(u8::MAX as usize) as u8;
+            self.server.send_multicast(Packet::Selection {
+                mode: false, ship: None, settings: Some(self.settings)
+            }, 0)?;
+        }
+        self.server.send_multicast(Packet::Game {
+            phase: GamePhase::World(world_seed)
+        }, 0)?;
+        self.server.send_host_candidates()?;
+        self.input_pool = Some(InputPool::new(self.players.values().cloned().collect(), world_seed,
+            self.settings));
+        self.sync_checker = Some(SyncChecker::new());
+        self.start_time = None;
+        self.phase = DedicatedServerPhase::Match;
+        Ok(())
+    }
+
+    fn end_match(&mut self) {
+        println!("Dedicated server: Match ended. Returning to lobby...");
+        self.input_pool = None;
+        self.sync_checker = None;
+        self.desync_investigation = DesyncInvestigation::new();
+        self.score_reports.clear();
+        self.players.clear();
+        self.server.set_accepting_connections(true);
+        self.phase = DedicatedServerPhase::Lobby;
+    }
+
+    fn update_match(&mut self) -> BbResult {
+        if self.server.get_player_count() == 0 {
+            self.end_match();
+            return Ok(())
+        }
+
+        let input_pool = self.input_pool.as_mut().unwrap();
+        if input_pool.is_step_phase_over() {
+            let delayed_players = input_pool.check_delayed_players();
+            // In the first generation every player has to send their state, to signal they're ready
+            if input_pool.curr_gen > 0 || delayed_players.is_empty() {
+                input_pool.flush_states();
+                self.server.send_multicast(Packet::InputStep {
+                    steps: input_pool.get_latest_steps(REDUNDANT_INPUT_STEPS)
+                }, 0)?;
+            } else if input_pool.curr_gen == 0 && input_pool.is_max_delay_exceeded() {
+                for id in delayed_players.into_iter() {
+                    println!("Player ^{} failed to send first input state in time. Terminating connection...", id);
+                    self.disconnect_player(id, DisconnectReason::Timeout)?;
+                }
+            }
+        }
+        if let Some(input_pool) = self.input_pool.as_mut() {
+            input_pool.update_states();
+        }
+        self.review_sync_states()?;
+        let timed_out_players = self.desync_investigation.update();
+        self.disconnect_players(timed_out_players, DisconnectReason::Desync)
+    }
+}
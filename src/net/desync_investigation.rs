@@ -0,0 +1,197 @@
+use std::{collections::{HashMap, HashSet}, fs::File, io::{self, Write}, path::Path, time::{Instant, SystemTime, UNIX_EPOCH}};
+use crate::{DIAGNOSTICS_LOG_PATH, packet::Packet, state_digest::{DigestAssembler, DigestMismatch, StateDigestPart}, sync_checker::SyncState};
+
+// Desynced players are kept connected until their digests arrived, or this many seconds passed
+pub const DIGEST_REQUEST_TIMEOUT: f32 = 10.0;
+
+// Bisects a desync in two round trips: First, the reference and suspect players send the hashes
+// of all their buffered digests, which reveals the first diverging generation. Then both send the
+// full digest of that generation, which is diffed entity by entity and field by field.
+pub struct DesyncInvestigation {
+    reference_id: Option<u16>,
+    suspects: HashMap<u16, Instant>,
+    summaries: HashMap<u16, Vec<SyncState>>,
+    target_gens: HashMap<u16, u64>,
+    digests: HashMap<(u16, u64), DigestAssembler>,
+    requests: HashSet<(u16, Option<u64>)>
+}
+
+impl DesyncInvestigation {
+    pub fn new() -> DesyncInvestigation {
+        DesyncInvestigation {
+            reference_id: None, suspects: HashMap::new(), summaries: HashMap::new(),
+            target_gens: HashMap::new(), digests: HashMap::new(), requests: HashSet::new()
+        }
+    }
+
+    pub fn is_suspect(&self, id: u16) -> bool {
+        self.suspects.contains_key(&id)
+    }
+
+    // Returns the digest requests to send, as (receiver, packet)
+    pub fn investigate(&mut self, reference_id: u16, desynced_players: Vec<u16>) -> Vec<(u16, Packet)> {
+        if self.suspects.is_empty() {
+            self.reference_id = Some(reference_id);
+        }
+        let reference_id = self.reference_id.unwrap();
+        let mut requests = Vec::new();
+        for id in desynced_players.into_iter() {
+            if id == reference_id || self.is_suspect(id) {
+                continue
+            }
+            println!("Investigating desync of ^{} against ^{}...", id, reference_id);
+            self.suspects.insert(id, Instant::now());
+            self.request(id, None, &mut requests);
+            self.request(reference_id, None, &mut requests);
+        }
+        requests
+    }
+
+    // Returns the digest requests to send. Suspects without a diverging gen are cleared.
+    pub fn add_summary(&mut self, sender: u16, states: Vec<SyncState>) -> Vec<(u16, Packet)> {
+        let mut requests = Vec::new();
+        // Only what was asked for is stored, so nobody can fill up the host's memory
+        if !self.requests.contains(&(sender, None)) {
+            println!("Dropped digest summary of ^{}. Reason: Not requested.", sender);
+            return requests
+        }
+        self.summaries.insert(sender, states);
+        let reference_id = match self.reference_id {
+            Some(reference_id) => reference_id,
+            None => return requests
+        };
+        let pending_suspects = self.suspects.keys()
+            .filter(|id| !self.target_gens.contains_key(id))
+            .copied().collect::<Vec<_>>();
+        for id in pending_suspects.into_iter() {
+            let (reference_states, suspect_states) = match (self.summaries.get(&reference_id),
+                self.summaries.get(&id)) {
+                (Some(reference_states), Some(suspect_states)) => (reference_states, suspect_states),
+                _ => continue
+            };
+            match find_first_diverging_gen(reference_states, suspect_states) {
+                Some(gen) => {
+                    println!("Digests of ^{} first diverged at gen {}. Requesting full digests...", id, gen);
+                    self.target_gens.insert(id, gen);
+                    self.request(id, Some(gen), &mut requests);
+                    self.request(reference_id, Some(gen), &mut requests);
+                },
+                None => {
+                    // Inconclusive, which is no reason to disconnect them
+                    println!("Digests of ^{} did not diverge in any buffered gen. Clearing suspect...", id);
+                    self.remove_suspect(id);
+                }
+            }
+        }
+        requests
+    }
+
+    // Returns all suspects whose investigation is completed
+    pub fn add_digest_part(&mut self, sender: u16, part: StateDigestPart) -> Vec<u16> {
+        if !self.requests.contains(&(sender, Some(part.gen))) {
+            println!("Dropped digest part of ^{} for gen {}. Reason: Not requested.", sender, part.gen);
+            return vec![]
+        }
+        self.digests.entry((sender, part.gen))
+            .or_insert_with(|| DigestAssembler::new(part.gen))
+            .add_part(part);
+
+        let reference_id = match self.reference_id {
+            Some(reference_id) => reference_id,
+            None => return vec![]
+        };
+        let mut finished_suspects = Vec::new();
+        for (&id, &gen) in self.target_gens.iter() {
+            let reference_digest = self.digests.get(&(reference_id, gen)).and_then(|d| d.get_digest());
+            let suspect_digest = self.digests.get(&(id, gen)).and_then(|d| d.get_digest());
+            if let (Some(reference_digest), Some(suspect_digest)) = (reference_digest, suspect_digest) {
+                let mismatch = reference_digest.diff(suspect_digest);
+                report_mismatch(id, reference_id, gen, mismatch);
+                finished_suspects.push(id);
+            }
+        }
+        for id in finished_suspects.iter() {
+            self.remove_suspect(*id);
+        }
+        finished_suspects
+    }
+
+    // Returns all suspects that failed to send their digests in time
+    pub fn update(&mut self) -> Vec<u16> {
+        let timed_out_suspects = self.suspects.iter()
+            .filter(|(_, t)| t.elapsed().as_secs_f32() >= DIGEST_REQUEST_TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in timed_out_suspects.iter() {
+            println!("^{} failed to send their state digests in time.", id);
+            self.remove_suspect(*id);
+        }
+        timed_out_suspects
+    }
+
+    // Without a reference, the investigation of all remaining suspects is cancelled
+    pub fn remove_player(&mut self, id: u16) -> Vec<u16> {
+        if self.reference_id == Some(id) {
+            let suspects = self.suspects.keys().copied().collect::<Vec<_>>();
+            println!("Reference player ^{} left. Cancelling desync investigation...", id);
+            *self = Self::new();
+            suspects
+        } else {
+            self.remove_suspect(id);
+            vec![]
+        }
+    }
+
+    fn remove_suspect(&mut self, id: u16) {
+        self.suspects.remove(&id);
+        self.summaries.remove(&id);
+        self.target_gens.remove(&id);
+        self.digests.retain(|(sender, _), _| *sender != id);
+        self.requests.retain(|(receiver, _)| *receiver != id);
+        if self.suspects.is_empty() {
+            *self = Self::new();
+        }
+    }
+
+    fn request(&mut self, id: u16, gen: Option<u64>, requests: &mut Vec<(u16, Packet)>) {
+        if self.requests.insert((id, gen)) {
+            requests.push((id, Packet::DigestRequest {
+                gen
+            }));
+        }
+    }
+}
+
+impl Default for DesyncInvestigation {
+    fn default() -> Self {
+        DesyncInvestigation::new()
+    }
+}
+
+fn find_first_diverging_gen(reference_states: &[SyncState], suspect_states: &[SyncState]) -> Option<u64> {
+    reference_states.iter()
+        .filter_map(|reference_state| suspect_states.iter()
+            .find(|s| s.t == reference_state.t && s.hash != reference_state.hash))
+        .map(|s| s.t)
+        .min()
+}
+
+fn report_mismatch(suspect_id: u16, reference_id: u16, gen: u64, mismatch: Option<DigestMismatch>) {
+    let report = match mismatch {
+        Some(mismatch) => format!("Desync of ^{} against ^{}: {}.", suspect_id, reference_id, mismatch),
+        None => format!("Desync of ^{} against ^{}: Digests of gen {} hash differently, but no field differs.",
+            suspect_id, reference_id, gen)
+    };
+    println!("{}", report);
+    if let Err(e) = save_report(suspect_id, &report) {
+        println!("Failed to save desync report. Reason: {}", e);
+    }
+}
+
+fn save_report(suspect_id: u16, report: &str) -> io::Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let mut file = File::create(Path::new(DIAGNOSTICS_LOG_PATH)
+        .join(format!("desync-{}-{}.txt", suspect_id, timestamp)))?;
+    writeln!(&mut file, "{}", report)?;
+    file.flush()
+}
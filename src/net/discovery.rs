@@ -0,0 +1,226 @@
+use std::{collections::HashMap, io::ErrorKind, net::{Ipv4Addr, SocketAddr, UdpSocket}, time::{Duration, Instant}};
+use binary_stream::{BinaryStream, Serializable};
+use crate::{BbError, BbResult, decode::{Decode, DecodeStream, decode_error}, game_settings::GameMode, version::GameVersion};
+
+pub const DISCOVERY_PORT: u16 = 22080;
+// Discovery datagrams don't go through laminar, so they are told apart by a magic prefix
+const DISCOVERY_REQUEST_MAGIC: &[u8] = b"BBDQ";
+const DISCOVERY_RESPONSE_MAGIC: &[u8] = b"BBDR";
+const DISCOVERY_INTERVAL: f32 = 2.0;
+const DISCOVERY_TIMEOUT: f32 = 5.0; // Lobbies that stopped answering are dropped from the list
+const MAX_DISCOVERY_DATAGRAM_SIZE: usize = 512;
+// Requests are padded to the largest response, so that spoofed ones can't be used to flood
+// a victim with more than the spoofer sent. Responses that don't fit are never sent.
+const DISCOVERY_REQUEST_SIZE: usize = MAX_DISCOVERY_DATAGRAM_SIZE;
+
+// What a host tells the local network about its lobby
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyInfo {
+    pub name: String,
+    pub port: u16, // Game port, which differs from the discovery port
+    pub version: GameVersion,
+    pub mode: GameMode,
+    pub players: u16,
+    pub max_players: u16,
+    pub password: bool,
+    pub open: bool // False while locked or during a match
+}
+
+impl LobbyInfo {
+    pub fn is_joinable(&self) -> bool {
+        self.open && self.players < self.max_players && self.version.is_compatible(&GameVersion::local())
+    }
+}
+
+impl Serializable for LobbyInfo {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        self.version.to_stream(stream); // First, so other versions can still be listed
+        stream.write_string(&self.name).unwrap();
+        stream.write_u16(self.port).unwrap();
+        self.mode.to_stream(stream);
+        stream.write_u16(self.players).unwrap();
+        stream.write_u16(self.max_players).unwrap();
+        stream.write_bool(self.password).unwrap();
+        stream.write_bool(self.open).unwrap();
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for LobbyInfo {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let version = GameVersion::decode(stream)?;
+        Ok(LobbyInfo {
+            version,
+            name: stream.decode_string()?,
+            port: stream.decode_u16()?,
+            mode: GameMode::decode(stream)?,
+            players: stream.decode_u16()?,
+            max_players: stream.decode_u16()?,
+            password: stream.decode_bool()?,
+            open: stream.decode_bool()?
+        })
+    }
+}
+
+pub fn serialize_lobby_info(info: &LobbyInfo) -> Vec<u8> {
+    let mut stream = BinaryStream::new();
+    stream.write_buffer(&DISCOVERY_RESPONSE_MAGIC.to_vec()).unwrap();
+    info.to_stream(&mut stream);
+    stream.get_buffer_vec()
+}
+
+pub fn gen_discovery_request() -> Vec<u8> {
+    let mut request = DISCOVERY_REQUEST_MAGIC.to_vec();
+    request.resize(DISCOVERY_REQUEST_SIZE, 0);
+    request
+}
+
+pub fn deserialize_lobby_info(bytes: &[u8]) -> BbResult<LobbyInfo> {
+    if !bytes.starts_with(DISCOVERY_RESPONSE_MAGIC) {
+        return Err(decode_error("Not a discovery response"))
+    }
+    LobbyInfo::decode(&mut BinaryStream::from_bytes(&bytes[DISCOVERY_RESPONSE_MAGIC.len()..]))
+}
+
+// Owned by a hosting server. Fails softly, as only one host per machine can take the port.
+pub struct DiscoveryResponder {
+    socket: UdpSocket
+}
+
+impl DiscoveryResponder {
+    pub fn bind(port: u16) -> Option<DiscoveryResponder> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+            .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+        match socket {
+            Ok(socket) => Some(DiscoveryResponder {
+                socket
+            }),
+            Err(e) => {
+                println!("Server: LAN discovery is unavailable on port {}. Reason: {}", port, e);
+                None
+            }
+        }
+    }
+
+    // Answers all pending requests, the lobby info is only gathered if anyone asked
+    pub fn answer(&self, get_info: impl Fn() -> LobbyInfo) -> BbResult {
+        let mut buffer = [0; MAX_DISCOVERY_DATAGRAM_SIZE];
+        let mut response = None;
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, addr)) => {
+                    if len < DISCOVERY_REQUEST_SIZE || !buffer.starts_with(DISCOVERY_REQUEST_MAGIC) {
+                        continue
+                    }
+                    let response = response.get_or_insert_with(|| serialize_lobby_info(&get_info()));
+                    if response.len() > len {
+                        continue
+                    }
+                    if let Err(e) = self.socket.send_to(response, addr) {
+                        println!("Server: Failed to answer discovery request of {}. Reason: {}", addr, e);
+                    }
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                // Windows reports unreachable browsers on the next receive
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(BbError::Io(e))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredLobby {
+    pub addr: SocketAddr, // Where to join
+    pub info: LobbyInfo,
+    last_seen: Instant
+}
+
+// Broadcasts requests on the local network and collects the answering lobbies
+pub struct DiscoveryBrowser {
+    socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    request: Vec<u8>,
+    lobbies: HashMap<SocketAddr, DiscoveredLobby>,
+    last_request_time: Option<Instant>
+}
+
+impl DiscoveryBrowser {
+    pub fn new(discovery_port: u16) -> BbResult<DiscoveryBrowser> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(BbError::Io)?;
+        socket.set_nonblocking(true).map_err(BbError::Io)?;
+        socket.set_broadcast(true).map_err(BbError::Io)?;
+        // Broadcasts don't always loop back, so hosts on this machine are asked directly
+        let targets = vec![
+            SocketAddr::from((Ipv4Addr::BROADCAST, discovery_port)),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, discovery_port))
+        ];
+        Ok(DiscoveryBrowser {
+            socket, targets, request: gen_discovery_request(), lobbies: HashMap::new(), last_request_time: None
+        })
+    }
+
+    // Returns whether the list of lobbies changed
+    pub fn update(&mut self) -> BbResult<bool> {
+        let now = Instant::now();
+        if self.last_request_time.is_none_or(|time| now - time
+            >= Duration::from_secs_f32(DISCOVERY_INTERVAL)) {
+            self.last_request_time = Some(now);
+            for target in self.targets.iter() {
+                // No network or no broadcast permission, asking the others may still work
+                if let Err(e) = self.socket.send_to(&self.request, target) {
+                    println!("Failed to send discovery request to {}. Reason: {}", target, e);
+                }
+            }
+        }
+
+        let mut changed = false;
+        let mut buffer = [0; MAX_DISCOVERY_DATAGRAM_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, sender)) => match deserialize_lobby_info(&buffer[..len]) {
+                    Ok(info) => changed |= self.add_lobby(sender, info, now),
+                    Err(e) => println!("Ignoring invalid discovery response from {}. Reason: {}", sender, e)
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(BbError::Io(e))
+            }
+        }
+
+        let count = self.lobbies.len();
+        self.lobbies.retain(|_, lobby| now - lobby.last_seen
+            < Duration::from_secs_f32(DISCOVERY_TIMEOUT));
+        Ok(changed || self.lobbies.len() < count)
+    }
+
+    // A host on this machine answers both on loopback and its network address, keep the latter
+    fn add_lobby(&mut self, sender: SocketAddr, info: LobbyInfo, now: Instant) -> bool {
+        let addr = SocketAddr::new(sender.ip(), info.port);
+        let is_same_host = |lobby: &DiscoveredLobby| lobby.addr.port() == addr.port()
+            && lobby.info.name == info.name;
+        if addr.ip().is_loopback() {
+            if self.lobbies.values().any(|lobby| !lobby.addr.ip().is_loopback() && is_same_host(lobby)) {
+                return false
+            }
+        } else {
+            self.lobbies.retain(|_, lobby| !(lobby.addr.ip().is_loopback() && is_same_host(lobby)));
+        }
+
+        let changed = self.lobbies.get(&addr).is_none_or(|lobby| lobby.info != info);
+        self.lobbies.insert(addr, DiscoveredLobby {
+            addr, info, last_seen: now
+        });
+        changed
+    }
+
+    // Sorted by name, so the list doesn't jump around
+    pub fn get_lobbies(&self) -> Vec<&DiscoveredLobby> {
+        let mut lobbies = self.lobbies.values().collect::<Vec<_>>();
+        lobbies.sort_by(|a, b| a.info.name.cmp(&b.info.name).then(a.addr.cmp(&b.addr)));
+        lobbies
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, time::Instant};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Each address may burst a few datagrams before it is connected, then a few a second.
+// Handshakes, challenge answers and migrations stay well below that.
+pub const UNKNOWN_SENDER_BURST: f32 = 8.0;
+pub const UNKNOWN_SENDER_REFILL_RATE: f32 = 4.0;
+// Beyond this, only senders whose bucket ran dry are remembered
+const MAX_TRACKED_SENDERS: usize = 4096;
+// Cookies stay valid for the interval they were handed out in and the one after
+pub const COOKIE_INTERVAL: f32 = 10.0;
+// Key of the HMAC-SHA256 cookies, as long as its output
+const COOKIE_SECRET_LEN: usize = 32;
+// Password challenges the server waits on at once
+pub const MAX_PENDING_HANDSHAKES: usize = 32;
+// Drops are summed up in the log at most this often
+const FLOOD_REPORT_INTERVAL: f32 = 10.0;
+
+// Datagrams dropped since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FloodStats {
+    pub rate_limited: u64,
+    pub invalid_cookies: u64,
+    pub pending_handshakes_full: u64
+}
+
+impl FloodStats {
+    pub fn get_total(&self) -> u64 {
+        self.rate_limited + self.invalid_cookies + self.pending_handshakes_full
+    }
+}
+
+struct SenderBucket {
+    tokens: f32,
+    last_refill_time: Instant
+}
+
+// Guards the server against unknown senders. Nothing is stored for a handshake until it
+// comes back with the cookie for its address, which spoofed addresses never receive.
+pub struct FloodGuard {
+    secret: [u8; COOKIE_SECRET_LEN],
+    start_time: Instant,
+    senders: HashMap<IpAddr, SenderBucket>, // By IP, so that new ports don't get a new bucket
+    stats: FloodStats,
+    reported_stats: FloodStats,
+    last_report_time: Instant
+}
+
+impl FloodGuard {
+    pub fn new() -> FloodGuard {
+        let now = Instant::now();
+        FloodGuard {
+            secret: rand::random(), start_time: now, senders: HashMap::new(),
+            stats: FloodStats::default(), reported_stats: FloodStats::default(), last_report_time: now
+        }
+    }
+
+    // Returns false if the datagram has to be dropped
+    pub fn allow(&mut self, addr: SocketAddr, now: Instant) -> bool {
+        if self.senders.len() >= MAX_TRACKED_SENDERS && !self.senders.contains_key(&addr.ip()) {
+            // Buckets that refilled are no different from new ones
+            self.senders.retain(|_, sender| sender.tokens + (now - sender.last_refill_time).as_secs_f32()
+                * UNKNOWN_SENDER_REFILL_RATE < UNKNOWN_SENDER_BURST);
+            if self.senders.len() >= MAX_TRACKED_SENDERS {
+                self.stats.rate_limited += 1;
+                return false
+            }
+        }
+        let sender = self.senders.entry(addr.ip()).or_insert(SenderBucket {
+            tokens: UNKNOWN_SENDER_BURST, last_refill_time: now
+        });
+        sender.tokens = (sender.tokens + (now - sender.last_refill_time).as_secs_f32()
+            * UNKNOWN_SENDER_REFILL_RATE).min(UNKNOWN_SENDER_BURST);
+        sender.last_refill_time = now;
+        if sender.tokens < 1.0 {
+            self.stats.rate_limited += 1;
+            return false
+        }
+        sender.tokens -= 1.0;
+        true
+    }
+
+    pub fn gen_cookie(&self, addr: SocketAddr, now: Instant) -> u64 {
+        self.gen_cookie_at(addr, self.get_interval(now))
+    }
+
+    // Handshakes without a cookie are only asked for one. Wrong cookies are counted.
+    pub fn check_cookie(&mut self, addr: SocketAddr, cookie: Option<u64>, now: Instant) -> bool {
+        let cookie = match cookie {
+            Some(cookie) => cookie,
+            None => return false
+        };
+        let interval = self.get_interval(now);
+        if cookie == self.gen_cookie_at(addr, interval)
+            || (interval > 0 && cookie == self.gen_cookie_at(addr, interval - 1)) {
+            true
+        } else {
+            self.stats.invalid_cookies += 1;
+            false
+        }
+    }
+
+    pub fn on_pending_handshakes_full(&mut self) {
+        self.stats.pending_handshakes_full += 1;
+    }
+
+    pub fn get_stats(&self) -> FloodStats {
+        self.stats
+    }
+
+    // What was dropped since the last report, once the report interval passed
+    pub fn take_report(&mut self, now: Instant) -> Option<String> {
+        if self.stats.get_total() == self.reported_stats.get_total()
+            || (now - self.last_report_time).as_secs_f32() < FLOOD_REPORT_INTERVAL {
+            return None
+        }
+        let report = format!("Flood protection dropped {} datagram(s) in the last {:.0}s \
+            (rate limited: {}, invalid cookies: {}, pending handshakes full: {}).",
+            self.stats.get_total() - self.reported_stats.get_total(),
+            (now - self.last_report_time).as_secs_f32(),
+            self.stats.rate_limited - self.reported_stats.rate_limited,
+            self.stats.invalid_cookies - self.reported_stats.invalid_cookies,
+            self.stats.pending_handshakes_full - self.reported_stats.pending_handshakes_full);
+        self.reported_stats = self.stats;
+        self.last_report_time = now;
+        Some(report)
+    }
+
+    fn get_interval(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start_time).as_secs_f32() / COOKIE_INTERVAL) as u64
+    }
+
+    // A MAC rather than a plain hash, so that the cookies of addresses an attacker owns
+    // tell nothing about the cookies of any other address
+    fn gen_cookie_at(&self, addr: SocketAddr, interval: u64) -> u64 {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(&interval.to_le_bytes());
+        match addr.ip() {
+            IpAddr::V4(ip) => mac.update(&ip.octets()),
+            IpAddr::V6(ip) => mac.update(&ip.octets())
+        }
+        mac.update(&addr.port().to_le_bytes());
+        let tag = mac.finalize().into_bytes();
+        u64::from_le_bytes([tag[0], tag[1], tag[2], tag[3], tag[4], tag[5], tag[6], tag[7]])
+    }
+}
+
+impl Default for FloodGuard {
+    fn default() -> Self {
+        FloodGuard::new()
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, time::{Duration, Instant}};
+use binary_stream::{BinaryStream, Serializable};
+use crate::{BbResult, ID, decode::{Decode, DecodeStream, write_socket_addr}, packet::Packet, replay::Replay};
+
+// How long the new host waits for the other peers before it resumes the match
+pub const MIGRATION_WAIT_TIME: f32 = 5.0;
+// Peers give up on a new host that never answers
+pub const MIGRATION_TIMEOUT: f32 = 15.0;
+pub const MIGRATION_RESEND_INTERVAL: f32 = 1.0;
+// Steps a peer may have received from the previous host, that the new host missed (~3 secs)
+pub const MIGRATION_STEP_WINDOW: usize = 20;
+
+// A player who could take over the match, at the address the previous host knew them by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostCandidate {
+    pub id: u16,
+    pub addr: SocketAddr
+}
+
+impl Serializable for HostCandidate {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_u16(self.id).unwrap();
+        write_socket_addr(stream, &self.addr);
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for HostCandidate {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(HostCandidate {
+            id: stream.decode_u16()?,
+            addr: stream.decode_socket_addr()?
+        })
+    }
+}
+
+// Hash of a peer's rejoin token, for the new host to tell the peer from anyone who knows its ID
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MigrationToken {
+    pub id: u16,
+    pub token_hash: u64
+}
+
+impl Serializable for MigrationToken {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_u16(self.id).unwrap();
+        stream.write_u64(self.token_hash).unwrap();
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for MigrationToken {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(MigrationToken {
+            id: stream.decode_u16()?,
+            token_hash: stream.decode_u64()?
+        })
+    }
+}
+
+// Every peer knows the same candidates, so they all agree on the lowest ID without talking
+pub fn elect_host(candidates: &[HostCandidate]) -> Option<HostCandidate> {
+    candidates.iter().min_by_key(|c| c.id).copied()
+}
+
+// Tells the new host how far the peer got with the previous one. The latest steps are
+// attached, in case the new host missed some of them. The rejoin token proves who the peer is.
+pub fn gen_migration_packet(id: &ID, rejoin_token: u64, history: &Replay) -> Packet {
+    let window_start = history.steps.len().saturating_sub(MIGRATION_STEP_WINDOW);
+    Packet::Migrate {
+        id: id.n, name: id.name.to_owned(), rejoin_token, cookie: None, gen: history.get_total_gens(),
+        steps: history.steps[window_start..].to_vec()
+    }
+}
+
+// Server side of a migration. New steps are only flushed once every remaining peer reported
+// their last gen or the wait time ran out, as any of them may still hand in missed steps.
+pub struct HostMigration {
+    expected_peers: HashSet<u16>,
+    reported_gens: HashMap<u16, u64>,
+    start_time: Instant
+}
+
+impl HostMigration {
+    pub fn new(expected_peers: Vec<u16>) -> HostMigration {
+        HostMigration {
+            expected_peers: expected_peers.into_iter().collect(), reported_gens: HashMap::new(),
+            start_time: Instant::now()
+        }
+    }
+
+    pub fn add_report(&mut self, id: u16, gen: u64) {
+        self.reported_gens.insert(id, gen);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.expected_peers.iter().all(|id| self.reported_gens.contains_key(id))
+            || self.start_time.elapsed() >= Duration::from_secs_f32(MIGRATION_WAIT_TIME)
+    }
+
+    pub fn get_missing_peers(&self) -> Vec<u16> {
+        self.expected_peers.iter()
+            .filter(|id| !self.reported_gens.contains_key(id))
+            .copied()
+            .collect()
+    }
+
+    // Peers and the last gen they received, to send them the steps they lack
+    pub fn get_reported_gens(&self) -> &HashMap<u16, u64> {
+        &self.reported_gens
+    }
+}
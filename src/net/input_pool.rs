@@ -1,40 +1,132 @@
 use std::{collections::{HashMap, HashSet}, iter::FromIterator};
-use crate::{DEFAULT_SIMULATION_TIMESTEP, packet::{InputState, InputStep}};
+use binary_stream::{BinaryStream, Serializable};
+use crate::{DEFAULT_SIMULATION_TIMESTEP, PlayerParams, game_settings::GameSettings, input_validation::{InputViolation, MAX_PURCHASES_PER_STEP, MapBounds, validate_input_state}, packet::{InputState, InputStep}, replay::Replay};
 
 pub const STEP_PHASE_FRAME_LENGTH: u32 = 3;
 pub const STEP_PHASE_TIME_SECS: f32 = STEP_PHASE_FRAME_LENGTH as f32 / DEFAULT_SIMULATION_TIMESTEP as f32;
 pub const MAX_CLIENT_STATE_SEND_DELAY: u32 = DEFAULT_SIMULATION_TIMESTEP as u32 * 15; // 15 secs
+// Input states and steps are sent unreliably, so each packet repeats the latest few of them
+pub const REDUNDANT_INPUT_STATES: usize = 3;
+pub const REDUNDANT_INPUT_STEPS: usize = 3;
+// Resent reliably at the end of a match, as lost steps can't be requested after (~1 sec)
+pub const FINAL_INPUT_STEPS: usize = 20;
 
 pub struct InputPool {
     pub curr_gen: u64,
     pub curr_frame_index: u32,
     players: HashSet<u16>,
+    suspended_players: HashSet<u16>,
     player_states: HashSet<u16>,
-    input_states: HashMap<u16, InputState>
+    input_states: HashMap<u16, InputState>,
+    purchases: HashMap<u16, u32>, // Of the current step
+    bounds: MapBounds,
+    history: Replay // Every flushed step, for players who rejoin the match
 }
 
 impl InputPool {
-    pub fn new(players: Vec<u16>) -> Self {
+    pub fn new(players: Vec<PlayerParams>, world_seed: u64, settings: GameSettings) -> Self {
         Self {
             curr_gen: 0, curr_frame_index: 0,
-            players: HashSet::from_iter(players.into_iter()),
-            player_states: HashSet::new(), input_states: HashMap::new()
+            players: HashSet::from_iter(players.iter().map(|p| p.id.n)), suspended_players: HashSet::new(),
+            player_states: HashSet::new(), input_states: HashMap::new(), purchases: HashMap::new(),
+            bounds: MapBounds::of_match(players.len() + settings.bots as usize),
+            history: Replay::new(world_seed, players, settings)
         }
     }
 
-    pub fn add_state(&mut self, sender: u16, state: InputState) {
+    // Carries on the match of a host that left, from the steps this peer received of it
+    pub fn from_history(history: Replay, players: Vec<u16>) -> Self {
+        Self {
+            curr_gen: history.get_total_gens(), curr_frame_index: 0,
+            players: HashSet::from_iter(players), suspended_players: HashSet::new(),
+            player_states: HashSet::new(), input_states: HashMap::new(), purchases: HashMap::new(),
+            bounds: MapBounds::of_match(history.players.len() + history.settings.bots as usize), history
+        }
+    }
+
+    pub fn get_history(&self) -> &Replay {
+        &self.history
+    }
+
+    // Players whose states are waited on, without those who timed out
+    pub fn get_active_players(&self) -> Vec<u16> {
+        self.players.iter().copied().collect()
+    }
+
+    pub fn get_latest_steps(&self, count: usize) -> Vec<InputStep> {
+        let start = self.history.steps.len().saturating_sub(count);
+        self.history.steps[start..].to_vec()
+    }
+
+    // Checks a state a client sent, before it may be added. Purchases count towards the step.
+    pub fn validate_state(&mut self, sender: u16, state: &InputState, in_harbour: Option<bool>)
+        -> Result<(), InputViolation> {
+        validate_input_state(state, &self.bounds, in_harbour)?;
+        if state.buy_mod {
+            let purchases = self.purchases.entry(sender).or_insert(0);
+            if *purchases >= MAX_PURCHASES_PER_STEP {
+                return Err(InputViolation::TooManyPurchases)
+            }
+            *purchases += 1;
+        }
+        Ok(())
+    }
+
+    pub fn add_state(&mut self, sender: u16, mut state: InputState) {
         self.player_states.insert(sender);
-        self.input_states.insert(sender, state); // If client sends state more than once during step, overwrite
+        // If client sends state more than once during step, overwrite. Purchases are only part of
+        // a single state though, which may arrive along with a later one.
+        if let Some(prev_state) = self.input_states.get(&sender).filter(|s| s.buy_mod && !state.buy_mod) {
+            state.buy_mod = true;
+            state.mod_type = prev_state.mod_type;
+        }
+        self.input_states.insert(sender, state);
     }
 
     pub fn remove_player(&mut self, id: u16) {
-        self.players.remove(&id);
+        // Spectators and players who already left have no ship to destroy
+        if !self.players.remove(&id) && !self.suspended_players.remove(&id) {
+            return
+        }
         self.player_states.remove(&id);
         self.add_state(id, InputState {
             disconnect: true, ..Default::default()
         });
     }
 
+    // Keeps the ship of a timed out player alive, without waiting for their states
+    pub fn suspend_player(&mut self, id: u16) {
+        if self.players.remove(&id) {
+            self.suspended_players.insert(id);
+        }
+        self.player_states.remove(&id);
+    }
+
+    pub fn resume_player(&mut self, id: u16) {
+        if self.suspended_players.remove(&id) {
+            self.players.insert(id);
+        }
+    }
+
+    // Checks the latest steps a migrating peer received against the history. New steps are
+    // only taken on while `adopt` is set. Returns false for contradicting steps or a gap.
+    pub fn merge_steps(&mut self, steps: Vec<InputStep>, adopt: bool) -> bool {
+        for step in steps.into_iter() {
+            if step.gen <= self.curr_gen {
+                let known_step = self.history.steps.iter().rev().find(|s| s.gen == step.gen);
+                if !known_step.is_some_and(|known_step| is_same_step(known_step, &step)) {
+                    return false
+                }
+            } else if step.gen == self.curr_gen + 1 && adopt {
+                self.curr_gen = step.gen;
+                self.history.add_step(step);
+            } else {
+                return false
+            }
+        }
+        true
+    }
+
     pub fn is_step_phase_over(&self) -> bool {
         self.curr_frame_index >= STEP_PHASE_FRAME_LENGTH
     }
@@ -56,10 +148,22 @@ impl InputPool {
 
     pub fn flush_states(&mut self) -> InputStep {
         self.player_states.clear();
+        self.purchases.clear();
         self.curr_frame_index = 0;
         self.curr_gen += 1;
 
-        let states = self.input_states.drain().collect::<Vec<_>>();
-        InputStep::new(states, self.curr_gen)
+        // Sorted, so clients predicting steps can compare them as they are
+        let mut states = self.input_states.drain().collect::<Vec<_>>();
+        states.sort_unstable_by_key(|(id, _)| *id);
+        let step = InputStep::new(states, self.curr_gen);
+        self.history.add_step(step.clone());
+        step
     }
 }
+
+pub fn is_same_step(a: &InputStep, b: &InputStep) -> bool {
+    let (mut stream_a, mut stream_b) = (BinaryStream::new(), BinaryStream::new());
+    a.to_stream(&mut stream_a);
+    b.to_stream(&mut stream_b);
+    stream_a.get_buffer_vec() == stream_b.get_buffer_vec()
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use crate::{V2, packet::InputState, simulation::{WORLD_BASE_TILE_SIZE, WORLD_TILE_PADDING, get_map_size}};
+
+// Purchases are sent with a single state, but two of a player's states may land in one step
+pub const MAX_PURCHASES_PER_STEP: u32 = 2;
+// Ships may sail off the edge of the map, though not by more than its own size
+const MAP_MARGIN_FACTOR: f32 = 1.0;
+// Strikes run out after a while, so that a rare glitch over a long match doesn't add up
+pub const INPUT_STRIKE_WINDOW: f32 = 30.0;
+pub const FLAG_INPUT_STRIKES: u32 = 3; // The host is told about the player
+pub const MAX_INPUT_STRIKES: u32 = 8; // The player is kicked
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputViolation {
+    InvalidPosition, // Not a number or infinite
+    OutOfBounds,
+    ForgedDisconnect, // Only the server removes ships, clients leave with a disconnect packet
+    TooManyPurchases,
+    PurchaseOutsideHarbour
+}
+
+impl fmt::Display for InputViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputViolation::InvalidPosition => write!(f, "Invalid target position"),
+            InputViolation::OutOfBounds => write!(f, "Target position far outside the map"),
+            InputViolation::ForgedDisconnect => write!(f, "Forged disconnect"),
+            InputViolation::TooManyPurchases => write!(f, "Too many purchases at once"),
+            InputViolation::PurchaseOutsideHarbour => write!(f, "Purchase outside a harbour")
+        }
+    }
+}
+
+impl InputViolation {
+    // Honest clients never send these. Purchases may be checked against a host simulation a
+    // few gens off, e.g. while leaving a harbour, so only the purchase itself is dropped.
+    pub fn is_forged(&self) -> bool {
+        !matches!(self, InputViolation::TooManyPurchases | InputViolation::PurchaseOutsideHarbour)
+    }
+}
+
+// Area that target positions have to lie in, the map plus its margin on every side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapBounds {
+    pub min: V2,
+    pub max: V2
+}
+
+impl MapBounds {
+    // Same size the simulation generates the world with
+    pub fn of_match(participants: usize) -> MapBounds {
+        let extent = get_map_size(participants) as f32 * WORLD_BASE_TILE_SIZE * WORLD_TILE_PADDING;
+        MapBounds {
+            min: V2::one() * -extent * MAP_MARGIN_FACTOR,
+            max: V2::one() * extent * (1.0 + MAP_MARGIN_FACTOR)
+        }
+    }
+
+    pub fn contains(&self, pos: V2) -> bool {
+        pos.x >= self.min.x && pos.y >= self.min.y && pos.x <= self.max.x && pos.y <= self.max.y
+    }
+}
+
+// Checks a state on its own. The harbour is only known to servers that run the simulation.
+pub fn validate_input_state(state: &InputState, bounds: &MapBounds, in_harbour: Option<bool>)
+    -> Result<(), InputViolation> {
+    if let Some(pos) = state.mouse_pos {
+        if !pos.x.is_finite() || !pos.y.is_finite() {
+            return Err(InputViolation::InvalidPosition)
+        }
+        if !bounds.contains(pos) {
+            return Err(InputViolation::OutOfBounds)
+        }
+    }
+    if state.disconnect {
+        return Err(InputViolation::ForgedDisconnect)
+    }
+    if state.buy_mod && in_harbour == Some(false) {
+        return Err(InputViolation::PurchaseOutsideHarbour)
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::time::Instant;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Answers that take longer are dropped, the client has to send a new handshake
+pub const CHALLENGE_TIMEOUT: f32 = 10.0;
+// HMAC-SHA256 tag
+pub const PASSWORD_PROOF_LEN: usize = 32;
+
+pub type PasswordProof = [u8; PASSWORD_PROOF_LEN];
+
+// Handshake to a password-protected lobby, waiting for the answer to its challenge
+pub struct PendingChallenge {
+    pub name: String,
+    pub spectator: bool,
+    pub rejoin_token: Option<u64>,
+    pub salt: u64,
+    pub sent_at: Instant
+}
+
+impl PendingChallenge {
+    pub fn is_expired(&self) -> bool {
+        self.sent_at.elapsed().as_secs_f32() >= CHALLENGE_TIMEOUT
+    }
+}
+
+// The password itself is never sent, only an HMAC keyed by it. The server's salt is new for
+// every handshake, so a captured proof can't be replayed to join later. The client's nonce
+// keeps a server from choosing the whole message it gets a tag for.
+pub fn gen_password_proof(salt: u64, nonce: u64, password: &str) -> PasswordProof {
+    gen_password_mac(salt, nonce, password).finalize().into_bytes().into()
+}
+
+// Compares in constant time, so the timing of refusals gives nothing away
+pub fn check_password_proof(salt: u64, nonce: u64, password: &str, proof: &PasswordProof) -> bool {
+    gen_password_mac(salt, nonce, password).verify_slice(proof).is_ok()
+}
+
+fn gen_password_mac(salt: u64, nonce: u64, password: &str) -> Hmac<Sha256> {
+    // Keys of any length are accepted
+    let mut mac = Hmac::<Sha256>::new_from_slice(password.as_bytes()).unwrap();
+    mac.update(&salt.to_le_bytes());
+    mac.update(&nonce.to_le_bytes());
+    mac
+}
@@ -0,0 +1,491 @@
+use std::{cmp::Reverse, collections::HashMap, io::ErrorKind, net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket}, thread, time::{Duration, Instant}};
+use binary_stream::{BinaryStream, Serializable};
+use crate::{BbError, BbResult, decode::{Decode, DecodeStream, decode_error, unknown_index, write_socket_addr}, discovery::LobbyInfo, flood_protection::FloodGuard, rand_u64, transport::Transport};
+
+pub const DEFAULT_MASTER_SERVER_PORT: u16 = 22079;
+// There is no public master server yet, so everyone runs their own
+pub const DEFAULT_MASTER_SERVER_ADDR: &str = "127.0.0.1:22079";
+// Like discovery, these datagrams bypass laminar and are told apart by their prefix
+const MASTER_MAGIC: &[u8] = b"BBMS";
+const PING_MAGIC: &[u8] = b"BBPI";
+const PONG_MAGIC: &[u8] = b"BBPO";
+const HEARTBEAT_INTERVAL: f32 = 10.0;
+const REGISTRATION_TIMEOUT: f32 = 35.0; // Three missed heartbeats
+const QUERY_INTERVAL: f32 = 5.0;
+const LISTING_TIMEOUT: f32 = 12.0; // Servers the master stopped listing are dropped by browsers
+const MAX_SERVERS_PER_IP: usize = 8;
+const SERVERS_PER_LIST_DATAGRAM: usize = 8; // Keeps list datagrams below common MTUs
+pub const MAX_LOBBY_NAME_LENGTH: usize = 32; // Characters, longer names are cut off
+const MAX_MASTER_DATAGRAM_SIZE: usize = 2048;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedServer {
+    pub addr: SocketAddr, // Game port of the host
+    pub info: LobbyInfo
+}
+
+impl Serializable for ListedServer {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        write_socket_addr(stream, &self.addr);
+        self.info.to_stream(stream);
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for ListedServer {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(ListedServer {
+            addr: stream.decode_socket_addr()?,
+            info: LobbyInfo::decode(stream)?
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MasterMessage {
+    Heartbeat { info: LobbyInfo }, // Host to master, registers the lobby at the sender's IP
+    Unregister { port: u16 }, // Host to master, when shutting down
+    Query { cookie: Option<u64> }, // Browser to master, only answered with a list given a cookie
+    ServerList { servers: Vec<ListedServer> }, // Master to browser, split into several datagrams
+    QueryCookie { cookie: u64 } // Master to browser, proves the browser owns its address
+}
+
+impl Serializable for MasterMessage {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        match self {
+            MasterMessage::Heartbeat { info } => {
+                stream.write_buffer_single(0).unwrap();
+                info.to_stream(stream);
+            },
+            MasterMessage::Unregister { port } => {
+                stream.write_buffer_single(1).unwrap();
+                stream.write_u16(*port).unwrap();
+            },
+            MasterMessage::Query { cookie } => {
+                stream.write_buffer_single(2).unwrap();
+                stream.write_bool(cookie.is_some()).unwrap();
+                if let Some(cookie) = cookie {
+                    stream.write_u64(*cookie).unwrap();
+                }
+            },
+            MasterMessage::ServerList { servers } => {
+                stream.write_buffer_single(3).unwrap();
+                stream.write_u32(servers.len() as u32).unwrap();
+                for server in servers.iter() {
+                    server.to_stream(stream);
+                }
+            },
+            MasterMessage::QueryCookie { cookie } => {
+                stream.write_buffer_single(4).unwrap();
+                stream.write_u64(*cookie).unwrap();
+            }
+        }
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for MasterMessage {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
+            0 => MasterMessage::Heartbeat { info: LobbyInfo::decode(stream)? },
+            1 => MasterMessage::Unregister { port: stream.decode_u16()? },
+            2 => MasterMessage::Query {
+                cookie: match stream.decode_bool()? {
+                    true => Some(stream.decode_u64()?),
+                    false => None
+                }
+            },
+            3 => MasterMessage::ServerList { servers: stream.decode_vec()? },
+            4 => MasterMessage::QueryCookie { cookie: stream.decode_u64()? },
+            n @ _ => return Err(unknown_index(n, "master message"))
+        })
+    }
+}
+
+pub fn serialize_master_message(message: &MasterMessage) -> Vec<u8> {
+    let mut stream = BinaryStream::new();
+    stream.write_buffer(&MASTER_MAGIC.to_vec()).unwrap();
+    message.to_stream(&mut stream);
+    stream.get_buffer_vec()
+}
+
+pub fn deserialize_master_message(bytes: &[u8]) -> BbResult<MasterMessage> {
+    if !bytes.starts_with(MASTER_MAGIC) {
+        return Err(decode_error("Not a master server message"))
+    }
+    MasterMessage::decode(&mut BinaryStream::from_bytes(&bytes[MASTER_MAGIC.len()..]))
+}
+
+// Browsers measure their ping on the game port, which is the only port hosts have to open.
+// The game socket answers these itself, so they never reach laminar.
+pub fn answer_ping(socket: &mut dyn Transport, payload: &[u8], addr: SocketAddr) -> bool {
+    if !payload.starts_with(PING_MAGIC) {
+        return false
+    }
+    let mut pong = PONG_MAGIC.to_vec();
+    pong.extend_from_slice(&payload[PING_MAGIC.len()..]);
+    let _ = socket.send_to(&pong, addr); // The browser simply pings again
+    true
+}
+
+fn resolve(addr: &str) -> BbResult<SocketAddr> {
+    addr.to_socket_addrs().map_err(BbError::Io)?
+        .next()
+        .ok_or_else(|| BbError::Io(std::io::Error::new(ErrorKind::NotFound,
+            format!("No address found for {}", addr))))
+}
+
+fn bind_nonblocking(port: u16) -> BbResult<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(BbError::Io)?;
+    socket.set_nonblocking(true).map_err(BbError::Io)?;
+    Ok(socket)
+}
+
+struct Registration {
+    info: LobbyInfo,
+    last_heartbeat_time: Instant,
+    verified: bool // Whether the game port answered the master's ping
+}
+
+// Standalone registry of public matches. Hosts keep their entry alive with heartbeats,
+// browsers query the whole list. Lists are only sent to addresses that answered with a
+// cookie, and lobbies only listed once their game port answered a ping, so neither can be
+// pointed at someone else.
+pub struct MasterServer {
+    socket: UdpSocket,
+    servers: HashMap<SocketAddr, Registration>,
+    flood_guard: FloodGuard
+}
+
+impl MasterServer {
+    pub fn bind(port: u16) -> BbResult<MasterServer> {
+        println!("Master server: Listening at {}.", port);
+        Ok(MasterServer {
+            socket: bind_nonblocking(port)?, servers: HashMap::new(), flood_guard: FloodGuard::new()
+        })
+    }
+
+    pub fn get_local_port(&self) -> BbResult<u16> {
+        Ok(self.socket.local_addr().map_err(BbError::Io)?.port())
+    }
+
+    pub fn get_servers(&self) -> Vec<ListedServer> {
+        self.servers.iter()
+            .filter(|(_, registration)| registration.verified)
+            .map(|(addr, registration)| ListedServer {
+                addr: *addr, info: registration.info.clone()
+            })
+            .collect()
+    }
+
+    pub fn run(&mut self) -> BbResult {
+        loop {
+            self.poll()?;
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    // Handles every pending datagram and drops hosts that stopped sending heartbeats
+    pub fn poll(&mut self) -> BbResult {
+        let mut buffer = [0; MAX_MASTER_DATAGRAM_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, sender)) if buffer[..len].starts_with(PONG_MAGIC) => self.handle_pong(&buffer[..len], sender),
+                Ok((len, sender)) => match deserialize_master_message(&buffer[..len]) {
+                    Ok(message) => self.handle_message(message, sender),
+                    Err(e) => println!("Master server: Ignoring invalid message from {}. Reason: {}", sender, e)
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(BbError::Io(e))
+            }
+        }
+
+        let now = Instant::now();
+        if let Some(report) = self.flood_guard.take_report(now) {
+            println!("Master server: {}", report);
+        }
+        self.servers.retain(|addr, registration| {
+            let alive = now - registration.last_heartbeat_time < Duration::from_secs_f32(REGISTRATION_TIMEOUT);
+            if !alive && registration.verified {
+                println!("Master server: {} ({}) timed out.", registration.info.name, addr);
+            }
+            alive
+        });
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: MasterMessage, sender: SocketAddr) {
+        match message {
+            MasterMessage::Heartbeat { mut info } => {
+                // Registered at the sender's IP, so nobody can list someone else's machine
+                let addr = SocketAddr::new(sender.ip(), info.port);
+                if !self.servers.contains_key(&addr)
+                    && self.count_servers_at(sender.ip()) >= MAX_SERVERS_PER_IP {
+                    println!("Master server: Refused {}, too many servers at that IP.", addr);
+                    return
+                }
+                info.name = info.name.chars().take(MAX_LOBBY_NAME_LENGTH).collect();
+                let now = Instant::now();
+                let verified = self.servers.get(&addr).is_some_and(|registration| registration.verified);
+                self.servers.insert(addr, Registration {
+                    info, last_heartbeat_time: now, verified
+                });
+                if !verified {
+                    // Answered from the game port, which the sender can't fake for a port
+                    // nobody listens at. No bigger than the heartbeat that caused it.
+                    let mut ping = PING_MAGIC.to_vec();
+                    ping.extend_from_slice(&self.flood_guard.gen_cookie(addr, now).to_le_bytes());
+                    self.send(&ping, addr);
+                }
+            },
+            MasterMessage::Unregister { port } => {
+                if let Some(registration) = self.servers.remove(&SocketAddr::new(sender.ip(), port)) {
+                    println!("Master server: {} ({}:{}) unregistered.", registration.info.name,
+                        sender.ip(), port);
+                }
+            },
+            MasterMessage::Query { cookie } => {
+                let now = Instant::now();
+                if !self.flood_guard.allow(sender, now) {
+                    return
+                }
+                if !self.flood_guard.check_cookie(sender, cookie, now) {
+                    let cookie = self.flood_guard.gen_cookie(sender, now);
+                    self.send(&serialize_master_message(&MasterMessage::QueryCookie { cookie }), sender);
+                    return
+                }
+                let servers = self.get_servers();
+                let mut chunks = servers.chunks(SERVERS_PER_LIST_DATAGRAM).map(|chunk| chunk.to_vec())
+                    .collect::<Vec<_>>();
+                if chunks.is_empty() {
+                    chunks.push(Vec::new()); // Still tells the browser the master is up
+                }
+                for chunk in chunks.into_iter() {
+                    let bytes = serialize_master_message(&MasterMessage::ServerList { servers: chunk });
+                    if let Err(e) = self.socket.send_to(&bytes, sender) {
+                        println!("Master server: Failed to answer query of {}. Reason: {}", sender, e);
+                        return
+                    }
+                }
+            },
+            MasterMessage::ServerList { .. } | MasterMessage::QueryCookie { .. } => ()
+        }
+    }
+
+    fn handle_pong(&mut self, bytes: &[u8], sender: SocketAddr) {
+        let cookie = match BinaryStream::from_bytes(&bytes[PONG_MAGIC.len()..]).decode_u64() {
+            Ok(cookie) => cookie,
+            Err(_) => return
+        };
+        if !self.flood_guard.check_cookie(sender, Some(cookie), Instant::now()) {
+            return
+        }
+        if let Some(registration) = self.servers.get_mut(&sender).filter(|r| !r.verified) {
+            registration.verified = true;
+            println!("Master server: {} ({}) registered.", registration.info.name, sender);
+        }
+    }
+
+    fn send(&self, bytes: &[u8], addr: SocketAddr) {
+        if let Err(e) = self.socket.send_to(bytes, addr) {
+            println!("Master server: Failed to send to {}. Reason: {}", addr, e);
+        }
+    }
+
+    fn count_servers_at(&self, ip: IpAddr) -> usize {
+        self.servers.keys().filter(|addr| addr.ip() == ip).count()
+    }
+}
+
+// Host side, keeps the lobby listed while the server runs
+pub struct MasterRegistration {
+    socket: UdpSocket,
+    master_addr: SocketAddr,
+    last_heartbeat_time: Option<Instant>
+}
+
+impl MasterRegistration {
+    // Fails softly, the lobby can still be joined directly
+    pub fn register(master_addr: &str) -> Option<MasterRegistration> {
+        let registration = bind_nonblocking(0).and_then(|socket| Ok(MasterRegistration {
+            socket, master_addr: resolve(master_addr)?, last_heartbeat_time: None
+        }));
+        match registration {
+            Ok(registration) => Some(registration),
+            Err(e) => {
+                println!("Server: Can't list the lobby at master server {}. Reason: {}", master_addr, e);
+                None
+            }
+        }
+    }
+
+    // Sends a heartbeat if one is due, the lobby info is only gathered then
+    pub fn update(&mut self, get_info: impl Fn() -> LobbyInfo) -> BbResult {
+        let now = Instant::now();
+        if self.last_heartbeat_time.is_some_and(|time| now - time
+            < Duration::from_secs_f32(HEARTBEAT_INTERVAL)) {
+            return Ok(())
+        }
+        self.last_heartbeat_time = Some(now);
+        self.send(&MasterMessage::Heartbeat { info: get_info() })
+    }
+
+    // Sends the next heartbeat right away, e.g. after players joined or left
+    pub fn refresh(&mut self) {
+        self.last_heartbeat_time = None;
+    }
+
+    pub fn unregister(&self, port: u16) -> BbResult {
+        self.send(&MasterMessage::Unregister { port })
+    }
+
+    fn send(&self, message: &MasterMessage) -> BbResult {
+        self.socket.send_to(&serialize_master_message(message), self.master_addr)
+            .map(|_| ()).map_err(BbError::Io)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowsedServer {
+    pub addr: SocketAddr,
+    pub info: LobbyInfo,
+    pub ping: Option<Duration>, // None until the host answered a ping
+    ping_nonce: u64,
+    ping_sent_at: Instant,
+    last_listed: Instant
+}
+
+impl BrowsedServer {
+    pub fn get_free_slots(&self) -> u16 {
+        self.info.max_players.saturating_sub(self.info.players)
+    }
+}
+
+// Client side, queries the master and pings every listed host
+pub struct ServerBrowser {
+    socket: UdpSocket,
+    master_addr: SocketAddr,
+    servers: HashMap<SocketAddr, BrowsedServer>,
+    last_query_time: Option<Instant>,
+    query_cookie: Option<u64> // Given by the master, kept as long as it accepts it
+}
+
+impl ServerBrowser {
+    pub fn new(master_addr: &str) -> BbResult<ServerBrowser> {
+        Ok(ServerBrowser {
+            socket: bind_nonblocking(0)?, master_addr: resolve(master_addr)?,
+            servers: HashMap::new(), last_query_time: None, query_cookie: None
+        })
+    }
+
+    // Returns whether the list of servers or any ping changed
+    pub fn update(&mut self) -> BbResult<bool> {
+        let now = Instant::now();
+        if self.last_query_time.is_none_or(|time| now - time >= Duration::from_secs_f32(QUERY_INTERVAL)) {
+            self.last_query_time = Some(now);
+            self.send(&serialize_master_message(&MasterMessage::Query { cookie: self.query_cookie }),
+                self.master_addr);
+            let addrs = self.servers.keys().copied().collect::<Vec<_>>();
+            for addr in addrs.into_iter() {
+                self.ping(addr, now);
+            }
+        }
+
+        let mut changed = false;
+        let mut buffer = [0; MAX_MASTER_DATAGRAM_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, sender)) => changed |= self.handle_datagram(&buffer[..len], sender, now),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(BbError::Io(e))
+            }
+        }
+
+        let count = self.servers.len();
+        self.servers.retain(|_, server| now - server.last_listed < Duration::from_secs_f32(LISTING_TIMEOUT));
+        Ok(changed || self.servers.len() < count)
+    }
+
+    fn handle_datagram(&mut self, bytes: &[u8], sender: SocketAddr, now: Instant) -> bool {
+        if let Some(nonce) = bytes.strip_prefix(PONG_MAGIC) {
+            return match self.servers.get_mut(&sender) {
+                Some(server) if nonce == server.ping_nonce.to_le_bytes() => {
+                    server.ping = Some(now - server.ping_sent_at);
+                    true
+                },
+                _ => false
+            }
+        }
+        if sender != self.master_addr {
+            return false
+        }
+        let servers = match deserialize_master_message(bytes) {
+            Ok(MasterMessage::ServerList { servers }) => servers,
+            Ok(MasterMessage::QueryCookie { cookie }) => {
+                // Asks again right away, instead of waiting for the next query
+                self.query_cookie = Some(cookie);
+                self.send(&serialize_master_message(&MasterMessage::Query { cookie: Some(cookie) }),
+                    self.master_addr);
+                return false
+            },
+            Ok(..) => return false,
+            Err(e) => {
+                println!("Ignoring invalid server list. Reason: {}", e);
+                return false
+            }
+        };
+        let mut changed = false;
+        for listed in servers.into_iter() {
+            match self.servers.get_mut(&listed.addr) {
+                Some(server) => {
+                    changed |= server.info != listed.info;
+                    server.info = listed.info;
+                    server.last_listed = now;
+                },
+                None => {
+                    self.servers.insert(listed.addr, BrowsedServer {
+                        addr: listed.addr, info: listed.info, ping: None, ping_nonce: 0,
+                        ping_sent_at: now, last_listed: now
+                    });
+                    self.ping(listed.addr, now);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    fn ping(&mut self, addr: SocketAddr, now: Instant) {
+        let nonce = rand_u64();
+        if let Some(server) = self.servers.get_mut(&addr) {
+            server.ping_nonce = nonce;
+            server.ping_sent_at = now;
+            let mut ping = PING_MAGIC.to_vec();
+            ping.extend_from_slice(&nonce.to_le_bytes());
+            self.send(&ping, addr);
+        }
+    }
+
+    fn send(&self, bytes: &[u8], addr: SocketAddr) {
+        // Unreachable hosts just stay without a ping, an unreachable master with an empty list
+        if let Err(e) = self.socket.send_to(bytes, addr) {
+            println!("Failed to send to {}. Reason: {}", addr, e);
+        }
+    }
+
+    // Matches with free slots first, closest first, emptiest first
+    pub fn get_servers(&self) -> Vec<&BrowsedServer> {
+        let mut servers = self.servers.values().collect::<Vec<_>>();
+        servers.sort_by_key(|server| (server.get_free_slots() == 0, server.ping.is_none(),
+            server.ping, Reverse(server.get_free_slots()), server.addr));
+        servers
+    }
+}
@@ -0,0 +1,141 @@
+use std::{fs, net::IpAddr, path::PathBuf};
+use crate::{BbError, BbResult, chat_policy::{DEFAULT_HOST_MUTE_MINUTES, MAX_HOST_MUTE_MINUTES}, get_settings_path};
+
+pub const BANS_FILE_NAME: &str = "bans.cfg";
+
+// Player addresses change between reconnects, so only the IP is banned along with the name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ban {
+    pub name: String,
+    pub ip: IpAddr,
+    pub persistent: bool // Written to the bans file, otherwise only kept for this session
+}
+
+pub struct BanList {
+    bans: Vec<Ban>,
+    path: Option<PathBuf>
+}
+
+impl BanList {
+    // Falls back to an empty list for a missing file and skips bad lines
+    pub fn load(path: Option<PathBuf>) -> BanList {
+        let bans = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| parse_bans(&contents))
+            .unwrap_or_default();
+        if !bans.is_empty() {
+            println!("Loaded {} persistent ban(s) from {:?}.", bans.len(), path.as_ref().unwrap());
+        }
+        BanList {
+            bans, path
+        }
+    }
+
+    pub fn get_bans(&self) -> &Vec<Ban> {
+        &self.bans
+    }
+
+    // Everyone on the host's machine shares the loopback address, so they are only banned by name
+    pub fn is_banned(&self, name: &str, ip: IpAddr) -> bool {
+        self.bans.iter().any(|ban| ban.name == name || (ban.ip == ip && !ip.is_loopback()))
+    }
+
+    pub fn add(&mut self, ban: Ban) -> BbResult {
+        let persistent = ban.persistent;
+        self.bans.push(ban);
+        if persistent {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    // Returns whether anyone was banned under that name
+    pub fn remove(&mut self, name: &str) -> BbResult<bool> {
+        let count = self.bans.len();
+        let persistent = self.bans.iter().any(|ban| ban.name == name && ban.persistent);
+        self.bans.retain(|ban| ban.name != name);
+        if persistent {
+            self.save()?;
+        }
+        Ok(self.bans.len() < count)
+    }
+
+    fn save(&self) -> BbResult {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(())
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(BbError::Io)?;
+        }
+        let lines = self.bans.iter()
+            .filter(|ban| ban.persistent)
+            .map(|ban| format!("{} {}\n", ban.name, ban.ip))
+            .collect::<String>();
+        fs::write(path, lines).map_err(BbError::Io)
+    }
+}
+
+// One "name ip" pair per line, names can't contain spaces
+fn parse_bans(contents: &str) -> Vec<Ban> {
+    contents.lines()
+        .filter_map(|line| {
+            let (name, ip) = line.trim().split_once(' ')?;
+            match ip.trim().parse() {
+                Ok(ip) => Some(Ban {
+                    name: name.to_owned(), ip, persistent: true
+                }),
+                Err(_) => {
+                    println!("Ignoring invalid ban entry: {}", line);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Next to the settings file
+pub fn get_bans_path() -> Option<PathBuf> {
+    get_settings_path()?.parent().map(|dir| dir.join(BANS_FILE_NAME))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCommand {
+    Kick { name: String, message: String },
+    Ban { name: String, message: String, persistent: bool },
+    Unban { name: String },
+    Mute { name: String, minutes: u64 },
+    Unmute { name: String },
+    Lock(bool) // Locked lobbies refuse new players, rejoining ones are still let in
+}
+
+pub const HOST_COMMAND_USAGE: &str = "Commands: /kick <name> [reason], /ban <name> [reason], \
+    /permaban <name> [reason], /unban <name>, /mute <name> [minutes, at most a day], /unmute <name>, /lock, /unlock";
+
+impl HostCommand {
+    // Chat messages starting with a slash, e.g. "/kick Blackbeard Stop ramming"
+    pub fn parse(message: &str) -> Result<HostCommand, String> {
+        let mut split = message.trim().splitn(3, ' ');
+        let command = split.next().unwrap_or_default().to_lowercase();
+        let name = split.next().map(|name| name.to_owned()).filter(|name| !name.is_empty());
+        let message = split.next().unwrap_or_default().trim().to_owned();
+        Ok(match (command.as_str(), name) {
+            ("/kick", Some(name)) => HostCommand::Kick { name, message },
+            ("/ban", Some(name)) => HostCommand::Ban { name, message, persistent: false },
+            ("/permaban", Some(name)) => HostCommand::Ban { name, message, persistent: true },
+            ("/unban", Some(name)) => HostCommand::Unban { name },
+            ("/mute", Some(name)) => HostCommand::Mute {
+                name, minutes: match message.as_str() {
+                    "" => DEFAULT_HOST_MUTE_MINUTES,
+                    minutes => minutes.parse().ok()
+                        .filter(|minutes| (1..=MAX_HOST_MUTE_MINUTES).contains(minutes))
+                        .ok_or_else(|| HOST_COMMAND_USAGE.to_owned())?
+                }
+            },
+            ("/unmute", Some(name)) => HostCommand::Unmute { name },
+            ("/lock", None) => HostCommand::Lock(true),
+            ("/unlock", None) => HostCommand::Lock(false),
+            _ => return Err(HOST_COMMAND_USAGE.to_owned())
+        })
+    }
+}
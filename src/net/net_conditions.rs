@@ -0,0 +1,217 @@
+use std::{cmp::Reverse, collections::BinaryHeap, io::{self, ErrorKind}, net::{SocketAddr, UdpSocket}, time::{Duration, Instant}};
+use laminar::DatagramSocket;
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro128Plus;
+use crate::{master_server::answer_ping, rand_f32, rand_u64, transport::Transport};
+
+// Large enough for any datagram laminar sends
+const MAX_DATAGRAM_SIZE: usize = 1500;
+// Bandwidth caps let a burst of this length through at once (100 ms)
+const BANDWIDTH_BURST_SECS: f32 = 0.1;
+
+// Bad connections, reproduced on a single machine. Every datagram a peer sends or receives is
+// conditioned, so a round trip between two conditioned peers is delayed four times over.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetConditions {
+    pub delay: u32, // ms
+    pub jitter: u32, // ms, added on top of the delay at random
+    pub loss: f32, // 0-1
+    pub duplication: f32, // 0-1
+    pub bandwidth: u32 // Bytes per sec and direction, 0 for no cap
+}
+
+impl NetConditions {
+    // BLACKBEARD_NET_DELAY=100 BLACKBEARD_NET_LOSS=0.05 ...
+    pub fn from_env() -> NetConditions {
+        let mut conditions = NetConditions::default();
+        read_env("BLACKBEARD_NET_DELAY", &mut conditions.delay);
+        read_env("BLACKBEARD_NET_JITTER", &mut conditions.jitter);
+        read_env("BLACKBEARD_NET_LOSS", &mut conditions.loss);
+        read_env("BLACKBEARD_NET_DUPLICATION", &mut conditions.duplication);
+        read_env("BLACKBEARD_NET_BANDWIDTH", &mut conditions.bandwidth);
+        conditions.loss = conditions.loss.clamp(0.0, 1.0);
+        conditions.duplication = conditions.duplication.clamp(0.0, 1.0);
+        if conditions.is_active() {
+            println!("Simulating network conditions: {:?}", conditions);
+        }
+        conditions
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self != NetConditions::default()
+    }
+}
+
+fn read_env<T: std::str::FromStr>(key: &str, target: &mut T) {
+    if let Ok(value) = std::env::var(key) {
+        match value.parse() {
+            Ok(value) => *target = value,
+            Err(_) => println!("Ignoring invalid value {} of {}", value, key)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct DelayedDatagram {
+    release_time: Instant,
+    seq: u64, // Keeps datagrams released at the same time in order
+    addr: SocketAddr,
+    payload: Vec<u8>
+}
+
+// Datagrams of one direction, waiting to be sent or received
+#[derive(Debug)]
+struct ConditionedLink {
+    queue: BinaryHeap<Reverse<DelayedDatagram>>,
+    queued_bytes: usize,
+    budget: f32, // Bytes that may pass right now under the bandwidth cap
+    last_refill_time: Instant
+}
+
+impl ConditionedLink {
+    fn new() -> ConditionedLink {
+        ConditionedLink {
+            queue: BinaryHeap::new(), queued_bytes: 0, budget: 0.0, last_refill_time: Instant::now()
+        }
+    }
+
+    fn push(&mut self, datagram: DelayedDatagram, conditions: &NetConditions) {
+        // Like a full router queue, a saturated link drops anything beyond a second's worth
+        if conditions.bandwidth > 0 && self.queued_bytes >= conditions.bandwidth as usize {
+            return
+        }
+        self.queued_bytes += datagram.payload.len();
+        self.queue.push(Reverse(datagram));
+    }
+
+    fn pop_due(&mut self, conditions: &NetConditions) -> Option<DelayedDatagram> {
+        let now = Instant::now();
+        if conditions.bandwidth > 0 {
+            let rate = conditions.bandwidth as f32;
+            let max_budget = (rate * BANDWIDTH_BURST_SECS).max(MAX_DATAGRAM_SIZE as f32);
+            self.budget = (self.budget + (now - self.last_refill_time).as_secs_f32() * rate)
+                .min(max_budget);
+            self.last_refill_time = now;
+        }
+        let Reverse(next) = self.queue.peek()?;
+        if next.release_time > now
+            || (conditions.bandwidth > 0 && (next.payload.len() as f32) > self.budget) {
+            return None
+        }
+        let Reverse(datagram) = self.queue.pop()?;
+        self.queued_bytes -= datagram.payload.len();
+        if conditions.bandwidth > 0 {
+            self.budget -= datagram.payload.len() as f32;
+        }
+        Some(datagram)
+    }
+}
+
+// Sits between laminar and the transport. Without any conditions, datagrams pass straight through.
+#[derive(Debug)]
+pub struct ConditionedSocket {
+    socket: Box<dyn Transport>,
+    conditions: NetConditions,
+    outgoing: ConditionedLink,
+    incoming: ConditionedLink,
+    rng: Xoshiro128Plus,
+    next_seq: u64
+}
+
+impl ConditionedSocket {
+    pub fn bind(addr: SocketAddr, conditions: NetConditions) -> io::Result<ConditionedSocket> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::new(Box::new(socket), conditions))
+    }
+
+    pub fn new(socket: Box<dyn Transport>, conditions: NetConditions) -> ConditionedSocket {
+        ConditionedSocket {
+            socket, conditions, outgoing: ConditionedLink::new(), incoming: ConditionedLink::new(),
+            rng: Xoshiro128Plus::seed_from_u64(rand_u64()), next_seq: 0
+        }
+    }
+
+    pub fn get_relay_endpoint(&self) -> Option<String> {
+        self.socket.get_relay_endpoint()
+    }
+
+    // Lost datagrams are never queued, duplicates are delayed on their own
+    fn condition(&mut self, addr: SocketAddr, payload: &[u8], outgoing: bool) {
+        if rand_f32(&mut self.rng) < self.conditions.loss {
+            return
+        }
+        let copies = if rand_f32(&mut self.rng) < self.conditions.duplication { 2 } else { 1 };
+        for _ in 0..copies {
+            let jitter = rand_f32(&mut self.rng) * self.conditions.jitter as f32;
+            let delay = Duration::from_secs_f32((self.conditions.delay as f32 + jitter) / 1000.0);
+            let datagram = DelayedDatagram {
+                release_time: Instant::now() + delay, seq: self.next_seq, addr,
+                payload: payload.to_vec()
+            };
+            self.next_seq += 1;
+            match outgoing {
+                true => self.outgoing.push(datagram, &self.conditions),
+                false => self.incoming.push(datagram, &self.conditions)
+            }
+        }
+    }
+
+    fn flush_outgoing(&mut self) {
+        while let Some(datagram) = self.outgoing.pop_due(&self.conditions) {
+            if let Err(e) = self.socket.send_to(&datagram.payload, datagram.addr) {
+                println!("Failed to send delayed datagram: {}", e);
+            }
+        }
+    }
+}
+
+impl DatagramSocket for ConditionedSocket {
+    fn send_packet(&mut self, addr: &SocketAddr, payload: &[u8]) -> io::Result<usize> {
+        if !self.conditions.is_active() {
+            return self.socket.send_to(payload, *addr)
+        }
+        self.condition(*addr, payload, true);
+        self.flush_outgoing();
+        Ok(payload.len())
+    }
+
+    // Laminar polls for received datagrams continuously, which also releases delayed outgoing ones.
+    // Server browser pings are answered right away, unconditioned.
+    fn receive_packet<'a>(&mut self, buffer: &'a mut [u8]) -> io::Result<(&'a [u8], SocketAddr)> {
+        if !self.conditions.is_active() {
+            loop {
+                let (len, addr) = self.socket.recv_from(buffer)?;
+                if !answer_ping(self.socket.as_mut(), &buffer[..len], addr) {
+                    return Ok((&buffer[..len], addr))
+                }
+            }
+        }
+        self.flush_outgoing();
+        let mut recv_buffer = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            match self.socket.recv_from(&mut recv_buffer) {
+                Ok((len, addr)) if answer_ping(self.socket.as_mut(), &recv_buffer[..len], addr) => (),
+                Ok((len, addr)) => self.condition(addr, &recv_buffer[..len], false),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e)
+            }
+        }
+        match self.incoming.pop_due(&self.conditions) {
+            Some(datagram) => {
+                let len = datagram.payload.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&datagram.payload[..len]);
+                Ok((&buffer[..len], datagram.addr))
+            },
+            None => Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn is_blocking_mode(&self) -> bool {
+        false
+    }
+}
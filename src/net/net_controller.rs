@@ -1,7 +1,7 @@
 use std::net::SocketAddr;
 
 use tetra::Context;
-use crate::{BbResult, ID, PlayerParams, client::ClientEvent, game_settings::GameSettings, packet::{GamePhase, InputState, InputStep, Packet}, peer::{DisconnectReason, is_auth_client}, server::ServerEvent, ship_data::ShipType, sync_checker::SyncState};
+use crate::{BbResult, ID, PlayerParams, client::ClientEvent, connection_quality::ConnectionQuality, game_settings::GameSettings, packet::{GamePhase, InputState, InputStep, Packet}, peer::DisconnectReason, replay::Replay, server::ServerEvent, ship_data::ShipType, state_digest::StateDigestPart, sync_checker::SyncState};
 
 pub trait NetController {
     fn poll_received_server_packets(&mut self, ctx: &mut Context) -> BbResult<ServerEvent>;
@@ -10,16 +10,23 @@ pub trait NetController {
             ServerEvent::ReceivePacket(sender, packet) => {
                 match packet {
                     Packet::ChatMessage { message } => self.on_server_receive_chat_message(sender, message),
-                    Packet::Input { state } => self.on_server_receive_input(ctx, sender, state),
+                    Packet::Input { states, .. } => states.into_iter()
+                        .try_for_each(|state| self.on_server_receive_input(ctx, sender, state)),
                     Packet::Game { phase } => self.on_server_set_game_phase(sender, phase),
                     Packet::Sync { state } => self.on_server_receive_sync_state(ctx, sender, state),
                     Packet::Selection { mode, ship, .. } if mode => self.on_server_receive_ship_selection(ctx, sender, ship.unwrap()),
                     Packet::Selection { settings, .. } => self.on_server_receive_settings(ctx, sender, settings.unwrap()),
+                    Packet::DigestSummary { states } => self.on_server_receive_digest_summary(sender, states),
+                    Packet::Digest { part } => self.on_server_receive_digest(sender, part),
+                    Packet::StepsRequest { gen } => self.on_server_receive_steps_request(sender, gen),
                     _=> Ok(())
                 }
             },
             ServerEvent::PlayerConnect(id, addr) => self.on_server_receive_handshake(id, addr),
+            ServerEvent::PlayerRejoin(id, addr) => self.on_server_receive_rejoin(id, addr),
+            ServerEvent::PlayerMigrate(id, gen, steps) => self.on_server_receive_migration(id, gen, steps),
             ServerEvent::PlayerDisconnect(sender, reason) => self.on_server_receive_disconnect(sender, reason),
+            ServerEvent::ReservationExpired(id) => self.on_server_reservation_expired(id),
             ServerEvent::Empty => Ok(()),
         }
     }
@@ -29,24 +36,28 @@ pub trait NetController {
         match self.poll_received_client_packets(ctx)? {
             ClientEvent::ReceivePacket(sender, packet) => {
                 match packet {
-                    Packet::PlayerConnect { name } => self.on_player_connect(ctx,
+                    Packet::PlayerConnect { name, spectator } if spectator => self.on_spectator_connect(ctx,
+                        ID::new(name, sender)),
+                    Packet::PlayerConnect { name, .. } => self.on_player_connect(ctx,
                         PlayerParams::new(ID::new(name, sender))),
-                    Packet::PlayerDisconnect { reason } => {
-                        if is_auth_client(sender) {
-                            self.on_connection_lost(ctx, DisconnectReason::HostShutdown)
-                        } else {
-                            self.on_player_disconnect(ctx, sender, reason)
-                        }
-                    },
+                    // The client already took care of the host leaving
+                    Packet::PlayerDisconnect { reason } => self.on_player_disconnect(ctx, sender, reason),
                     Packet::ChatMessage { message } => self.on_chat_message(ctx, message, sender),
-                    Packet::InputStep { step } => self.on_input_step(ctx, step),
+                    Packet::Notice { message } => self.on_notice(ctx, message),
+                    Packet::InputStep { steps } => steps.into_iter()
+                        .try_for_each(|step| self.on_input_step(ctx, step)),
+                    Packet::RejoinSteps { steps } => self.on_rejoin_steps(ctx, steps),
                     Packet::Game { phase } => self.on_game_phase_changed(ctx, phase),
                     Packet::Selection { mode, ship, .. } if mode => self.on_select_ship(ctx, sender, ship.unwrap()),
                     Packet::Selection { settings, .. } => self.on_change_settings(ctx, settings.unwrap()),
+                    Packet::DigestRequest { gen } => self.on_digest_request(ctx, gen),
+                    Packet::QualityTable { entries } => self.on_quality_table(ctx, entries),
                     _ => Ok(())
                 }
             },
             ClientEvent::Connect(local_player, players) => self.on_establish_connection(ctx, local_player, players), 
+            ClientEvent::Rejoin(setup, gen) => self.on_rejoin_match(ctx, setup, gen),
+            ClientEvent::HostMigration(new_host) => self.on_host_migration(ctx, new_host),
             ClientEvent::Disconnect(reason) => self.on_connection_lost(ctx, reason),
             _ => Ok(())
         }
@@ -61,17 +72,33 @@ pub trait NetController {
         Ok(())
     }
 
+    fn on_server_receive_rejoin(&mut self, id: ID, remote_addr: SocketAddr) -> BbResult {
+        Ok(())
+    }
+
     fn on_server_receive_disconnect(&mut self, sender: u16, reason: DisconnectReason) -> BbResult {
         Ok(())
     }
 
+    fn on_server_reservation_expired(&mut self, id: u16) -> BbResult {
+        Ok(())
+    }
+
+    fn on_server_receive_migration(&mut self, id: ID, gen: u64, steps: Vec<InputStep>) -> BbResult {
+        Ok(())
+    }
+
+    fn on_server_receive_steps_request(&mut self, sender: u16, gen: u64) -> BbResult {
+        Ok(())
+    }
+
     fn on_server_set_game_phase(&mut self, sender: u16, phase: GamePhase) -> BbResult {
         // Check authority and current scene
         Ok(())
     }
 
+    // The server already dropped messages that break the chat policy
     fn on_server_receive_chat_message(&mut self, sender: u16, message: String) -> BbResult {
-        // Check for size and profanity
         Ok(())
     }
 
@@ -84,6 +111,14 @@ pub trait NetController {
         Ok(())
     }
 
+    fn on_server_receive_digest_summary(&mut self, sender: u16, states: Vec<SyncState>) -> BbResult {
+        Ok(())
+    }
+
+    fn on_server_receive_digest(&mut self, sender: u16, part: StateDigestPart) -> BbResult {
+        Ok(())
+    }
+
     fn on_server_receive_ship_selection(&mut self, ctx: &mut Context, sender: u16,
         ship_type: ShipType) -> BbResult {
         Ok(())
@@ -101,10 +136,23 @@ pub trait NetController {
     fn on_connection_lost(&mut self, ctx: &mut Context, reason: DisconnectReason)-> BbResult {
         Ok(())
     }
+    fn on_rejoin_match(&mut self, ctx: &mut Context, setup: Replay, gen: u64) -> BbResult {
+        Ok(())
+    }
+    fn on_rejoin_steps(&mut self, ctx: &mut Context, steps: Vec<InputStep>) -> BbResult {
+        Ok(())
+    }
+    // Only running matches can move to another host
+    fn on_host_migration(&mut self, ctx: &mut Context, new_host: u16) -> BbResult {
+        self.on_connection_lost(ctx, DisconnectReason::HostShutdown)
+    }
 
     fn on_player_connect(&mut self, ctx: &mut Context, player: PlayerParams) -> BbResult {
         Ok(())
     }
+    fn on_spectator_connect(&mut self, ctx: &mut Context, id: ID) -> BbResult {
+        Ok(())
+    }
     fn on_player_disconnect(&mut self, ctx: &mut Context, id: u16,
         reason: DisconnectReason) -> BbResult {
         Ok(())
@@ -112,6 +160,9 @@ pub trait NetController {
     fn on_chat_message(&mut self, ctx: &mut Context, text: String, sender: u16) -> BbResult {
         Ok(())
     }
+    fn on_notice(&mut self, ctx: &mut Context, text: String) -> BbResult {
+        Ok(())
+    }
     fn on_input_step(&mut self, ctx: &mut Context, step: InputStep) -> BbResult {
         Ok(())
     }
@@ -124,4 +175,11 @@ pub trait NetController {
     fn on_change_settings(&mut self, ctx: &mut Context, settings: GameSettings) -> BbResult {
         Ok(())
     }
+    fn on_digest_request(&mut self, ctx: &mut Context, gen: Option<u64>) -> BbResult {
+        Ok(())
+    }
+    // Also stored in the client, this only signals that it changed
+    fn on_quality_table(&mut self, ctx: &mut Context, entries: Vec<ConnectionQuality>) -> BbResult {
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -1,17 +1,53 @@
+use std::path::PathBuf;
+use crate::{chat_policy::get_chat_filter_path, discovery::DISCOVERY_PORT, moderation::get_bans_path, net_conditions::NetConditions};
+
+pub const DEFAULT_MAX_SPECTATORS: usize = 8;
+
 pub struct NetSettings {
-    pub max_players: usize
+    pub max_players: usize,
+    pub max_spectators: usize, // Spectators don't count against max_players
+    pub dedicated: bool, // Server does not host a local player, ID 0 stays reserved
+    pub conditions: NetConditions, // Simulated for testing, read from the environment by default
+    pub password: Option<String>,
+    pub allow_list: Vec<String>, // Names that may join, empty for everyone
+    pub bans_path: Option<PathBuf>, // Persistent bans, None to keep them for the session only
+    pub chat_filter_path: Option<PathBuf>,
+    pub lobby_name: String, // Shown to players browsing the local network
+    pub discovery_port: Option<u16>, // None to stay hidden from LAN discovery
+    pub master_server: Option<String>, // Address to list the lobby at, None to stay unlisted
+    pub relay: Option<String> // Relay to host through instead of accepting players directly
 }
 
 impl NetSettings {
-    fn new(max_players: usize) -> NetSettings {
+    fn new(max_players: usize, max_spectators: usize, dedicated: bool) -> NetSettings {
         NetSettings {
-            max_players
+            max_players, max_spectators, dedicated, conditions: NetConditions::from_env(),
+            password: None, allow_list: Vec::new(), bans_path: get_bans_path(),
+            chat_filter_path: get_chat_filter_path(), lobby_name: "Blackbeard Lobby".to_owned(),
+            discovery_port: Some(DISCOVERY_PORT), master_server: None,
+            relay: None
         }
     }
+
+    pub fn dedicated(max_players: usize, max_spectators: usize) -> NetSettings {
+        Self::new(max_players, max_spectators, true)
+    }
+
+    // Comma-separated, e.g. "AnneBonny,MaryRead"
+    pub fn set_allow_list(&mut self, names: &str) {
+        self.allow_list = names.split(',')
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty())
+            .collect();
+    }
+
+    pub fn is_invited(&self, name: &str) -> bool {
+        self.allow_list.is_empty() || self.allow_list.iter().any(|invited| invited == name)
+    }
 }
 
 impl Default for NetSettings {
     fn default() -> Self {
-        NetSettings::new(4)
+        NetSettings::new(4, DEFAULT_MAX_SPECTATORS, false)
     }
 }
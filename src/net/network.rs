@@ -1,4 +1,4 @@
-use crate::{BbError, BbErrorType, BbResult, client::{Client, ClientEvent}, net_settings::NetSettings, packet::{GamePhase, InputState, Packet}, peer::{DisconnectReason}, server::{Server, ServerEvent}};
+use crate::{BbError, BbErrorType, BbResult, ID, client::{Client, ClientEvent}, net_conditions::NetConditions, net_settings::NetSettings, packet::{GamePhase, InputState, Packet}, peer::{DisconnectReason}, server::{Server, ServerEvent}};
 
 pub struct Network {
     pub client: Client,
@@ -6,16 +6,27 @@ pub struct Network {
 }
 
 impl Network {
-    pub fn create(port: u16, name: String, settings: NetSettings) -> BbResult<Network> {
+    pub fn create(port: u16, name: String, mut settings: NetSettings) -> BbResult<Network> {
+        if !settings.is_invited(&name) {
+            settings.allow_list.push(name.clone()); // The host is always invited
+        }
+        settings.lobby_name = format!("{}'s Lobby", name);
+        let password = settings.password.clone();
         let server = Server::host(port, settings)?;
-        let client = Client::connect(format!("127.0.0.1:{}", port).as_str(), name)?;
+        // A relayed host joins its own session like everyone else
+        let endpoint = server.get_relay_endpoint().map(|endpoint| endpoint.to_owned())
+            .unwrap_or(format!("127.0.0.1:{}", port));
+        let client = Client::connect(&endpoint, name, false, password, None,
+            NetConditions::default())?; // The local connection stays unconditioned
         Ok(Network {
             client, server: Some(server)
         })
     }
 
-    pub fn join(server_addr: &str, name: String) -> BbResult<Network> {
-        let client = Client::connect(server_addr, name)?;
+    pub fn join(server_addr: &str, name: String, spectator: bool, password: Option<String>,
+        rejoin_token: Option<u64>) -> BbResult<Network> {
+        let client = Client::connect(server_addr, name, spectator, password, rejoin_token,
+            NetConditions::from_env())?;
         Ok(Network {
             client, server: None
         })
@@ -25,6 +36,25 @@ impl Network {
         self.server.is_some()
     }
 
+    pub fn is_host_dedicated(&self) -> bool {
+        self.client.is_connected() && self.client.get_host_id().is_none()
+    }
+
+    // The elected host keeps the socket the other peers know it by, so they reach its
+    // server right away. Its own client reconnects to that server locally.
+    pub fn take_over_hosting(&mut self, players: Vec<ID>, spectators: Vec<ID>) -> BbResult {
+        let local_id = self.client.get_local_id().ok_or(BbError::Bb(BbErrorType::NetNotConnected))?;
+        let peer = self.client.hand_over_peer()?;
+        // The allow-list is only known to the previous host, but the password carries over
+        let settings = NetSettings {
+            password: self.client.get_password(), lobby_name: format!("{}'s Lobby", local_id.name),
+            ..NetSettings::default()
+        };
+        let tokens = self.client.get_migration_tokens();
+        self.server = Some(Server::migrate(peer, settings, local_id.n, players, spectators, tokens));
+        Ok(())
+    }
+
     pub fn poll_received_client_packets(&mut self) -> BbResult<ClientEvent> {
         self.client.poll_received_packets()
     }
@@ -55,9 +85,7 @@ impl Network {
     }
 
     pub fn send_input(&mut self, state: InputState) -> BbResult {
-        self.send_packet(Packet::Input {
-            state
-        })
+        self.client.send_input(state)
     }
 
     pub fn load_world_phase(&mut self, world_seed: u64) -> BbResult {
@@ -70,6 +98,17 @@ impl Network {
         }
     }
 
+    pub fn load_score_phase(&mut self) -> BbResult {
+        // Dedicated servers can't run the simulation themselves, so they rely on player reports
+        if !self.has_authority() && !self.is_host_dedicated() {
+            Err(BbError::Bb(BbErrorType::NetInsufficientAuthority))
+        } else {
+            self.send_packet(Packet::Game {
+                phase: GamePhase::Score
+            })
+        }
+    }
+
     pub fn disconnect(&mut self, reason: DisconnectReason) -> BbResult {
         self.client.disconnect(reason)?;
         if let Some(mut server) = self.server.take() {
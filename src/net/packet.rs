@@ -1,19 +1,27 @@
 use binary_stream::{BinaryStream, Serializable};
 use tetra::{Context, input::{Key, MouseButton, get_mouse_position, is_key_down, is_mouse_button_down}};
-use crate::{PlayerParams, V2, deserialize_v2, game_settings::GameSettings, peer::DisconnectReason, serialize_v2, ship_data::ShipType, ship_mod::ShipModType, sync_checker::SyncState};
+use crate::{BbResult, ID, PlayerParams, V2, connection_quality::ConnectionQuality, decode::{Decode, DecodeStream, decode_error, unknown_index}, game_settings::GameSettings, host_migration::{HostCandidate, MigrationToken}, lobby_access::{PASSWORD_PROOF_LEN, PasswordProof}, peer::{CONTROL_STREAM, Delivery, DisconnectReason, INPUT_STREAM, PING_STREAM, QUALITY_STREAM, STEP_STREAM, SYNC_STREAM}, serialize_v2, ship_data::ShipType, ship_mod::ShipModType, state_digest::StateDigestPart, sync_checker::SyncState, version::GameVersion};
 use std::fmt;
 
 #[derive(Clone)]
 pub enum Packet {
     Handshake {
-        name: String
+        version: GameVersion,
+        name: String,
+        spectator: bool, // Watches the match without owning a ship
+        cookie: Option<u64>, // Echoed from the server, handshakes without one are only answered with it
+        rejoin_token: Option<u64> // Of the match the player timed out of
     },
     HandshakeReply {
         players: Vec<PlayerParams>,
+        spectators: Vec<ID>,
+        host: Option<u16>, // Player running the server, None for dedicated servers
+        rejoin_token: u64 // Takes back the slot after a timeout, only ever sent to the player
         /* + Game Settings */
     },
     PlayerConnect {
-        name: String
+        name: String,
+        spectator: bool
     },
     PlayerDisconnect {
         reason: DisconnectReason
@@ -22,10 +30,11 @@ pub enum Packet {
         message: String
     },
     Input {
-        state: InputState
+        seq: u32, // Of the latest state
+        states: Vec<InputState> // Latest last, earlier ones repeated in case they got lost
     },
     InputStep {
-        step: InputStep
+        steps: Vec<InputStep> // Latest last, earlier ones repeated in case they got lost
     },
     Game {
         phase: GamePhase,
@@ -37,6 +46,67 @@ pub enum Packet {
         mode: bool,
         ship: Option<ShipType>,
         settings: Option<GameSettings>
+    },
+    DigestRequest {
+        gen: Option<u64> // None requests the digest hashes of all buffered gens
+    },
+    DigestSummary {
+        states: Vec<SyncState>
+    },
+    Digest {
+        part: StateDigestPart
+    },
+    Rejoin {
+        world_seed: u64,
+        settings: GameSettings,
+        players: Vec<PlayerParams>, // Players at match start, which the world is built from
+        host: Option<u16>, // Player running the server, who may have taken over the match
+        gen: u64 // Steps to catch up on, sent in RejoinSteps packets right after
+    },
+    RejoinSteps {
+        steps: Vec<InputStep>
+    },
+    HostCandidates {
+        host: Option<u16>, // Player running the server, None for dedicated servers
+        candidates: Vec<HostCandidate>,
+        tokens: Vec<MigrationToken> // Of every peer but the host, spectators included
+    },
+    Migrate {
+        id: u16,
+        name: String,
+        rejoin_token: u64, // Checked against the hash the previous host sent along with the candidates
+        cookie: Option<u64>, // As with handshakes, echoed from the new host
+        gen: u64, // Last step received from the previous host
+        steps: Vec<InputStep>
+    },
+    StepsRequest {
+        gen: u64 // Last step received in one piece, more were lost than repeated since
+    },
+    Challenge {
+        salt: u64 // Sent in reply to handshakes with password-protected lobbies
+    },
+    ChallengeAnswer {
+        nonce: u64, // Picked by the client for every answer
+        proof: PasswordProof
+    },
+    Kick {
+        reason: DisconnectReason, // Kicked or banned
+        message: String // Given by the host, may be empty
+    },
+    Notice {
+        message: String // From the server itself, unlike chat messages
+    },
+    Ping {
+        seq: u32
+    },
+    Pong {
+        seq: u32 // Of the ping answered
+    },
+    QualityTable {
+        entries: Vec<ConnectionQuality> // Connections the server has measured yet
+    },
+    HandshakeCookie {
+        cookie: u64 // Proves the sender receives at its address before the server keeps any state
     }
 }
 
@@ -52,7 +122,37 @@ impl Packet {
             Packet::InputStep { .. } => 6,
             Packet::Game { .. } => 7,
             Packet::Sync { .. } => 8,
-            Packet::Selection { .. } => 9
+            Packet::Selection { .. } => 9,
+            Packet::DigestRequest { .. } => 10,
+            Packet::DigestSummary { .. } => 11,
+            Packet::Digest { .. } => 12,
+            Packet::Rejoin { .. } => 13,
+            Packet::RejoinSteps { .. } => 14,
+            Packet::HostCandidates { .. } => 15,
+            Packet::Migrate { .. } => 16,
+            Packet::StepsRequest { .. } => 17,
+            Packet::Challenge { .. } => 18,
+            Packet::ChallengeAnswer { .. } => 19,
+            Packet::Kick { .. } => 20,
+            Packet::Notice { .. } => 21,
+            Packet::Ping { .. } => 22,
+            Packet::Pong { .. } => 23,
+            Packet::QualityTable { .. } => 24,
+            Packet::HandshakeCookie { .. } => 25
+        }
+    }
+
+    // Lockstep traffic is sent every step and carries its own redundancy, so a lost datagram
+    // is made up for by the next one instead of holding back everything behind it
+    pub fn get_delivery(&self) -> Delivery {
+        match self {
+            Packet::Input { .. } => Delivery::UnreliableSequenced(INPUT_STREAM),
+            Packet::InputStep { .. } => Delivery::UnreliableSequenced(STEP_STREAM),
+            Packet::Sync { .. } | Packet::DigestRequest { .. } | Packet::DigestSummary { .. }
+                | Packet::Digest { .. } => Delivery::ReliableOrdered(SYNC_STREAM),
+            Packet::Ping { .. } | Packet::Pong { .. } => Delivery::UnreliableSequenced(PING_STREAM),
+            Packet::QualityTable { .. } => Delivery::UnreliableSequenced(QUALITY_STREAM),
+            _ => Delivery::ReliableOrdered(CONTROL_STREAM)
         }
     }
 }
@@ -60,19 +160,43 @@ impl Packet {
 impl fmt::Debug for Packet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Packet::Handshake { name } => write!(f, "Handshake Packet (name: {})", name),
-            Packet::HandshakeReply { players } => write!(f, "Handshake Reply Packet (players: {:?})",
-                players),
-            Packet::PlayerConnect { name } => write!(f, "Player Connect Packet (name: {})", name),
+            Packet::Handshake { version, name, spectator, cookie, rejoin_token } => write!(f, "Handshake Packet (version: {:?}, name: {}, spectator: {}, cookie: {}, rejoin: {})",
+                version, name, spectator, cookie.is_some(), rejoin_token.is_some()),
+            Packet::HandshakeReply { players, spectators, host, .. } => write!(f, "Handshake Reply Packet (players: {:?}, spectators: {:?}, host: {:?})",
+                players, spectators, host),
+            Packet::PlayerConnect { name, spectator } => write!(f, "Player Connect Packet (name: {}, spectator: {})",
+                name, spectator),
             Packet::PlayerDisconnect { reason } => write!(f, "Player Disconnect Packet (reason: {:?})",
                 reason),
             Packet::ChatMessage { message } => write!(f, "Chat Message Packet (message: {})",
                 message),
-            Packet::Input { state } => write!(f, "Input State Packet ({:?})", state),
-            Packet::InputStep { step } => write!(f, "Input Step Packet (states: {:?}, gen: {})", step.states, step.gen),
+            Packet::Input { seq, states } => write!(f, "Input State Packet (seq: {}, states: {:?})", seq, states),
+            Packet::InputStep { steps } => write!(f, "Input Step Packet (gens: {:?}, states: {:?})",
+                steps.iter().map(|s| s.gen).collect::<Vec<_>>(), steps.last().map(|s| &s.states)),
             Packet::Game { phase } => write!(f, "Game Packet (phase: {:?})", phase),
             Packet::Sync { state } => write!(f, "Sync Packet (state: {:?})", state),
-            Packet::Selection { ship, settings, .. } => write!(f, "Selection Packet (ship: {:?}, settings: {:?}", ship, settings)
+            Packet::Selection { ship, settings, .. } => write!(f, "Selection Packet (ship: {:?}, settings: {:?}", ship, settings),
+            Packet::DigestRequest { gen } => write!(f, "Digest Request Packet (gen: {:?})", gen),
+            Packet::DigestSummary { states } => write!(f, "Digest Summary Packet ({} states)", states.len()),
+            Packet::Digest { part } => write!(f, "Digest Packet (gen: {}, part {}/{})",
+                part.gen, part.index + 1, part.count),
+            Packet::Rejoin { world_seed, players, host, gen, .. } => write!(f, "Rejoin Packet (world seed: {}, players: {:?}, host: {:?}, gen: {})",
+                world_seed, players, host, gen),
+            Packet::RejoinSteps { steps } => write!(f, "Rejoin Steps Packet ({} steps)", steps.len()),
+            Packet::HostCandidates { host, candidates, tokens } => write!(f, "Host Candidates Packet (host: {:?}, candidates: {:?}, {} tokens)",
+                host, candidates, tokens.len()),
+            Packet::Migrate { id, name, cookie, gen, steps, .. } => write!(f, "Migrate Packet (id: {}, name: {}, cookie: {}, gen: {}, {} steps)",
+                id, name, cookie.is_some(), gen, steps.len()),
+            Packet::StepsRequest { gen } => write!(f, "Steps Request Packet (gen: {})", gen),
+            Packet::Challenge { salt } => write!(f, "Challenge Packet (salt: {})", salt),
+            Packet::ChallengeAnswer { .. } => write!(f, "Challenge Answer Packet"),
+            Packet::Kick { reason, message } => write!(f, "Kick Packet (reason: {:?}, message: {})",
+                reason, message),
+            Packet::Notice { message } => write!(f, "Notice Packet (message: {})", message),
+            Packet::Ping { seq } => write!(f, "Ping Packet (seq: {})", seq),
+            Packet::Pong { seq } => write!(f, "Pong Packet (seq: {})", seq),
+            Packet::QualityTable { entries } => write!(f, "Quality Table Packet (entries: {:?})", entries),
+            Packet::HandshakeCookie { .. } => write!(f, "Handshake Cookie Packet")
         }
     }
 }
@@ -81,14 +205,31 @@ impl Serializable for Packet {
     fn to_stream(&self, stream: &mut BinaryStream) {
         stream.write_buffer_single(self.to_num()).unwrap();
         match self {
-            Packet::Handshake { name } => {
+            Packet::Handshake { version, name, spectator, cookie, rejoin_token } => {
+                version.to_stream(stream);
                 stream.write_string(&name).unwrap();
+                stream.write_bool(*spectator).unwrap();
+                stream.write_bool(cookie.is_some()).unwrap();
+                if let Some(cookie) = cookie {
+                    stream.write_u64(*cookie).unwrap();
+                }
+                stream.write_bool(rejoin_token.is_some()).unwrap();
+                if let Some(rejoin_token) = rejoin_token {
+                    stream.write_u64(*rejoin_token).unwrap();
+                }
             },
-            Packet::HandshakeReply { players } => {
+            Packet::HandshakeReply { players, spectators, host, rejoin_token } => {
                 stream.write_vec(players).unwrap();
+                stream.write_vec(spectators).unwrap();
+                stream.write_bool(host.is_some()).unwrap();
+                if let Some(host) = host {
+                    stream.write_u16(*host).unwrap();
+                }
+                stream.write_u64(*rejoin_token).unwrap();
             },
-            Packet::PlayerConnect { name } => {
+            Packet::PlayerConnect { name, spectator } => {
                 stream.write_string(name).unwrap();
+                stream.write_bool(*spectator).unwrap();
             }
             Packet::PlayerDisconnect { reason } => {
                 reason.to_stream(stream);
@@ -96,11 +237,12 @@ impl Serializable for Packet {
             Packet::ChatMessage { message } => {
                 stream.write_string(message).unwrap();
             },
-            Packet::Input { state } => {
-                state.to_stream(stream);
+            Packet::Input { seq, states } => {
+                stream.write_u32(*seq).unwrap();
+                stream.write_vec(states).unwrap();
             }
-            Packet::InputStep { step } => {
-                step.to_stream(stream);
+            Packet::InputStep { steps } => {
+                stream.write_vec(steps).unwrap();
             },
             Packet::Game { phase } => {
                 phase.to_stream(stream);
@@ -115,71 +257,274 @@ impl Serializable for Packet {
                 } else {
                     settings.as_ref().unwrap().to_stream(stream);
                 }
+            },
+            Packet::DigestRequest { gen } => {
+                stream.write_bool(gen.is_some()).unwrap();
+                if let Some(gen) = gen {
+                    stream.write_u64(*gen).unwrap();
+                }
+            },
+            Packet::DigestSummary { states } => {
+                stream.write_vec(states).unwrap();
+            },
+            Packet::Digest { part } => {
+                part.to_stream(stream);
+            },
+            Packet::Rejoin { world_seed, settings, players, host, gen } => {
+                stream.write_u64(*world_seed).unwrap();
+                settings.to_stream(stream);
+                stream.write_vec(players).unwrap();
+                stream.write_bool(host.is_some()).unwrap();
+                if let Some(host) = host {
+                    stream.write_u16(*host).unwrap();
+                }
+                stream.write_u64(*gen).unwrap();
+            },
+            Packet::RejoinSteps { steps } => {
+                stream.write_vec(steps).unwrap();
+            },
+            Packet::HostCandidates { host, candidates, tokens } => {
+                stream.write_bool(host.is_some()).unwrap();
+                if let Some(host) = host {
+                    stream.write_u16(*host).unwrap();
+                }
+                stream.write_vec(candidates).unwrap();
+                stream.write_vec(tokens).unwrap();
+            },
+            Packet::Migrate { id, name, rejoin_token, cookie, gen, steps } => {
+                stream.write_u16(*id).unwrap();
+                stream.write_string(name).unwrap();
+                stream.write_u64(*rejoin_token).unwrap();
+                stream.write_bool(cookie.is_some()).unwrap();
+                if let Some(cookie) = cookie {
+                    stream.write_u64(*cookie).unwrap();
+                }
+                stream.write_u64(*gen).unwrap();
+                stream.write_vec(steps).unwrap();
+            },
+            Packet::StepsRequest { gen } => {
+                stream.write_u64(*gen).unwrap();
+            },
+            Packet::Challenge { salt } => {
+                stream.write_u64(*salt).unwrap();
+            },
+            Packet::ChallengeAnswer { nonce, proof } => {
+                stream.write_u64(*nonce).unwrap();
+                stream.write_buffer(&proof.to_vec()).unwrap();
+            },
+            Packet::Kick { reason, message } => {
+                reason.to_stream(stream);
+                stream.write_string(message).unwrap();
+            },
+            Packet::Notice { message } => {
+                stream.write_string(message).unwrap();
+            },
+            Packet::Ping { seq } | Packet::Pong { seq } => {
+                stream.write_u32(*seq).unwrap();
+            },
+            Packet::QualityTable { entries } => {
+                stream.write_vec(entries).unwrap();
+            },
+            Packet::HandshakeCookie { cookie } => {
+                stream.write_u64(*cookie).unwrap();
             }
         };
     }
     
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let type_num = stream.read_buffer_single().unwrap();
-        match type_num {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for Packet {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let type_num = stream.decode_u8()?;
+        Ok(match type_num {
             0 => {
-                let name = stream.read_string().unwrap();
-                Packet::Handshake { name }
+                let version = GameVersion::decode(stream)?;
+                let name = stream.decode_string()?;
+                let spectator = stream.decode_bool()?;
+                let cookie = match stream.decode_bool()? {
+                    true => Some(stream.decode_u64()?),
+                    false => None
+                };
+                let rejoin_token = match stream.decode_bool()? {
+                    true => Some(stream.decode_u64()?),
+                    false => None
+                };
+                Packet::Handshake { version, name, spectator, cookie, rejoin_token }
             },
             1 => {
-                let players = stream.read_vec::<PlayerParams>().unwrap();
+                let players = stream.decode_vec::<PlayerParams>()?;
+                let spectators = stream.decode_vec::<ID>()?;
+                let host = match stream.decode_bool()? {
+                    true => Some(stream.decode_u16()?),
+                    false => None
+                };
+                let rejoin_token = stream.decode_u64()?;
                 Packet::HandshakeReply {
-                    players
+                    players, spectators, host, rejoin_token
                 }
             },
             2 => {
-                let name = stream.read_string().unwrap();
-                Packet::PlayerConnect { name }
+                let name = stream.decode_string()?;
+                let spectator = stream.decode_bool()?;
+                Packet::PlayerConnect { name, spectator }
             }
             3 => {
-                let reason = DisconnectReason::from_stream(stream);
+                let reason = DisconnectReason::decode(stream)?;
                 Packet::PlayerDisconnect { reason }
             },
             4 => {
                 Packet::ChatMessage {
-                    message: stream.read_string().unwrap()
+                    message: stream.decode_string()?
                 }
             },
             5 => {
+                let seq = stream.decode_u32()?;
+                let states = stream.decode_vec::<InputState>()?;
                 Packet::Input {
-                    state: InputState::from_stream(stream)
+                    seq, states
                 }
             }
             6 => {
                 Packet::InputStep {
-                    step: InputStep::from_stream(stream)
+                    steps: stream.decode_vec::<InputStep>()?
                 }
             },
             7 => {
-                let phase = GamePhase::from_stream(stream);
+                let phase = GamePhase::decode(stream)?;
                 Packet::Game {
                     phase
                 }
             },
             8 => {
                 Packet::Sync {
-                    state: SyncState::from_stream(stream)
+                    state: SyncState::decode(stream)?
                 }
             },
             9 => {
-                let mode = stream.read_bool().unwrap();
+                let mode = stream.decode_bool()?;
                 if mode {
                     Packet::Selection {
-                        mode, ship: Some(ShipType::from_stream(stream)), settings: None
+                        mode, ship: Some(ShipType::decode(stream)?), settings: None
                     }
                 } else {
                     Packet::Selection {
-                        mode, ship: None, settings: Some(GameSettings::from_stream(stream))
+                        mode, ship: None, settings: Some(GameSettings::decode(stream)?)
                     }
                 }
             },
-            n @ _ => panic!("Index {} not assigned to any packet type", n)
-        }
+            10 => {
+                let gen = match stream.decode_bool()? {
+                    true => Some(stream.decode_u64()?),
+                    false => None
+                };
+                Packet::DigestRequest {
+                    gen
+                }
+            },
+            11 => {
+                Packet::DigestSummary {
+                    states: stream.decode_vec::<SyncState>()?
+                }
+            },
+            12 => {
+                Packet::Digest {
+                    part: StateDigestPart::decode(stream)?
+                }
+            },
+            13 => {
+                let world_seed = stream.decode_u64()?;
+                let settings = GameSettings::decode(stream)?;
+                let players = stream.decode_vec::<PlayerParams>()?;
+                let host = match stream.decode_bool()? {
+                    true => Some(stream.decode_u16()?),
+                    false => None
+                };
+                let gen = stream.decode_u64()?;
+                Packet::Rejoin {
+                    world_seed, settings, players, host, gen
+                }
+            },
+            14 => {
+                Packet::RejoinSteps {
+                    steps: stream.decode_vec::<InputStep>()?
+                }
+            },
+            15 => {
+                let host = match stream.decode_bool()? {
+                    true => Some(stream.decode_u16()?),
+                    false => None
+                };
+                let candidates = stream.decode_vec::<HostCandidate>()?;
+                let tokens = stream.decode_vec::<MigrationToken>()?;
+                Packet::HostCandidates {
+                    host, candidates, tokens
+                }
+            },
+            16 => {
+                let id = stream.decode_u16()?;
+                let name = stream.decode_string()?;
+                let rejoin_token = stream.decode_u64()?;
+                let cookie = match stream.decode_bool()? {
+                    true => Some(stream.decode_u64()?),
+                    false => None
+                };
+                let gen = stream.decode_u64()?;
+                let steps = stream.decode_vec::<InputStep>()?;
+                Packet::Migrate {
+                    id, name, rejoin_token, cookie, gen, steps
+                }
+            },
+            17 => {
+                Packet::StepsRequest {
+                    gen: stream.decode_u64()?
+                }
+            },
+            18 => {
+                Packet::Challenge {
+                    salt: stream.decode_u64()?
+                }
+            },
+            19 => {
+                let nonce = stream.decode_u64()?;
+                let mut proof = [0; PASSWORD_PROOF_LEN];
+                proof.copy_from_slice(&stream.decode_bytes(PASSWORD_PROOF_LEN)?);
+                Packet::ChallengeAnswer { nonce, proof }
+            },
+            20 => {
+                let reason = DisconnectReason::decode(stream)?;
+                let message = stream.decode_string()?;
+                Packet::Kick { reason, message }
+            },
+            21 => {
+                Packet::Notice {
+                    message: stream.decode_string()?
+                }
+            },
+            22 => {
+                Packet::Ping {
+                    seq: stream.decode_u32()?
+                }
+            },
+            23 => {
+                Packet::Pong {
+                    seq: stream.decode_u32()?
+                }
+            },
+            24 => {
+                Packet::QualityTable {
+                    entries: stream.decode_vec::<ConnectionQuality>()?
+                }
+            },
+            25 => {
+                Packet::HandshakeCookie {
+                    cookie: stream.decode_u64()?
+                }
+            },
+            n @ _ => return Err(unknown_index(n, "packet type"))
+        })
     }
 }
 
@@ -198,15 +543,32 @@ pub fn serialize_packet_unsigned(packet: Packet) -> Vec<u8> {
     stream.get_buffer_vec()
 }
 
-pub fn deserialize_packet(packet_bytes: Vec<u8>) -> (Packet, u16) {
+pub fn deserialize_packet(packet_bytes: Vec<u8>) -> BbResult<(Packet, u16)> {
     let mut stream = BinaryStream::from_bytes(&packet_bytes);
-    let sender = stream.read_u16().unwrap();
-    (Packet::from_stream(&mut stream), sender)
+    let sender = stream.decode_u16()?;
+    Ok((decode_packet_body(&mut stream)?, sender))
 }
 
-pub fn deserialize_packet_unsigned(packet_bytes: Vec<u8>) -> Packet {
+pub fn deserialize_packet_unsigned(packet_bytes: Vec<u8>) -> BbResult<Packet> {
     let mut stream = BinaryStream::from_bytes(&packet_bytes);
-    Packet::from_stream(&mut stream)
+    decode_packet_body(&mut stream)
+}
+
+// Handshakes of other protocol versions may not decode, but their version always comes first
+pub fn peek_handshake_version(packet_bytes: &[u8]) -> Option<GameVersion> {
+    let mut stream = BinaryStream::from_bytes(packet_bytes);
+    match stream.decode_u8() {
+        Ok(0) => GameVersion::decode(&mut stream).ok(),
+        _ => None
+    }
+}
+
+fn decode_packet_body(stream: &mut BinaryStream) -> BbResult<Packet> {
+    let packet = Packet::decode(stream)?;
+    if stream.size() > 0 {
+        return Err(decode_error(&format!("{} trailing bytes after {:?}", stream.size(), packet)))
+    }
+    Ok(packet)
 }
 
 // Send rate: every 15 frames at 60 fixed FPS = 1/4 = 0.25 secs
@@ -279,7 +641,13 @@ impl Serializable for InputState {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let input_bits = stream.read_buffer_single().unwrap();
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for InputState {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let input_bits = stream.decode_u8()?;
         // 0b00000_0_0_0
         //       e q r rmb
         let rmb = (input_bits & 0b1) != 0;
@@ -290,14 +658,14 @@ impl Serializable for InputState {
         let disconnect = (input_bits & (0b1 << 5u8)) != 0;
 
         let mod_type = match buy_mod {
-            true => Some(ShipModType::from_stream(stream)),
+            true => Some(ShipModType::decode(stream)?),
             false => None
         };
         let mouse_pos = match rmb || r {
-            true => Some(deserialize_v2(stream)),
+            true => Some(stream.decode_v2()?),
             false => None
         };
-        InputState::new(rmb, r, q, e, buy_mod, disconnect, mod_type, mouse_pos)
+        Ok(InputState::new(rmb, r, q, e, buy_mod, disconnect, mod_type, mouse_pos))
     }
 }
 
@@ -337,15 +705,21 @@ impl Serializable for InputStep {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let len = stream.read_buffer_single().unwrap() as usize;
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for InputStep {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let len = stream.decode_u8()? as usize;
         let mut states = Vec::with_capacity(len);
         for _ in 0..len {
-            let sender = stream.read_u16().unwrap();
-            let state = InputState::from_stream(stream);
+            let sender = stream.decode_u16()?;
+            let state = InputState::decode(stream)?;
             states.push((sender, state));
         }
-        let gen = stream.read_u64().unwrap();
-        InputStep::new(states, gen)
+        let gen = stream.decode_u64()?;
+        Ok(InputStep::new(states, gen))
     }
 }
 
@@ -367,13 +741,19 @@ impl Serializable for GamePhase {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        match stream.read_buffer_single().unwrap() {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for GamePhase {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
             0 => {
-                let world_seed = stream.read_u64().unwrap();
+                let world_seed = stream.decode_u64()?;
                 GamePhase::World(world_seed)
             },
             1 => GamePhase::Score,
-            n @ _ => panic!("Index {} not assigned to any game phase", n)
-        }
+            n @ _ => return Err(unknown_index(n, "game phase"))
+        })
     }
 }
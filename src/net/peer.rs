@@ -1,12 +1,29 @@
-use std::{net::SocketAddr, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use std::{fmt, net::SocketAddr, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::{Duration, Instant}};
 use binary_stream::{BinaryStream, Serializable};
 use crossbeam_channel::{Receiver, Sender};
-use laminar::{Config, Packet as LaminarPacket, Socket, SocketEvent};
-use crate::{BbError, BbErrorType, BbResult};
+use laminar::{Config, ConnectionManager, DatagramSocket, Packet as LaminarPacket, SocketEvent, VirtualConnection};
+use crate::{BbError, BbErrorType, BbResult, decode::{Decode, DecodeStream, unknown_index}, net_conditions::{ConditionedSocket, NetConditions}, transport::TransportSetup, version::GameVersion};
 
 const IDLE_TIMEOUT_DURATION: f32 = 15.0;
 const HEARTBEAT_INTERVAL: f32 = 5.0;
 
+// Each stream is ordered or sequenced on its own, so a retransmission only holds back its stream
+pub const CONTROL_STREAM: u8 = 0;
+pub const INPUT_STREAM: u8 = 1;
+pub const STEP_STREAM: u8 = 2;
+pub const SYNC_STREAM: u8 = 3;
+// Pings are never resent, as only their round trip is of interest
+pub const PING_STREAM: u8 = 4;
+pub const QUALITY_STREAM: u8 = 5;
+// Sender of what dedicated servers send on their own behalf, their players are numbered from 1
+pub const DEDICATED_SERVER_ID: u16 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    ReliableOrdered(u8),
+    UnreliableSequenced(u8) // Lost or outdated packets are dropped
+}
+
 pub trait NetPeer {
     fn get_peer(&self) -> &Peer;
 }
@@ -15,23 +32,27 @@ pub struct Peer {
     sender: Sender<LaminarPacket>,
     receiver: Receiver<SocketEvent>,
     poll_thread: Option<JoinHandle<()>>,
-    running: Arc<AtomicBool>
+    running: Arc<AtomicBool>,
+    local_port: u16,
+    relay_endpoint: Option<String>
 }
 
 impl Peer {
-    pub fn setup(port: Option<u16>) -> BbResult<Self> {
+    pub fn setup(transport: TransportSetup, conditions: NetConditions) -> BbResult<Self> {
         let config = Config {
             idle_connection_timeout: Duration::from_secs_f32(IDLE_TIMEOUT_DURATION),
             heartbeat_interval: Some(Duration::from_secs_f32(HEARTBEAT_INTERVAL)),
             socket_event_buffer_size: 1024 * 50,
             ..Default::default()
         };
-        let mut socket = match port {
-            Some(port) => Socket::bind_with_config(format!("0.0.0.0:{}", port), config),
-            None => Socket::bind_with_config("0.0.0.0:0", config)
-        }.or_else(|err| Err(BbError::Laminar(err)))?;
-        let sender = socket.get_packet_sender();
-        let receiver = socket.get_event_receiver();
+        let conditioned_socket = ConditionedSocket::new(transport.open()?, conditions);
+        let local_port = conditioned_socket.local_addr()
+            .or_else(|err| Err(BbError::Laminar(err.into())))?.port();
+        let relay_endpoint = conditioned_socket.get_relay_endpoint();
+        let mut socket: ConnectionManager<ConditionedSocket, VirtualConnection> =
+            ConnectionManager::new(conditioned_socket, config);
+        let sender = socket.event_sender().clone();
+        let receiver = socket.event_receiver().clone();
 
         let running = Arc::new(AtomicBool::new(true));
         let running_ref = running.clone();
@@ -42,12 +63,27 @@ impl Peer {
             };
         }));
         Ok(Peer {
-            sender, receiver, poll_thread, running
+            sender, receiver, poll_thread, running, local_port, relay_endpoint
         })
     }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port
+    }
+
+    // Only set for peers that are reached through a relay
+    pub fn get_relay_endpoint(&self) -> Option<&str> {
+        self.relay_endpoint.as_deref()
+    }
     
-    pub fn send_raw_packet(&mut self, packet_bytes: Vec<u8>, target_addr: SocketAddr) -> BbResult {
-        let packet = LaminarPacket::reliable_ordered(target_addr, packet_bytes, None);
+    pub fn send_raw_packet(&mut self, packet_bytes: Vec<u8>, target_addr: SocketAddr, delivery: Delivery)
+        -> BbResult {
+        let packet = match delivery {
+            Delivery::ReliableOrdered(stream) =>
+                LaminarPacket::reliable_ordered(target_addr, packet_bytes, Some(stream)),
+            Delivery::UnreliableSequenced(stream) =>
+                LaminarPacket::unreliable_sequenced(target_addr, packet_bytes, Some(stream))
+        };
         if let Err(e) = self.sender.send(packet) {
             println!("Failed to send packet: {:?}", e);
         }
@@ -89,7 +125,19 @@ pub enum DisconnectReason {
     Manual,
     Timeout,
     HostShutdown,
-    Desync
+    Desync,
+    Incompatible(GameVersion), // Carries the version of the refusing server
+    InvalidPackets,
+    WrongPassword,
+    NotInvited, // Name is not on the allow-list of the lobby
+    Kicked,
+    Banned,
+    LobbyLocked,
+    InvalidInput, // Kicked by the server after repeated invalid input states
+    ServerBusy, // Too many handshakes waiting on the server already
+    SpectatorsFull,
+    MatchInProgress, // Only spectators and rejoining players are let in once the match started
+    LobbyFull
 }
 
 impl Serializable for DisconnectReason {
@@ -98,21 +146,84 @@ impl Serializable for DisconnectReason {
             DisconnectReason::Manual => 0,
             DisconnectReason::Timeout => 1,
             DisconnectReason::HostShutdown => 2,
-            DisconnectReason::Desync => 3
+            DisconnectReason::Desync => 3,
+            DisconnectReason::Incompatible(..) => 4,
+            DisconnectReason::InvalidPackets => 5,
+            DisconnectReason::WrongPassword => 6,
+            DisconnectReason::NotInvited => 7,
+            DisconnectReason::Kicked => 8,
+            DisconnectReason::Banned => 9,
+            DisconnectReason::LobbyLocked => 10,
+            DisconnectReason::InvalidInput => 11,
+            DisconnectReason::ServerBusy => 12,
+            DisconnectReason::SpectatorsFull => 13,
+            DisconnectReason::MatchInProgress => 14,
+            DisconnectReason::LobbyFull => 15
         }).unwrap();
+        if let DisconnectReason::Incompatible(version) = self {
+            version.to_stream(stream);
+        }
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        match stream.read_buffer_single().unwrap() {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for DisconnectReason {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
             0 => DisconnectReason::Manual,
             1 => DisconnectReason::Timeout,
             2 => DisconnectReason::HostShutdown,
             3 => DisconnectReason::Desync,
-            n @ _ => panic!("Index {} not assigned to any disconnect reason", n)
-        }
+            4 => DisconnectReason::Incompatible(GameVersion::decode(stream)?),
+            5 => DisconnectReason::InvalidPackets,
+            6 => DisconnectReason::WrongPassword,
+            7 => DisconnectReason::NotInvited,
+            8 => DisconnectReason::Kicked,
+            9 => DisconnectReason::Banned,
+            10 => DisconnectReason::LobbyLocked,
+            11 => DisconnectReason::InvalidInput,
+            12 => DisconnectReason::ServerBusy,
+            13 => DisconnectReason::SpectatorsFull,
+            14 => DisconnectReason::MatchInProgress,
+            15 => DisconnectReason::LobbyFull,
+            n @ _ => return Err(unknown_index(n, "disconnect reason"))
+        })
     }
 }
 
-pub fn is_auth_client(id: u16) -> bool {
-    id == 0
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisconnectReason::Manual => write!(f, "Left the game"),
+            DisconnectReason::Timeout => write!(f, "Connection timed out"),
+            DisconnectReason::HostShutdown => write!(f, "Host shut down the game"),
+            DisconnectReason::Desync => write!(f, "Game state desynchronised"),
+            DisconnectReason::Incompatible(server_version) => {
+                let local_version = GameVersion::local();
+                if server_version.protocol != local_version.protocol
+                    || server_version.primary != local_version.primary
+                    || server_version.secondary != local_version.secondary {
+                    write!(f, "Incompatible version. Server runs v{}.{}, you run v{}.{}",
+                        server_version.primary, server_version.secondary,
+                        local_version.primary, local_version.secondary)
+                } else {
+                    write!(f, "Incompatible ship balance. Server and client builds differ")
+                }
+            },
+            DisconnectReason::InvalidPackets => write!(f, "Sent malformed packets"),
+            DisconnectReason::WrongPassword => write!(f, "Wrong lobby password"),
+            DisconnectReason::NotInvited => write!(f, "Lobby is invite-only"),
+            DisconnectReason::Kicked => write!(f, "Kicked by the host"),
+            DisconnectReason::Banned => write!(f, "Banned by the host"),
+            DisconnectReason::LobbyLocked => write!(f, "Lobby is locked"),
+            DisconnectReason::InvalidInput => write!(f, "Kicked for sending invalid input"),
+            DisconnectReason::ServerBusy => write!(f, "Server is busy, try again later"),
+            DisconnectReason::SpectatorsFull => write!(f, "No spectator slots left"),
+            DisconnectReason::MatchInProgress => write!(f, "Match is already in progress"),
+            DisconnectReason::LobbyFull => write!(f, "Lobby is full")
+        }
+    }
 }
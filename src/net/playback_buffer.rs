@@ -1,5 +1,5 @@
 use std::{collections::VecDeque, time::Instant};
-use tetra::{Context, State, math::Clamp};
+use tetra::math::Clamp;
 use crate::{DEFAULT_SIMULATION_TIMESTEP, input_pool::{STEP_PHASE_FRAME_LENGTH, STEP_PHASE_TIME_SECS}, packet::{InputStep}};
 
 const MAX_BUFFER_SIZE: usize = (DEFAULT_SIMULATION_TIMESTEP * 0.5) as usize
@@ -58,6 +58,11 @@ impl PlaybackBuffer {
         self.get_buffer_size() > 0
     }
 
+    // Received, but not yet simulated
+    pub fn get_buffered_steps(&self) -> impl Iterator<Item = &InputStep> {
+        self.steps.iter()
+    }
+
     pub fn get_next_step(&mut self) -> Option<InputStep> {
         if let Some(step) = self.steps.pop_front() {
             self.curr_frame_index = 0;
@@ -67,6 +72,25 @@ impl PlaybackBuffer {
         }
     }
 
+    // A predicted step runs in place of the next one, which stays buffered until it arrives
+    pub fn restart_phase(&mut self) {
+        self.curr_frame_index = 0;
+    }
+
+    // The step was already simulated as predicted
+    pub fn skip_step(&mut self) -> Option<InputStep> {
+        self.steps.pop_front()
+    }
+
+    pub fn get_curr_frame_index(&self) -> u32 {
+        self.curr_frame_index
+    }
+
+    pub fn rewind(&mut self, frames: u64, frame_index: u32) {
+        self.curr_frames = frames;
+        self.curr_frame_index = frame_index;
+    }
+
     pub fn get_latency(&self) -> f32 {
         if self.received_steps == 0 {
             0.0
@@ -80,12 +104,9 @@ impl PlaybackBuffer {
         let optimal_latency = STEP_PHASE_TIME_SECS;
         ((latency / optimal_latency).round() as usize).clamped(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE)
     }
-}
 
-impl State for PlaybackBuffer {
-    fn update(&mut self, _ctx: &mut Context) -> tetra::Result {
+    pub fn update(&mut self) {
         self.curr_frame_index += 1;
         self.curr_frames += 1;
-        Ok(())
     }
 }
@@ -0,0 +1,62 @@
+use binary_stream::{BinaryStream, Serializable};
+use sha2::{Digest, Sha256};
+use crate::{packet::{InputStep, Packet}, replay::Replay};
+
+// How long the server keeps the slot and ship of a timed out player
+pub const REJOIN_GRACE_PERIOD: f32 = 60.0;
+// Simulation speed factor while a rejoining player replays the buffered steps
+pub const REJOIN_CATCH_UP_SPEED: f64 = 10.0;
+// Well below laminar's maximum packet size of 16 KiB
+const MAX_REJOIN_STEPS_PACKET_SIZE: usize = 8 * 1024;
+
+// Servers only keep the hash of a token once they are no longer the ones handing it out
+pub fn hash_rejoin_token(token: u64) -> u64 {
+    let hash = Sha256::digest(token.to_le_bytes());
+    u64::from_le_bytes([hash[0], hash[1], hash[2], hash[3], hash[4], hash[5], hash[6], hash[7]])
+}
+
+// The world itself can't be sent, but as the simulation is deterministic, the match setup
+// plus every step since the start rebuild it exactly. Steps follow in as many packets as needed.
+pub fn gen_rejoin_packets(history: &Replay, host: Option<u16>) -> Vec<Packet> {
+    let mut packets = vec![Packet::Rejoin {
+        world_seed: history.world_seed, settings: history.settings,
+        players: history.players.clone(), host, gen: history.get_total_gens()
+    }];
+    packets.extend(gen_steps_packets(&history.steps));
+    packets
+}
+
+// Steps after the given gen, for peers that lost them or missed them during a host migration
+pub fn gen_missed_steps_packets(history: &Replay, gen: u64) -> Vec<Packet> {
+    let start = history.steps.iter().position(|step| step.gen > gen).unwrap_or(history.steps.len());
+    gen_steps_packets(&history.steps[start..])
+}
+
+pub fn gen_steps_packets(history_steps: &[InputStep]) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut steps: Vec<InputStep> = Vec::new();
+    let mut size = 0;
+    for step in history_steps.iter() {
+        let step_size = get_serialized_size(step);
+        if size + step_size > MAX_REJOIN_STEPS_PACKET_SIZE && !steps.is_empty() {
+            packets.push(Packet::RejoinSteps {
+                steps: std::mem::take(&mut steps)
+            });
+            size = 0;
+        }
+        steps.push(step.clone());
+        size += step_size;
+    }
+    if !steps.is_empty() {
+        packets.push(Packet::RejoinSteps {
+            steps
+        });
+    }
+    packets
+}
+
+fn get_serialized_size(step: &InputStep) -> usize {
+    let mut stream = BinaryStream::new();
+    step.to_stream(&mut stream);
+    stream.get_buffer_vec().len()
+}
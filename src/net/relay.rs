@@ -0,0 +1,380 @@
+use std::{collections::HashMap, io::{self, ErrorKind}, net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket}, thread, time::{Duration, Instant}};
+use crate::{BbError, BbErrorType, BbResult, decode::{decode_error, unknown_index}, rand_u64, transport::Transport};
+
+pub const DEFAULT_RELAY_PORT: u16 = 22078;
+pub const RELAY_ENDPOINT_PREFIX: &str = "relay://";
+const RELAY_MAGIC: &[u8] = b"BBRL";
+// Magic, type, session and peer ID
+const RELAY_HEADER_SIZE: usize = 11;
+// Large enough for any datagram laminar sends, plus the header
+const MAX_RELAY_DATAGRAM_SIZE: usize = 2048;
+// Laminar heartbeats keep every connection busy, so silence means the peer is gone
+const RELAY_PEER_TIMEOUT: f32 = 30.0;
+const RELAY_CONNECT_ATTEMPTS: u32 = 10;
+const RELAY_CONNECT_RESEND_INTERVAL: f32 = 0.5;
+const MAX_RELAY_PEERS_PER_SESSION: usize = 64;
+// Like the master server's limit, so a single machine can't take up the whole relay
+pub const MAX_RELAY_SESSIONS_PER_IP: usize = 4;
+pub const MAX_RELAY_PEERS_PER_IP: usize = 32; // Hosts and clients, players may share a NAT
+const MAX_RELAY_SESSIONS: usize = 1024;
+const MAX_RELAY_PEERS: usize = 16384;
+
+// Peers are told apart by the ID the relay handed out. Laminar needs an address for
+// each of them, so they get one from the reserved 240.0.0.0/4 block.
+pub fn get_virtual_addr(peer: u16) -> SocketAddr {
+    SocketAddr::from(([240, 0, (peer >> 8) as u8, peer as u8], DEFAULT_RELAY_PORT))
+}
+
+pub fn get_virtual_peer(addr: SocketAddr) -> Option<u16> {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.octets()[..2] == [240, 0] => Some(u16::from_be_bytes([ip.octets()[2], ip.octets()[3]])),
+        _ => None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayMessage {
+    Host, // Opens a new session, the host always is peer 0
+    Hosting { session: u32 },
+    Join { session: u32 },
+    Joined { session: u32, peer: u16 },
+    Refused { session: u32 }, // Unknown or full session, no session could be opened for 0
+    // Clients only talk to the host, so towards clients the peer is always 0. Towards the
+    // host it is the sending client, from the host the receiving one.
+    Data { session: u32, peer: u16, payload: Vec<u8> }
+}
+
+// Every message has the same header, so data can be forwarded without decoding the payload
+pub fn serialize_relay_message(message: &RelayMessage) -> Vec<u8> {
+    let (kind, session, peer, payload): (u8, u32, u16, &[u8]) = match message {
+        RelayMessage::Host => (0, 0, 0, &[]),
+        RelayMessage::Hosting { session } => (1, *session, 0, &[]),
+        RelayMessage::Join { session } => (2, *session, 0, &[]),
+        RelayMessage::Joined { session, peer } => (3, *session, *peer, &[]),
+        RelayMessage::Refused { session } => (4, *session, 0, &[]),
+        RelayMessage::Data { session, peer, payload } => (5, *session, *peer, payload)
+    };
+    let mut bytes = Vec::with_capacity(RELAY_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(RELAY_MAGIC);
+    bytes.push(kind);
+    bytes.extend_from_slice(&session.to_le_bytes());
+    bytes.extend_from_slice(&peer.to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn deserialize_relay_message(bytes: &[u8]) -> BbResult<RelayMessage> {
+    if bytes.len() < RELAY_HEADER_SIZE || !bytes.starts_with(RELAY_MAGIC) {
+        return Err(decode_error("Not a relay message"))
+    }
+    let session = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+    let peer = u16::from_le_bytes([bytes[9], bytes[10]]);
+    Ok(match bytes[4] {
+        0 => RelayMessage::Host,
+        1 => RelayMessage::Hosting { session },
+        2 => RelayMessage::Join { session },
+        3 => RelayMessage::Joined { session, peer },
+        4 => RelayMessage::Refused { session },
+        5 => RelayMessage::Data { session, peer, payload: bytes[RELAY_HEADER_SIZE..].to_vec() },
+        n @ _ => return Err(unknown_index(n, "relay message"))
+    })
+}
+
+fn resolve(addr: &str) -> BbResult<SocketAddr> {
+    addr.to_socket_addrs().map_err(BbError::Io)?
+        .next()
+        .ok_or_else(|| relay_failure(&format!("No address found for relay {}", addr)))
+}
+
+fn relay_failure(reason: &str) -> BbError {
+    BbError::Bb(BbErrorType::NetRelayFailure(reason.to_owned()))
+}
+
+// Transport of a peer that is reached through a relay instead of at its own address
+#[derive(Debug)]
+pub struct RelaySocket {
+    socket: UdpSocket,
+    relay_name: String, // As given, for the endpoint shown to others
+    relay_addr: SocketAddr,
+    session: u32,
+    peer: u16
+}
+
+impl RelaySocket {
+    pub fn host(relay_addr: &str) -> BbResult<RelaySocket> {
+        Self::connect(relay_addr, RelayMessage::Host)
+    }
+
+    pub fn join(relay_addr: &str, session: u32) -> BbResult<RelaySocket> {
+        Self::connect(relay_addr, RelayMessage::Join { session })
+    }
+
+    // Blocks until the relay answered, resending the request a few times
+    fn connect(relay_name: &str, request: RelayMessage) -> BbResult<RelaySocket> {
+        let relay_addr = resolve(relay_name)?;
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).map_err(BbError::Io)?;
+        socket.set_read_timeout(Some(Duration::from_secs_f32(RELAY_CONNECT_RESEND_INTERVAL)))
+            .map_err(BbError::Io)?;
+        let mut buffer = [0; MAX_RELAY_DATAGRAM_SIZE];
+        for _ in 0..RELAY_CONNECT_ATTEMPTS {
+            socket.send_to(&serialize_relay_message(&request), relay_addr).map_err(BbError::Io)?;
+            let (len, sender) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    | ErrorKind::ConnectionReset) => continue,
+                Err(e) => return Err(BbError::Io(e))
+            };
+            if sender != relay_addr {
+                continue
+            }
+            let (session, peer) = match deserialize_relay_message(&buffer[..len]) {
+                Ok(RelayMessage::Hosting { session }) => (session, 0),
+                Ok(RelayMessage::Joined { session, peer }) => (session, peer),
+                Ok(RelayMessage::Refused { session: 0 }) =>
+                    return Err(relay_failure("Relay is full")),
+                Ok(RelayMessage::Refused { session }) =>
+                    return Err(relay_failure(&format!("Session {} is unknown or full", session))),
+                _ => continue
+            };
+            socket.set_nonblocking(true).map_err(BbError::Io)?;
+            println!("Relay: Joined session {} at {} as peer {}.", session, relay_name, peer);
+            return Ok(RelaySocket {
+                socket, relay_name: relay_name.to_owned(), relay_addr, session, peer
+            })
+        }
+        Err(relay_failure(&format!("Relay {} did not answer", relay_name)))
+    }
+
+    pub fn get_session(&self) -> u32 {
+        self.session
+    }
+
+    pub fn get_peer(&self) -> u16 {
+        self.peer
+    }
+}
+
+impl Transport for RelaySocket {
+    fn send_to(&mut self, payload: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let peer = match (self.peer, get_virtual_peer(addr)) {
+            (0, Some(peer)) => peer,
+            (_, Some(0)) => 0,
+            _ => return Err(io::Error::new(ErrorKind::AddrNotAvailable,
+                format!("{} can't be reached through the relay", addr)))
+        };
+        self.socket.send_to(&serialize_relay_message(&RelayMessage::Data {
+            session: self.session, peer, payload: payload.to_vec()
+        }), self.relay_addr)?;
+        Ok(payload.len())
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut recv_buffer = [0; MAX_RELAY_DATAGRAM_SIZE];
+        loop {
+            let (len, sender) = self.socket.recv_from(&mut recv_buffer)?;
+            if sender != self.relay_addr {
+                continue
+            }
+            match deserialize_relay_message(&recv_buffer[..len]) {
+                Ok(RelayMessage::Data { session, peer, payload }) if session == self.session => {
+                    let len = payload.len().min(buffer.len());
+                    buffer[..len].copy_from_slice(&payload[..len]);
+                    return Ok((len, get_virtual_addr(peer)))
+                },
+                _ => continue // Late answers to the connect request
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(get_virtual_addr(self.peer))
+    }
+
+    fn get_relay_endpoint(&self) -> Option<String> {
+        Some(format!("{}{}/{}", RELAY_ENDPOINT_PREFIX, self.relay_name, self.session))
+    }
+}
+
+struct RelaySession {
+    host: SocketAddr,
+    peers: HashMap<u16, SocketAddr>,
+    next_peer: u16
+}
+
+struct RelayPeer {
+    session: u32,
+    peer: u16,
+    last_seen: Instant
+}
+
+// Forwards datagrams between the host and clients of each session. Everyone only sends
+// out to the relay, so nobody needs to accept inbound traffic.
+pub struct RelayServer {
+    socket: UdpSocket,
+    sessions: HashMap<u32, RelaySession>,
+    peers: HashMap<SocketAddr, RelayPeer>
+}
+
+impl RelayServer {
+    pub fn bind(port: u16) -> BbResult<RelayServer> {
+        println!("Relay: Listening at {}.", port);
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(BbError::Io)?;
+        socket.set_nonblocking(true).map_err(BbError::Io)?;
+        Ok(RelayServer {
+            socket, sessions: HashMap::new(), peers: HashMap::new()
+        })
+    }
+
+    pub fn get_local_port(&self) -> BbResult<u16> {
+        Ok(self.socket.local_addr().map_err(BbError::Io)?.port())
+    }
+
+    pub fn get_session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn get_peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn run(&mut self) -> BbResult {
+        loop {
+            self.poll()?;
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // Forwards every pending datagram and drops peers that went silent
+    pub fn poll(&mut self) -> BbResult {
+        let mut buffer = [0; MAX_RELAY_DATAGRAM_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                // Stray datagrams aren't worth logging
+                Ok((len, sender)) => if let Ok(message) = deserialize_relay_message(&buffer[..len]) {
+                    self.handle_message(message, sender)
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(BbError::Io(e))
+            }
+        }
+        self.drop_silent_peers();
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: RelayMessage, sender: SocketAddr) {
+        let registered = self.peers.get_mut(&sender).map(|peer| {
+            peer.last_seen = Instant::now();
+            (peer.session, peer.peer)
+        });
+        let answer = match (message, registered) {
+            (RelayMessage::Data { session, peer, payload }, Some((sender_session, sender_peer)))
+                if session == sender_session => {
+                let relay_session = match self.sessions.get(&session) {
+                    Some(relay_session) => relay_session,
+                    None => return
+                };
+                // The host addresses a client, a client always the host
+                let (receiver, peer) = match sender_peer {
+                    0 => match relay_session.peers.get(&peer) {
+                        Some(receiver) => (*receiver, 0),
+                        None => return
+                    },
+                    _ => (relay_session.host, sender_peer)
+                };
+                self.send(&RelayMessage::Data { session, peer, payload }, receiver);
+                return
+            },
+            // Repeated requests, as the answer got lost
+            (RelayMessage::Host, Some((session, 0))) => RelayMessage::Hosting { session },
+            (RelayMessage::Join { session }, Some((sender_session, peer))) if session == sender_session =>
+                RelayMessage::Joined { session, peer },
+            (RelayMessage::Host, None) => self.open_session(sender),
+            (RelayMessage::Join { session }, None) => self.join_session(session, sender),
+            _ => return
+        };
+        self.send(&answer, sender);
+    }
+
+    fn open_session(&mut self, host: SocketAddr) -> RelayMessage {
+        if self.sessions.len() >= MAX_RELAY_SESSIONS || self.peers.len() >= MAX_RELAY_PEERS
+            || self.count_sessions_at(host.ip()) >= MAX_RELAY_SESSIONS_PER_IP
+            || self.count_peers_at(host.ip()) >= MAX_RELAY_PEERS_PER_IP {
+            println!("Relay: Refused to open a session for {}, too many sessions.", host);
+            return RelayMessage::Refused { session: 0 }
+        }
+        let session = loop {
+            let session = rand_u64() as u32;
+            if session != 0 && !self.sessions.contains_key(&session) {
+                break session
+            }
+        };
+        println!("Relay: {} opened session {}.", host, session);
+        self.sessions.insert(session, RelaySession {
+            host, peers: HashMap::new(), next_peer: 1
+        });
+        self.peers.insert(host, RelayPeer {
+            session, peer: 0, last_seen: Instant::now()
+        });
+        RelayMessage::Hosting { session }
+    }
+
+    fn join_session(&mut self, session: u32, client: SocketAddr) -> RelayMessage {
+        if self.peers.len() >= MAX_RELAY_PEERS || self.count_peers_at(client.ip()) >= MAX_RELAY_PEERS_PER_IP {
+            println!("Relay: Refused {} in session {}, too many peers.", client, session);
+            return RelayMessage::Refused { session }
+        }
+        let relay_session = match self.sessions.get_mut(&session) {
+            Some(relay_session) if relay_session.peers.len() < MAX_RELAY_PEERS_PER_SESSION => relay_session,
+            _ => return RelayMessage::Refused { session }
+        };
+        // IDs aren't reused within a session, so a late datagram can't reach the wrong client
+        let peer = relay_session.next_peer;
+        relay_session.next_peer = relay_session.next_peer.wrapping_add(1).max(1);
+        relay_session.peers.insert(peer, client);
+        self.peers.insert(client, RelayPeer {
+            session, peer, last_seen: Instant::now()
+        });
+        println!("Relay: {} joined session {} as peer {}.", client, session, peer);
+        RelayMessage::Joined { session, peer }
+    }
+
+    // Sessions close with their host
+    fn drop_silent_peers(&mut self) {
+        let now = Instant::now();
+        let silent = self.peers.iter()
+            .filter(|(_, peer)| now - peer.last_seen >= Duration::from_secs_f32(RELAY_PEER_TIMEOUT))
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+        for addr in silent.into_iter() {
+            let peer = match self.peers.remove(&addr) {
+                Some(peer) => peer,
+                None => continue // Already dropped along with its session
+            };
+            if peer.peer == 0 {
+                if let Some(relay_session) = self.sessions.remove(&peer.session) {
+                    println!("Relay: Session {} closed.", peer.session);
+                    for client in relay_session.peers.values() {
+                        self.peers.remove(client);
+                    }
+                }
+            } else if let Some(relay_session) = self.sessions.get_mut(&peer.session) {
+                relay_session.peers.remove(&peer.peer);
+            }
+        }
+    }
+
+    fn send(&self, message: &RelayMessage, addr: SocketAddr) {
+        if let Err(e) = self.socket.send_to(&serialize_relay_message(message), addr) {
+            println!("Relay: Failed to send to {}. Reason: {}", addr, e);
+        }
+    }
+
+    fn count_sessions_at(&self, ip: IpAddr) -> usize {
+        self.sessions.values().filter(|session| session.host.ip() == ip).count()
+    }
+
+    fn count_peers_at(&self, ip: IpAddr) -> usize {
+        self.peers.keys().filter(|addr| addr.ip() == ip).count()
+    }
+}
@@ -1,35 +1,148 @@
-use std::{collections::{HashMap, hash_map::Values}, net::SocketAddr, thread, time::{Duration}};
+use std::{collections::{HashMap, HashSet, hash_map::Values}, iter, net::SocketAddr, thread, time::{Duration, Instant}};
 use laminar::SocketEvent;
-use crate::{BbError, BbErrorType, BbResult, ID, net_settings::NetSettings, packet::{Packet, deserialize_packet_unsigned, serialize_packet}, peer::{DisconnectReason, Peer}};
+use crate::{BbError, BbErrorType, BbResult, ID, chat_policy::ChatPolicy, connection_quality::{ConnectionQuality, PING_INTERVAL, QualityTracker}, discovery::{DiscoveryResponder, LobbyInfo}, flood_protection::{FloodGuard, FloodStats, MAX_PENDING_HANDSHAKES}, game_settings::{GameMode, GameSettings}, host_migration::{HostCandidate, MigrationToken}, input_validation::{FLAG_INPUT_STRIKES, INPUT_STRIKE_WINDOW, InputViolation, MAX_INPUT_STRIKES}, lobby_access::{PendingChallenge, check_password_proof}, master_server::MasterRegistration, moderation::{Ban, BanList, HostCommand}, net_settings::NetSettings, packet::{InputState, InputStep, Packet, deserialize_packet_unsigned, peek_handshake_version, serialize_packet}, peer::{CONTROL_STREAM, DEDICATED_SERVER_ID, Delivery, DisconnectReason, Peer}, rand_u64, rejoin::{REJOIN_GRACE_PERIOD, hash_rejoin_token}, transport::TransportSetup, version::GameVersion};
+
+// Senders of malformed packets get a few strikes within the window, then are ignored
+const MAX_MALFORMED_PACKETS: u32 = 5;
+const MALFORMED_PACKET_WINDOW: f32 = 10.0;
+const MALFORMED_SENDER_BLOCK_DURATION: f32 = 60.0;
 
 pub enum ServerEvent {
     ReceivePacket(u16, Packet),
     PlayerConnect(ID, SocketAddr),
+    PlayerRejoin(ID, SocketAddr),
+    PlayerMigrate(ID, u64, Vec<InputStep>), // Gen and latest steps the peer got from the previous host
     PlayerDisconnect(u16, DisconnectReason),
+    ReservationExpired(u16),
     Empty
 }
 
 #[derive(Clone)]
 pub struct ClientConnection(pub ID, pub SocketAddr);
 
+struct MalformedSender {
+    strikes: u32,
+    first_strike: Instant,
+    blocked_until: Option<Instant>
+}
+
+// Players whose input states the match refused
+struct InputOffender {
+    strikes: u32,
+    first_strike: Instant,
+    flagged: bool
+}
+
+// Slot of a player who timed out during a match, until they rejoin or the grace period ends.
+// After a host migration, every remaining peer has one to migrate into.
+struct ReservedSlot {
+    id: ID,
+    token_hash: Option<u64>, // Of the rejoin token the player was given, all a new host knows of it
+    expires_at: Instant,
+    spectator: bool,
+    migrating: bool
+}
+
 pub struct Server {
     settings: NetSettings,
     peer: Peer,
     connections: HashMap<u16, ClientConnection>,
     connections_addr: HashMap<SocketAddr, ID>,
-    curr_id: u16
+    curr_id: Option<u16>, // Next ID to hand out, None once all were. IDs are never reused.
+    accepting_connections: bool,
+    malformed_senders: HashMap<SocketAddr, MalformedSender>,
+    reserved_slots: HashMap<u16, ReservedSlot>,
+    spectators: HashSet<u16>,
+    host_id: Option<u16>, // Player whose client runs alongside this server
+    input_seqs: HashMap<u16, u32>, // Latest input state received of each player
+    pending_challenges: HashMap<SocketAddr, PendingChallenge>,
+    rejoin_tokens: HashMap<u16, u64>, // Handed out with the handshake reply, proves who rejoins
+    bans: BanList,
+    locked: bool, // Refuses new players, unlike accepting_connections only by choice of the host
+    chat_policy: ChatPolicy,
+    discovery: Option<DiscoveryResponder>,
+    master: Option<MasterRegistration>,
+    game_mode: GameMode, // Only known for LAN discovery and the master server
+    quality_trackers: HashMap<u16, QualityTracker>,
+    last_ping_time: Instant,
+    input_offenders: HashMap<u16, InputOffender>,
+    flood_guard: FloodGuard // Against datagrams from addresses that aren't connected
 }
 
 impl Server {
     pub fn host(port: u16, settings: NetSettings) -> BbResult<Server> {
-        println!("Server: Hosting at {}.", port);
+        println!("Server: Hosting at {}{}.", port, if settings.dedicated {
+            " (dedicated)"
+        } else {
+            ""
+        });
+        // A dedicated server is the authority itself, so no player may take the host ID
+        let (curr_id, host_id) = if settings.dedicated {
+            (Some(DEDICATED_SERVER_ID + 1), None)
+        } else {
+            (Some(0), Some(0))
+        };
+        let transport = match settings.relay.as_ref() {
+            Some(relay_addr) => TransportSetup::Relay { relay_addr: relay_addr.to_owned(), session: None },
+            None => TransportSetup::Direct { port: Some(port) }
+        };
+        let peer = Peer::setup(transport, settings.conditions)?;
+        let bans = BanList::load(settings.bans_path.clone());
+        let chat_policy = ChatPolicy::load(settings.chat_filter_path.clone());
+        // Both announce the local port, which relayed players can't reach
+        let (discovery, master) = match peer.get_relay_endpoint() {
+            Some(relay_endpoint) => {
+                println!("Server: Players join via {}.", relay_endpoint);
+                (None, None)
+            },
+            None => (settings.discovery_port.and_then(DiscoveryResponder::bind),
+                settings.master_server.as_deref().and_then(MasterRegistration::register))
+        };
         Ok(Server {
-            settings, peer: Peer::setup(Some(port))?,
+            settings, peer,
             connections: HashMap::new(), connections_addr: HashMap::new(),
-            curr_id: 0
+            curr_id, accepting_connections: true, malformed_senders: HashMap::new(),
+            reserved_slots: HashMap::new(), spectators: HashSet::new(), host_id,
+            input_seqs: HashMap::new(), pending_challenges: HashMap::new(), rejoin_tokens: HashMap::new(),
+            bans, locked: false,
+            chat_policy, discovery, master, game_mode: GameSettings::default().mode,
+            quality_trackers: HashMap::new(), last_ping_time: Instant::now(), input_offenders: HashMap::new(),
+            flood_guard: FloodGuard::new()
         })
     }
 
+    // Carries on a running match whose host left, on the socket the peers already know.
+    // Everyone keeps their ID, new connections are only accepted into the reserved slots.
+    pub fn migrate(peer: Peer, settings: NetSettings, host_id: u16, players: Vec<ID>,
+        spectators: Vec<ID>, tokens: Vec<MigrationToken>) -> Server {
+        println!("Server: Taking over the match at {}.", peer.get_local_port());
+        let expires_at = Instant::now() + Duration::from_secs_f32(REJOIN_GRACE_PERIOD);
+        let slots = players.into_iter().map(|id| (id, false))
+            .chain(spectators.into_iter().map(|id| (id, true)))
+            .map(|(id, spectator)| (id.n, ReservedSlot {
+                token_hash: tokens.iter().find(|token| token.id == id.n).map(|token| token.token_hash),
+                id, expires_at, spectator, migrating: true
+            }))
+            .collect::<HashMap<_, _>>();
+        // Past every known ID, the new host's included
+        let curr_id = slots.keys().copied().chain(iter::once(host_id)).max()
+            .and_then(|id| id.checked_add(1));
+        let bans = BanList::load(settings.bans_path.clone());
+        let chat_policy = ChatPolicy::load(settings.chat_filter_path.clone());
+        let discovery = settings.discovery_port.and_then(DiscoveryResponder::bind);
+        let master = settings.master_server.as_deref().and_then(MasterRegistration::register);
+        Server {
+            settings, peer, connections: HashMap::new(), connections_addr: HashMap::new(),
+            curr_id, accepting_connections: false, malformed_senders: HashMap::new(),
+            reserved_slots: slots, spectators: HashSet::new(), host_id: Some(host_id),
+            input_seqs: HashMap::new(), pending_challenges: HashMap::new(), rejoin_tokens: HashMap::new(),
+            bans, locked: false,
+            chat_policy, discovery, master, game_mode: GameSettings::default().mode,
+            quality_trackers: HashMap::new(), last_ping_time: Instant::now(), input_offenders: HashMap::new(),
+            flood_guard: FloodGuard::new()
+        }
+    }
+
     pub fn get_connections(&self) -> Values<u16, ClientConnection> {
         self.connections.values()
     }
@@ -38,6 +151,57 @@ impl Server {
         self.connections.len()
     }
 
+    // Connections minus spectators, which don't take a player slot
+    pub fn get_player_count(&self) -> usize {
+        self.connections.len() - self.spectators.len()
+    }
+
+    pub fn get_spectators(&self) -> Vec<ID> {
+        self.spectators.iter()
+            .filter_map(|id| self.connections.get(id).map(|conn| conn.0.clone()))
+            .collect()
+    }
+
+    pub fn is_spectator(&self, player_id: u16) -> bool {
+        self.spectators.contains(&player_id)
+    }
+
+    pub fn is_host(&self, player_id: u16) -> bool {
+        self.host_id == Some(player_id)
+    }
+
+    // None for dedicated servers
+    pub fn get_host_id(&self) -> Option<u16> {
+        self.host_id
+    }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.peer.get_local_port()
+    }
+
+    pub fn get_relay_endpoint(&self) -> Option<&str> {
+        self.peer.get_relay_endpoint()
+    }
+
+    // None until the player answered a ping
+    pub fn get_connection_quality(&self, player_id: u16) -> Option<ConnectionQuality> {
+        self.quality_trackers.get(&player_id)
+            .and_then(|tracker| tracker.get_quality(player_id, Instant::now()))
+    }
+
+    pub fn get_quality_table(&self) -> Vec<ConnectionQuality> {
+        let now = Instant::now();
+        let mut entries = self.quality_trackers.iter()
+            .filter_map(|(id, tracker)| tracker.get_quality(*id, now))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|entry| entry.id);
+        entries
+    }
+
+    pub fn get_max_players(&self) -> usize {
+        self.settings.max_players
+    }
+
     pub fn get_conn_by_id(&self, id: u16) -> Option<&ClientConnection> {
         self.connections.get(&id)
     }
@@ -46,9 +210,60 @@ impl Server {
         self.connections_addr.get(&addr)
     }
 
+    pub fn is_dedicated(&self) -> bool {
+        self.settings.dedicated
+    }
+
+    pub fn set_accepting_connections(&mut self, accepting_connections: bool) {
+        self.accepting_connections = accepting_connections;
+        self.refresh_listing();
+        if accepting_connections {
+            self.reserved_slots.clear(); // Match is over
+            self.input_offenders.clear();
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        println!("Server: {} the lobby.", if locked { "Locked" } else { "Unlocked" });
+        self.locked = locked;
+        self.refresh_listing();
+    }
+
+    // Players who kept sending invalid input, which the host was told about
+    pub fn is_flagged(&self, player_id: u16) -> bool {
+        self.input_offenders.get(&player_id).is_some_and(|offender| offender.flagged)
+    }
+
+    pub fn get_flood_stats(&self) -> FloodStats {
+        self.flood_guard.get_stats()
+    }
+
+    pub fn get_ban_list(&self) -> &BanList {
+        &self.bans
+    }
+
+    // Sent along with the handshake reply, the player needs it to take back their slot
+    pub fn get_rejoin_token(&self, player_id: u16) -> Option<u64> {
+        self.rejoin_tokens.get(&player_id).copied()
+    }
+
+    pub fn is_slot_reserved(&self, player_id: u16) -> bool {
+        self.reserved_slots.contains_key(&player_id)
+    }
+
     pub fn disconnect_player(&mut self, player_id: u16, reason: DisconnectReason) -> BbResult {
         if let Some(conn) = self.connections.remove(&player_id) {
             println!("Server: {:?} disconnected. Reason: {:?}", conn.0, reason);
+            self.connections_addr.remove(&conn.1);
+            self.spectators.remove(&player_id);
+            self.input_seqs.remove(&player_id); // A rejoining client counts from the start
+            self.quality_trackers.remove(&player_id);
+            self.rejoin_tokens.remove(&player_id);
+            self.refresh_listing();
             // As this method is also called upon timeouts (which don't simply echo back
             // all client packets), this is done manually here.
             self.send_multicast(Packet::PlayerDisconnect {
@@ -60,65 +275,218 @@ impl Server {
         }
     }
 
+    // Tells the player why before dropping them. Banned players are refused from then on,
+    // under their name as well as from their IP address.
+    pub fn kick_player(&mut self, player_id: u16, message: String, ban: Option<bool>) -> BbResult {
+        let conn = self.connections.get(&player_id).cloned()
+            .ok_or(BbError::Bb(BbErrorType::InvalidPlayerID(player_id)))?;
+        let reason = match ban {
+            Some(persistent) => {
+                self.bans.add(Ban {
+                    name: conn.0.name.clone(), ip: conn.1.ip(), persistent
+                })?;
+                DisconnectReason::Banned
+            },
+            None => DisconnectReason::Kicked
+        };
+        self.expel_player(player_id, reason, message)
+    }
+
+    // Input states are only validated by the match logic, which reports what it refused.
+    // Offenders are warned, flagged to the host and eventually kicked. Returns whether they were.
+    pub fn on_receive_invalid_input(&mut self, player_id: u16, violation: InputViolation) -> BbResult<bool> {
+        let name = match self.connections.get(&player_id) {
+            Some(conn) => conn.0.name.clone(),
+            None => return Ok(false)
+        };
+        println!("Server: Dropped input state of {}^{}. Reason: {}", name, player_id, violation);
+        let now = Instant::now();
+        let offender = self.input_offenders.entry(player_id).or_insert(InputOffender {
+            strikes: 0, first_strike: now, flagged: false
+        });
+        if now.duration_since(offender.first_strike).as_secs_f32() >= INPUT_STRIKE_WINDOW {
+            offender.strikes = 0;
+            offender.first_strike = now;
+        }
+        offender.strikes += 1;
+        if offender.strikes >= MAX_INPUT_STRIKES {
+            println!("Server: Kicking {}^{} after {} invalid input states.", name, player_id, offender.strikes);
+            self.expel_player(player_id, DisconnectReason::InvalidInput, violation.to_string())?;
+            return Ok(true)
+        }
+        if offender.strikes >= FLAG_INPUT_STRIKES && !offender.flagged {
+            offender.flagged = true;
+            println!("Server: Flagged {}^{} for repeated invalid input.", name, player_id);
+            self.send_notice(&format!("Your input was refused ({}). Keep it up and you will be kicked.",
+                violation), player_id)?;
+            if let Some(host_id) = self.host_id.filter(|id| *id != player_id && self.connections.contains_key(id)) {
+                self.send_notice(&format!("{} keeps sending invalid input ({}).", name, violation), host_id)?;
+            }
+        }
+        Ok(false)
+    }
+
+    // Kicks the player, with a message they get to see
+    fn expel_player(&mut self, player_id: u16, reason: DisconnectReason, message: String) -> BbResult {
+        self.send_unicast(Packet::Kick {
+            reason, message
+        }, player_id)?;
+        self.disconnect_player(player_id, reason)
+    }
+
     pub fn send_unicast(&mut self, packet: Packet, target_id: u16) -> BbResult {
         let connections = &self.connections;
         if let Some(conn) = connections.get(&target_id) {
         //                          Is target_id right? Implies sender is always receiver
         //                                                    \/
-            self.peer.send_raw_packet(serialize_packet(packet, target_id), conn.1)
+            let delivery = packet.get_delivery();
+            self.peer.send_raw_packet(serialize_packet(packet, target_id), conn.1, delivery)
         } else {
             Err(BbError::Bb(BbErrorType::InvalidPlayerID(target_id)))
         }
     }
 
     pub fn send_raw_unicast(&mut self, packet_bytes: Vec<u8>, addr: SocketAddr) -> BbResult {
-        self.peer.send_raw_packet(packet_bytes, addr)
+        self.peer.send_raw_packet(packet_bytes, addr, Delivery::ReliableOrdered(CONTROL_STREAM))
     }
 
     pub fn send_multicast(&mut self, packet: Packet, sender: u16) -> BbResult {
+        let delivery = packet.get_delivery();
         let packet_bytes = serialize_packet(packet, sender);
         let peer = &mut self.peer;
         self.connections.values().try_for_each(
-            |conn| peer.send_raw_packet(packet_bytes.clone(), conn.1))
+            |conn| peer.send_raw_packet(packet_bytes.clone(), conn.1, delivery))
     }
 
     pub fn send_multicast_group(&mut self, packet: Packet, sender: u16, targets: &[u16]) -> BbResult {
+        let delivery = packet.get_delivery();
         let packet_bytes = serialize_packet(packet, sender);
         targets.into_iter().try_for_each(|id| {
             if let Some(conn) = self.connections.get(id) {
-                self.peer.send_raw_packet(packet_bytes.clone(), conn.1)
+                self.peer.send_raw_packet(packet_bytes.clone(), conn.1, delivery)
             } else {
                 Err(BbError::Bb(BbErrorType::InvalidPlayerID(*id)))
             }
         })
     }
 
+    // Tells every peer where the others can be reached, should the host leave mid-match.
+    // Relayed peers only know each other by their relay IDs, so their matches end with the host.
+    pub fn send_host_candidates(&mut self) -> BbResult {
+        let (candidates, tokens) = match self.get_relay_endpoint() {
+            Some(..) => (Vec::new(), Vec::new()),
+            None => {
+                let peers = self.connections.values().filter(|conn| !self.is_host(conn.0.n));
+                let candidates = peers.clone()
+                    .filter(|conn| !self.is_spectator(conn.0.n))
+                    .map(|conn| HostCandidate {
+                        id: conn.0.n, addr: conn.1
+                    })
+                    .collect();
+                let tokens = peers
+                    .filter_map(|conn| self.get_rejoin_token(conn.0.n).map(|token| MigrationToken {
+                        id: conn.0.n, token_hash: hash_rejoin_token(token)
+                    }))
+                    .collect();
+                (candidates, tokens)
+            }
+        };
+        self.send_multicast(Packet::HostCandidates {
+            host: self.host_id, candidates, tokens
+        }, 0)
+    }
+
+    pub fn set_game_mode(&mut self, mode: GameMode) {
+        self.game_mode = mode;
+        self.refresh_listing();
+    }
+
+    pub fn get_lobby_info(&self) -> LobbyInfo {
+        LobbyInfo {
+            name: self.settings.lobby_name.clone(), port: self.peer.get_local_port(),
+            version: GameVersion::local(), mode: self.game_mode,
+            players: self.get_player_count() as u16, max_players: self.settings.max_players as u16,
+            password: self.settings.password.is_some(),
+            open: self.accepting_connections && !self.locked
+        }
+    }
+
     pub fn shutdown(&mut self) -> BbResult {
+        if let Some(master) = self.master.as_ref() {
+            if let Err(e) = master.unregister(self.peer.get_local_port()) {
+                println!("Server: Failed to unregister from the master server. Reason: {}", e);
+            }
+        }
+        // Sent as the host, who may have taken over from the player that started the match
         self.send_multicast(Packet::PlayerDisconnect {
             reason: DisconnectReason::HostShutdown
-        }, 0)?;
+        }, self.host_id.unwrap_or(DEDICATED_SERVER_ID))?;
         thread::sleep(Duration::from_secs_f32(1.5));
         self.peer.shutdown()
     }
 
     pub fn poll_received_packets(&mut self) -> BbResult<ServerEvent> {
+        if let Some(discovery) = self.discovery.as_ref() {
+            // Not worth interrupting the match for
+            if let Err(e) = discovery.answer(|| self.get_lobby_info()) {
+                println!("Server: Failed to answer discovery requests. Reason: {}", e);
+            }
+        }
+        if let Some(mut master) = self.master.take() {
+            if let Err(e) = master.update(|| self.get_lobby_info()) {
+                println!("Server: Failed to send heartbeat to the master server. Reason: {}", e);
+            }
+            self.master = Some(master);
+        }
+        if self.last_ping_time.elapsed().as_secs_f32() >= PING_INTERVAL {
+            self.send_pings()?;
+        }
+        if let Some(report) = self.flood_guard.take_report(Instant::now()) {
+            println!("Server: {}", report);
+        }
+        if let Some(id) = self.take_expired_slot() {
+            return Ok(ServerEvent::ReservationExpired(id))
+        }
         if let Some(event) = self.peer.poll_received_packets()? {
             Ok(match event {
                 SocketEvent::Packet(packet) =>  {
                     let sender_addr = packet.addr();
-                    let packet = deserialize_packet_unsigned(packet.payload().to_vec());
-                    if let Some(id) = self.get_connection_by_addr(sender_addr) {
-                        let id = id.clone();
-                        self.handle_internal_packet(packet, id)?
+                    if self.is_sender_blocked(sender_addr) {
+                        return Ok(ServerEvent::Empty)
                     }
-                    else {
-                        self.handle_external_packet(packet, sender_addr)?
+                    // Unknown senders are limited before anything is decoded, let alone answered
+                    if self.get_connection_by_addr(sender_addr).is_none()
+                        && !self.flood_guard.allow(sender_addr, Instant::now()) {
+                        return Ok(ServerEvent::Empty)
+                    }
+                    match deserialize_packet_unsigned(packet.payload().to_vec()) {
+                        Ok(packet) => {
+                            if let Some(id) = self.get_connection_by_addr(sender_addr) {
+                                let id = id.clone();
+                                self.handle_internal_packet(packet, id)?
+                            }
+                            else {
+                                self.handle_external_packet(packet, sender_addr)?
+                            }
+                        },
+                        Err(e) => match peek_handshake_version(packet.payload()) {
+                            Some(version) if !version.is_compatible(&GameVersion::local()) =>
+                                self.refuse_undecodable_handshake(&version, packet.payload().len(),
+                                    sender_addr, e)?,
+                            _ => self.on_receive_malformed_packet(sender_addr, e)?
+                        }
                     }
                 },
                 SocketEvent::Timeout(addr) => {
                     if let Some(id) = self.get_connection_by_addr(addr) {
                         let id = id.clone();
+                        let spectator = self.is_spectator(id.n);
+                        let token = self.get_rejoin_token(id.n);
                         self.disconnect_player(id.n, DisconnectReason::Timeout)?;
+                        // Nobody else can take the slot during a match, so keep it for a while
+                        if !self.accepting_connections && !spectator {
+                            self.reserve_slot(id.clone(), token);
+                        }
                         ServerEvent::PlayerDisconnect(id.n, DisconnectReason::Timeout)
                     } else {
                         ServerEvent::Empty
@@ -147,12 +515,37 @@ impl Server {
                 self.disconnect_player(sender.n, *reason)?;
                 return Ok(ServerEvent::PlayerDisconnect(sender.n, *reason))
             },
-            Packet::Input { .. } => {
-                return Ok(ServerEvent::ReceivePacket(sender.n, packet))
+            // Spectators don't own a ship and have no say in the match
+            Packet::Input { .. } | Packet::Sync { .. } | Packet::Selection { .. } | Packet::Game { .. }
+                if self.is_spectator(sender.n) => {
+                println!("Server: Dropped {:?} from spectator {:?}.", packet, sender);
+                return Ok(ServerEvent::Empty)
+            },
+            Packet::Input { seq, states } => {
+                return Ok(self.take_new_input_states(sender.n, *seq, states))
             },
             Packet::Sync { .. } => {
                 return Ok(ServerEvent::ReceivePacket(sender.n, packet))
             },
+            Packet::Migrate { .. } => {
+                return Ok(ServerEvent::Empty) // Resent until the peer heard back from the new host
+            },
+            Packet::Pong { seq } => {
+                if let Some(tracker) = self.quality_trackers.get_mut(&sender.n) {
+                    tracker.on_pong(*seq, Instant::now());
+                }
+                return Ok(ServerEvent::Empty)
+            },
+            Packet::ChatMessage { message } if message.starts_with('/') => {
+                return self.on_receive_command(sender.n, message)
+            },
+            Packet::ChatMessage { message } => {
+                if let Err(warning) = self.chat_policy.check(&sender.name, message) {
+                    println!("Server: Dropped chat message of {:?}. Reason: {}", sender, warning);
+                    self.send_notice(&warning, sender.n)?;
+                    return Ok(ServerEvent::Empty)
+                }
+            },
             _ => () // Should filter invalid packets here
         }
         // Echo is done further down the hierarchy
@@ -163,13 +556,78 @@ impl Server {
     fn handle_external_packet(&mut self, packet: Packet, sender_addr: SocketAddr)
         -> BbResult<ServerEvent> {
         Ok(match &packet {
-            Packet::Handshake { name } => {
-                if self.connections.len() >= self.settings.max_players {
-                    println!("Server: Blocked connection attempt by {} ({}). Reason: Server is full.",
+            Packet::Handshake { version, name, spectator, cookie, rejoin_token } => {
+                let now = Instant::now();
+                // Nothing but the cookie is sent before the sender proved its address
+                if !self.flood_guard.check_cookie(sender_addr, *cookie, now) {
+                    // Costs no more than the handshake itself, and only reaches the actual sender
+                    let cookie = self.flood_guard.gen_cookie(sender_addr, now);
+                    self.send_raw_unicast(serialize_packet(Packet::HandshakeCookie {
+                        cookie
+                    }, 0), sender_addr)?;
+                    ServerEvent::Empty
+                } else if !version.is_compatible(&GameVersion::local()) {
+                    self.refuse_incompatible_version(version, sender_addr)?
+                } else if self.bans.is_banned(name, sender_addr.ip()) {
+                    println!("Server: Refused connection attempt by {} ({}). Reason: Banned.",
+                        name, sender_addr);
+                    self.refuse_connection(DisconnectReason::Banned, sender_addr)?
+                } else if !self.settings.is_invited(name) {
+                    println!("Server: Refused connection attempt by {} ({}). Reason: Not invited.",
                         name, sender_addr);
+                    self.refuse_connection(DisconnectReason::NotInvited, sender_addr)?
+                } else if self.settings.password.is_some() {
+                    self.send_challenge(name.to_owned(), *spectator, *rejoin_token, sender_addr)?
+                } else {
+                    self.admit_handshake(name, *spectator, *rejoin_token, sender_addr)?
+                }
+            },
+            Packet::ChallengeAnswer { nonce, proof } => {
+                match self.pending_challenges.remove(&sender_addr) {
+                    Some(challenge) if challenge.is_expired() => {
+                        println!("Server: Dropped late challenge answer by {} ({}).",
+                            challenge.name, sender_addr);
+                        ServerEvent::Empty
+                    },
+                    Some(challenge) => {
+                        let password = self.settings.password.as_deref().unwrap_or_default();
+                        if check_password_proof(challenge.salt, *nonce, password, proof) {
+                            self.admit_handshake(&challenge.name, challenge.spectator, challenge.rejoin_token,
+                                sender_addr)?
+                        } else {
+                            println!("Server: Refused connection attempt by {} ({}). Reason: Wrong password.",
+                                challenge.name, sender_addr);
+                            self.refuse_connection(DisconnectReason::WrongPassword, sender_addr)?
+                        }
+                    },
+                    None => {
+                        println!("Server: Dropped challenge answer from {}. Reason: No challenge sent.",
+                            sender_addr);
+                        ServerEvent::Empty
+                    }
+                }
+            },
+            Packet::Migrate { id, name, rejoin_token, cookie, gen, steps } => {
+                let now = Instant::now();
+                let token_hash = hash_rejoin_token(*rejoin_token);
+                if !self.flood_guard.check_cookie(sender_addr, *cookie, now) {
+                    let cookie = self.flood_guard.gen_cookie(sender_addr, now);
+                    self.send_raw_unicast(serialize_packet(Packet::HandshakeCookie {
+                        cookie
+                    }, 0), sender_addr)?;
                     ServerEvent::Empty
                 } else {
-                    self.on_receive_handshake(name.to_owned(), sender_addr)
+                    match self.reserved_slots.get(id) {
+                        Some(slot) if slot.migrating && slot.id.name == *name && slot.token_hash == Some(token_hash) => {
+                            let id = slot.id.clone();
+                            self.on_receive_migration(id, *rejoin_token, sender_addr, *gen, steps.clone())
+                        },
+                        _ => {
+                            println!("Server: Blocked migration attempt by {} ^{} ({}). Reason: No such peer.",
+                                name, id, sender_addr);
+                            ServerEvent::Empty
+                        }
+                    }
                 }
             },
             _ => {
@@ -179,18 +637,326 @@ impl Server {
         })
     }
 
-    fn on_receive_handshake(&mut self, name: String, remote_addr: SocketAddr) -> ServerEvent {
-        let new_idn = self.curr_id;
+    // Handshakes that passed the version, ban, invite and password checks
+    fn admit_handshake(&mut self, name: &str, spectator: bool, rejoin_token: Option<u64>,
+        sender_addr: SocketAddr) -> BbResult<ServerEvent> {
+        let reserved_slot = rejoin_token.and_then(|token| self.find_reserved_slot(token).map(|id| (id, token)));
+        Ok(if let Some((id, token)) = reserved_slot.filter(|_| !spectator) {
+            self.on_receive_rejoin(id, token, sender_addr)
+        } else if self.locked {
+            println!("Server: Refused connection attempt by {} ({}). Reason: Lobby is locked.",
+                name, sender_addr);
+            self.refuse_connection(DisconnectReason::LobbyLocked, sender_addr)?
+        } else if self.curr_id.is_none() {
+            println!("Server: Refused connection attempt by {} ({}). Reason: No IDs left.",
+                name, sender_addr);
+            self.refuse_connection(DisconnectReason::ServerBusy, sender_addr)?
+        } else if spectator {
+            // Spectators don't take part in the match, so they may join at any time
+            if self.spectators.len() >= self.settings.max_spectators {
+                println!("Server: Refused spectator {} ({}). Reason: No spectator slots left.",
+                    name, sender_addr);
+                self.refuse_connection(DisconnectReason::SpectatorsFull, sender_addr)?
+            } else {
+                self.on_receive_handshake(name.to_owned(), sender_addr, true)
+            }
+        } else if !self.accepting_connections {
+            println!("Server: Refused connection attempt by {} ({}). Reason: Match in progress.",
+                name, sender_addr);
+            self.refuse_connection(DisconnectReason::MatchInProgress, sender_addr)?
+        } else if self.get_player_count() >= self.settings.max_players {
+            println!("Server: Refused connection attempt by {} ({}). Reason: Server is full.",
+                name, sender_addr);
+            self.refuse_connection(DisconnectReason::LobbyFull, sender_addr)?
+        } else {
+            self.on_receive_handshake(name.to_owned(), sender_addr, false)
+        })
+    }
+
+    // Chat commands of the host, which are never passed on to the other players
+    fn on_receive_command(&mut self, sender: u16, message: &str) -> BbResult<ServerEvent> {
+        if !self.is_host(sender) {
+            self.send_notice("Only the host can use commands.", sender)?;
+            return Ok(ServerEvent::Empty)
+        }
+        let command = match HostCommand::parse(message) {
+            Ok(command) => command,
+            Err(usage) => {
+                self.send_notice(&usage, sender)?;
+                return Ok(ServerEvent::Empty)
+            }
+        };
+        match command {
+            HostCommand::Kick { name, message } => return self.on_kick_command(sender, &name, message, None),
+            HostCommand::Ban { name, message, persistent } => {
+                return self.on_kick_command(sender, &name, message, Some(persistent))
+            },
+            HostCommand::Unban { name } => {
+                let notice = match self.bans.remove(&name)? {
+                    true => format!("Unbanned {}.", name),
+                    false => format!("{} is not banned.", name)
+                };
+                self.send_notice(&notice, sender)?;
+            },
+            HostCommand::Mute { name, minutes } => {
+                if self.find_connection_by_name(&name) == Some(sender) {
+                    self.send_notice("You can't mute yourself.", sender)?;
+                } else {
+                    self.chat_policy.mute(&name, Duration::from_secs(minutes * 60));
+                    self.send_notice(&format!("Muted {} for {} minute(s).", name, minutes), sender)?;
+                    if let Some(id) = self.find_connection_by_name(&name) {
+                        self.send_notice(&format!("The host muted you for {} minute(s).", minutes), id)?;
+                    }
+                }
+            },
+            HostCommand::Unmute { name } => {
+                let notice = match self.chat_policy.unmute(&name) {
+                    true => format!("Unmuted {}.", name),
+                    false => format!("{} is not muted.", name)
+                };
+                self.send_notice(&notice, sender)?;
+            },
+            HostCommand::Lock(locked) => {
+                self.set_locked(locked);
+                // Everyone should know why nobody else joins
+                self.send_multicast(Packet::Notice {
+                    message: format!("The host {} the lobby.", if locked { "locked" } else { "unlocked" })
+                }, sender)?;
+            }
+        }
+        Ok(ServerEvent::Empty)
+    }
+
+    fn on_kick_command(&mut self, sender: u16, name: &str, message: String, ban: Option<bool>)
+        -> BbResult<ServerEvent> {
+        match self.find_connection_by_name(name) {
+            Some(id) if id == sender => self.send_notice("You can't kick yourself.", sender)?,
+            Some(id) => {
+                self.kick_player(id, message, ban)?;
+                return Ok(ServerEvent::PlayerDisconnect(id, match ban {
+                    Some(_) => DisconnectReason::Banned,
+                    None => DisconnectReason::Kicked
+                }))
+            },
+            None => self.send_notice(&format!("No player named {}.", name), sender)?
+        }
+        Ok(ServerEvent::Empty)
+    }
+
+    fn find_connection_by_name(&self, name: &str) -> Option<u16> {
+        self.connections.values()
+            .find(|conn| conn.0.name == name)
+            .map(|conn| conn.0.n)
+    }
+
+    fn send_notice(&mut self, notice: &str, target_id: u16) -> BbResult {
+        self.send_unicast(Packet::Notice {
+            message: notice.to_owned()
+        }, target_id)
+    }
+
+    fn send_challenge(&mut self, name: String, spectator: bool, rejoin_token: Option<u64>,
+        sender_addr: SocketAddr) -> BbResult<ServerEvent> {
+        self.pending_challenges.retain(|_, challenge| !challenge.is_expired());
+        if self.pending_challenges.len() >= MAX_PENDING_HANDSHAKES
+            && !self.pending_challenges.contains_key(&sender_addr) {
+            self.flood_guard.on_pending_handshakes_full();
+            return self.refuse_connection(DisconnectReason::ServerBusy, sender_addr)
+        }
+        let salt = rand_u64();
+        self.pending_challenges.insert(sender_addr, PendingChallenge {
+            name, spectator, rejoin_token, salt, sent_at: Instant::now()
+        });
+        self.send_raw_unicast(serialize_packet(Packet::Challenge {
+            salt
+        }, 0), sender_addr)?;
+        Ok(ServerEvent::Empty)
+    }
+
+    fn refuse_connection(&mut self, reason: DisconnectReason, sender_addr: SocketAddr)
+        -> BbResult<ServerEvent> {
+        self.send_raw_unicast(serialize_packet(Packet::PlayerDisconnect {
+            reason
+        }, 0), sender_addr)?;
+        Ok(ServerEvent::Empty)
+    }
+
+    fn refuse_incompatible_version(&mut self, version: &GameVersion, sender_addr: SocketAddr)
+        -> BbResult<ServerEvent> {
+        let local_version = GameVersion::local();
+        println!("Server: Refused connection attempt by {}. Reason: Incompatible version {:?}, expected {:?}.",
+            sender_addr, version, local_version);
+        self.refuse_connection(DisconnectReason::Incompatible(local_version), sender_addr)
+    }
+
+    // Other versions may not know about cookies, so their address stays unproven. They are
+    // only told why if the refusal is no larger than their handshake, which leaves nothing
+    // to gain from spoofing it.
+    fn refuse_undecodable_handshake(&mut self, version: &GameVersion, handshake_len: usize,
+        sender_addr: SocketAddr, e: BbError) -> BbResult<ServerEvent> {
+        let refusal_len = serialize_packet(Packet::PlayerDisconnect {
+            reason: DisconnectReason::Incompatible(GameVersion::local())
+        }, 0).len();
+        if refusal_len > handshake_len {
+            return self.on_receive_malformed_packet(sender_addr, e)
+        }
+        self.refuse_incompatible_version(version, sender_addr)
+    }
+
+    // Each input packet repeats the latest few states, only those not seen yet are passed on
+    fn take_new_input_states(&mut self, player_id: u16, seq: u32, states: &[InputState]) -> ServerEvent {
+        let new_count = match self.input_seqs.get(&player_id) {
+            Some(last_seq) => seq.saturating_sub(*last_seq) as usize,
+            None => states.len()
+        }.min(states.len());
+        if new_count == 0 {
+            return ServerEvent::Empty // Resent while waiting for the next step
+        }
+        self.input_seqs.insert(player_id, seq);
+        ServerEvent::ReceivePacket(player_id, Packet::Input {
+            seq, states: states[states.len() - new_count..].to_vec()
+        })
+    }
+
+    fn is_sender_blocked(&self, addr: SocketAddr) -> bool {
+        self.malformed_senders.get(&addr)
+            .and_then(|sender| sender.blocked_until)
+            .is_some_and(|blocked_until| Instant::now() < blocked_until)
+    }
+
+    fn on_receive_malformed_packet(&mut self, sender_addr: SocketAddr, error: BbError)
+        -> BbResult<ServerEvent> {
+        println!("Server: Dropped malformed packet from {}. Reason: {:?}", sender_addr, error);
+        let now = Instant::now();
+        // Forget senders whose strikes or blocks ran out
+        self.malformed_senders.retain(|_, sender| match sender.blocked_until {
+            Some(blocked_until) => now < blocked_until,
+            None => now.duration_since(sender.first_strike).as_secs_f32() < MALFORMED_PACKET_WINDOW
+        });
+        let sender = self.malformed_senders.entry(sender_addr).or_insert(MalformedSender {
+            strikes: 0, first_strike: now, blocked_until: None
+        });
+        sender.strikes += 1;
+        if sender.strikes < MAX_MALFORMED_PACKETS {
+            return Ok(ServerEvent::Empty)
+        }
+
+        println!("Server: Blocking {} for {}s after {} malformed packets.", sender_addr,
+            MALFORMED_SENDER_BLOCK_DURATION, sender.strikes);
+        sender.blocked_until = Some(now + Duration::from_secs_f32(MALFORMED_SENDER_BLOCK_DURATION));
+        if let Some(id) = self.get_connection_by_addr(sender_addr) {
+            let id = id.clone();
+            self.send_unicast(Packet::PlayerDisconnect {
+                reason: DisconnectReason::InvalidPackets
+            }, id.n)?;
+            self.disconnect_player(id.n, DisconnectReason::InvalidPackets)?;
+            Ok(ServerEvent::PlayerDisconnect(id.n, DisconnectReason::InvalidPackets))
+        } else {
+            Ok(ServerEvent::Empty)
+        }
+    }
+
+    fn on_receive_handshake(&mut self, name: String, remote_addr: SocketAddr, spectator: bool)
+        -> ServerEvent {
+        let new_idn = match self.curr_id {
+            Some(id) => id,
+            None => return ServerEvent::Empty // Refused on admission
+        };
         let new_id = ID::new(name, new_idn);
-        println!("Server: {:?} ({:?}) joined the server.", new_id, remote_addr);
+        println!("Server: {:?} ({:?}) joined the server{}.", new_id, remote_addr, match spectator {
+            true => " as spectator",
+            false => ""
+        });
         self.add_connection(ClientConnection(new_id.clone(), remote_addr));
+        self.rejoin_tokens.insert(new_idn, rand_u64());
+        if spectator {
+            self.spectators.insert(new_idn);
+        }
 
-        self.curr_id += 1;
+        self.curr_id = new_idn.checked_add(1);
         ServerEvent::PlayerConnect(new_id, remote_addr)
     }
 
+    fn on_receive_rejoin(&mut self, id: ID, token: u64, remote_addr: SocketAddr) -> ServerEvent {
+        self.take_reserved_slot(id.clone(), token, remote_addr);
+        println!("Server: {:?} ({:?}) rejoined the match.", id, remote_addr);
+        ServerEvent::PlayerRejoin(id, remote_addr)
+    }
+
+    fn on_receive_migration(&mut self, id: ID, token: u64, remote_addr: SocketAddr, gen: u64,
+        steps: Vec<InputStep>) -> ServerEvent {
+        self.take_reserved_slot(id.clone(), token, remote_addr);
+        println!("Server: {:?} ({:?}) migrated at gen {}.", id, remote_addr, gen);
+        ServerEvent::PlayerMigrate(id, gen, steps)
+    }
+
+    // Players keep the token they proved themselves with, so they can rejoin again after the next
+    // timeout or migration
+    fn take_reserved_slot(&mut self, id: ID, token: u64, remote_addr: SocketAddr) {
+        if let Some(slot) = self.reserved_slots.remove(&id.n) {
+            if slot.spectator {
+                self.spectators.insert(slot.id.n);
+            }
+            self.rejoin_tokens.insert(id.n, token);
+        }
+        self.add_connection(ClientConnection(id, remote_addr));
+    }
+
+    fn reserve_slot(&mut self, id: ID, token: Option<u64>) {
+        println!("Server: Reserving slot of {:?} for {}s.", id, REJOIN_GRACE_PERIOD);
+        self.reserved_slots.insert(id.n, ReservedSlot {
+            id, token_hash: token.map(hash_rejoin_token),
+            expires_at: Instant::now() + Duration::from_secs_f32(REJOIN_GRACE_PERIOD),
+            spectator: false, migrating: false
+        });
+    }
+
+    // Names can be taken by anyone, only the token proves the player is the one who left
+    fn find_reserved_slot(&self, token: u64) -> Option<ID> {
+        self.reserved_slots.values()
+            .find(|slot| !slot.migrating && slot.token_hash == Some(hash_rejoin_token(token)))
+            .map(|slot| slot.id.clone())
+    }
+
+    fn take_expired_slot(&mut self) -> Option<u16> {
+        let now = Instant::now();
+        let id = self.reserved_slots.values()
+            .find(|slot| now >= slot.expires_at)
+            .map(|slot| slot.id.clone())?;
+        println!("Server: {:?} did not rejoin in time. Releasing slot...", id);
+        self.reserved_slots.remove(&id.n);
+        Some(id.n)
+    }
+
+    // Everyone gets the table along with the next ping, including the latest round trips
+    fn send_pings(&mut self) -> BbResult {
+        let now = Instant::now();
+        self.last_ping_time = now;
+        let entries = self.get_quality_table();
+        let pings = self.quality_trackers.iter_mut()
+            .map(|(id, tracker)| (*id, tracker.send_ping(now)))
+            .collect::<Vec<_>>();
+        for (id, seq) in pings.into_iter() {
+            self.send_unicast(Packet::Ping {
+                seq
+            }, id)?;
+        }
+        self.send_multicast(Packet::QualityTable {
+            entries
+        }, 0)
+    }
+
     fn add_connection(&mut self, conn: ClientConnection) {
+        self.quality_trackers.insert(conn.0.n, QualityTracker::new());
         self.connections.insert(conn.0.n, conn.clone());
         self.connections_addr.insert(conn.1, conn.0);
+        self.refresh_listing();
+    }
+
+    // Browsers shouldn't have to wait for the next heartbeat to see the lobby changed
+    fn refresh_listing(&mut self) {
+        if let Some(master) = self.master.as_mut() {
+            master.refresh();
+        }
     }
 }
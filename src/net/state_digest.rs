@@ -0,0 +1,504 @@
+use std::{collections::{HashMap, VecDeque}, fmt};
+use binary_stream::{BinaryStream, Serializable};
+use rapier2d::data::Index;
+use crate::{BbResult, decode::{Decode, DecodeStream, unknown_index}, entity::EntityType, sync_checker::{DESIRED_SYNC_STATES_BUFFER_SIZE, SyncState}};
+
+// Gens of digests every client keeps around, to answer requests after a desync was detected
+pub const DIGEST_HISTORY_LENGTH: usize = DESIRED_SYNC_STATES_BUFFER_SIZE;
+// Large worlds easily exceed the maximum laminar packet size, so digests are sent in parts
+pub const DIGEST_PART_ENTITIES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DigestField {
+    PosX,
+    PosY,
+    Rot,
+    VelX,
+    VelY,
+    Health,
+    Balance,
+    Networth,
+    Sinkings,
+    Destroy,
+    StunTime,
+    TargetX,
+    TargetY,
+    RotateOnly,
+    InHarbour,
+    CannonReload(u8),
+    CannonDamage(u8),
+    CannonReloadTime(u8),
+    CannonShootingPower(u8),
+    ModCount,
+    Mod(u8),
+    Damage,
+    State,
+    Shooter
+}
+
+impl fmt::Display for DigestField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestField::CannonReload(i) => write!(f, "Cannon {} Reload", i),
+            DigestField::CannonDamage(i) => write!(f, "Cannon {} Damage", i),
+            DigestField::CannonReloadTime(i) => write!(f, "Cannon {} Reload Time", i),
+            DigestField::CannonShootingPower(i) => write!(f, "Cannon {} Shooting Power", i),
+            DigestField::Mod(i) => write!(f, "Mod {}", i),
+            field => write!(f, "{:?}", field)
+        }
+    }
+}
+
+impl Serializable for DigestField {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        let (n, i) = match self {
+            DigestField::PosX => (0, None),
+            DigestField::PosY => (1, None),
+            DigestField::Rot => (2, None),
+            DigestField::VelX => (3, None),
+            DigestField::VelY => (4, None),
+            DigestField::Health => (5, None),
+            DigestField::Balance => (6, None),
+            DigestField::Networth => (7, None),
+            DigestField::Sinkings => (8, None),
+            DigestField::Destroy => (9, None),
+            DigestField::StunTime => (10, None),
+            DigestField::TargetX => (11, None),
+            DigestField::TargetY => (12, None),
+            DigestField::RotateOnly => (13, None),
+            DigestField::InHarbour => (14, None),
+            DigestField::CannonReload(i) => (15, Some(*i)),
+            DigestField::CannonDamage(i) => (16, Some(*i)),
+            DigestField::CannonReloadTime(i) => (17, Some(*i)),
+            DigestField::CannonShootingPower(i) => (18, Some(*i)),
+            DigestField::ModCount => (19, None),
+            DigestField::Mod(i) => (20, Some(*i)),
+            DigestField::Damage => (21, None),
+            DigestField::State => (22, None),
+            DigestField::Shooter => (23, None)
+        };
+        stream.write_buffer_single(n).unwrap();
+        if let Some(i) = i {
+            stream.write_buffer_single(i).unwrap();
+        }
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for DigestField {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
+            0 => DigestField::PosX,
+            1 => DigestField::PosY,
+            2 => DigestField::Rot,
+            3 => DigestField::VelX,
+            4 => DigestField::VelY,
+            5 => DigestField::Health,
+            6 => DigestField::Balance,
+            7 => DigestField::Networth,
+            8 => DigestField::Sinkings,
+            9 => DigestField::Destroy,
+            10 => DigestField::StunTime,
+            11 => DigestField::TargetX,
+            12 => DigestField::TargetY,
+            13 => DigestField::RotateOnly,
+            14 => DigestField::InHarbour,
+            15 => DigestField::CannonReload(stream.decode_u8()?),
+            16 => DigestField::CannonDamage(stream.decode_u8()?),
+            17 => DigestField::CannonReloadTime(stream.decode_u8()?),
+            18 => DigestField::CannonShootingPower(stream.decode_u8()?),
+            19 => DigestField::ModCount,
+            20 => DigestField::Mod(stream.decode_u8()?),
+            21 => DigestField::Damage,
+            22 => DigestField::State,
+            23 => DigestField::Shooter,
+            n @ _ => return Err(unknown_index(n, "digest field"))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DigestValue {
+    Bool(bool),
+    U16(u16),
+    U32(u32),
+    F32(f32),
+    Index(u32, u32)
+}
+
+impl PartialEq for DigestValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DigestValue::Bool(a), DigestValue::Bool(b)) => a == b,
+            (DigestValue::U16(a), DigestValue::U16(b)) => a == b,
+            (DigestValue::U32(a), DigestValue::U32(b)) => a == b,
+            // Bitwise, as even the smallest rounding difference is a desync
+            (DigestValue::F32(a), DigestValue::F32(b)) => a.to_bits() == b.to_bits(),
+            (DigestValue::Index(a1, a2), DigestValue::Index(b1, b2)) => a1 == b1 && a2 == b2,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for DigestValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestValue::Bool(val) => write!(f, "{}", val),
+            DigestValue::U16(val) => write!(f, "{}", val),
+            DigestValue::U32(val) => write!(f, "{}", val),
+            DigestValue::F32(val) => write!(f, "{:?} ({:#010x})", val, val.to_bits()),
+            DigestValue::Index(i, gen) => write!(f, "#{}v{}", i, gen)
+        }
+    }
+}
+
+impl From<Index> for DigestValue {
+    fn from(index: Index) -> Self {
+        let (i, gen) = index.into_raw_parts();
+        DigestValue::Index(i, gen)
+    }
+}
+
+impl From<bool> for DigestValue {
+    fn from(val: bool) -> Self {
+        DigestValue::Bool(val)
+    }
+}
+
+impl From<u16> for DigestValue {
+    fn from(val: u16) -> Self {
+        DigestValue::U16(val)
+    }
+}
+
+impl From<u32> for DigestValue {
+    fn from(val: u32) -> Self {
+        DigestValue::U32(val)
+    }
+}
+
+impl From<f32> for DigestValue {
+    fn from(val: f32) -> Self {
+        DigestValue::F32(val)
+    }
+}
+
+impl Serializable for DigestValue {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        match self {
+            DigestValue::Bool(val) => {
+                stream.write_buffer_single(0).unwrap();
+                stream.write_bool(*val).unwrap();
+            },
+            DigestValue::U16(val) => {
+                stream.write_buffer_single(1).unwrap();
+                stream.write_u16(*val).unwrap();
+            },
+            DigestValue::U32(val) => {
+                stream.write_buffer_single(2).unwrap();
+                stream.write_u32(*val).unwrap();
+            },
+            DigestValue::F32(val) => {
+                stream.write_buffer_single(3).unwrap();
+                stream.write_f32(*val).unwrap();
+            },
+            DigestValue::Index(i, gen) => {
+                stream.write_buffer_single(4).unwrap();
+                stream.write_u32(*i).unwrap();
+                stream.write_u32(*gen).unwrap();
+            }
+        }
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for DigestValue {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
+            0 => DigestValue::Bool(stream.decode_bool()?),
+            1 => DigestValue::U16(stream.decode_u16()?),
+            2 => DigestValue::U32(stream.decode_u32()?),
+            3 => DigestValue::F32(stream.decode_f32()?),
+            4 => DigestValue::Index(stream.decode_u32()?, stream.decode_u32()?),
+            n @ _ => return Err(unknown_index(n, "digest value"))
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EntityDigest {
+    pub index: (u32, u32),
+    pub entity_type: EntityType,
+    pub name: String,
+    pub fields: Vec<(DigestField, DigestValue)>
+}
+
+impl EntityDigest {
+    pub fn new(index: Index, entity_type: EntityType, name: String) -> EntityDigest {
+        EntityDigest {
+            index: index.into_raw_parts(), entity_type, name, fields: Vec::new()
+        }
+    }
+
+    pub fn add<T: Into<DigestValue>>(&mut self, field: DigestField, value: T) {
+        self.fields.push((field, value.into()));
+    }
+
+    fn get_field(&self, field: DigestField) -> Option<DigestValue> {
+        self.fields.iter().find(|(f, _)| *f == field).map(|(_, v)| *v)
+    }
+}
+
+impl fmt::Display for EntityDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?} #{}v{})", self.name, self.entity_type, self.index.0, self.index.1)
+    }
+}
+
+impl Serializable for EntityDigest {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_u32(self.index.0).unwrap();
+        stream.write_u32(self.index.1).unwrap();
+        stream.write_buffer_single(self.entity_type.to_num() as u8).unwrap();
+        stream.write_string(&self.name).unwrap();
+        stream.write_u16(self.fields.len() as u16).unwrap();
+        for (field, value) in self.fields.iter() {
+            field.to_stream(stream);
+            value.to_stream(stream);
+        }
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for EntityDigest {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let index = (stream.decode_u32()?, stream.decode_u32()?);
+        let entity_type = match stream.decode_u8()? {
+            n @ 0..=3 => EntityType::to_entity_type(n as u128),
+            n @ _ => return Err(unknown_index(n, "entity type"))
+        };
+        let name = stream.decode_string()?;
+        let len = stream.decode_u16()? as usize;
+        let mut fields = Vec::with_capacity(len.min(stream.size()));
+        for _ in 0..len {
+            let field = DigestField::decode(stream)?;
+            let value = DigestValue::decode(stream)?;
+            fields.push((field, value));
+        }
+        Ok(EntityDigest {
+            index, entity_type, name, fields
+        })
+    }
+}
+
+// Every simulated field of every entity in the world at the end of a generation.
+// Where the sync state hash only tells that clients diverged, digests tell where.
+#[derive(Debug, Clone)]
+pub struct StateDigest {
+    pub gen: u64,
+    pub entities: Vec<EntityDigest>
+}
+
+impl StateDigest {
+    pub fn new(gen: u64) -> StateDigest {
+        StateDigest {
+            gen, entities: Vec::new()
+        }
+    }
+
+    pub fn gen_hash(&self) -> SyncState {
+        let mut stream = BinaryStream::new();
+        stream.write_vec(&self.entities).unwrap();
+        SyncState::gen(self.gen, &stream.get_buffer_vec())
+    }
+
+    pub fn split(&self) -> Vec<StateDigestPart> {
+        let chunks = self.entities.chunks(DIGEST_PART_ENTITIES).collect::<Vec<_>>();
+        let count = chunks.len().max(1) as u16;
+        if chunks.is_empty() {
+            return vec![StateDigestPart::new(self.gen, 0, count, Vec::new())]
+        }
+        chunks.into_iter().enumerate().map(|(i, chunk)| StateDigestPart::new(
+            self.gen, i as u16, count, chunk.to_vec())).collect()
+    }
+
+    // Compares entities in world order, returns the first mismatch
+    pub fn diff(&self, other: &StateDigest) -> Option<DigestMismatch> {
+        let other_entities = other.entities.iter()
+            .map(|e| (e.index, e))
+            .collect::<HashMap<_, _>>();
+        for entity in self.entities.iter() {
+            let other_entity = match other_entities.get(&entity.index) {
+                Some(other_entity) => other_entity,
+                None => return Some(DigestMismatch::new(self.gen, entity.to_string(), None, None, None))
+            };
+            for (field, value) in entity.fields.iter() {
+                let other_value = other_entity.get_field(*field);
+                if other_value != Some(*value) {
+                    return Some(DigestMismatch::new(self.gen, entity.to_string(), Some(*field),
+                        Some(*value), other_value))
+                }
+            }
+            if entity.fields.len() != other_entity.fields.len() {
+                let field = other_entity.fields.iter()
+                    .find(|(f, _)| entity.get_field(*f).is_none())
+                    .map(|(f, _)| *f);
+                return Some(DigestMismatch::new(self.gen, entity.to_string(), field, None,
+                    field.and_then(|f| other_entity.get_field(f))))
+            }
+        }
+        // Entities only the other client simulates
+        let indices = self.entities.iter().map(|e| e.index).collect::<Vec<_>>();
+        other.entities.iter()
+            .find(|e| !indices.contains(&e.index))
+            .map(|e| DigestMismatch::new(self.gen, e.to_string(), None, None, None))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DigestMismatch {
+    pub gen: u64,
+    pub entity: String,
+    pub field: Option<DigestField>, // None, if the entity only exists for one client
+    pub expected: Option<DigestValue>,
+    pub actual: Option<DigestValue>
+}
+
+impl DigestMismatch {
+    pub fn new(gen: u64, entity: String, field: Option<DigestField>,
+        expected: Option<DigestValue>, actual: Option<DigestValue>) -> DigestMismatch {
+        DigestMismatch {
+            gen, entity, field, expected, actual
+        }
+    }
+}
+
+impl fmt::Display for DigestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_value = |value: Option<DigestValue>| value
+            .map_or("missing".to_owned(), |v| v.to_string());
+        match self.field {
+            Some(field) => write!(f, "Gen {}: {}, field {} is {}, expected {}",
+                self.gen, self.entity, field, format_value(self.actual), format_value(self.expected)),
+            None => write!(f, "Gen {}: {} only exists for one client", self.gen, self.entity)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StateDigestPart {
+    pub gen: u64,
+    pub index: u16,
+    pub count: u16,
+    pub entities: Vec<EntityDigest>
+}
+
+impl StateDigestPart {
+    pub fn new(gen: u64, index: u16, count: u16, entities: Vec<EntityDigest>) -> StateDigestPart {
+        StateDigestPart {
+            gen, index, count, entities
+        }
+    }
+}
+
+impl Serializable for StateDigestPart {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_u64(self.gen).unwrap();
+        stream.write_u16(self.index).unwrap();
+        stream.write_u16(self.count).unwrap();
+        stream.write_vec(&self.entities).unwrap();
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for StateDigestPart {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let gen = stream.decode_u64()?;
+        let index = stream.decode_u16()?;
+        let count = stream.decode_u16()?;
+        let entities = stream.decode_vec()?;
+        Ok(StateDigestPart::new(gen, index, count, entities))
+    }
+}
+
+// Reassembles digests from their parts, which arrive in order over the reliable channel
+pub struct DigestAssembler {
+    digest: StateDigest,
+    received_parts: u16,
+    count: u16
+}
+
+impl DigestAssembler {
+    pub fn new(gen: u64) -> DigestAssembler {
+        DigestAssembler {
+            digest: StateDigest::new(gen), received_parts: 0, count: 0
+        }
+    }
+
+    pub fn add_part(&mut self, mut part: StateDigestPart) {
+        if part.gen != self.digest.gen || part.index != self.received_parts || self.is_complete() {
+            println!("Discarded unexpected digest part {}/{} of gen {}.",
+                part.index + 1, part.count, part.gen);
+            return
+        }
+        self.count = part.count;
+        self.received_parts += 1;
+        self.digest.entities.append(&mut part.entities);
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.count > 0 && self.received_parts == self.count
+    }
+
+    pub fn get_digest(&self) -> Option<&StateDigest> {
+        match self.is_complete() {
+            true => Some(&self.digest),
+            false => None
+        }
+    }
+}
+
+pub struct DigestHistory {
+    digests: VecDeque<StateDigest>
+}
+
+impl DigestHistory {
+    pub fn new() -> DigestHistory {
+        DigestHistory {
+            digests: VecDeque::with_capacity(DIGEST_HISTORY_LENGTH)
+        }
+    }
+
+    pub fn add_digest(&mut self, digest: StateDigest) {
+        if self.digests.len() >= DIGEST_HISTORY_LENGTH {
+            self.digests.pop_front();
+        }
+        self.digests.push_back(digest);
+    }
+
+    pub fn get_digest(&self, gen: u64) -> Option<&StateDigest> {
+        self.digests.iter().find(|d| d.gen == gen)
+    }
+
+    pub fn gen_summary(&self) -> Vec<SyncState> {
+        self.digests.iter().map(|d| d.gen_hash()).collect()
+    }
+}
+
+impl Default for DigestHistory {
+    fn default() -> Self {
+        DigestHistory::new()
+    }
+}
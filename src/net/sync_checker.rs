@@ -1,8 +1,8 @@
 use core::fmt;
-use std::{collections::HashMap, iter::FromIterator};
+use std::{collections::HashMap, time::Instant};
 use binary_stream::{BinaryStream, Serializable};
 use indexmap::IndexMap;
-use crate::{DEFAULT_SIMULATION_TIMESTEP, Rcc, input_pool::STEP_PHASE_FRAME_LENGTH, round_f32, ship::Ship};
+use crate::{BbResult, DEFAULT_SIMULATION_TIMESTEP, Rcc, decode::{Decode, DecodeStream}, input_pool::STEP_PHASE_FRAME_LENGTH, round_f32, ship::Ship};
 
 pub const SYNC_STATE_GEN_INTERVAL: u64 = (DEFAULT_SIMULATION_TIMESTEP as u64 / 2)
     / STEP_PHASE_FRAME_LENGTH as u64;
@@ -55,9 +55,15 @@ impl Serializable for SyncState {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let gen = stream.read_u64().unwrap();
-        let hash = stream.read_u64().unwrap();
-        Self::new(gen, hash)
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for SyncState {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let gen = stream.decode_u64()?;
+        let hash = stream.decode_u64()?;
+        Ok(Self::new(gen, hash))
     }
 }
 
@@ -73,8 +79,26 @@ impl fmt::Debug for SyncState {
     }
 }
 
+// How long the states of a gen are waited on, should a player's get lost or never be sent
+pub const SYNC_REVIEW_TIMEOUT: f32 = 5.0;
+
+// States the players sent of a single gen
+struct GenStates {
+    states: HashMap<u16, SyncState>,
+    first_arrival: Instant,
+    reviewed: bool
+}
+
+// Outcome of comparing the states of a gen against the reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncReview {
+    pub gen: u64,
+    pub reference_id: u16,
+    pub desynced_players: Vec<u16>
+}
+
 pub struct SyncChecker {
-    states: IndexMap<u64, HashMap<u16, SyncState>>
+    states: IndexMap<u64, GenStates>
 }
 
 impl SyncChecker {
@@ -90,25 +114,51 @@ impl SyncChecker {
             self.states.drain(..(states_len / 2));
         }
 
-        if let Some(gen_states) = self.states.get_mut(&state.t) {
-            gen_states.insert(sender, state);
-        } else {
-            self.states.insert(state.t, HashMap::from_iter([(sender, state); 1]));
-        }
+        self.states.entry(state.t).or_insert_with(|| GenStates {
+            states: HashMap::new(), first_arrival: Instant::now(), reviewed: false
+        }).states.insert(sender, state);
     }
 
-    pub fn review_desyncs(&mut self, t: u64) -> Vec<u16> {
-        if let Some(gen_states) = self.states.get(&t) {
-            if let Some(auth_client_state) = gen_states.get(&0) {
-                gen_states
-                    .iter()
-                    .filter(|(_, s)| s.hash != auth_client_state.hash)
-                    .map(|(&id, _)| id).collect()
-            } else {
-                vec![]
+    // Reviews every gen once all active players sent their state of it, or it timed out.
+    // Judging on the first few states would let whoever reports first decide the reference.
+    pub fn take_reviews(&mut self, players: &[u16], host_id: Option<u16>) -> Vec<SyncReview> {
+        let mut reviews = Vec::new();
+        for (&gen, gen_states) in self.states.iter_mut() {
+            let complete = players.iter().all(|id| gen_states.states.contains_key(id));
+            if gen_states.reviewed || !(complete
+                || gen_states.first_arrival.elapsed().as_secs_f32() >= SYNC_REVIEW_TIMEOUT) {
+                continue
             }
-        } else {
-            vec![]
+            gen_states.reviewed = true;
+            match Self::get_reference_state(&gen_states.states, players.len(), host_id) {
+                Some((reference_id, reference_state)) => reviews.push(SyncReview {
+                    gen, reference_id,
+                    desynced_players: gen_states.states.iter()
+                        .filter(|(_, s)| s.hash != reference_state.hash)
+                        .map(|(&id, _)| id).collect()
+                }),
+                None => println!("No majority agrees on the state of gen {}. Skipping review...", gen)
+            }
+        }
+        reviews
+    }
+
+    fn get_reference_state(gen_states: &HashMap<u16, SyncState>, player_count: usize,
+        host_id: Option<u16>) -> Option<(u16, SyncState)> {
+        if let Some((host_id, host_state)) = host_id.and_then(|id| gen_states.get(&id).map(|s| (id, s))) {
+            return Some((host_id, *host_state))
+        }
+        // Without a playing host (dedicated server), a strict majority of the players decides.
+        // The lowest ID holding the majority state is the reference, regardless of map order.
+        let voters = player_count.max(gen_states.len());
+        let mut votes: HashMap<u64, (usize, u16)> = HashMap::new();
+        for (&id, state) in gen_states.iter() {
+            let vote = votes.entry(state.hash).or_insert((0, id));
+            vote.0 += 1;
+            vote.1 = vote.1.min(id);
         }
+        votes.into_values()
+            .find(|(count, _)| count * 2 > voters)
+            .and_then(|(_, id)| gen_states.get(&id).map(|s| (id, *s)))
     }
 }
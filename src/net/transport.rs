@@ -0,0 +1,68 @@
+use std::{fmt::Debug, io, net::{SocketAddr, UdpSocket}};
+use crate::{BbError, BbErrorType, BbResult, relay::{RELAY_ENDPOINT_PREFIX, RelaySocket, get_virtual_addr}};
+
+// What a peer's datagrams travel over. Laminar and the simulated network conditions sit
+// on top and only see addresses, whether those are real or handed out by a relay.
+pub trait Transport: Send + Debug {
+    fn send_to(&mut self, payload: &[u8], addr: SocketAddr) -> io::Result<usize>;
+    // Never blocks, WouldBlock once nothing is left
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    // Where others can join a server on this transport, if it differs from its address
+    fn get_relay_endpoint(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&mut self, payload: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, payload, addr)
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buffer)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportSetup {
+    Direct { port: Option<u16> }, // Any free port for None
+    Relay { relay_addr: String, session: Option<u32> } // Hosts a new session for None
+}
+
+impl TransportSetup {
+    pub fn open(&self) -> BbResult<Box<dyn Transport>> {
+        Ok(match self {
+            TransportSetup::Direct { port } => {
+                let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port.unwrap_or(0))))
+                    .map_err(BbError::Io)?;
+                socket.set_nonblocking(true).map_err(BbError::Io)?;
+                Box::new(socket)
+            },
+            TransportSetup::Relay { relay_addr, session: None } => Box::new(RelaySocket::host(relay_addr)?),
+            TransportSetup::Relay { relay_addr, session: Some(session) } =>
+                Box::new(RelaySocket::join(relay_addr, *session)?)
+        })
+    }
+}
+
+// Either a plain address, e.g. "127.0.0.1:22081", or a relay session, e.g.
+// "relay://127.0.0.1:22078/1234567". Returns how to reach the server and its address.
+pub fn parse_server_endpoint(endpoint: &str) -> BbResult<(TransportSetup, SocketAddr)> {
+    let invalid = || BbError::Bb(BbErrorType::NetInvalidEndpoint(endpoint.to_owned()));
+    match endpoint.trim().strip_prefix(RELAY_ENDPOINT_PREFIX) {
+        Some(relay_endpoint) => {
+            let (relay_addr, session) = relay_endpoint.rsplit_once('/').ok_or_else(invalid)?;
+            let session = session.parse().map_err(|_| invalid())?;
+            Ok((TransportSetup::Relay {
+                relay_addr: relay_addr.to_owned(), session: Some(session)
+            }, get_virtual_addr(0))) // The relay knows the host as its first peer
+        },
+        None => Ok((TransportSetup::Direct { port: None }, endpoint.trim().parse().map_err(|_| invalid())?))
+    }
+}
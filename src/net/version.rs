@@ -0,0 +1,77 @@
+use std::fmt;
+use binary_stream::{BinaryStream, Serializable};
+use crate::{BbResult, PRIMARY_VERSION, PROTOCOL_VERSION, SECONDARY_VERSION, decode::{Decode, DecodeStream}, ship_data::ShipAttributes, ship_mod::{ShipModType, get_ship_mod_cost}};
+
+// Everything two peers need to agree on before playing together. Builds with different
+// balance tables simulate differently and would desync right away.
+#[derive(Clone, Copy, PartialEq)]
+pub struct GameVersion {
+    pub protocol: u16,
+    pub primary: u32,
+    pub secondary: u32,
+    pub balance_hash: u64
+}
+
+impl GameVersion {
+    pub fn local() -> GameVersion {
+        GameVersion {
+            protocol: PROTOCOL_VERSION, primary: PRIMARY_VERSION,
+            secondary: SECONDARY_VERSION, balance_hash: get_balance_hash()
+        }
+    }
+
+    pub fn is_compatible(&self, other: &GameVersion) -> bool {
+        self == other
+    }
+}
+
+impl fmt::Debug for GameVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{} (protocol {}, balance {:016x})", self.primary, self.secondary,
+            self.protocol, self.balance_hash)
+    }
+}
+
+impl Serializable for GameVersion {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        // Protocol comes first, so future layouts can still be told apart
+        stream.write_u16(self.protocol).unwrap();
+        stream.write_u32(self.primary).unwrap();
+        stream.write_u32(self.secondary).unwrap();
+        stream.write_u64(self.balance_hash).unwrap();
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for GameVersion {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(GameVersion {
+            protocol: stream.decode_u16()?,
+            primary: stream.decode_u32()?,
+            secondary: stream.decode_u32()?,
+            balance_hash: stream.decode_u64()?
+        })
+    }
+}
+
+pub fn get_balance_hash() -> u64 {
+    let mut buffer = Vec::new();
+    for attr in [ShipAttributes::caravel(), ShipAttributes::galleon(),
+        ShipAttributes::schooner()].iter() {
+        buffer.extend(attr.health.to_le_bytes());
+        buffer.extend(attr.defense.to_le_bytes());
+        buffer.extend(attr.movement_speed.to_le_bytes());
+        buffer.extend(attr.turn_rate.to_le_bytes());
+        buffer.extend(attr.cannon_damage.to_le_bytes());
+        buffer.extend(attr.cannon_reload_time.to_le_bytes());
+        buffer.extend(attr.ram_damage.to_le_bytes());
+    }
+    for ship_mod in [ShipModType::Repair, ShipModType::CannonAmmoUpgrade,
+        ShipModType::CannonReloadUpgrade, ShipModType::CannonRangeUpgrade].iter() {
+        buffer.extend(get_ship_mod_cost(*ship_mod).to_le_bytes());
+    }
+    seahash::hash(&buffer)
+}
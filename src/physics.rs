@@ -1,6 +1,6 @@
 use crossbeam_channel::{Receiver};
 use rapier2d::{math::Real, na::{Isometry2}, prelude::{ActiveEvents, BroadPhase, CCDSolver, ChannelEventCollector, Collider, ColliderBuilder, ColliderHandle, ColliderSet, ContactEvent, Cuboid, IntegrationParameters, InteractionGroups, IntersectionEvent, IslandManager, JointSet, NarrowPhase, PhysicsPipeline, QueryPipeline, Ray, RigidBody, RigidBodyBuilder, RigidBodyHandle, RigidBodySet}};
-use tetra::{State, graphics::{Color, DrawParams}, math::{Vec2}};
+use tetra::{graphics::{Color, DrawParams}, math::{Vec2}};
 use crate::{conv_vec, conv_vec_point, entity::EntityType, object::ObjectType, ship_data::ShipType};
 
 pub const MASS_FORCE_SCALE: f32 = 1000.0;
@@ -26,6 +26,22 @@ pub type V2 = Vec2<f32>;
 #[derive(Clone, Copy)]
 pub struct PhysicsHandle(pub RigidBodyHandle, pub ColliderHandle);
 
+// Everything a physics step depends on, to rewind the simulation to an earlier frame
+#[derive(Clone)]
+pub struct PhysicsSnapshot {
+    rb_set: RigidBodySet,
+    coll_set: ColliderSet,
+    wind: V2,
+    island_manager: Vec<u8>, // Not Clone, so it is serialized instead
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    joint_set: JointSet,
+    ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+    intersections: Vec<IntersectionEvent>,
+    contacts: Vec<ContactEvent>
+}
+
 pub struct Physics {
     pub rb_set: RigidBodySet,
     pub coll_set: ColliderSet,
@@ -38,6 +54,9 @@ pub struct Physics {
     ccd_solver: CCDSolver,
     intersection_receiver: Receiver<IntersectionEvent>,
     contact_receiver: Receiver<ContactEvent>,
+    // Events of the last step, until the world handles them
+    intersections: Vec<IntersectionEvent>,
+    contacts: Vec<ContactEvent>,
     event_handler: ChannelEventCollector,
     physics_pipeline: PhysicsPipeline,
     query_pipeline: QueryPipeline
@@ -61,6 +80,8 @@ impl Physics {
             ccd_solver: CCDSolver::new(),
             intersection_receiver,
             contact_receiver,
+            intersections: Vec::new(),
+            contacts: Vec::new(),
             event_handler,
             physics_pipeline: PhysicsPipeline::new(),
             query_pipeline: QueryPipeline::new()
@@ -157,6 +178,8 @@ impl Physics {
         for rb in rbs {
             self.remove_collider_by_rb(rb);
         }
+        self.intersections.clear();
+        self.contacts.clear();
     }
 
     pub fn get_coll(&self, coll_handle: ColliderHandle) -> &Collider {
@@ -172,8 +195,14 @@ impl Physics {
     }
 
     pub fn set_coll_group(&mut self, handle: ColliderHandle, member: u32, filter: u32) {
-        self.get_coll_mut(handle).set_collision_groups(InteractionGroups::new(
-            member, filter))
+        let coll = self.get_coll_mut(handle);
+        coll.set_collision_groups(InteractionGroups::new(member, filter));
+        // Rapier's broad-phase stops processing modified colliders at the first one that
+        // doesn't need a broad-phase update, which a group change alone doesn't. Any collider
+        // inserted in the same step is then left half-registered and panics the next step.
+        // Flagging the position keeps this collider from cutting the update short.
+        let pos = *coll.position();
+        coll.set_position(pos);
     }
 
     pub fn get_rb(&self, rb_handle: RigidBodyHandle) -> &RigidBody {
@@ -205,20 +234,42 @@ impl Physics {
         }
     }
 
-    pub fn get_intersections(&self) -> Vec<IntersectionEvent> {
-        let mut events = Vec::new();
-        while let Ok(ev) = self.intersection_receiver.try_recv() {
-            events.push(ev);
-        }
-        events
+    pub fn get_intersections(&mut self) -> Vec<IntersectionEvent> {
+        std::mem::take(&mut self.intersections)
+    }
+
+    pub fn get_contacts(&mut self) -> Vec<ContactEvent> {
+        std::mem::take(&mut self.contacts)
     }
 
-    pub fn get_contacts(&self) -> Vec<ContactEvent> {
-        let mut events = Vec::new();
-        while let Ok(ev) = self.contact_receiver.try_recv() {
-            events.push(ev);
+    pub fn save_state(&self) -> PhysicsSnapshot {
+        PhysicsSnapshot {
+            rb_set: self.rb_set.clone(),
+            coll_set: self.coll_set.clone(),
+            wind: self.wind,
+            island_manager: bincode::serialize(&self.island_manager).unwrap(),
+            broad_phase: self.broad_phase.clone(),
+            narrow_phase: self.narrow_phase.clone(),
+            joint_set: self.joint_set.clone(),
+            ccd_solver: self.ccd_solver.clone(),
+            query_pipeline: self.query_pipeline.clone(),
+            intersections: self.intersections.clone(),
+            contacts: self.contacts.clone()
         }
-        events
+    }
+
+    pub fn restore_state(&mut self, snapshot: &PhysicsSnapshot) {
+        self.rb_set = snapshot.rb_set.clone();
+        self.coll_set = snapshot.coll_set.clone();
+        self.wind = snapshot.wind;
+        self.island_manager = bincode::deserialize(&snapshot.island_manager).unwrap();
+        self.broad_phase = snapshot.broad_phase.clone();
+        self.narrow_phase = snapshot.narrow_phase.clone();
+        self.joint_set = snapshot.joint_set.clone();
+        self.ccd_solver = snapshot.ccd_solver.clone();
+        self.query_pipeline = snapshot.query_pipeline.clone();
+        self.intersections = snapshot.intersections.clone();
+        self.contacts = snapshot.contacts.clone();
     }
 
     pub fn cast_ray(&self, ray: Ray, dist: f32) -> Option<ColliderHandle> {
@@ -257,13 +308,14 @@ impl Physics {
     }
 }
 
-impl State for Physics {
-    fn update(&mut self, _ctx: &mut tetra::Context) -> tetra::Result {
+impl Physics {
+    pub fn update(&mut self) {
         self.physics_pipeline.step(&conv_vec(self.wind), &self.integration_params,
             &mut self.island_manager, &mut self.broad_phase, &mut self.narrow_phase,
             &mut self.rb_set, &mut self.coll_set, &mut self.joint_set,
             &mut self.ccd_solver, &(), &self.event_handler);
         self.query_pipeline.update(&mut self.island_manager, &self.rb_set, &self.coll_set);
-        Ok(())
+        self.intersections.extend(self.intersection_receiver.try_iter());
+        self.contacts.extend(self.contact_receiver.try_iter());
     }
 }
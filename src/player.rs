@@ -1,7 +1,6 @@
 use binary_stream::{BinaryStream, Serializable};
 use rapier2d::data::Index;
-use tetra::Context;
-use crate::{CannonSide, GC, ID, Rcc, TransformResult, World, entity::Entity, packet::InputState, ship::{Ship}, ship_data::ShipType, ship_mod::{CannonAmmoUpgradeMod, CannonRangeUpgradeMod, CannonReloadUpgradeMod, ShipMod, ShipModType, get_ship_mod_cost}};
+use crate::{BbResult, CannonSide, GC, ID, Rcc, TransformResult, World, decode::Decode, entity::Entity, packet::InputState, ship::{Ship}, ship_data::ShipType, ship_mod::{CannonAmmoUpgradeMod, CannonRangeUpgradeMod, CannonReloadUpgradeMod, ShipMod, ShipModType, get_ship_mod_cost}};
 
 pub struct Player {
     pub id: ID,
@@ -23,8 +22,7 @@ impl Player {
         self.possessed_ship = possessed_ship;
     }
 
-    pub fn apply_state(&mut self, ctx: &mut Context, state: InputState,
-        world: &mut World) -> tetra::Result {
+    pub fn apply_state(&mut self, state: InputState, world: &mut World) -> tetra::Result {
         let mut ship_ref = self.possessed_ship.borrow_mut();
         if state.disconnect {
             ship_ref.destroy();
@@ -32,11 +30,11 @@ impl Player {
         }
         
         if state.q && state.e {
-            ship_ref.shoot_cannons(ctx, None, world)?;
+            ship_ref.shoot_cannons(None, world)?;
         } else if state.q {
-            ship_ref.shoot_cannons(ctx, Some(CannonSide::Bowside), world)?;
+            ship_ref.shoot_cannons(Some(CannonSide::Bowside), world)?;
         } else if state.e {
-            ship_ref.shoot_cannons(ctx, Some(CannonSide::Portside), world)?;
+            ship_ref.shoot_cannons(Some(CannonSide::Portside), world)?;
         }
 
         if let Some(mouse_pos) = state.mouse_pos {
@@ -58,19 +56,19 @@ impl Player {
                             self.possessed_ship.borrow_mut().repair();
                         },
                         ShipModType::CannonAmmoUpgrade => {
-                            let mut ship_mod = CannonAmmoUpgradeMod::new(ctx,
+                            let mut ship_mod = CannonAmmoUpgradeMod::new(
                                 self.possessed_ship.clone(), self.game.clone())?;
                             ship_mod.on_apply().convert()?;
                             self.possessed_ship.borrow_mut().apply_mod(ship_mod);
                         },
                         ShipModType::CannonReloadUpgrade => {
-                            let mut ship_mod = CannonReloadUpgradeMod::new(ctx,
+                            let mut ship_mod = CannonReloadUpgradeMod::new(
                                 self.possessed_ship.clone(), self.game.clone())?;
                             ship_mod.on_apply().convert()?;
                             self.possessed_ship.borrow_mut().apply_mod(ship_mod);
                         },
                         ShipModType::CannonRangeUpgrade => {
-                            let mut ship_mod = CannonRangeUpgradeMod::new(ctx,
+                            let mut ship_mod = CannonRangeUpgradeMod::new(
                                 self.possessed_ship.clone(), self.game.clone())?;
                             ship_mod.on_apply().convert()?;
                             self.possessed_ship.borrow_mut().apply_mod(ship_mod);
@@ -106,10 +104,16 @@ impl Serializable for PlayerParams {
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        let id = ID::from_stream(stream);
-        let ship_type = ShipType::from_stream(stream);
-        PlayerParams {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for PlayerParams {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let id = ID::decode(stream)?;
+        let ship_type = ShipType::decode(stream)?;
+        Ok(PlayerParams {
             id, ship_type
-        }
+        })
     }
 }
@@ -0,0 +1,234 @@
+use std::{fs, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use binary_stream::{BinaryStream, Serializable};
+use crate::{BbError, BbErrorType, BbResult, PlayerParams, decode::{Decode, DecodeStream}, game_settings::GameSettings, get_version, packet::InputStep, playback_buffer::PlaybackBuffer, sync_checker::SyncState};
+
+pub const REPLAYS_PATH: &str = "replays";
+pub const REPLAY_FILE_EXTENSION: &str = "bbr";
+pub const REPLAY_FORMAT_VERSION: u16 = 2;
+const REPLAY_MAGIC: u32 = 0x42425250; // "BBRP"
+// Keep a few steps buffered, so playback never blocks between two steps
+const REPLAY_BUFFERED_STEPS: usize = 3;
+const MAX_REPLAY_SPEED: f64 = 4.0;
+pub const REPLAY_SEEK_GENS: u64 = 200; // ~10 secs
+const SEEK_SPEED: f64 = 10.0;
+
+// Everything required to re-simulate a match: as the simulation is deterministic, the
+// world seed, the initial players, the settings and all input steps reproduce it exactly.
+#[derive(Clone)]
+pub struct Replay {
+    pub world_seed: u64,
+    pub players: Vec<PlayerParams>,
+    pub settings: GameSettings,
+    pub steps: Vec<InputStep>,
+    pub sync_states: Vec<SyncState> // Hashes at time of recording, to detect divergent playback
+}
+
+impl Replay {
+    pub fn new(world_seed: u64, players: Vec<PlayerParams>, settings: GameSettings) -> Replay {
+        Replay {
+            world_seed, players, settings, steps: Vec::new(), sync_states: Vec::new()
+        }
+    }
+
+    pub fn add_step(&mut self, step: InputStep) {
+        self.steps.push(step);
+    }
+
+    pub fn add_sync_state(&mut self, state: SyncState) {
+        self.sync_states.push(state);
+    }
+
+    pub fn get_total_gens(&self) -> u64 {
+        self.steps.last().map_or(0, |s| s.gen)
+    }
+
+    pub fn get_sync_state(&self, gen: u64) -> Option<SyncState> {
+        self.sync_states.iter().find(|s| s.t == gen).copied()
+    }
+
+    pub fn save(&self) -> BbResult<PathBuf> {
+        fs::create_dir_all(REPLAYS_PATH).map_err(BbError::Io)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let path = Path::new(REPLAYS_PATH).join(format!("replay-{}.{}",
+            timestamp, REPLAY_FILE_EXTENSION));
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    pub fn save_to(&self, path: &Path) -> BbResult {
+        let mut payload = BinaryStream::new();
+        self.to_stream(&mut payload);
+        let payload = payload.get_buffer_vec();
+
+        let mut stream = BinaryStream::new();
+        stream.write_u32(REPLAY_MAGIC).unwrap();
+        stream.write_u16(REPLAY_FORMAT_VERSION).unwrap();
+        stream.write_string(&get_version()).unwrap();
+        stream.write_byte_vec(&payload).unwrap();
+        fs::write(path, stream.get_buffer_vec()).map_err(BbError::Io)
+    }
+
+    pub fn load(path: &Path) -> BbResult<Replay> {
+        let bytes = fs::read(path).map_err(BbError::Io)?;
+        // Header is u32 magic, u16 format version and at least the u32 length of the version string
+        if bytes.len() < 10 {
+            return Err(invalid_replay("File is too short"))
+        }
+        let mut stream = BinaryStream::from_bytes(&bytes);
+        if stream.read_u32().unwrap() != REPLAY_MAGIC {
+            return Err(invalid_replay("Not a replay file"))
+        }
+        let format_version = stream.read_u16().unwrap();
+        if format_version != REPLAY_FORMAT_VERSION {
+            return Err(invalid_replay(&format!("Unsupported format version {}", format_version)))
+        }
+        let version_len = stream.read_u32().unwrap() as usize;
+        if stream.size() < version_len {
+            return Err(invalid_replay("Game version is truncated"))
+        }
+        let version = String::from_utf8(stream.read_buffer(version_len).unwrap())
+            .map_err(|_| invalid_replay("Invalid game version"))?;
+        if version != get_version() {
+            println!("Replay was recorded with game version {} (current: {}). Playback may diverge.",
+                version, get_version());
+        }
+        // Steps are only decoded once the payload is known to be complete
+        if stream.size() < 4 {
+            return Err(invalid_replay("Missing payload"))
+        }
+        let payload_len = stream.read_u32().unwrap() as usize;
+        if stream.size() != payload_len {
+            return Err(invalid_replay("Payload is truncated"))
+        }
+        let payload = stream.read_buffer(payload_len).unwrap();
+        Replay::decode(&mut BinaryStream::from_bytes(&payload))
+            .map_err(|e| invalid_replay(&format!("Corrupted payload ({:?})", e)))
+    }
+
+    pub fn find_latest() -> Option<PathBuf> {
+        // File names contain the timestamp, so the latest replay also sorts last
+        fs::read_dir(REPLAYS_PATH).ok()?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == REPLAY_FILE_EXTENSION))
+            .max()
+    }
+}
+
+impl Serializable for Replay {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_u64(self.world_seed).unwrap();
+        stream.write_vec(&self.players).unwrap();
+        self.settings.to_stream(stream);
+        stream.write_vec(&self.steps).unwrap();
+        stream.write_vec(&self.sync_states).unwrap();
+    }
+
+    fn from_stream(stream: &mut BinaryStream) -> Self {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for Replay {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        let world_seed = stream.decode_u64()?;
+        let players = stream.decode_vec()?;
+        let settings = GameSettings::decode(stream)?;
+        let steps = stream.decode_vec()?;
+        let sync_states = stream.decode_vec()?;
+        Ok(Replay {
+            world_seed, players, settings, steps, sync_states
+        })
+    }
+}
+
+fn invalid_replay(reason: &str) -> BbError {
+    BbError::Bb(BbErrorType::InvalidReplay(reason.to_owned()))
+}
+
+// Feeds the recorded steps into the playback buffer in place of the server
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    next_step: usize,
+    paused: bool,
+    speed: f64,
+    seek_target: Option<u64>,
+    diverged_gen: Option<u64>
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay, seek_target: Option<u64>) -> ReplayPlayback {
+        ReplayPlayback {
+            replay, next_step: 0, paused: false, speed: 1.0,
+            seek_target: seek_target.filter(|gen| *gen > 0), diverged_gen: None
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Cycles through 1x, 2x and 4x
+    pub fn toggle_fast_forward(&mut self) {
+        self.speed = if self.speed >= MAX_REPLAY_SPEED {
+            1.0
+        } else {
+            self.speed * 2.0
+        };
+    }
+
+    pub fn get_speed(&self) -> f64 {
+        match self.seek_target {
+            Some(_) => SEEK_SPEED,
+            None => self.speed
+        }
+    }
+
+    pub fn seek(&mut self, target_gen: u64) {
+        self.seek_target = Some(target_gen.min(self.replay.get_total_gens()));
+    }
+
+    pub fn get_seek_target(&self) -> Option<u64> {
+        self.seek_target
+    }
+
+    pub fn get_diverged_gen(&self) -> Option<u64> {
+        self.diverged_gen
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_step >= self.replay.steps.len()
+    }
+
+    pub fn feed(&mut self, buffer: &mut PlaybackBuffer, curr_gen: u64) {
+        if self.seek_target.is_some_and(|target| curr_gen >= target) {
+            self.seek_target = None;
+        }
+        if self.paused && self.seek_target.is_none() {
+            return
+        }
+        while buffer.get_buffer_size() < REPLAY_BUFFERED_STEPS && !self.is_finished() {
+            buffer.add_step(self.replay.steps[self.next_step].clone());
+            self.next_step += 1;
+        }
+    }
+
+    // Compares a state of the playback against the recording. Returns false at the first divergence.
+    pub fn verify_sync_state(&mut self, state: SyncState) -> bool {
+        if self.diverged_gen.is_some() {
+            return false
+        }
+        match self.replay.get_sync_state(state.t) {
+            Some(recorded_state) if recorded_state.hash != state.hash => {
+                println!("Replay diverged at gen {}: recorded hash {}, playback hash {}.",
+                    state.t, recorded_state.hash, state.hash);
+                self.diverged_gen = Some(state.t);
+                false
+            },
+            _ => true
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use indexmap::IndexMap;
+use crate::{PhysicsSnapshot, Player, Rcc, SyncStateShipData, economy::Economy, input_pool::is_same_step, match_result::MatchResult, packet::{InputState, InputStep}, ship_ai::ShipAI, state_digest::StateDigest, sync_checker::SyncState, weather::WeatherSystem, world::WorldSnapshot};
+
+// How far the simulation may run ahead of the last received step (~0.4 secs)
+pub const MAX_PREDICTED_GENS: usize = 8;
+
+// The whole simulation state at the start of a frame
+pub struct SimulationSnapshot {
+    pub gen: u64,
+    pub frames: u64,
+    pub frame_index: u32,
+    pub players: IndexMap<u16, Rcc<Player>>,
+    pub computers: IndexMap<u16, ShipAI>,
+    pub match_result: Option<MatchResult>,
+    pub world: WorldSnapshot,
+    pub physics: PhysicsSnapshot,
+    pub economy: Economy,
+    pub weather: WeatherSystem
+}
+
+pub struct PendingSync {
+    pub state: SyncState,
+    pub ship_data: Vec<SyncStateShipData>,
+    pub frames: u64
+}
+
+// A step the simulation ran ahead with. Digests and sync states are only passed on once
+// the actual step confirms the prediction.
+pub struct PredictedGen {
+    pub step: InputStep,
+    pub snapshot: SimulationSnapshot, // Right before the step was applied
+    pub digest: Option<StateDigest>,
+    pub sync: Option<PendingSync>
+}
+
+pub enum Prediction {
+    Correct(PredictedGen),
+    Incorrect(PredictedGen)
+}
+
+pub struct Rollback {
+    pub resimulating: bool,
+    pub rollbacks: u32,
+    predicted_gens: VecDeque<PredictedGen>,
+    confirmed_gen: u64,
+    last_states: Vec<(u16, InputState)>, // Of the last confirmed step, repeated for remote players
+    sent_states: VecDeque<(u64, InputState)> // Local states, by the gen they were sent after
+}
+
+impl Rollback {
+    pub fn new() -> Rollback {
+        Rollback {
+            resimulating: false, rollbacks: 0, predicted_gens: VecDeque::new(), confirmed_gen: 0,
+            last_states: Vec::new(), sent_states: VecDeque::new()
+        }
+    }
+
+    pub fn get_confirmed_gen(&self) -> u64 {
+        self.confirmed_gen
+    }
+
+    pub fn get_predicted_gens(&self) -> usize {
+        self.predicted_gens.len()
+    }
+
+    // Remote states are only known after the first step
+    pub fn can_predict(&self) -> bool {
+        self.confirmed_gen > 0 && self.predicted_gens.len() < MAX_PREDICTED_GENS
+    }
+
+    pub fn add_sent_state(&mut self, gen: u64, state: InputState) {
+        self.sent_states.push_back((gen, state));
+    }
+
+    // The local state is expected to make it into the step right after it was sent. Remote
+    // players keep doing what they did last, but one-shot actions are never repeated.
+    pub fn predict_step(&self, gen: u64, local_id: Option<u16>) -> InputStep {
+        let local_state = self.sent_states.iter()
+            .find(|(sent_gen, _)| *sent_gen + 1 == gen)
+            .map(|(_, state)| state.clone())
+            .unwrap_or_default();
+        let states = self.last_states.iter()
+            .map(|(id, state)| match local_id {
+                Some(local_id) if local_id == *id => (*id, local_state.clone()),
+                _ => (*id, state.clone())
+            })
+            .collect();
+        InputStep::new(states, gen)
+    }
+
+    pub fn add_prediction(&mut self, predicted_gen: PredictedGen) {
+        self.predicted_gens.push_back(predicted_gen);
+    }
+
+    // Checks a received step against the oldest prediction. Any prediction after an incorrect
+    // one builds on it, so they are all dropped.
+    pub fn check_prediction(&mut self, step: &InputStep) -> Option<Prediction> {
+        let predicted_gen = self.predicted_gens.pop_front()?;
+        if is_same_step(&predicted_gen.step, step) {
+            Some(Prediction::Correct(predicted_gen))
+        } else {
+            self.predicted_gens.clear();
+            self.rollbacks += 1;
+            Some(Prediction::Incorrect(predicted_gen))
+        }
+    }
+
+    pub fn confirm_step(&mut self, step: &InputStep) {
+        self.confirmed_gen = step.gen;
+        self.last_states = step.states.iter()
+            .filter(|(_, state)| !state.disconnect)
+            .map(|(id, state)| (*id, InputState {
+                q: false, e: false, buy_mod: false, mod_type: None, ..state.clone()
+            }))
+            .collect();
+        while self.sent_states.front().is_some_and(|(gen, _)| *gen < step.gen) {
+            self.sent_states.pop_front();
+        }
+    }
+}
+
+impl Default for Rollback {
+    fn default() -> Self {
+        Rollback::new()
+    }
+}
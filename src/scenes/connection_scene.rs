@@ -1,5 +1,6 @@
+use std::{net::SocketAddr, time::Duration};
 use tetra::{Context, State};
-use crate::{BbResult, GC, Rcc, TransformResult, V2, button::{Button, DefaultButton}, grid::{Grid, UIAlignment}, label::{FontSize, Label}, lobby_scene::LobbyScene, menu_scene::MenuScene, net_settings::NetSettings, textbox::Textbox, ui_element::{DefaultUIReactor}};
+use crate::{BbResult, GC, Rcc, TransformResult, V2, button::{Button, DefaultButton}, discovery::{DISCOVERY_PORT, DiscoveryBrowser, LobbyInfo}, game_settings::GameMode, grid::{Grid, UIAlignment}, label::{FontSize, Label}, lobby_scene::LobbyScene, master_server::ServerBrowser, menu_scene::MenuScene, net_settings::NetSettings, textbox::Textbox, ui_element::{DefaultUIReactor}, version::GameVersion};
 use super::scenes::{Scene, SceneType};
 
 const DEFAULT_HOST_PORT: u16 = 22081;
@@ -9,7 +10,16 @@ pub struct ConnectionScene {
     back_button: Rcc<DefaultButton>,
     create_button: Rcc<DefaultButton>,
     join_button: Rcc<DefaultButton>,
+    spectate_button: Rcc<DefaultButton>,
     join_endpoint_txt: Rcc<Textbox>,
+    password_txt: Rcc<Textbox>,
+    invite_txt: Rcc<Textbox>,
+    lan_grid: Rcc<Grid>,
+    lan_buttons: Vec<(Rcc<DefaultButton>, SocketAddr)>,
+    browser: Option<DiscoveryBrowser>, // None if the socket couldn't be set up
+    online_grid: Rcc<Grid>,
+    online_buttons: Vec<(Rcc<DefaultButton>, SocketAddr)>,
+    server_browser: Option<ServerBrowser>, // None without a master server
     game: GC
 }
 
@@ -20,25 +30,92 @@ impl ConnectionScene {
         let back_button = grid.add_element(Button::new(ctx, "Back to Menu",
             V2::new(135.0, 35.0), 5.0, DefaultUIReactor::new(), game.clone())?);
         
+        // Set by the host when creating, entered by everyone else when joining
+        grid.add_element(Label::new(ctx, "Lobby Password (optional)", FontSize::Normal,
+            5.0, game.clone())?);
+        let password_txt = grid.add_element(Textbox::new(ctx, "", V2::new(200.0, 35.0), 5.0,
+            game.clone())?);
+
         grid.add_element(Label::new(ctx, "Create Match", FontSize::Normal,
             5.0, game.clone())?);
-        let create_button = grid.add_element(Button::new(ctx, "Create", V2::new(80.0, 35.0),
+        grid.add_element(Label::new(ctx, "Invited players, comma-separated (optional)",
+            FontSize::Small, 5.0, game.clone())?);
+        let mut create_grid = Grid::default(ctx, UIAlignment::Horizontal,
+            V2::zero(), V2::new(150.0, 35.0), 5.0)?;
+        let invite_txt = create_grid.add_element(Textbox::new(ctx, "", V2::new(200.0, 35.0), 5.0,
+            game.clone())?);
+        let create_button = create_grid.add_element(Button::new(ctx, "Create", V2::new(80.0, 35.0),
             3.0, DefaultUIReactor::new(), game.clone())?);
+        grid.add_element(create_grid);
         
         grid.add_element(Label::new(ctx, "Join Match", FontSize::Normal,
             5.0, game.clone())?);
         let mut join_grid = Grid::default(ctx, UIAlignment::Horizontal,
             V2::zero(), V2::new(150.0, 35.0), 5.0)?;
+        let last_endpoint = game.borrow().settings.get_last_endpoint().map(|e| e.to_owned())
+            .unwrap_or(format!("127.0.0.1:{}", DEFAULT_HOST_PORT));
         let join_endpoint_txt = join_grid.add_element(Textbox::new(ctx,
-            format!("127.0.0.1:{}", DEFAULT_HOST_PORT).as_str(), V2::new(200.0, 35.0),
-            5.0, game.clone())?);
+            last_endpoint.as_str(), V2::new(200.0, 35.0), 5.0, game.clone())?);
         let join_button = join_grid.add_element(Button::new(ctx, "Join",
             V2::new(70.0, 35.0), 3.0, DefaultUIReactor::new(), game.clone())?);
+        let spectate_button = join_grid.add_element(Button::new(ctx, "Spectate",
+            V2::new(100.0, 35.0), 3.0, DefaultUIReactor::new(), game.clone())?);
         grid.add_element(join_grid);
+
+        grid.add_element(Label::new(ctx, "LAN Matches", FontSize::Normal,
+            5.0, game.clone())?);
+        let lan_grid = grid.add_element(Grid::default(ctx, UIAlignment::Vertical,
+            V2::zero(), V2::new(400.0, 120.0), 5.0)?);
+        let browser = DiscoveryBrowser::new(DISCOVERY_PORT)
+            .map_err(|e| println!("LAN discovery is unavailable. Reason: {}", e))
+            .ok();
+
+        grid.add_element(Label::new(ctx, "Online Matches", FontSize::Normal,
+            5.0, game.clone())?);
+        let online_grid = grid.add_element(Grid::default(ctx, UIAlignment::Vertical,
+            V2::zero(), V2::new(400.0, 160.0), 5.0)?);
+        let master_server = game.borrow().settings.master_server.to_owned();
+        let server_browser = Some(master_server).filter(|addr| !addr.is_empty())
+            .and_then(|addr| ServerBrowser::new(&addr)
+                .map_err(|e| println!("Server browser is unavailable. Reason: {}", e))
+                .ok());
         
-        Ok(ConnectionScene {
-            grid, back_button, create_button, join_button, join_endpoint_txt, game
-        })
+        let mut scene = ConnectionScene {
+            grid, back_button, create_button, join_button, spectate_button, join_endpoint_txt,
+            password_txt, invite_txt, lan_grid, lan_buttons: Vec::new(), browser, online_grid,
+            online_buttons: Vec::new(), server_browser, game
+        };
+        scene.update_lan_matches(ctx)?;
+        scene.update_online_matches(ctx)?;
+        Ok(scene)
+    }
+
+    fn update_lan_matches(&mut self, ctx: &mut Context) -> tetra::Result {
+        let (matches, empty_text) = match self.browser.as_ref() {
+            Some(browser) => (browser.get_lobbies().into_iter()
+                .map(|lobby| (lobby.addr, lobby.info.clone(), None))
+                .collect(), "  Searching..."),
+            None => (Vec::new(), "  Unavailable")
+        };
+        self.lan_buttons = fill_match_list(ctx, &mut self.lan_grid.borrow_mut(), matches,
+            empty_text, self.game.clone())?;
+        Ok(())
+    }
+
+    fn update_online_matches(&mut self, ctx: &mut Context) -> tetra::Result {
+        let (matches, empty_text) = match self.server_browser.as_ref() {
+            Some(server_browser) => (server_browser.get_servers().into_iter()
+                .map(|server| (server.addr, server.info.clone(), server.ping))
+                .collect(), "  Searching..."),
+            None => (Vec::new(), "  Unavailable, no master server set")
+        };
+        self.online_buttons = fill_match_list(ctx, &mut self.online_grid.borrow_mut(), matches,
+            empty_text, self.game.clone())?;
+        Ok(())
+    }
+
+    fn get_password(&self) -> Option<String> {
+        Some(self.password_txt.borrow().get_text().to_owned()).filter(|p| !p.is_empty())
     }
 
     // fn check_buttons(&mut self) {
@@ -57,6 +134,40 @@ impl ConnectionScene {
     // }
 }
 
+// Joinable matches get a button, the others are only listed
+fn fill_match_list(ctx: &mut Context, grid: &mut Grid,
+    matches: Vec<(SocketAddr, LobbyInfo, Option<Duration>)>, empty_text: &str, game: GC)
+    -> tetra::Result<Vec<(Rcc<DefaultButton>, SocketAddr)>> {
+    grid.clear_elements();
+    if matches.is_empty() {
+        grid.add_element(Label::new(ctx, empty_text, FontSize::Small, 2.0, game.clone())?);
+    }
+    let mut buttons = Vec::new();
+    for (addr, info, ping) in matches.into_iter() {
+        let text = format!("{} - {} - {}/{}{}{}", info.name, match info.mode {
+            GameMode::Raid(escudos) => format!("Raid {}", escudos),
+            GameMode::Deathmatch(sinkings) => format!("Deathmatch {}", sinkings)
+        }, info.players, info.max_players,
+            ping.map(|ping| format!(" - {} ms", ping.as_millis())).unwrap_or_default(),
+            if info.password { " (Password)" } else { "" });
+        if info.is_joinable() {
+            buttons.push((grid.add_element(Button::new(ctx, &text, V2::new(400.0, 30.0),
+                2.0, DefaultUIReactor::new(), game.clone())?), addr));
+        } else {
+            let reason = if !info.version.is_compatible(&GameVersion::local()) {
+                "Incompatible"
+            } else if !info.open {
+                "Closed"
+            } else {
+                "Full"
+            };
+            grid.add_element(Label::new(ctx, &format!("  {} ({})", text, reason),
+                FontSize::Small, 2.0, game.clone())?);
+        }
+    }
+    Ok(buttons)
+}
+
 impl Scene for ConnectionScene {
     fn get_type(&self) -> SceneType {
         SceneType::Connection
@@ -74,13 +185,34 @@ impl Scene for ConnectionScene {
         if self.back_button.borrow().is_pressed() {
             return Ok(Some(Box::new(MenuScene::new(ctx, self.game.clone()).convert()?)))
         }
+        if let Some((_, addr)) = self.lan_buttons.iter().chain(self.online_buttons.iter())
+            .find(|(button, _)| button.borrow().is_pressed()) {
+            return Ok(Some(Box::new(LobbyScene::join(ctx, &addr.to_string(), false, self.get_password(),
+                self.game.clone())?)))
+        }
         if self.create_button.borrow().is_pressed() {
-            return Ok(Some(Box::new(LobbyScene::create(ctx, DEFAULT_HOST_PORT,
-                NetSettings::default(), self.game.clone())?))) // TODO: Add settings customisation UI
+            let mut settings = NetSettings::default(); // TODO: Add settings customisation UI
+            settings.password = self.get_password();
+            settings.set_allow_list(self.invite_txt.borrow().get_text());
+            let master_server = self.game.borrow().settings.master_server.to_owned();
+            settings.master_server = Some(master_server).filter(|addr| !addr.is_empty());
+            let relay = self.game.borrow().settings.relay.to_owned();
+            settings.relay = Some(relay).filter(|addr| !addr.is_empty());
+            return Ok(Some(Box::new(LobbyScene::create(ctx, DEFAULT_HOST_PORT, settings,
+                self.game.clone())?)))
         }
-        if self.join_button.borrow().is_pressed() {
-            return Ok(Some(Box::new(LobbyScene::join(ctx,
-                self.join_endpoint_txt.borrow().get_text(), self.game.clone())?)))
+        let spectate = self.spectate_button.borrow().is_pressed();
+        if self.join_button.borrow().is_pressed() || spectate {
+            let endpoint = self.join_endpoint_txt.borrow().get_text().to_owned();
+            {
+                let mut game_ref = self.game.borrow_mut();
+                game_ref.settings.add_last_endpoint(&endpoint);
+                if let Err(e) = game_ref.settings.save() {
+                    println!("Failed to save settings. Reason: {}", e);
+                }
+            }
+            return Ok(Some(Box::new(LobbyScene::join(ctx, &endpoint, spectate, self.get_password(),
+                self.game.clone())?)))
         }
 
         Ok(None)
@@ -88,4 +220,31 @@ impl Scene for ConnectionScene {
 }
 
 impl State for ConnectionScene {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        let lan_changed = match self.browser.as_mut().map(|browser| browser.update()) {
+            Some(Ok(changed)) => changed,
+            Some(Err(e)) => {
+                println!("LAN discovery failed. Reason: {}", e);
+                self.browser = None;
+                true
+            },
+            None => false
+        };
+        if lan_changed {
+            self.update_lan_matches(ctx)?;
+        }
+        let online_changed = match self.server_browser.as_mut().map(|server_browser| server_browser.update()) {
+            Some(Ok(changed)) => changed,
+            Some(Err(e)) => {
+                println!("Server browser failed. Reason: {}", e);
+                self.server_browser = None;
+                true
+            },
+            None => false
+        };
+        if online_changed {
+            self.update_online_matches(ctx)?;
+        }
+        Ok(())
+    }
 }
@@ -1,9 +1,9 @@
 use tetra::{Context, State};
-use crate::{BbResult, GC, PlayerParams, Timer, V2, grid::{Grid, UIAlignment}, image::Image, label::{FontSize, Label}, rand_u32, world_scene::WorldScene};
+use crate::{BbResult, GC, PlayerParams, Timer, V2, game_settings::GameSettings, grid::{Grid, UIAlignment}, image::Image, label::{FontSize, Label}, rand_u32, world_scene::WorldScene};
 use super::scenes::{Scene, SceneType};
 
 const MIN_LOADING_TIME: f32 = 1.0;
-const LOADING_HINTS: [&str; 11] = [
+const LOADING_HINTS: [&str; 13] = [
     "Ship collisions stun the crew. Duration and damage depend on the ship's defence value.",
     "Use the Q and E keys to shoot cannons on star- and portside respectively.",
     "Use the R key to reorientate your ship to the target direction.",
@@ -15,11 +15,14 @@ const LOADING_HINTS: [&str; 11] = [
     "Use your escudos to buy upgrades and repairs at harbours.",
     "Reefs will prove an impassable barrier to heavier ships, while allowing schooners to pass.",
     "Beware of bandit outposts! They will shoot any ship on sight.",
+    "Sailing with the wind is faster than sailing against it.",
+    "Rain shortens the range of cannons. Storms wear down any hull outside of harbours.",
 ];
 
 pub struct LoadingScene {
     players: Vec<PlayerParams>,
     world_seed: u64,
+    settings: GameSettings,
     min_load_timer: Timer,
     grid: Grid,
     image_loaded: bool,
@@ -27,7 +30,8 @@ pub struct LoadingScene {
 }
 
 impl LoadingScene {
-    pub fn new(ctx: &mut Context, players: Vec<PlayerParams>, world_seed: u64, game: GC)
+    pub fn new(ctx: &mut Context, players: Vec<PlayerParams>, world_seed: u64,
+        settings: GameSettings, game: GC)
         -> tetra::Result<LoadingScene> {
         let mut grid = Grid::default(ctx, UIAlignment::Vertical,
             V2::zero(), V2::one() * 200.0, 0.0)?;
@@ -44,7 +48,7 @@ impl LoadingScene {
         grid.add_element(title_grid);
         
         Ok(LoadingScene {
-            players, world_seed, min_load_timer: Timer::start(MIN_LOADING_TIME),
+            players, world_seed, settings, min_load_timer: Timer::start(MIN_LOADING_TIME),
             grid, image_loaded: false, game
         })
     }
@@ -77,7 +81,7 @@ impl Scene for LoadingScene {
     fn poll(&self, ctx: &mut Context) -> BbResult<Option<Box<dyn Scene + 'static>>> {
         Ok(if self.min_load_timer.is_over() {
             Some(Box::new(WorldScene::new(ctx, self.players.clone(), self.world_seed,
-                self.game.clone())?))
+                self.settings, self.game.clone())?))
         } else {
             None
         })
@@ -86,7 +90,7 @@ impl Scene for LoadingScene {
 
 impl State for LoadingScene {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.min_load_timer.update(ctx);
+        self.min_load_timer.update();
         if self.min_load_timer.max > 0.0 && self.min_load_timer.curr_time > 0.1 {
             self.load_image(ctx)?;
         }
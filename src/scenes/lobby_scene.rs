@@ -1,6 +1,6 @@
 use std::{collections::HashMap, net::SocketAddr};
 use tetra::{Context, State};
-use crate::{BbResult, GC, ID, PlayerParams, Rcc, TransformResult, V2, button::{Button, DefaultButton}, chat::Chat, client::ClientEvent, game_settings::GameSettings, grid::{Grid, UIAlignment, UILayout}, label::{FontSize, Label}, loading_scene::LoadingScene, menu_scene::MenuScene, net_controller::NetController, net_settings::NetSettings, network::Network, packet::{GamePhase, Packet, serialize_packet}, peer::{DisconnectReason, is_auth_client}, rand_u64, server::ServerEvent, ship_data::ShipType, ui_element::{DefaultUIReactor, UIElement}};
+use crate::{BbResult, GC, ID, PlayerParams, Rcc, TransformResult, V2, button::{Button, DefaultButton}, chat::Chat, client::ClientEvent, connection_quality::ConnectionQuality, game_settings::GameSettings, grid::{Grid, UIAlignment, UILayout}, label::{FontSize, Label}, loading_scene::LoadingScene, menu_scene::MenuScene, moderation::HOST_COMMAND_USAGE, net_controller::NetController, net_settings::NetSettings, network::Network, packet::{GamePhase, Packet, serialize_packet}, peer::DisconnectReason, rand_u64, replay::Replay, server::ServerEvent, ship_data::ShipType, ui_element::{DefaultUIReactor, UIElement}, world_scene::WorldScene};
 use super::scenes::{Scene, SceneType};
 
 pub struct LobbyScene {
@@ -9,7 +9,8 @@ pub struct LobbyScene {
     players: HashMap<u16, PlayerParams>,
     world_seed: u64,
     game_started: bool,
-    disconnected: bool,
+    disconnected: Option<DisconnectReason>,
+    rejoin: Option<(Replay, u64)>, // Setup of the running match and the gen to catch up to
     game_settings: GameSettings,
     game: GC
 }
@@ -24,12 +25,13 @@ impl LobbyScene {
         Self::new(ctx, game)
     }
 
-    pub fn join(ctx: &mut Context, endpoint: &str, game: GC)
-        -> BbResult<LobbyScene> {
+    pub fn join(ctx: &mut Context, endpoint: &str, spectator: bool, password: Option<String>,
+        game: GC) -> BbResult<LobbyScene> {
         {
             let mut game_ref = game.borrow_mut();
             let name = game_ref.settings.name.to_owned();
-            game_ref.network = Some(Network::join(endpoint, name)?);
+            let rejoin_token = game_ref.rejoin_tokens.remove(endpoint).filter(|_| !spectator);
+            game_ref.network = Some(Network::join(endpoint, name, spectator, password, rejoin_token)?);
         }
         Self::new(ctx, game)
     }
@@ -41,7 +43,7 @@ impl LobbyScene {
 
         Ok(LobbyScene {
             grid, ui, players: HashMap::new(), world_seed: 0, game_started: false,
-            disconnected: false, game_settings: GameSettings::default(), game
+            disconnected: None, rejoin: None, game_settings: GameSettings::default(), game
         })
     }
 
@@ -77,12 +79,16 @@ impl Scene for LobbyScene {
     fn poll(&self, ctx: &mut Context) -> BbResult<Option<Box<dyn Scene + 'static>>> {
         Ok(if self.game_started {
             Some(Box::new(LoadingScene::new(ctx, self.players.values().map(|p| p.clone())
-                .collect(), self.world_seed, self.game.clone()).convert()?))
+                .collect(), self.world_seed, self.game_settings, self.game.clone()).convert()?))
+        } else if let Some((setup, gen)) = self.rejoin.as_ref() {
+            Some(Box::new(WorldScene::rejoin(ctx, setup.clone(), *gen, self.game.clone())?))
         } else if self.ui.disconnect_button.borrow().is_pressed() {
             self.game.borrow_mut().network.as_mut().unwrap().disconnect(DisconnectReason::Manual)?;
             Some(Box::new(MenuScene::new(ctx, self.game.clone()).convert()?))
-        } else if self.disconnected {
-            Some(Box::new(MenuScene::new(ctx, self.game.clone()).convert()?))
+        } else if let Some(reason) = self.disconnected {
+            let reason = self.game.borrow().network.as_ref().unwrap().client.describe_disconnect(reason);
+            Some(Box::new(MenuScene::with_notice(ctx, &format!("Disconnected: {}.", reason),
+                self.game.clone()).convert()?))
         } else {
             None
         })
@@ -91,7 +97,7 @@ impl Scene for LobbyScene {
 
 impl State for LobbyScene {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.ui.update(ctx).convert()?;
+        self.ui.update(ctx, self.game_settings, self.players.len()).convert()?;
         self.update_ship_selection().convert()?;
         
         self.handle_received_packets(ctx).convert()
@@ -114,13 +120,16 @@ impl NetController for LobbyScene {
             players: self.players
                 .values()
                 .map(|p| p.clone())
-                .collect()
+                .collect(),
+            spectators: server.get_spectators(),
+            host: server.get_host_id(),
+            rejoin_token: server.get_rejoin_token(id.n).unwrap_or_default()
         }, id.n), remote_addr)?;
 
         if server.get_connection_count() > 1 {
             // Notify all other players that a newcomer has arrived
             server.send_multicast_group(Packet::PlayerConnect {
-                name: id.name.to_owned()
+                name: id.name.to_owned(), spectator: server.is_spectator(id.n)
             }, id.n, server.get_connections()
                 .filter(|conn| id != conn.0)
                 .map(|conn| conn.0.n)
@@ -135,11 +144,16 @@ impl NetController for LobbyScene {
             GamePhase::World(..) => true,
             _ => false
         };
-        Ok(match (is_auth_client(sender), is_valid_phase) {
-            (true, true) => self.game.borrow_mut().network.as_mut().unwrap()
-                .server.as_mut().unwrap().send_multicast(Packet::Game {
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        Ok(match (server.is_host(sender), is_valid_phase) {
+            (true, true) => {
+                server.set_accepting_connections(false); // Only players who time out may come back
+                server.send_multicast(Packet::Game {
                     phase
-                }, sender)?,
+                }, sender)?;
+                server.send_host_candidates()?
+            },
             (true, false) => println!("^{} failed to set {:?} phase: invalid phase.",
                 sender, phase),
             (false, _) => println!("^{} failed to set {:?} phase: insufficient permissions.",
@@ -156,14 +170,15 @@ impl NetController for LobbyScene {
     }
 
     fn on_server_receive_settings(&mut self, _: &mut Context, sender: u16, settings: GameSettings) -> BbResult {
-        self.game.borrow_mut().network.as_mut().unwrap()
-            .server.as_mut().unwrap().send_multicast(Packet::Selection {
-                mode: false, ship: None, settings: Some(settings)
-            }, sender)
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        server.set_game_mode(settings.mode);
+        server.send_multicast(Packet::Selection {
+            mode: false, ship: None, settings: Some(settings)
+        }, sender)
     }
 
     fn on_server_receive_chat_message(&mut self, sender: u16, message: String) -> BbResult {
-        // Check for spam/profanity?
         self.game.borrow_mut().network.as_mut().unwrap()
             .server.as_mut().unwrap().send_multicast(Packet::ChatMessage {
                 message
@@ -172,16 +187,35 @@ impl NetController for LobbyScene {
 
     fn on_establish_connection(&mut self, ctx: &mut Context, local_player: PlayerParams,
         players: Vec<PlayerParams>) -> BbResult {
-        self.add_player(ctx, local_player)?;
+        let spectator = self.game.borrow().network.as_ref().unwrap().client.is_spectator();
+        if !spectator {
+            self.add_player(ctx, local_player)?;
+        }
         for player in players.into_iter() {
             self.on_player_connect(ctx, player)?;
         }
         self.ui.match_grid.borrow_mut().remove_element_at(0);
+        if spectator {
+            self.ui.chat.add_line(ctx, "You are spectating. The match starts without a ship for you.").convert()?;
+        }
+        if self.game.borrow().network.as_ref().unwrap().has_authority() {
+            self.ui.chat.add_line(ctx, HOST_COMMAND_USAGE).convert()?;
+            let relay_endpoint = self.game.borrow().network.as_ref().unwrap().server.as_ref()
+                .and_then(|server| server.get_relay_endpoint().map(|endpoint| endpoint.to_owned()));
+            if let Some(relay_endpoint) = relay_endpoint {
+                self.ui.chat.add_line(ctx, &format!("Players join via {}", relay_endpoint)).convert()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_rejoin_match(&mut self, _: &mut Context, setup: Replay, gen: u64) -> BbResult {
+        self.rejoin = Some((setup, gen));
         Ok(())
     }
 
     fn on_connection_lost(&mut self, _: &mut Context, reason: DisconnectReason) -> BbResult {
-        self.disconnected = true;
+        self.disconnected = Some(reason);
         println!("Connection to server was lost. Reason: {:?}. Returning to menu...", reason);
         Ok(())
     }
@@ -191,6 +225,10 @@ impl NetController for LobbyScene {
         self.ui.chat.add_line(ctx, &format!("{:?} connected to the game!", player.id)).convert()
     }
 
+    fn on_spectator_connect(&mut self, ctx: &mut Context, id: ID) -> BbResult {
+        self.ui.chat.add_line(ctx, &format!("{:?} is spectating the game.", id)).convert()
+    }
+
     fn on_player_disconnect(&mut self, ctx: &mut Context, id: u16, reason: DisconnectReason)
         -> BbResult {
         if let Some(_) = self.players.remove(&id) {
@@ -211,6 +249,10 @@ impl NetController for LobbyScene {
         self.ui.chat.add_message(ctx, sender.as_str(), text.as_str()).convert()
     }
 
+    fn on_notice(&mut self, ctx: &mut Context, text: String) -> BbResult {
+        self.ui.chat.add_line(ctx, &text).convert()
+    }
+
     fn on_game_phase_changed(&mut self, _: &mut Context, phase: GamePhase) -> BbResult {
         Ok(match phase {
             GamePhase::World(world_seed) => {
@@ -238,12 +280,19 @@ impl NetController for LobbyScene {
         self.game_settings = settings;
         Ok(())
     }
+
+    fn on_quality_table(&mut self, ctx: &mut Context, _: Vec<ConnectionQuality>) -> BbResult {
+        self.ui.update_player_list(ctx, self.players.values()
+            .map(|p| p.clone()).collect())
+    }
 }
 
 struct LobbySceneUI {
     match_grid: Rcc<Grid>,
     chat: Chat,
     start_game_button: Rcc<DefaultButton>,
+    fill_bots_button: Rcc<DefaultButton>,
+    fill_bots: bool,
     disconnect_button: Rcc<DefaultButton>,
     player_list_grid: Rcc<Grid>,
     caravel_ship_button: Rcc<DefaultButton>,
@@ -263,10 +312,19 @@ impl LobbySceneUI {
 
         let mut start_game_button = Button::new(ctx, "Start Game",
             V2::new(110.0, 30.0), 2.0, DefaultUIReactor::new(), game.clone()).convert()?;
-        if !game.borrow().network.as_ref().unwrap().has_authority() {
+        let mut fill_bots_button = Button::new(ctx, "Bots: Off",
+            V2::new(150.0, 30.0), 2.0, DefaultUIReactor::new(), game.clone()).convert()?;
+        let (has_authority, spectator) = {
+            let game_ref = game.borrow();
+            let network = game_ref.network.as_ref().unwrap();
+            (network.has_authority(), network.client.is_spectator())
+        };
+        if !has_authority {
             start_game_button.set_disabled(true);
+            fill_bots_button.set_disabled(true);
         }
         let start_game_button = match_grid.add_element(start_game_button);
+        let fill_bots_button = match_grid.add_element(fill_bots_button);
         let disconnect_button = match_grid.add_element(Button::new(ctx, "Disconnect", 
             V2::new(105.0, 30.0), 2.0, DefaultUIReactor::new(), game.clone()).convert()?);
         match_grid.add_element(Label::new(ctx, "Connected Players", FontSize::Header,
@@ -294,28 +352,44 @@ impl LobbySceneUI {
             "Schooner: Light fore-and-aft rig. 3 cannons/side. 120 HP. 35 Defence. Quick and mobile.", FontSize::Small, 2.0, game.clone()).convert()?);
         let schooner_ship_button = game_settings_grid.add_element(Button::new(ctx,
             "Schooner", V2::new(90.0, 35.0), 2.0, DefaultUIReactor::new(), game.clone()).convert()?);
+        if spectator {
+            for button in [&caravel_ship_button, &galleon_ship_button, &schooner_ship_button].iter() {
+                button.borrow_mut().set_disabled(true);
+            }
+        }
         game_grid.add_element(game_settings_grid);
 
         let chat = Chat::new(ctx, UILayout::Default, &mut game_grid, game.clone()).convert()?;
         grid.add_element(game_grid);
         
         Ok(LobbySceneUI {
-            match_grid, chat, start_game_button, disconnect_button, player_list_grid,
+            match_grid, chat, start_game_button, fill_bots_button, fill_bots: false, disconnect_button, player_list_grid,
             caravel_ship_button, galleon_ship_button, schooner_ship_button,
-            selected_ship_type: Some(ShipType::Caravel),
+            selected_ship_type: match spectator {
+                true => None,
+                false => Some(ShipType::Caravel)
+            },
             game_started: false, game
         })
     }
 
     fn add_player(&mut self, ctx: &mut Context, player: PlayerParams) -> BbResult {
+        let (quality, host_id) = match self.game.borrow().network.as_ref() {
+            Some(network) => (network.client.get_connection_quality(player.id.n).copied(),
+                network.client.get_host_id()),
+            None => (None, None)
+        };
         let mut player_list_grid_ref = self.player_list_grid.borrow_mut();
-        let name = format!("  {:?} {} - {:?}", &player.id, {
-            if is_auth_client(player.id.n) {
+        let name = format!("  {:?} {} - {:?}{}", &player.id, {
+            if host_id == Some(player.id.n) {
                 "(Host)"
             } else {
                 ""
             }
-        }, player.ship_type);
+        }, player.ship_type, match quality {
+            Some(quality) => format!(" - {}", quality),
+            None => String::new()
+        });
         player_list_grid_ref.add_element(
             Label::new(ctx, name.as_str(), FontSize::Normal, 2.0, self.game.clone()).convert()?);
         Ok(())
@@ -333,13 +407,35 @@ impl LobbySceneUI {
         Ok(())
     }
 
-    fn update(&mut self, ctx: &mut Context) -> BbResult {
+    fn update(&mut self, ctx: &mut Context, game_settings: GameSettings, player_count: usize)
+        -> BbResult {
+        {
+            let mut fill_bots_button_ref = self.fill_bots_button.borrow_mut();
+            if fill_bots_button_ref.is_pressed() {
+                self.fill_bots = !self.fill_bots;
+                fill_bots_button_ref.set_text(match self.fill_bots {
+                    true => "Bots: Fill Slots",
+                    false => "Bots: Off"
+                });
+            }
+        }
         {
             let mut start_game_button_ref = self.start_game_button.borrow_mut();
             if start_game_button_ref.is_pressed() && self.game.borrow().network.as_ref().unwrap()
                 .has_authority() && !self.game_started {
-                self.game.borrow_mut().network.as_mut().unwrap()
-                    .load_world_phase(rand_u64())?;
+                let mut game_ref = self.game.borrow_mut();
+                let network = game_ref.network.as_mut().unwrap();
+                if self.fill_bots {
+                    // Settings arrive before the world phase, as both are sent in order
+                    let max_players = network.server.as_ref().unwrap().get_max_players();
+                    network.send_packet(Packet::Selection {
+                        mode: false, ship: None, settings: Some(GameSettings {
+                            bots: max_players.saturating_sub(player_count).min(u8::MAX as usize) as u8,
+                            ..game_settings
+                        })
+                    })?;
+                }
+                network.load_world_phase(rand_u64())?;
                 start_game_button_ref.set_disabled(true);
                 self.game_started = true;
             }
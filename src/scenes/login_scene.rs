@@ -18,7 +18,9 @@ impl LoginScene {
         let header = grid.add_element(Image::new(ctx, V2::new(673.0 * 0.65, 117.0 * 0.65),
             5.0, "UI/Header-Text.png".to_owned(), true, game.clone())?);
         grid.add_element(Label::new(ctx, "Choose a name", FontSize::Normal, 0.0, game.clone())?);
-        let name_txt = grid.add_element(Textbox::new(ctx, "", V2::new(200.0, 30.0), 2.0, game.clone())?);
+        let last_name = game.borrow().settings.name.to_owned();
+        let name_txt = grid.add_element(Textbox::new(ctx, &last_name, V2::new(200.0, 30.0),
+            2.0, game.clone())?);
         let login_button = grid.add_element(DefaultButton::new(ctx, "Login",
             V2::new(70.0, 35.0), 3.0, DefaultUIReactor::new(), game.clone())?);
         Ok(LoginScene {
@@ -44,7 +46,12 @@ impl Scene for LoginScene {
         let name_txt_ref = self.name_txt.borrow_mut();
         let name = name_txt_ref.get_text().to_owned();
         if self.login_button.borrow().is_pressed() || name_txt_ref.confirm_enter(ctx) {
-            if self.game.borrow_mut().settings.set_name(name) {
+            let mut game_ref = self.game.borrow_mut();
+            if game_ref.settings.set_name(name) {
+                if let Err(e) = game_ref.settings.save() {
+                    println!("Failed to save settings. Reason: {}", e);
+                }
+                std::mem::drop(game_ref);
                 return Ok(Some(Box::new(MenuScene::new(ctx, self.game.clone()).convert()?)))
             }
         }
@@ -1,10 +1,11 @@
 use tetra::{Context, State, window::quit};
-use crate::{BbResult, GC, Rcc, TransformResult, V2, button::{Button, DefaultButton}, connection_scene::ConnectionScene, grid::{Grid, UIAlignment}, label::{FontSize, Label}, ui_element::{DefaultUIReactor}};
+use crate::{BbResult, GC, Rcc, TransformResult, V2, button::{Button, DefaultButton}, connection_scene::ConnectionScene, grid::{Grid, UIAlignment}, label::{FontSize, Label}, replay::Replay, ui_element::{DefaultUIReactor, UIElement}, world_scene::WorldScene};
 use super::scenes::{Scene, SceneType};
 
 pub struct MenuScene {
     pub grid: Grid,
     online_game_button: Rcc<DefaultButton>,
+    replay_button: Rcc<DefaultButton>,
     exit_button: Rcc<DefaultButton>,
     game: GC
 }
@@ -20,14 +21,24 @@ impl MenuScene {
         //     V2::new(125.0, 30.0), 5.0, DefaultUIReactor::new(), game.clone())?);
         let online_game_button = grid.add_element(Button::new(ctx, "Play Online",
             V2::new(130.0, 35.0), 2.0, DefaultUIReactor::new(), game.clone())?);
+        let replay_button = grid.add_element(Button::new(ctx, "Watch Last Replay",
+            V2::new(170.0, 35.0), 2.0, DefaultUIReactor::new(), game.clone())?);
+        replay_button.borrow_mut().set_disabled(Replay::find_latest().is_none());
         let exit_button = grid.add_element(Button::new(ctx, "Exit",
             V2::new(80.0, 35.0), 2.0, DefaultUIReactor::new(), game.clone())?);
         
         Ok(MenuScene {
-            grid, online_game_button, exit_button,
+            grid, online_game_button, replay_button, exit_button,
             game: game.clone()
         })
     }
+
+    // Shown after being thrown back to the menu, e.g. when a server refused to connect
+    pub fn with_notice(ctx: &mut Context, notice: &str, game: GC) -> tetra::Result<MenuScene> {
+        let mut scene = Self::new(ctx, game.clone())?;
+        scene.grid.add_element(Label::new(ctx, notice, FontSize::Normal, 5.0, game)?);
+        Ok(scene)
+    }
 }
 
 impl Scene for MenuScene {
@@ -49,6 +60,18 @@ impl Scene for MenuScene {
             return Ok(Some(Box::new(
                 ConnectionScene::new(ctx, self.game.clone()).convert()?)))
         }
+        else if self.replay_button.borrow().is_pressed() {
+            if let Some(path) = Replay::find_latest() {
+                match Replay::load(&path) {
+                    Ok(replay) => {
+                        println!("Playing replay {:?} ({} gens)...", path, replay.get_total_gens());
+                        return Ok(Some(Box::new(WorldScene::replay(ctx, replay, None,
+                            self.game.clone())?)))
+                    },
+                    Err(e) => println!("Failed to load replay {:?}. Reason: {}", path, e)
+                }
+            }
+        }
         else if self.exit_button.borrow().is_pressed() {
             quit(ctx);
             return Ok(None)
@@ -9,7 +9,8 @@ pub enum SceneType {
     Loading,
     Connection,
     Lobby,
-    World
+    World,
+    Score
 }
 
 pub trait Scene : State {
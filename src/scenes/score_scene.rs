@@ -0,0 +1,95 @@
+use tetra::{Context, State};
+use crate::{BbResult, GC, Rcc, TransformResult, V2, button::{Button, DefaultButton}, client::ClientEvent, game_settings::GameMode, grid::{Grid, UIAlignment}, label::{FontSize, Label}, match_result::MatchResult, menu_scene::MenuScene, net_controller::NetController, peer::DisconnectReason, server::ServerEvent, ui_element::DefaultUIReactor};
+use super::scenes::{Scene, SceneType};
+
+pub struct ScoreScene {
+    pub grid: Grid,
+    back_to_menu_button: Rcc<DefaultButton>,
+    game: GC
+}
+
+impl ScoreScene {
+    pub fn new(ctx: &mut Context, result: MatchResult, game: GC) -> tetra::Result<ScoreScene> {
+        let mut grid = Grid::default(ctx, UIAlignment::Vertical,
+            V2::zero(), V2::new(500.0, 500.0), 5.0)?;
+        grid.add_element(Label::new(ctx, "Match Over", FontSize::Header,
+            5.0, game.clone())?);
+        let (goal, unit) = match result.mode {
+            GameMode::Raid(escudos_goal) => (escudos_goal, "escudos"),
+            GameMode::Deathmatch(sinkings_goal) => (sinkings_goal as u32, "sinkings")
+        };
+        grid.add_element(Label::new(ctx, &format!("{:?} reached {} {} first (Gen {}).",
+            result.get_winner().id, goal, unit, result.gen), FontSize::Normal, 5.0, game.clone())?);
+
+        grid.add_element(Label::new(ctx, "Final Standings", FontSize::Header,
+            5.0, game.clone())?);
+        let mut standings_grid = Grid::default(ctx, UIAlignment::Vertical,
+            V2::zero(), V2::new(450.0, 300.0), 5.0)?;
+        for (i, standing) in result.standings.iter().enumerate() {
+            standings_grid.add_element(Label::new(ctx, &format!("  {}. {:?} - {} Escudos net worth, {} sinkings",
+                i + 1, standing.id, standing.networth, standing.sinkings), FontSize::Normal,
+                2.0, game.clone())?);
+        }
+        grid.add_element(standings_grid);
+
+        let back_to_menu_button = grid.add_element(Button::new(ctx, "Back to Menu",
+            V2::new(130.0, 35.0), 2.0, DefaultUIReactor::new(), game.clone())?);
+        Ok(ScoreScene {
+            grid, back_to_menu_button, game
+        })
+    }
+}
+
+impl Scene for ScoreScene {
+    fn get_type(&self) -> SceneType {
+        SceneType::Score
+    }
+
+    fn get_grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    fn get_grid_mut(&mut self) -> &mut Grid {
+        &mut self.grid
+    }
+
+    fn poll(&self, ctx: &mut Context) -> BbResult<Option<Box<dyn Scene + 'static>>> {
+        Ok(if self.back_to_menu_button.borrow().is_pressed() {
+            {
+                let mut game_ref = self.game.borrow_mut();
+                if let Some(mut network) = game_ref.network.take() {
+                    network.disconnect(DisconnectReason::Manual)?;
+                }
+            }
+            Some(Box::new(MenuScene::new(ctx, self.game.clone()).convert()?))
+        } else {
+            None
+        })
+    }
+}
+
+impl State for ScoreScene {
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        // Keep the connection alive until the player leaves
+        if self.game.borrow().network.is_some() {
+            self.handle_received_packets(ctx).convert()?;
+        }
+        Ok(())
+    }
+}
+
+impl NetController for ScoreScene {
+    fn poll_received_server_packets(&mut self, _: &mut Context) -> BbResult<ServerEvent> {
+        self.game.borrow_mut().network.as_mut().unwrap().poll_received_server_packets()
+    }
+
+    fn poll_received_client_packets(&mut self, _: &mut Context) -> BbResult<ClientEvent> {
+        self.game.borrow_mut().network.as_mut().unwrap().poll_received_client_packets()
+    }
+
+    fn on_connection_lost(&mut self, _: &mut Context, reason: DisconnectReason) -> BbResult {
+        // Standings remain visible, the network is torn down when leaving the scene
+        println!("Connection to server was lost. Reason: {:?}.", reason);
+        Ok(())
+    }
+}
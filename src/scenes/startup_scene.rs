@@ -59,7 +59,7 @@ impl Scene for StartupScene {
 
 impl State for StartupScene {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.timer.update(ctx);
+        self.timer.update();
         Ok(())
     }
 
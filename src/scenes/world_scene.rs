@@ -1,93 +1,168 @@
-use tetra::{Context, Event, State, input::Key};
-use crate::{BbResult, Controller, GC, ID, Player, PlayerParams, Rcc, TransformResult, V2, WorldEvent, button::{Button, DefaultButton}, chat::Chat, client::ClientEvent, entity::{GameState}, gen_world, grid::{Grid, UIAlignment, UILayout}, image::Image, input_pool::{InputPool, STEP_PHASE_FRAME_LENGTH}, label::{FontSize, Label}, menu_scene::MenuScene, net_controller::NetController, packet::{InputState, InputStep, Packet}, peer::DisconnectReason, server::ServerEvent, ship_data::{ShipID, ShipType}, ship_mod::{HARBOUR_REPAIR_COST, ShipModType}, sync_checker::{SyncChecker, SyncState}, ui_element::{DefaultUIReactor, UIElement}, world::World};
+use std::{collections::{HashMap, HashSet}, net::SocketAddr};
+use tetra::{Context, Event, State, input::Key, time::{Timestep, set_timestep}};
+use crate::{BbError, BbErrorType, BbResult, DEFAULT_SIMULATION_TIMESTEP, GC, ID, Player, PlayerParams, Rcc, TransformResult, V2, WorldEvent, button::{Button, DefaultButton}, chat::Chat, client::ClientEvent, connection_quality::ConnectionQuality, desync_investigation::DesyncInvestigation, entity::{GameState}, game_settings::GameSettings, grid::{Grid, UIAlignment, UILayout}, host_migration::{HostMigration, gen_migration_packet}, image::Image, input_pool::{FINAL_INPUT_STEPS, InputPool, REDUNDANT_INPUT_STEPS, STEP_PHASE_FRAME_LENGTH}, label::{FontSize, Label}, menu_scene::MenuScene, net_controller::NetController, packet::{GamePhase, InputState, InputStep, Packet}, peer::DisconnectReason, rejoin::{REJOIN_CATCH_UP_SPEED, REJOIN_GRACE_PERIOD, gen_missed_steps_packets, gen_rejoin_packets}, replay::{REPLAY_SEEK_GENS, Replay, ReplayPlayback}, score_scene::ScoreScene, server::ServerEvent, ship_data::ShipID, ship_mod::{HARBOUR_REPAIR_COST, ShipModType}, simulation::Simulation, state_digest::StateDigestPart, sync_checker::{SyncChecker, SyncState}, ui_element::{DefaultUIReactor, UIElement}, weather::WeatherSystem};
 use super::scenes::{Scene, SceneType};
 
 pub struct WorldScene {
-    pub controller: Controller,
-    pub world: World,
+    pub simulation: Simulation,
     grid: Grid,
     ui: WorldSceneUI,
     back_to_menu: bool,
+    score_phase: bool,
+    match_result_announced: bool,
     input_pool: Option<InputPool>,
     sync_checker: Option<SyncChecker>,
+    desync_investigation: Option<DesyncInvestigation>,
+    host_migration: Option<HostMigration>, // Until the peers moved over to this client's server
+    replay: Option<ReplayPlayback>,
+    rewind_target: Option<u64>,
+    last_verified_gen: u64,
+    catch_up_gen: Option<u64>, // Gen a rejoining player fast-forwards to
+    follow_cam: bool, // Spectators only
+    disconnected: Option<DisconnectReason>,
+    lagging_players: HashSet<u16>, // Already warned about
     game: GC
 }
 
 impl WorldScene {
     pub fn new(ctx: &mut Context, players: Vec<PlayerParams>, world_seed: u64,
-        game: GC) -> BbResult<WorldScene> {
+        settings: GameSettings, game: GC) -> BbResult<WorldScene> {
+        let local_id = {
+            game.borrow().network.as_ref().unwrap().client
+                .get_local_id().expect("Client has no local ID assigned")
+        };
+        let mut world_scene = Self::build(ctx, players.clone(), world_seed, settings,
+            Some(local_id), game)?;
+        world_scene.simulation.controller.recording = Some(Replay::new(world_seed, players, settings));
+        Ok(world_scene)
+    }
+
+    // Rebuilds the running match from its setup. The buffered steps arrive right after and are
+    // fast-forwarded, until the simulation caught up with the other players.
+    pub fn rejoin(ctx: &mut Context, setup: Replay, gen: u64, game: GC) -> BbResult<WorldScene> {
+        let mut world_scene = Self::new(ctx, setup.players, setup.world_seed, setup.settings, game)?;
+        world_scene.catch_up_gen = Some(gen);
+        Ok(world_scene)
+    }
+
+    // Replays the recorded match locally, without any network. Seeking backwards
+    // rebuilds the scene and fast-forwards to the target gen.
+    pub fn replay(ctx: &mut Context, replay: Replay, seek_target: Option<u64>, game: GC)
+        -> BbResult<WorldScene> {
+        let mut world_scene = Self::build(ctx, replay.players.clone(), replay.world_seed,
+            replay.settings, None, game)?;
+        world_scene.ui.toggle_menu_visibility(); // Show controls right away
+        world_scene.replay = Some(ReplayPlayback::new(replay, seek_target));
+        Ok(world_scene)
+    }
+
+    fn build(ctx: &mut Context, players: Vec<PlayerParams>, world_seed: u64,
+        settings: GameSettings, local_id: Option<ID>, game: GC) -> BbResult<WorldScene> {
         let mut grid = Grid::default(ctx, UIAlignment::Horizontal,
             V2::zero(), V2::one() * 200.0, 0.0).convert()?;
         let mut ui = WorldSceneUI::new(ctx, game.clone(), &mut grid).convert()?;
-        ui.update_players(ctx, players.iter().map(|p| p.id.clone()).collect()).convert()?;
+        let host_id = game.borrow().network.as_ref().and_then(|network| network.client.get_host_id());
+        ui.update_players(ctx, players.iter().map(|p| p.id.clone()).collect(), host_id).convert()?;
         
-        let (input_pool, sync_checker) = match game.borrow().network.as_ref().unwrap()
-            .has_authority() {
-            true => (Some(InputPool::new(players.iter().map(|p| p.id.n).collect())),
-                Some(SyncChecker::new())),
-            false => (None, None)
+        let has_authority = game.borrow().network.as_ref().is_some_and(|n| n.has_authority());
+        let (input_pool, sync_checker, desync_investigation) = match has_authority {
+            true => (Some(InputPool::new(players.clone(), world_seed, settings)),
+                Some(SyncChecker::new()), Some(DesyncInvestigation::new())),
+            false => (None, None, None)
         };
         let mut world_scene = WorldScene {
-            controller: Controller::new(ctx, game.clone()).convert()?,
-            world: World::new(ctx, game.clone()),
-            grid, ui, back_to_menu: false, input_pool, sync_checker, game: game.clone()
+            simulation: Simulation::new(players, world_seed, settings, game.clone())?,
+            grid, ui, back_to_menu: false, score_phase: false, match_result_announced: false, input_pool, sync_checker,
+            desync_investigation, host_migration: None, replay: None, rewind_target: None, last_verified_gen: 0, catch_up_gen: None,
+            follow_cam: false, disconnected: None, lagging_players: HashSet::new(), game: game.clone()
         };
-        let map_size = (10 + 5 * players.len()).min(30) as i64;
-        gen_world(ctx, map_size, map_size, 475.0, 1.7,
-            world_seed, 2, &mut world_scene.world).convert()?;
-
-        world_scene.init_players(ctx, players)?;
-        world_scene.ui.set_local_player(
-            world_scene.controller.local_player.as_ref().unwrap().clone());
+        world_scene.init_local_player(local_id);
+        if let Some(local_player) = world_scene.simulation.controller.local_player.clone() {
+            world_scene.ui.set_local_player(local_player);
+        }
         Ok(world_scene)
     }
 
-    pub fn add_player(&mut self, ctx: &mut Context, id: ID, ship_type: ShipType) -> BbResult<Rcc<Player>> {
-        let ship = self.world.add_player_ship(ctx, id.clone(), ship_type).convert()?;
-        Ok(self.controller.add_player(Player::new(id, ship, self.game.clone())))
-    }
-
     pub fn leave_match(&mut self) -> BbResult {
-        self.game.borrow_mut().network.as_mut().unwrap().disconnect(
-            DisconnectReason::Timeout)?;
+        if let Some(network) = self.game.borrow_mut().network.as_mut() {
+            network.disconnect(DisconnectReason::Timeout)?;
+        }
         self.back_to_menu = true;
         Ok(())
     }
 
-    fn init_players(&mut self, ctx: &mut Context, mut players: Vec<PlayerParams>)
-        -> BbResult {
-        let local_id = {
-            self.game.borrow().network.as_ref().unwrap().client
-                .get_local_id().expect("Client has no local ID assigned")
+    fn update_player_list(&mut self, ctx: &mut Context) -> BbResult {
+        let (players, host_id) = match self.game.borrow().network.as_ref() {
+            Some(network) => (network.client.get_connections(), network.client.get_host_id()),
+            None => return Ok(())
         };
+        self.ui.update_players(ctx, players, host_id).convert()
+    }
 
-        players.sort_unstable_by(|a, b| a.id.n.cmp(&b.id.n));
-        for player in players.into_iter() {
-            let player_instance = self.add_player(ctx, player.id.clone(), player.ship_type)?;
-            if player.id == local_id { // Is local player?
-                self.controller.set_local_player(player_instance.clone());
-                // Adjust camera for player
-                let pos = {
-                    let player_ref = player_instance.borrow();
-                    let mut ship_ref = player_ref.possessed_ship.borrow_mut();
-                    // As id.is_local_player is required in constructor, this wont cut it
-                    ship_ref.data.id = ShipID::Player(local_id.clone(), true);
-                    ship_ref.transform.get_translation().0
-                };
-                self.game.borrow_mut().cam.centre_on(pos);
-            }
+    fn init_local_player(&mut self, local_id: Option<ID>) {
+        let local_player = local_id.as_ref()
+            .and_then(|id| self.simulation.controller.players.get(&id.n).cloned());
+        if let (Some(local_id), Some(player)) = (local_id, local_player) {
+            self.simulation.controller.set_local_player(player.clone());
+            // Adjust camera for player
+            let pos = {
+                let player_ref = player.borrow();
+                let mut ship_ref = player_ref.possessed_ship.borrow_mut();
+                // As id.is_local_player is required in constructor, this wont cut it
+                ship_ref.data.id = ShipID::Player(local_id, true);
+                ship_ref.transform.get_translation().0
+            };
+            self.game.borrow_mut().cam.centre_on(pos);
+        } else {
+            self.follow_next_player();
+            self.follow_cam = true;
+        }
+    }
+
+    // Spectating replays: Centres the camera on the next player and shows their stats
+    fn follow_next_player(&mut self) {
+        let followed_id = self.ui.local_player.as_ref().map(|p| p.borrow().id.n);
+        let next_player = self.simulation.controller.players.values()
+            .find(|p| followed_id.is_none_or(|id| p.borrow().id.n > id))
+            .or_else(|| self.simulation.controller.players.values().next())
+            .cloned();
+        if let Some(player) = next_player {
+            let pos = player.borrow().possessed_ship.borrow().transform.get_translation().0;
+            self.game.borrow_mut().cam.centre_on(pos);
+            self.ui.set_local_player(player);
         }
-        Ok(())
     }
 
     fn update_world(&mut self, ctx: &mut Context) -> tetra::Result {
-        let is_next_frame_ready = self.controller.is_next_frame_ready();
-        self.game.borrow_mut().simulation_settings.run = is_next_frame_ready;
+        if let Some(playback) = self.replay.as_mut() {
+            // Seeking keeps simulating even when paused
+            if playback.is_paused() && playback.get_seek_target().is_none() {
+                self.game.borrow_mut().simulation_settings.run = false;
+                return Ok(())
+            }
+            let curr_gen = self.simulation.controller.get_curr_gen();
+            playback.feed(&mut self.simulation.controller.input_buffer, curr_gen);
+            self.simulation.controller.playback_speed = Some(playback.get_speed());
+        }
+        if let Some(catch_up_gen) = self.catch_up_gen {
+            if self.simulation.controller.get_curr_gen() >= catch_up_gen {
+                self.catch_up_gen = None;
+                self.simulation.controller.playback_speed = None;
+                self.ui.chat.add_line(ctx, "Caught up with the match!")?;
+            } else {
+                self.simulation.controller.playback_speed = Some(REJOIN_CATCH_UP_SPEED);
+            }
+        }
 
-        if is_next_frame_ready {
-            self.controller.update(ctx, &mut self.world)?;
-            self.world.update(ctx)
-        } else if self.controller.is_block_timed_out() {
+        if self.simulation.update()? {
+            if let Some(timestep) = self.simulation.controller.take_next_timestep() {
+                set_timestep(ctx, Timestep::Fixed(timestep));
+            }
+            self.verify_replay_sync_state();
+            Ok(())
+        } else if self.replay.is_some() { // Replay is finished
+            Ok(())
+        } else if self.simulation.controller.is_block_timed_out() {
             println!("Failed to procure next input step in time. Leaving match...");
             self.leave_match().convert()
         } else {
@@ -95,22 +170,95 @@ impl WorldScene {
         }
     }
 
+    fn verify_replay_sync_state(&mut self) {
+        if let (Some(playback), Some(state)) = (self.replay.as_mut(),
+            self.simulation.controller.get_last_sync_state()) {
+            if state.t > self.last_verified_gen {
+                self.last_verified_gen = state.t;
+                playback.verify_sync_state(state);
+            }
+        }
+    }
+
+    fn event_replay(&mut self, event: Event) {
+        let curr_gen = self.simulation.controller.get_curr_gen();
+        if let (Some(playback), Event::KeyPressed { key }) = (self.replay.as_mut(), event) {
+            match key {
+                Key::Space => playback.toggle_pause(),
+                Key::F => playback.toggle_fast_forward(),
+                Key::Right => playback.seek(curr_gen + REPLAY_SEEK_GENS),
+                Key::Left => self.rewind_target = Some(curr_gen.saturating_sub(REPLAY_SEEK_GENS)),
+                _ => ()
+            }
+        }
+    }
+
+    // Without an own ship, the camera either follows a player or is moved freely
+    fn event_spectator(&mut self, event: Event) {
+        if self.simulation.controller.local_player.is_some() {
+            return
+        }
+        if let Event::KeyPressed { key } = event {
+            let keys = self.game.borrow().settings.key_bindings;
+            if key == keys.centre_cam {
+                self.follow_next_player();
+                self.follow_cam = true;
+            } else if [keys.cam_up, keys.cam_down, keys.cam_left, keys.cam_right].contains(&key) {
+                self.follow_cam = false;
+            }
+        }
+    }
+
+    fn update_spectator_cam(&mut self) {
+        if let (true, Some(player)) = (self.follow_cam, self.ui.local_player.as_ref()) {
+            let pos = player.borrow().possessed_ship.borrow().transform.get_translation().0;
+            self.game.borrow_mut().cam.centre_on(pos);
+        }
+    }
+
     fn event_world(&mut self, ctx: &mut Context, event: Event) -> tetra::Result {
-        if self.controller.is_next_frame_ready() {
-            self.controller.event(ctx, event.clone(), &mut self.world)?;
-            self.world.event(ctx, event.clone())
+        if self.simulation.controller.is_next_frame_ready() {
+            self.simulation.controller.event(ctx, event.clone(), &mut self.simulation.world)?;
+            self.simulation.world.event(ctx, event.clone())
         } else {
             Ok(())
         }
     }
 
     fn update_menu_ui(&mut self) -> BbResult {
-        if self.controller.input_buffer.curr_frames
+        if let Some(playback) = self.replay.as_ref() {
+            let curr_gen = self.simulation.controller.get_curr_gen();
+            let status = if let Some(diverged_gen) = playback.get_diverged_gen() {
+                format!("Diverged at Gen {}!", diverged_gen)
+            } else if let Some(seek_target) = playback.get_seek_target() {
+                format!("Seeking Gen {}...", seek_target)
+            } else if playback.is_finished() && self.simulation.controller.input_buffer.get_buffer_size() == 0 {
+                "Finished".to_owned()
+            } else if playback.is_paused() {
+                "Paused".to_owned()
+            } else {
+                format!("{}x", playback.get_speed())
+            };
+            self.ui.update_match_info(&format!("Replay: Gen {}/{} ({}). [Space] Pause, [F] Speed, [Left/Right] Seek, [Tab] Follow",
+                curr_gen, playback.replay.get_total_gens(), status));
+        } else if let Some(catch_up_gen) = self.catch_up_gen {
+            self.ui.update_match_info(&format!("Rejoining: Gen {}/{}",
+                self.simulation.controller.get_curr_gen(), catch_up_gen));
+        } else if self.simulation.controller.input_buffer.curr_frames
             % (STEP_PHASE_FRAME_LENGTH as u64 * 5) == 0 {
-            let step_latency = self.controller.input_buffer.get_latency();
-            let feedback_latency = self.controller.calc_input_feedback_latency();
-            self.ui.update_match_info(&format!("Latency: Step ~ {:.2}s, Feedback ~ {:.2}s",
-                step_latency, feedback_latency));
+            let step_latency = self.simulation.controller.input_buffer.get_latency();
+            let ping = self.game.borrow().network.as_ref()
+                .and_then(|network| network.client.get_local_id()
+                    .and_then(|id| network.client.get_connection_quality(id.n).map(|quality| quality.rtt)))
+                .map_or(String::new(), |rtt| format!("Ping {}ms, ", rtt));
+            if let Some(rollback) = self.simulation.controller.get_rollback() {
+                self.ui.update_match_info(&format!("Latency: {}Step ~ {:.2}s, Predicted gens: {}, Rollbacks: {}",
+                    ping, step_latency, rollback.get_predicted_gens(), rollback.rollbacks));
+            } else {
+                let feedback_latency = self.simulation.controller.calc_input_feedback_latency();
+                self.ui.update_match_info(&format!("Latency: {}Step ~ {:.2}s, Feedback ~ {:.2}s",
+                    ping, step_latency, feedback_latency));
+            }
         }
 
         if self.ui.leave_button.borrow().is_pressed() {
@@ -121,37 +269,84 @@ impl WorldScene {
     }
 
     fn update_harbour_ui(&mut self) -> BbResult {
-        if !self.controller.local_player.as_ref().unwrap().borrow()
-            .possessed_ship.borrow().status.is_in_harbour {
+        let is_in_harbour = self.simulation.controller.local_player.as_ref().is_some_and(
+            |p| p.borrow().possessed_ship.borrow().status.is_in_harbour);
+        if !is_in_harbour {
             return Ok(())
         }
         
         if self.ui.harbour_ui.repair_ship_button.borrow().is_pressed() {
-            self.controller.buy_ship_mod(ShipModType::Repair)
+            self.simulation.controller.buy_ship_mod(ShipModType::Repair)
         }
         if self.ui.harbour_ui.buy_ammo_upgrade_button.borrow().is_pressed() {
-            self.controller.buy_ship_mod(ShipModType::CannonAmmoUpgrade);
+            self.simulation.controller.buy_ship_mod(ShipModType::CannonAmmoUpgrade);
         }
         if self.ui.harbour_ui.buy_cannon_reload_upgrade_button.borrow().is_pressed() {
-            self.controller.buy_ship_mod(ShipModType::CannonReloadUpgrade);
+            self.simulation.controller.buy_ship_mod(ShipModType::CannonReloadUpgrade);
         }
         if self.ui.harbour_ui.buy_cannon_range_upgrade_button.borrow().is_pressed() {
-            self.controller.buy_ship_mod(ShipModType::CannonRangeUpgrade);
+            self.simulation.controller.buy_ship_mod(ShipModType::CannonRangeUpgrade);
+        }
+        Ok(())
+    }
+
+    fn update_match_result(&mut self, ctx: &mut Context) -> BbResult {
+        if self.match_result_announced {
+            return Ok(())
+        }
+        if let Some(result) = self.simulation.controller.get_match_result() {
+            self.match_result_announced = true;
+            self.ui.chat.add_line(ctx, &format!("{:?} won the match!",
+                result.get_winner().id)).convert()?;
+
+            // Replays have no score phase, they just keep running until the last recorded step
+            let mut game_ref = self.game.borrow_mut();
+            if let Some(network) = game_ref.network.as_mut() {
+                if network.has_authority() || (network.is_host_dedicated()
+                    && !network.client.is_spectator()) {
+                    network.load_score_phase()?;
+                }
+            }
         }
         Ok(())
     }
 
+    fn cleanup(&self, ctx: &mut Context) {
+        set_timestep(ctx, Timestep::Fixed(DEFAULT_SIMULATION_TIMESTEP));
+        if let Some(recording) = self.simulation.controller.recording.as_ref()
+            .filter(|r| !r.steps.is_empty()) {
+            match recording.save() {
+                Ok(path) => println!("Saved replay of {} gens to {:?}.", recording.get_total_gens(), path),
+                Err(e) => println!("Failed to save replay. Reason: {}", e)
+            }
+        }
+
+        let mut game_ref = self.game.borrow_mut();
+        game_ref.physics.clear_colliders();
+        game_ref.physics.wind = V2::zero();
+        game_ref.weather = WeatherSystem::calm();
+        if let Err(e) = game_ref.diagnostics.backup_states("final") {
+            println!("Failed to back up diagnostic states. Reason: {}", e);
+        }
+    }
+
     fn update_serverside(&mut self) -> BbResult {
+        if let Some(host_migration) = self.host_migration.as_ref() {
+            if !host_migration.is_complete() {
+                return Ok(())
+            }
+            self.finish_host_migration()?;
+        }
         if let Some(input_pool) = self.input_pool.as_mut() {
             if input_pool.is_step_phase_over() {
                 // By now clients should have sent all states, so server can bundle and send them back to all
                 let delayed_players = input_pool.check_delayed_players();
                 // In the first generation every player has to send their state, to signal they're ready
                 if input_pool.curr_gen > 0 || delayed_players.len() == 0 {
-                    let step = input_pool.flush_states();
+                    input_pool.flush_states();
                     self.game.borrow_mut().network.as_mut().unwrap()
                         .server.as_mut().unwrap().send_multicast(Packet::InputStep {
-                            step
+                            steps: input_pool.get_latest_steps(REDUNDANT_INPUT_STEPS)
                     }, 0)?;
                 } else if input_pool.curr_gen == 0 && delayed_players.len() > 0
                     && input_pool.is_max_delay_exceeded() {
@@ -165,6 +360,130 @@ impl WorldScene {
             // Update after checking, to start checking at frame zero
             input_pool.update_states();
         }
+        self.review_sync_states()?;
+        if let Some(desync_investigation) = self.desync_investigation.as_mut() {
+            let timed_out_players = desync_investigation.update();
+            self.disconnect_desynced_players(timed_out_players)?;
+        }
+        Ok(())
+    }
+
+    // Every peer's history up to the last step it received, applied or still buffered
+    fn gen_received_history(&self) -> Option<Replay> {
+        let controller = &self.simulation.controller;
+        let mut history = controller.recording.clone()?;
+        controller.input_buffer.get_buffered_steps().for_each(|step| history.add_step(step.clone()));
+        Some(history)
+    }
+
+    // Runs the server side of the match from here on. Remaining peers keep their ships while
+    // they move over, everyone else is removed with the next step.
+    fn take_over_hosting(&mut self) -> BbResult {
+        let history = self.gen_received_history().ok_or(BbError::Bb(BbErrorType::NetNotConnected))?;
+        let mut game_ref = self.game.borrow_mut();
+        let network = game_ref.network.as_mut().unwrap();
+        let connections = network.client.get_connections();
+        let players = self.simulation.controller.players.keys().copied().collect::<Vec<_>>();
+        let mut input_pool = InputPool::from_history(history, players.clone());
+        for id in players.iter() {
+            if connections.iter().any(|conn| conn.n == *id) {
+                input_pool.suspend_player(*id);
+            } else {
+                input_pool.remove_player(*id);
+            }
+        }
+        let (match_players, spectators) = connections.iter().cloned()
+            .partition::<Vec<_>, _>(|conn| players.contains(&conn.n));
+        network.take_over_hosting(match_players, spectators)?;
+
+        println!("Taking over the match at gen {}.", input_pool.curr_gen);
+        self.host_migration = Some(HostMigration::new(connections.iter().map(|conn| conn.n).collect()));
+        self.input_pool = Some(input_pool);
+        self.sync_checker = Some(SyncChecker::new());
+        self.desync_investigation = Some(DesyncInvestigation::new());
+        Ok(())
+    }
+
+    fn finish_host_migration(&mut self) -> BbResult {
+        let host_migration = self.host_migration.take().unwrap();
+        for id in host_migration.get_missing_peers().into_iter() {
+            println!("Player ^{} did not migrate in time.", id);
+        }
+        for (id, gen) in host_migration.get_reported_gens().iter() {
+            self.send_missed_steps(*id, *gen)?;
+        }
+        self.game.borrow_mut().network.as_mut().unwrap().server.as_mut().unwrap()
+            .send_host_candidates()
+    }
+
+    fn send_missed_steps(&mut self, id: u16, gen: u64) -> BbResult {
+        let packets = match self.input_pool.as_ref() {
+            Some(input_pool) => gen_missed_steps_packets(input_pool.get_history(), gen),
+            None => return Ok(())
+        };
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        for packet in packets.into_iter() {
+            server.send_unicast(packet, id)?;
+        }
+        Ok(())
+    }
+
+    // Lets a newcomer catch up on the match and announces them to everyone else
+    fn send_match_history(&mut self, id: &ID, spectator: bool) -> BbResult {
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        let packets = match self.input_pool.as_ref() {
+            Some(input_pool) => gen_rejoin_packets(input_pool.get_history(), server.get_host_id()),
+            None => return Ok(())
+        };
+        for packet in packets.into_iter() {
+            server.send_unicast(packet, id.n)?;
+        }
+        let others = server.get_connections()
+            .filter(|conn| *id != conn.0)
+            .map(|conn| conn.0.n)
+            .collect::<Vec<u16>>();
+        server.send_multicast_group(Packet::PlayerConnect {
+            name: id.name.to_owned(), spectator
+        }, id.n, others.as_slice())?;
+        server.send_host_candidates()
+    }
+
+    fn review_sync_states(&mut self) -> BbResult {
+        let (sync_checker, input_pool) = match (self.sync_checker.as_mut(), self.input_pool.as_ref()) {
+            (Some(sync_checker), Some(input_pool)) => (sync_checker, input_pool),
+            _ => return Ok(())
+        };
+        let host_id = self.game.borrow().network.as_ref()
+            .and_then(|network| network.server.as_ref())
+            .and_then(|server| server.get_host_id());
+        for review in sync_checker.take_reviews(&input_pool.get_active_players(), host_id).into_iter() {
+            if !review.desynced_players.is_empty() {
+                // Desynced players are only disconnected once their digests were diffed
+                let requests = self.desync_investigation.as_mut().unwrap()
+                    .investigate(review.reference_id, review.desynced_players);
+                self.send_digest_requests(requests)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_digest_requests(&mut self, requests: Vec<(u16, Packet)>) -> BbResult {
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        for (id, packet) in requests.into_iter() {
+            server.send_unicast(packet, id)?;
+        }
+        Ok(())
+    }
+
+    fn disconnect_desynced_players(&mut self, ids: Vec<u16>) -> BbResult {
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        for id in ids.into_iter() {
+            server.disconnect_player(id, DisconnectReason::Desync)?;
+        }
         Ok(())
     }
 }
@@ -180,17 +499,28 @@ impl Scene for WorldScene {
 
     fn poll(&self, ctx: &mut Context) -> BbResult<Option<Box<dyn Scene>>> {
         Ok(if self.back_to_menu {
-            {
-                let mut game_ref = self.game.borrow_mut();
-                // Cleanup
-                game_ref.physics.clear_colliders();
-                if let Err(e) = game_ref.diagnostics.backup_states("final") {
-                    println!("Failed to back up diagnostic states. Reason: {}", e);
-                }
-            }
-            Some(Box::new(MenuScene::new(ctx, self.game.clone()).convert()?))
-        }
-        else {
+            self.cleanup(ctx);
+            Some(Box::new(match self.disconnected {
+                Some(DisconnectReason::Timeout) => MenuScene::with_notice(ctx, &format!(
+                    "Connection timed out. Join again within {}s to rejoin the match.", REJOIN_GRACE_PERIOD),
+                    self.game.clone()),
+                Some(reason @ (DisconnectReason::Kicked | DisconnectReason::Banned
+                    | DisconnectReason::InvalidInput)) => {
+                    let reason = self.game.borrow().network.as_ref()
+                        .map_or(reason.to_string(), |network| network.client.describe_disconnect(reason));
+                    MenuScene::with_notice(ctx, &format!("Disconnected: {}.", reason), self.game.clone())
+                },
+                _ => MenuScene::new(ctx, self.game.clone())
+            }.convert()?))
+        } else if let (Some(playback), Some(target)) = (self.replay.as_ref(), self.rewind_target) {
+            self.cleanup(ctx);
+            Some(Box::new(WorldScene::replay(ctx, playback.replay.clone(), Some(target),
+                self.game.clone())?))
+        } else if let (true, Some(result)) = (self.score_phase, self.simulation.controller.get_match_result()) {
+            // Lagging clients keep simulating until they also reach the deciding generation
+            self.cleanup(ctx);
+            Some(Box::new(ScoreScene::new(ctx, result.clone(), self.game.clone()).convert()?))
+        } else {
             None
         })
     }
@@ -202,24 +532,33 @@ impl Scene for WorldScene {
 
 impl State for WorldScene {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.handle_received_packets(ctx).convert()?;
+        if self.game.borrow().network.is_some() {
+            self.handle_received_packets(ctx).convert()?;
+        }
         self.update_serverside().convert()?;
         self.update_world(ctx)?;
+        self.update_spectator_cam();
+        self.update_match_result(ctx).convert()?;
 
+        self.ui.update_world_events(ctx, self.simulation.controller.get_confirmed_gen())?;
         self.ui.update(ctx)?;
         self.update_menu_ui().convert()?;
         self.update_harbour_ui().convert()?;
-        self.controller.catch_input = !self.ui.is_chat_focused(); // Don't react to input if player is writing in chat
+        self.simulation.controller.catch_input = !self.ui.is_chat_focused(); // Don't react to input if player is writing in chat
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.controller.draw(ctx)?;
-        self.world.draw(ctx)
+        self.simulation.controller.draw(ctx)?;
+        self.simulation.world.draw(ctx)
     }
 
     fn event(&mut self, ctx: &mut Context, event: Event)
         -> tetra::Result {
+        if !self.ui.is_chat_focused() {
+            self.event_replay(event.clone());
+            self.event_spectator(event.clone());
+        }
         self.event_world(ctx, event.clone())?;
         self.ui.event(ctx, event)
     }
@@ -234,16 +573,92 @@ impl NetController for WorldScene {
         self.game.borrow_mut().network.as_mut().unwrap().poll_received_client_packets()
     }
 
+    // Only spectators are let in during a match
+    fn on_server_receive_handshake(&mut self, id: ID, _remote_addr: SocketAddr) -> BbResult {
+        self.send_match_history(&id, true)
+    }
+
+    fn on_server_receive_rejoin(&mut self, id: ID, _remote_addr: SocketAddr) -> BbResult {
+        if let Some(input_pool) = self.input_pool.as_mut() {
+            input_pool.resume_player(id.n);
+        }
+        self.send_match_history(&id, false)
+    }
+
     fn on_server_receive_disconnect(&mut self, sender: u16, _reason: DisconnectReason) -> BbResult {
+        let is_slot_reserved = self.game.borrow().network.as_ref().unwrap().server.as_ref()
+            .is_some_and(|server| server.is_slot_reserved(sender));
         if let Some(input_pool) = self.input_pool.as_mut() {
-            input_pool.remove_player(sender);
+            if is_slot_reserved {
+                input_pool.suspend_player(sender);
+            } else {
+                input_pool.remove_player(sender);
+            }
+        }
+        if let Some(desync_investigation) = self.desync_investigation.as_mut() {
+            let suspects = desync_investigation.remove_player(sender);
+            self.disconnect_desynced_players(suspects)?;
         }
         Ok(())
     }
 
-    fn on_server_receive_input(&mut self, _: &mut Context, sender: u16, input: InputState) -> BbResult {
+    fn on_server_reservation_expired(&mut self, id: u16) -> BbResult {
         if let Some(input_pool) = self.input_pool.as_mut() {
-            input_pool.add_state(sender, input);
+            input_pool.remove_player(id);
+        }
+        Ok(())
+    }
+
+    fn on_server_receive_migration(&mut self, id: ID, gen: u64, steps: Vec<InputStep>) -> BbResult {
+        let input_pool = match self.input_pool.as_mut() {
+            Some(input_pool) => input_pool,
+            None => return Ok(())
+        };
+        // Peers may hold a few steps of the previous host that this one missed. Once new
+        // steps were flushed, those can't be taken on anymore.
+        if !input_pool.merge_steps(steps, self.host_migration.is_some()) || gen > input_pool.curr_gen {
+            println!("{:?} diverged from the match at gen {}. Terminating connection...", id, gen);
+            input_pool.remove_player(id.n);
+            return self.disconnect_desynced_players(vec![id.n])
+        }
+        input_pool.resume_player(id.n);
+        match self.host_migration.as_mut() {
+            Some(host_migration) => {
+                host_migration.add_report(id.n, gen);
+                Ok(())
+            },
+            None => {
+                self.send_missed_steps(id.n, gen)?;
+                self.game.borrow_mut().network.as_mut().unwrap().server.as_mut().unwrap()
+                    .send_host_candidates()
+            }
+        }
+    }
+
+    // The host's simulation may be a few gens off, but knows well enough who is in a harbour
+    fn on_server_receive_input(&mut self, _: &mut Context, sender: u16, input: InputState) -> BbResult {
+        let in_harbour = self.simulation.controller.players.get(&sender)
+            .map(|p| p.borrow().possessed_ship.borrow().status.is_in_harbour);
+        let violation = match self.input_pool.as_mut() {
+            Some(input_pool) => match input_pool.validate_state(sender, &input, in_harbour) {
+                Ok(()) => {
+                    input_pool.add_state(sender, input);
+                    return Ok(())
+                },
+                // Steering and firing still count. Players re-check purchases when applying states.
+                Err(violation) if !violation.is_forged() => {
+                    println!("Dropped purchase of player {}. Reason: {}.", sender, violation);
+                    input_pool.add_state(sender, InputState { buy_mod: false, mod_type: None, ..input });
+                    return Ok(())
+                },
+                Err(violation) => violation
+            },
+            None => return Ok(())
+        };
+        let kicked = self.game.borrow_mut().network.as_mut().unwrap().server.as_mut().unwrap()
+            .on_receive_invalid_input(sender, violation)?;
+        if kicked {
+            self.on_server_receive_disconnect(sender, DisconnectReason::InvalidInput)?;
         }
         Ok(())
     }
@@ -252,16 +667,60 @@ impl NetController for WorldScene {
         state: SyncState) -> BbResult {
         if let Some(sync_checker) = self.sync_checker.as_mut() {
             sync_checker.add_state(sender, state);
-            for id in sync_checker.review_desyncs(state.t).into_iter() {
-                self.game.borrow_mut().network.as_mut().unwrap().server.as_mut().unwrap()
-                    .disconnect_player(id, DisconnectReason::Desync)?;
-            }
+        }
+        self.review_sync_states()
+    }
+
+    fn on_server_receive_digest_summary(&mut self, sender: u16, states: Vec<SyncState>) -> BbResult {
+        if let Some(desync_investigation) = self.desync_investigation.as_mut() {
+            let requests = desync_investigation.add_summary(sender, states);
+            self.send_digest_requests(requests)?;
+        }
+        Ok(())
+    }
+
+    fn on_server_receive_digest(&mut self, sender: u16, part: StateDigestPart) -> BbResult {
+        if let Some(desync_investigation) = self.desync_investigation.as_mut() {
+            let finished_players = desync_investigation.add_digest_part(sender, part);
+            self.disconnect_desynced_players(finished_players)?;
         }
         Ok(())
     }
 
+    // More steps got lost in a row than each packet repeats
+    fn on_server_receive_steps_request(&mut self, sender: u16, gen: u64) -> BbResult {
+        self.send_missed_steps(sender, gen)
+    }
+
+    fn on_server_set_game_phase(&mut self, sender: u16, phase: GamePhase) -> BbResult {
+        let is_valid_phase = matches!(phase, GamePhase::Score);
+        let mut game_ref = self.game.borrow_mut();
+        let server = game_ref.network.as_mut().unwrap().server.as_mut().unwrap();
+        match (server.is_host(sender), is_valid_phase) {
+            (true, true) => {
+                // The host's scene ends along with its server, which then can't be asked for
+                // lost steps anymore. Lagging clients still need the last ones to reach the end.
+                if let Some(input_pool) = self.input_pool.as_ref() {
+                    server.send_multicast(Packet::RejoinSteps {
+                        steps: input_pool.get_latest_steps(FINAL_INPUT_STEPS)
+                    }, 0)?;
+                }
+                server.send_multicast(Packet::Game {
+                    phase
+                }, sender)
+            },
+            (true, false) => {
+                println!("^{} failed to set {:?} phase: invalid phase.", sender, phase);
+                Ok(())
+            },
+            (false, _) => {
+                println!("^{} failed to set {:?} phase: insufficient permissions.", sender, phase);
+                Ok(())
+            }
+        }
+    }
+
     fn on_server_receive_chat_message(&mut self, sender: u16, message: String) -> BbResult {
-        // Check for spam/profanity
         self.game.borrow_mut().network.as_mut().unwrap()
             .server.as_mut().unwrap().send_multicast(Packet::ChatMessage {
             message
@@ -270,12 +729,33 @@ impl NetController for WorldScene {
 
     fn on_connection_lost(&mut self, _ctx: &mut Context, reason: DisconnectReason) -> BbResult {
         println!("Lost connection to server! Reason: {:?}", reason);
+        self.disconnected = Some(reason);
+        if reason == DisconnectReason::Timeout {
+            // The server keeps the slot for a while, for whoever brings the token
+            let mut game_ref = self.game.borrow_mut();
+            let rejoin_ticket = game_ref.network.as_ref().and_then(|network| network.client.get_rejoin_token()
+                .map(|token| (network.client.get_endpoint().to_owned(), token)));
+            if let Some((endpoint, token)) = rejoin_ticket {
+                game_ref.rejoin_tokens.insert(endpoint, token);
+            }
+        }
         self.leave_match() // Previously only set self.back_to_menu to true. Problem if connection is already terminated when calling network.disconnect()? 
     }
     
+    // Only players who rejoin connect during a match
+    fn on_player_connect(&mut self, ctx: &mut Context, player: PlayerParams) -> BbResult {
+        self.update_player_list(ctx)?;
+        self.ui.chat.add_line(ctx, &format!("{:?} rejoined the match!", player.id)).convert()
+    }
+
+    fn on_spectator_connect(&mut self, ctx: &mut Context, id: ID) -> BbResult {
+        self.update_player_list(ctx)?;
+        self.ui.chat.add_line(ctx, &format!("{:?} is spectating the match.", id)).convert()
+    }
+
     fn on_player_disconnect(&mut self, ctx: &mut Context, id: u16, reason: DisconnectReason)
         -> BbResult {
-        if let Some(player) = self.controller.players.get(&id) {
+        if let Some(player) = self.simulation.controller.players.get(&id).cloned() {
             // Player removal is done in controller, when appropiate input state is received
             if reason == DisconnectReason::Desync {
                 if let Err(e) = self.game.borrow_mut().diagnostics
@@ -283,18 +763,43 @@ impl NetController for WorldScene {
                     println!("Failed to back up diagnostic states. Reason: {}", e);
                 }
             }
-            self.ui.update_players(ctx, self.game.borrow().network.as_ref().unwrap()
-                .client.get_connections()).convert()?;
+            self.update_player_list(ctx)?;
             self.ui.chat.add_line(ctx,
                 &format!("{:?} left the game. Reason: {:?}.", player.borrow().id, reason)).convert()
         } else {
-            println!("Received disconnect from player with invalid ID {}.", id);
-            Ok(())
+            // Spectators have no ship, but are listed among the connections
+            self.update_player_list(ctx)
         }
     }
 
     fn on_input_step(&mut self, _: &mut Context, step: InputStep) -> BbResult {
-        self.controller.add_step(step);
+        self.simulation.controller.add_step(step);
+        Ok(())
+    }
+
+    fn on_host_migration(&mut self, ctx: &mut Context, new_host: u16) -> BbResult {
+        let local_id = self.game.borrow().network.as_ref().unwrap().client.get_local_id().unwrap();
+        if new_host == local_id.n {
+            self.take_over_hosting()?;
+            self.ui.chat.add_line(ctx, "The host left. You are hosting the match now.").convert()?;
+        } else {
+            let name = self.game.borrow().network.as_ref().unwrap().get_connection_name(new_host);
+            self.ui.chat.add_line(ctx, &format!("The host left. {}^{} takes over the match...",
+                name, new_host)).convert()?;
+        }
+        let rejoin_token = self.game.borrow().network.as_ref().unwrap().client.get_rejoin_token().unwrap_or_default();
+        let packet = match self.gen_received_history() {
+            Some(history) => gen_migration_packet(&local_id, rejoin_token, &history),
+            None => return Ok(())
+        };
+        self.game.borrow_mut().network.as_mut().unwrap().client.send_migration(packet)?;
+        self.update_player_list(ctx)
+    }
+
+    fn on_rejoin_steps(&mut self, _: &mut Context, steps: Vec<InputStep>) -> BbResult {
+        for step in steps.into_iter() {
+            self.simulation.controller.add_step(step);
+        }
         Ok(())
     }
 
@@ -304,6 +809,72 @@ impl NetController for WorldScene {
         };
         self.ui.chat.add_message(ctx, sender.as_str(), text.as_str()).convert()
     }
+
+    fn on_notice(&mut self, ctx: &mut Context, text: String) -> BbResult {
+        self.ui.chat.add_line(ctx, &text).convert()
+    }
+
+    fn on_game_phase_changed(&mut self, _: &mut Context, phase: GamePhase) -> BbResult {
+        if phase == GamePhase::Score {
+            self.score_phase = true;
+        }
+        Ok(())
+    }
+
+    // Only players hold back the steps, spectators may lag as much as they like
+    fn on_quality_table(&mut self, ctx: &mut Context, entries: Vec<ConnectionQuality>) -> BbResult {
+        let local_id = self.game.borrow().network.as_ref().unwrap().client.get_local_id().map(|id| id.n);
+        for entry in entries.iter() {
+            if !self.simulation.controller.players.contains_key(&entry.id) {
+                continue
+            }
+            let warning = match (entry.is_lagging(), self.lagging_players.contains(&entry.id)) {
+                (true, false) => {
+                    self.lagging_players.insert(entry.id);
+                    match local_id == Some(entry.id) {
+                        true => format!("Your connection is lagging ({}). Everyone waits for your input.", entry),
+                        false => format!("{} is lagging ({}). The match may stutter.",
+                            self.game.borrow().network.as_ref().unwrap().get_connection_name(entry.id), entry)
+                    }
+                },
+                (false, true) => {
+                    self.lagging_players.remove(&entry.id);
+                    match local_id == Some(entry.id) {
+                        true => "Your connection recovered.".to_owned(),
+                        false => format!("{} recovered.",
+                            self.game.borrow().network.as_ref().unwrap().get_connection_name(entry.id))
+                    }
+                },
+                _ => continue
+            };
+            self.ui.chat.add_line(ctx, &warning).convert()?;
+        }
+        self.ui.set_quality_table(entries);
+        self.update_player_list(ctx)
+    }
+
+    fn on_digest_request(&mut self, _: &mut Context, gen: Option<u64>) -> BbResult {
+        let packets = match gen {
+            None => vec![Packet::DigestSummary {
+                states: self.simulation.controller.digest_history.gen_summary()
+            }],
+            Some(gen) => match self.simulation.controller.digest_history.get_digest(gen) {
+                Some(digest) => digest.split().into_iter()
+                    .map(|part| Packet::Digest { part })
+                    .collect(),
+                None => {
+                    println!("Requested digest of gen {} is no longer buffered.", gen);
+                    vec![]
+                }
+            }
+        };
+        let mut game_ref = self.game.borrow_mut();
+        let network = game_ref.network.as_mut().unwrap();
+        for packet in packets.into_iter() {
+            network.send_packet(packet)?;
+        }
+        Ok(())
+    }
 }
 
 struct WorldSceneUI {
@@ -315,9 +886,11 @@ struct WorldSceneUI {
     players_grid: Rcc<Grid>,
     health_label: Rcc<Label>,
     escudos_label: Rcc<Label>,
+    weather_label: Rcc<Label>,
     harbour_ui: HarbourUI,
     ship_stats_panel: Rcc<Grid>,
     local_player: Option<Rcc<Player>>,
+    quality_table: HashMap<u16, ConnectionQuality>,
     game: GC
 }
 
@@ -341,7 +914,9 @@ impl WorldSceneUI {
         let menu_grid = grid.add_element(menu_grid);
 
         let mut player_info_grid = Grid::new(ctx, UIAlignment::Horizontal,
-            UILayout::TopRight, V2::new(330.0, 35.0), 0.0)?;
+            UILayout::TopRight, V2::new(520.0, 35.0), 0.0)?;
+        let weather_label = player_info_grid.add_element(Label::new(ctx,
+            "Stormy, Wind 100", FontSize::Normal, 1.0, game.clone())?);
         let health_label = player_info_grid.add_element(Label::new(ctx,
             "1000/1000 Health", FontSize::Normal, 1.0, game.clone())?);
         let escudos_label = player_info_grid.add_element(Label::new(ctx,
@@ -356,8 +931,8 @@ impl WorldSceneUI {
 
         Ok(WorldSceneUI {
             chat, menu_button, menu_grid, leave_button, match_info_label, players_grid,
-            health_label, escudos_label, harbour_ui, ship_stats_panel: ship_stats_grid,
-            local_player: None, game
+            health_label, escudos_label, weather_label, harbour_ui, ship_stats_panel: ship_stats_grid,
+            local_player: None, quality_table: HashMap::new(), game
         })
     }
 
@@ -375,15 +950,24 @@ impl WorldSceneUI {
         self.match_info_label.borrow_mut().set_text(text);
     }
 
-    pub fn update_players(&mut self, ctx: &mut Context, players: Vec<ID>) -> tetra::Result {
+    pub fn set_quality_table(&mut self, entries: Vec<ConnectionQuality>) {
+        self.quality_table = entries.into_iter().map(|entry| (entry.id, entry)).collect();
+    }
+
+    pub fn update_players(&mut self, ctx: &mut Context, players: Vec<ID>, host_id: Option<u16>) -> tetra::Result {
         let mut players_grid_ref = self.players_grid.borrow_mut();
         players_grid_ref.clear_elements();
         for player in players.into_iter() {
-            players_grid_ref.add_element(Label::new(ctx, format!("  {:?} {}", player,
-                match player.n {
-                    0 => "(Host)",
-                    _ => ""
-                }).as_str(), FontSize::Normal, 2.0, self.game.clone())?);
+            let quality = match self.quality_table.get(&player.n) {
+                Some(quality) if quality.is_lagging() => format!(" {} (Lagging)", quality),
+                Some(quality) => format!(" {}", quality),
+                None => String::new()
+            };
+            players_grid_ref.add_element(Label::new(ctx, format!("  {:?} {}{}", player,
+                match host_id == Some(player.n) {
+                    true => "(Host)",
+                    false => ""
+                }, quality).as_str(), FontSize::Normal, 2.0, self.game.clone())?);
         }
         Ok(())
     }
@@ -392,10 +976,10 @@ impl WorldSceneUI {
         self.chat.is_focused()
     }
 
-    fn update_world_events(&mut self, ctx: &mut Context) -> tetra::Result {
+    fn update_world_events(&mut self, ctx: &mut Context, confirmed_gen: u64) -> tetra::Result {
         let events = {
             let mut game_ref = self.game.borrow_mut();
-            game_ref.world.flush_events().into_iter()
+            game_ref.world.flush_events(confirmed_gen).into_iter()
         };
         for event in events {
             self.chat.add_line(ctx, &match event {
@@ -411,14 +995,16 @@ impl WorldSceneUI {
     }
 
     fn update_ship_stats_panel(&mut self, ctx: &mut Context) -> tetra::Result {
-        let player_ref = self.local_player.as_ref().unwrap().borrow();
+        let player_ref = match self.local_player.as_ref() {
+            Some(local_player) => local_player.borrow(),
+            None => return Ok(())
+        };
         let ship_ref = player_ref.possessed_ship.borrow();
         let mut panel_ref = self.ship_stats_panel.borrow_mut();
         if ship_ref.mods.len() != panel_ref.elements.len() {
             panel_ref.clear_elements();
-            for ship_mod in ship_ref.mods.iter() {
-                panel_ref.add_element(Image::from(ctx, V2::new(50.0, 50.0), 5.0,
-                    ship_mod.get_icon())?);
+            for icon in ship_ref.mods.iter().filter_map(|m| m.get_icon()) {
+                panel_ref.add_element(Image::from(ctx, V2::new(50.0, 50.0), 5.0, icon)?);
             }
         }
         Ok(())
@@ -431,9 +1017,11 @@ impl State for WorldSceneUI {
             self.toggle_menu_visibility();
         }
         if let Some(message) = self.chat.check_messages(ctx) {
-            self.game.borrow_mut().network.as_mut().unwrap().send_packet(Packet::ChatMessage {
-                message
-            }).convert()?;
+            if let Some(network) = self.game.borrow_mut().network.as_mut() {
+                network.send_packet(Packet::ChatMessage {
+                    message
+                }).convert()?;
+            }
         }
         if let Some(local_player) = self.local_player.as_ref() {
             let player_ref = local_player.borrow();
@@ -443,29 +1031,30 @@ impl State for WorldSceneUI {
             self.escudos_label.borrow_mut().set_text(
                 &format!("{} Escudos", ship_ref.treasury.balance));
         }
+        {
+            let game_ref = self.game.borrow();
+            self.weather_label.borrow_mut().set_text(&format!("{:?}, Wind {:.0}",
+                game_ref.weather.weather, game_ref.weather.wind.magnitude()));
+        }
 
-        self.update_world_events(ctx)?;
         self.update_ship_stats_panel(ctx)
     }
 
     fn event(&mut self, _ctx: &mut Context, event: Event) -> tetra::Result {
-        let is_in_harbour = self.local_player.as_ref().unwrap().borrow()
-            .possessed_ship.borrow().status.is_in_harbour;
+        let is_in_harbour = self.local_player.as_ref().is_some_and(
+            |p| p.borrow().possessed_ship.borrow().status.is_in_harbour);
         if !is_in_harbour {
             self.harbour_ui.set_visibility(false);
         }
         match event {
             Event::KeyPressed { key } => {
-                match key {
-                    Key::Escape => self.toggle_menu_visibility(),
-                    Key::T => {
-                        if is_in_harbour {
-                            let invisible = self.harbour_ui.grid.borrow().is_invisible();
-                            self.harbour_ui.set_visibility(invisible);
-                        }
-                    },
-                    _ => ()
-                };
+                let keys = self.game.borrow().settings.key_bindings;
+                if key == keys.menu {
+                    self.toggle_menu_visibility();
+                } else if key == keys.harbour && is_in_harbour {
+                    let invisible = self.harbour_ui.grid.borrow().is_invisible();
+                    self.harbour_ui.set_visibility(invisible);
+                }
             },
             _ => ()
         }
@@ -1,17 +1,132 @@
+use std::{env, fmt, fs, path::PathBuf};
+use tetra::input::Key;
+use crate::{BbError, BbResult, CAM_MAX_ZOOM, CAM_MIN_ZOOM, master_server::DEFAULT_MASTER_SERVER_ADDR};
+
+pub const DEFAULT_WINDOW_SIZE_WIDTH: i32 = 1200;
+pub const DEFAULT_WINDOW_SIZE_HEIGHT: i32 = 640;
+pub const DEFAULT_CAM_SPEED: f32 = 800.0;
+pub const MAX_LAST_ENDPOINTS: usize = 5;
+pub const SETTINGS_DIR_NAME: &str = "Blackbeard";
+pub const SETTINGS_FILE_NAME: &str = "settings.cfg";
+
+// Keys that can be bound in the settings file, looked up by their debug name
+const BINDABLE_KEYS: [Key; 62] = [
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K,
+    Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V,
+    Key::W, Key::X, Key::Y, Key::Z, Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+    Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9, Key::F1, Key::F2, Key::F3, Key::F4,
+    Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12, Key::Up,
+    Key::Down, Key::Left, Key::Right, Key::LeftCtrl, Key::LeftShift, Key::LeftAlt,
+    Key::RightCtrl, Key::RightShift, Key::RightAlt, Key::Space, Key::Tab, Key::Escape,
+    Key::Enter
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyBindings {
+    pub rotate: Key,
+    pub shoot_bowside: Key,
+    pub shoot_portside: Key,
+    pub shoot_all: Key,
+    pub centre_cam: Key,
+    pub harbour: Key,
+    pub menu: Key,
+    pub cam_up: Key,
+    pub cam_down: Key,
+    pub cam_left: Key,
+    pub cam_right: Key
+}
+
+impl KeyBindings {
+    pub fn default() -> KeyBindings {
+        KeyBindings {
+            rotate: Key::R, shoot_bowside: Key::Q, shoot_portside: Key::E, shoot_all: Key::Space,
+            centre_cam: Key::Tab, harbour: Key::T, menu: Key::Escape,
+            cam_up: Key::W, cam_down: Key::S, cam_left: Key::A, cam_right: Key::D
+        }
+    }
+
+    fn get_mut(&mut self, action: &str) -> Option<&mut Key> {
+        Some(match action {
+            "rotate" => &mut self.rotate,
+            "shoot_bowside" => &mut self.shoot_bowside,
+            "shoot_portside" => &mut self.shoot_portside,
+            "shoot_all" => &mut self.shoot_all,
+            "centre_cam" => &mut self.centre_cam,
+            "harbour" => &mut self.harbour,
+            "menu" => &mut self.menu,
+            "cam_up" => &mut self.cam_up,
+            "cam_down" => &mut self.cam_down,
+            "cam_left" => &mut self.cam_left,
+            "cam_right" => &mut self.cam_right,
+            _ => return None
+        })
+    }
+
+    fn to_pairs(&self) -> [(&'static str, Key); 11] {
+        [
+            ("rotate", self.rotate), ("shoot_bowside", self.shoot_bowside),
+            ("shoot_portside", self.shoot_portside), ("shoot_all", self.shoot_all),
+            ("centre_cam", self.centre_cam), ("harbour", self.harbour), ("menu", self.menu),
+            ("cam_up", self.cam_up), ("cam_down", self.cam_down),
+            ("cam_left", self.cam_left), ("cam_right", self.cam_right)
+        ]
+    }
+}
+
 pub struct Settings {
     pub show_watermark: bool,
-    pub name: String
+    pub name: String,
+    pub last_endpoints: Vec<String>, // Most recent first
+    pub window_width: i32,
+    pub window_height: i32,
+    pub fullscreen: bool,
+    pub cam_speed: f32,
+    pub cam_min_zoom: f32,
+    pub cam_max_zoom: f32,
+    pub key_bindings: KeyBindings,
+    pub rollback: bool, // Predict steps instead of waiting for them in online matches
+    pub master_server: String, // Lists hosted lobbies and finds public ones, empty to stay offline
+    pub relay: String // Hosts through this relay if set, for players who can't open a port
 }
 
 impl Settings {
     pub fn new() -> Settings {
         Settings {
-            show_watermark: true, name: String::new()
+            show_watermark: true, name: String::new(), last_endpoints: Vec::new(),
+            window_width: DEFAULT_WINDOW_SIZE_WIDTH, window_height: DEFAULT_WINDOW_SIZE_HEIGHT,
+            fullscreen: false, cam_speed: DEFAULT_CAM_SPEED, cam_min_zoom: CAM_MIN_ZOOM,
+            cam_max_zoom: CAM_MAX_ZOOM, key_bindings: KeyBindings::default(), rollback: false,
+            master_server: DEFAULT_MASTER_SERVER_ADDR.to_owned(), relay: String::new()
         }
     }
 
+    // Falls back to the defaults for a missing file and for every bad entry
     pub fn load() -> Settings {
-        todo!()
+        let mut settings = Settings::new();
+        let path = match get_settings_path() {
+            Some(path) => path,
+            None => {
+                println!("No user config directory found. Using default settings.");
+                return settings
+            }
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                println!("Loading settings from {:?}...", path);
+                settings.parse(&contents);
+            },
+            Err(_) => println!("No settings found at {:?}. Using default settings.", path)
+        }
+        settings
+    }
+
+    pub fn save(&self) -> BbResult {
+        let path = get_settings_path().ok_or_else(|| BbError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound, "No user config directory found")))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(BbError::Io)?;
+        }
+        fs::write(&path, self.to_string()).map_err(BbError::Io)
     }
 
     pub fn set_name(&mut self, name: String) -> bool {
@@ -23,4 +138,124 @@ impl Settings {
             false
         }
     }
+
+    pub fn add_last_endpoint(&mut self, endpoint: &str) {
+        self.last_endpoints.retain(|e| e != endpoint);
+        self.last_endpoints.insert(0, endpoint.to_owned());
+        self.last_endpoints.truncate(MAX_LAST_ENDPOINTS);
+    }
+
+    pub fn get_last_endpoint(&self) -> Option<&str> {
+        self.last_endpoints.first().map(|e| e.as_str())
+    }
+
+    // One "key = value" pair per line, lines starting with # are comments
+    pub fn parse(&mut self, contents: &str) {
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let valid = match line.split_once('=') {
+                Some((key, value)) => self.parse_entry(key.trim(), value.trim()),
+                None => false
+            };
+            if !valid {
+                println!("Ignoring invalid settings entry in line {}: {}", i + 1, line);
+            }
+        }
+        if self.cam_min_zoom > self.cam_max_zoom {
+            println!("Minimum camera zoom exceeds maximum zoom. Using default zoom limits.");
+            self.cam_min_zoom = CAM_MIN_ZOOM;
+            self.cam_max_zoom = CAM_MAX_ZOOM;
+        }
+    }
+
+    fn parse_entry(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "name" => value.is_empty() || self.set_name(value.to_owned()),
+            "show_watermark" => parse_into(value, &mut self.show_watermark),
+            "last_endpoint" => {
+                if self.last_endpoints.len() < MAX_LAST_ENDPOINTS && !value.is_empty() {
+                    self.last_endpoints.push(value.to_owned());
+                }
+                true
+            },
+            "window_width" => parse_positive(value, &mut self.window_width),
+            "window_height" => parse_positive(value, &mut self.window_height),
+            "fullscreen" => parse_into(value, &mut self.fullscreen),
+            "cam_speed" => parse_positive(value, &mut self.cam_speed),
+            "cam_min_zoom" => parse_positive(value, &mut self.cam_min_zoom),
+            "cam_max_zoom" => parse_positive(value, &mut self.cam_max_zoom),
+            "rollback" => parse_into(value, &mut self.rollback),
+            "master_server" => {
+                self.master_server = value.to_owned();
+                true
+            },
+            "relay" => {
+                self.relay = value.to_owned();
+                true
+            },
+            _ => match (key.strip_prefix("key_"), parse_key(value)) {
+                (Some(action), Some(bound_key)) => {
+                    self.key_bindings.get_mut(action).map(|k| *k = bound_key).is_some()
+                },
+                _ => false
+            }
+        }
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = vec![
+            "# Blackbeard settings. Command-line arguments take precedence.".to_owned(),
+            format!("name = {}", self.name),
+            format!("show_watermark = {}", self.show_watermark),
+            format!("window_width = {}", self.window_width),
+            format!("window_height = {}", self.window_height),
+            format!("fullscreen = {}", self.fullscreen),
+            format!("cam_speed = {}", self.cam_speed),
+            format!("cam_min_zoom = {}", self.cam_min_zoom),
+            format!("cam_max_zoom = {}", self.cam_max_zoom),
+            format!("rollback = {}", self.rollback),
+            format!("master_server = {}", self.master_server),
+            format!("relay = {}", self.relay)
+        ];
+        lines.extend(self.last_endpoints.iter().map(|e| format!("last_endpoint = {}", e)));
+        lines.extend(self.key_bindings.to_pairs().iter()
+            .map(|(action, key)| format!("key_{} = {:?}", action, key)));
+        writeln!(f, "{}", lines.join("\n"))
+    }
+}
+
+fn parse_into<T: std::str::FromStr>(value: &str, target: &mut T) -> bool {
+    value.parse().map(|v| *target = v).is_ok()
+}
+
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(value: &str, target: &mut T) -> bool {
+    match value.parse::<T>() {
+        Ok(v) if v > T::default() => {
+            *target = v;
+            true
+        },
+        _ => false
+    }
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    BINDABLE_KEYS.iter().find(|key| format!("{:?}", key).eq_ignore_ascii_case(name)).copied()
+}
+
+// Platform config directory, e.g. ~/.config/Blackbeard/settings.cfg on Linux
+pub fn get_settings_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    }?;
+    Some(config_dir.join(SETTINGS_DIR_NAME).join(SETTINGS_FILE_NAME))
 }
@@ -0,0 +1,156 @@
+use std::f32::consts::PI;
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro128Plus;
+use crate::{CannonSide, V2, get_angle, packet::InputState, polar_to_cartesian, rand_f32, ship::Ship, ship_mod::{HARBOUR_REPAIR_COST, ShipModType}, vec_distance};
+
+// Computer players take IDs from the top of the range, so they never collide with connections
+pub const COMPUTER_ID_OFFSET: u16 = 0xFF00;
+pub const COMPUTER_NAMES: [&str; 8] = [
+    "Anne Bonny", "Calico Jack", "Mary Read", "Black Bart",
+    "Stede Bonnet", "Ching Shih", "Henry Every", "Charles Vane"
+];
+
+const SIGHT_RANGE: f32 = 1800.0;
+const BROADSIDE_RANGE: f32 = 650.0;
+const BROADSIDE_STEER_DIST: f32 = 400.0;
+const BROADSIDE_AIM_MARGIN: f32 = PI / 10.0;
+const RETREAT_HEALTH_RATIO: f32 = 0.35;
+const WAYPOINT_REACHED_DIST: f32 = 150.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShipAIState {
+    Patrol,
+    Chase(u16),
+    Broadside(u16),
+    Retreat
+}
+
+// What a computer player knows about any other ship
+#[derive(Debug, Clone, Copy)]
+pub struct ShipSighting {
+    pub id: u16,
+    pub pos: V2
+}
+
+// Computer players produce their input states inside the simulation step, from nothing but the
+// world state and the world seed. Every client therefore arrives at the same input, and no
+// states have to be sent for them.
+#[derive(Clone)]
+pub struct ShipAI {
+    pub state: ShipAIState,
+    rng: Xoshiro128Plus,
+    patrol_area: V2,
+    waypoint: Option<V2>
+}
+
+impl ShipAI {
+    pub fn new(world_seed: u64, id: u16, patrol_area: V2) -> ShipAI {
+        ShipAI {
+            state: ShipAIState::Patrol,
+            rng: Xoshiro128Plus::seed_from_u64(world_seed ^ (id as u64).wrapping_mul(0x9E3779B97F4A7C15)),
+            patrol_area, waypoint: None
+        }
+    }
+
+    pub fn gen_input_state(&mut self, id: u16, ship: &Ship, ships: &[ShipSighting],
+        harbours: &[V2]) -> InputState {
+        let (pos, rot) = ship.transform.get_translation();
+        if let Some(state) = self.retreat(ship, pos, harbours) {
+            return state
+        }
+
+        // Closest enemy first, ties are broken by ID so all clients pick the same one
+        let enemy = ships.iter()
+            .filter(|s| s.id != id)
+            .map(|s| (s, vec_distance(pos, s.pos)))
+            .filter(|(_, dist)| *dist <= SIGHT_RANGE)
+            .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.id.cmp(&b.0.id)));
+        match enemy {
+            Some((enemy, dist)) if dist > BROADSIDE_RANGE => {
+                self.state = ShipAIState::Chase(enemy.id);
+                move_to(enemy.pos)
+            },
+            Some((enemy, _)) => {
+                self.state = ShipAIState::Broadside(enemy.id);
+                broadside(ship, pos, rot, enemy.pos)
+            },
+            None => {
+                self.state = ShipAIState::Patrol;
+                self.patrol(pos)
+            }
+        }
+    }
+
+    fn retreat(&mut self, ship: &Ship, pos: V2, harbours: &[V2]) -> Option<InputState> {
+        let health_ratio = ship.data.curr_health as f32 / ship.data.attr.health as f32;
+        let can_afford_repair = ship.treasury.balance >= HARBOUR_REPAIR_COST;
+        let must_retreat = match self.state {
+            // Sinking restores full health, then there is nothing left to repair
+            ShipAIState::Retreat => health_ratio < 1.0 && can_afford_repair,
+            _ => health_ratio <= RETREAT_HEALTH_RATIO && can_afford_repair
+        };
+        if !must_retreat {
+            if self.state == ShipAIState::Retreat {
+                self.state = ShipAIState::Patrol;
+            }
+            return None
+        }
+
+        let harbour = harbours.iter().copied()
+            .min_by(|a, b| vec_distance(pos, *a).total_cmp(&vec_distance(pos, *b)))?;
+        self.state = ShipAIState::Retreat;
+        Some(if ship.status.is_in_harbour {
+            InputState::new(false, false, false, false, true, false, Some(ShipModType::Repair), None)
+        } else {
+            move_to(harbour)
+        })
+    }
+
+    fn patrol(&mut self, pos: V2) -> InputState {
+        let waypoint = match self.waypoint {
+            Some(waypoint) if vec_distance(pos, waypoint) > WAYPOINT_REACHED_DIST => waypoint,
+            _ => {
+                let waypoint = V2::new(rand_f32(&mut self.rng) * self.patrol_area.x,
+                    rand_f32(&mut self.rng) * self.patrol_area.y);
+                self.waypoint = Some(waypoint);
+                waypoint
+            }
+        };
+        move_to(waypoint)
+    }
+}
+
+fn move_to(target_pos: V2) -> InputState {
+    InputState::new(true, false, false, false, false, false, None, Some(target_pos))
+}
+
+// Turns the nearer side towards the enemy and fires as soon as it bears
+fn broadside(ship: &Ship, pos: V2, rot: f32, enemy_pos: V2) -> InputState {
+    let enemy_dir = get_angle(enemy_pos - pos);
+    // Bowside cannons point a quarter turn counter-clockwise, portside cannons clockwise
+    let enemy_angle = wrap_angle(enemy_dir - rot);
+    let heading = match enemy_angle < 0.0 {
+        true => enemy_dir + PI / 2.0,
+        false => enemy_dir - PI / 2.0
+    };
+    let q = (enemy_angle + PI / 2.0).abs() <= BROADSIDE_AIM_MARGIN
+        && can_shoot(ship, CannonSide::Bowside);
+    let e = (enemy_angle - PI / 2.0).abs() <= BROADSIDE_AIM_MARGIN
+        && can_shoot(ship, CannonSide::Portside);
+    InputState::new(true, false, q, e, false, false, None,
+        Some(pos + polar_to_cartesian(BROADSIDE_STEER_DIST, heading)))
+}
+
+fn can_shoot(ship: &Ship, side: CannonSide) -> bool {
+    ship.cannons.iter().any(|c| c.side == side && c.can_shoot())
+}
+
+fn wrap_angle(rads: f32) -> f32 {
+    let mut rads = rads % (PI * 2.0);
+    if rads > PI {
+        rads -= PI * 2.0;
+    } else if rads <= -PI {
+        rads += PI * 2.0;
+    }
+    rads
+}
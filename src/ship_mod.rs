@@ -1,9 +1,10 @@
 use std::ops::{Add, AddAssign, DivAssign, MulAssign, SubAssign};
 
 use binary_stream::{BinaryStream, Serializable};
-use tetra::{Context, graphics::Texture};
-use crate::{BbResult, GC, Rcc, entity::GameState, ship::Ship};
+use tetra::graphics::Texture;
+use crate::{BbResult, GC, Rcc, decode::{Decode, DecodeStream, unknown_index}, entity::GameState, ship::Ship};
 
+#[derive(Clone, Copy)]
 pub struct Attribute<T>
     where T:
         Clone + Copy+ PartialEq + PartialOrd + Add<Output = T> + AddAssign + SubAssign + MulAssign + DivAssign + From<u8> {
@@ -43,24 +44,36 @@ pub enum ShipModType {
     CannonRangeUpgrade
 }
 
-impl Serializable for ShipModType {
-    fn to_stream(&self, stream: &mut BinaryStream) {
-        stream.write_buffer_single(match self {
+impl ShipModType {
+    pub fn to_num(&self) -> u8 {
+        match self {
             ShipModType::Repair => 0,
             ShipModType::CannonAmmoUpgrade => 1,
             ShipModType::CannonReloadUpgrade => 2,
             ShipModType::CannonRangeUpgrade => 3
-        }).unwrap();
+        }
+    }
+}
+
+impl Serializable for ShipModType {
+    fn to_stream(&self, stream: &mut BinaryStream) {
+        stream.write_buffer_single(self.to_num()).unwrap();
     }
 
     fn from_stream(stream: &mut BinaryStream) -> Self {
-        match stream.read_buffer_single().unwrap() {
+        Self::decode(stream).unwrap()
+    }
+}
+
+impl Decode for ShipModType {
+    fn decode(stream: &mut BinaryStream) -> BbResult<Self> {
+        Ok(match stream.decode_u8()? {
             0 => ShipModType::Repair,
             1 => ShipModType::CannonAmmoUpgrade,
             2 => ShipModType::CannonReloadUpgrade,
             3 => ShipModType::CannonRangeUpgrade,
-            n @ _ => panic!("Index {} not assigned to any ship mod type", n)
-        }
+            n @ _ => return Err(unknown_index(n, "ship mod type"))
+        })
     }
 }
 
@@ -68,7 +81,7 @@ pub trait ShipMod : GameState {
     fn get_name(&self) -> String;
     fn get_description(&self) -> String;
     fn get_type(&self) -> ShipModType;
-    fn get_icon(&self) -> Texture;
+    fn get_icon(&self) -> Option<Texture>; // None in headless simulations
     fn get_applied_ship(&self) -> Rcc<Ship>;
     fn on_apply(&mut self) -> BbResult;
     fn on_remove(&mut self) -> BbResult;
@@ -89,15 +102,14 @@ pub fn get_ship_mod_cost(ship_mod: ShipModType) -> u32 {
 }
 
 pub struct CannonAmmoUpgradeMod {
-    icon: Texture,
+    icon: Option<Texture>,
     ship: Rcc<Ship>
 }
 
 impl CannonAmmoUpgradeMod {
-    pub fn new(ctx: &mut Context, ship: Rcc<Ship>, game: GC) // Separate apply method
+    pub fn new(ship: Rcc<Ship>, game: GC) // Separate apply method
         -> tetra::Result<CannonAmmoUpgradeMod> {
-        let icon = game.borrow_mut().assets.load_texture(ctx,
-            "UI/Ammo Upgrade Mod.png".to_owned(), true)?;
+        let icon = game.borrow().assets.get_texture("UI/Ammo Upgrade Mod.png");
         Ok(CannonAmmoUpgradeMod {
             icon, ship
         })
@@ -121,7 +133,7 @@ impl ShipMod for CannonAmmoUpgradeMod {
         ShipModType::CannonAmmoUpgrade
     }
 
-    fn get_icon(&self) -> Texture {
+    fn get_icon(&self) -> Option<Texture> {
         self.icon.clone()
     }
 
@@ -148,16 +160,15 @@ impl GameState for CannonAmmoUpgradeMod {
 }
 
 pub struct CannonReloadUpgradeMod {
-    icon: Texture,
+    icon: Option<Texture>,
     ship: Rcc<Ship>
 }
 
 impl CannonReloadUpgradeMod {
-    pub fn new(ctx: &mut Context, ship: Rcc<Ship>, game: GC)
+    pub fn new(ship: Rcc<Ship>, game: GC)
         -> tetra::Result<CannonReloadUpgradeMod> {
         Ok(CannonReloadUpgradeMod {
-            icon: game.borrow_mut().assets.load_texture(ctx,
-                "UI/Cannon Reload Upgrade Mod.png".to_owned(), true)?,
+            icon: game.borrow().assets.get_texture("UI/Cannon Reload Upgrade Mod.png"),
             ship
         })
     }
@@ -180,7 +191,7 @@ impl ShipMod for CannonReloadUpgradeMod {
         ShipModType::CannonReloadUpgrade
     }
 
-    fn get_icon(&self) -> Texture {
+    fn get_icon(&self) -> Option<Texture> {
         self.icon.clone()
     }
 
@@ -207,16 +218,15 @@ impl GameState for CannonReloadUpgradeMod {
 }
 
 pub struct CannonRangeUpgradeMod {
-    icon: Texture,
+    icon: Option<Texture>,
     ship: Rcc<Ship>
 }
 
 impl CannonRangeUpgradeMod {
-    pub fn new(ctx: &mut Context, ship: Rcc<Ship>, game: GC)
+    pub fn new(ship: Rcc<Ship>, game: GC)
         -> tetra::Result<CannonRangeUpgradeMod> {
         Ok(CannonRangeUpgradeMod {
-            icon: game.borrow_mut().assets.load_texture(ctx,
-                "UI/Cannon Range Upgrade Mod.png".to_owned(), true)?,
+            icon: game.borrow().assets.get_texture("UI/Cannon Range Upgrade Mod.png"),
             ship
         })
     }
@@ -240,7 +250,7 @@ impl ShipMod for CannonRangeUpgradeMod {
         ShipModType::CannonRangeUpgrade
     }
 
-    fn get_icon(&self) -> Texture {
+    fn get_icon(&self) -> Option<Texture> {
         self.icon.clone()
     }
 
@@ -0,0 +1,143 @@
+use crate::{BbResult, Controller, GC, GameContainer, ID, Player, PlayerParams, Rcc, TransformResult, V2, entity::GameState, game_settings::GameSettings, gen_world, packet::InputStep, rollback::SimulationSnapshot, ship_ai::{COMPUTER_ID_OFFSET, COMPUTER_NAMES, ShipAI}, ship_data::ShipType, sync_checker::SyncState, weather::WeatherSystem, world::World, wrap_rcc};
+
+pub const WORLD_BASE_TILE_SIZE: f32 = 475.0;
+pub const WORLD_TILE_PADDING: f32 = 1.7;
+
+// Tiles per side, the map grows with every ship
+pub fn get_map_size(participants: usize) -> i64 {
+    (10 + 5 * participants).min(30) as i64
+}
+
+// Everything that makes up a match, minus rendering and input. Two simulations that are fed
+// the same players, seed, settings and input steps have to end up in the same state, which
+// is what keeps lockstep clients in sync.
+pub struct Simulation {
+    pub controller: Controller,
+    pub world: World,
+    game: GC
+}
+
+impl Simulation {
+    pub fn new(mut players: Vec<PlayerParams>, world_seed: u64, settings: GameSettings,
+        game: GC) -> BbResult<Simulation> {
+        {
+            let mut game_ref = game.borrow_mut();
+            game_ref.weather = WeatherSystem::new(settings.weather, world_seed);
+            game_ref.physics.wind = game_ref.weather.wind;
+        }
+        let mut simulation = Simulation {
+            controller: Controller::new(settings, game.clone()).convert()?,
+            world: World::new(game.clone()),
+            game
+        };
+        let map_size = get_map_size(players.len() + settings.bots as usize);
+        gen_world(map_size, map_size, WORLD_BASE_TILE_SIZE, WORLD_TILE_PADDING,
+            world_seed, 2, &mut simulation.world).convert()?;
+
+        let spawn_slot = players.iter().map(|p| p.id.n + 1).max().unwrap_or(0);
+        players.sort_unstable_by_key(|p| p.id.n);
+        for player in players.into_iter() {
+            simulation.add_player(player.id, player.ship_type)?;
+        }
+        simulation.init_computer_players(settings.bots, spawn_slot, world_seed,
+            V2::one() * map_size as f32 * WORLD_BASE_TILE_SIZE * WORLD_TILE_PADDING)?;
+        Ok(simulation)
+    }
+
+    // Runs without window or network, steps have to be fed through add_step
+    pub fn headless(players: Vec<PlayerParams>, world_seed: u64, settings: GameSettings)
+        -> BbResult<Simulation> {
+        let game = wrap_rcc(GameContainer::headless().convert()?);
+        Self::new(players, world_seed, settings, game)
+    }
+
+    pub fn add_player(&mut self, id: ID, ship_type: ShipType) -> BbResult<Rcc<Player>> {
+        let ship = self.world.add_player_ship(id.clone(), ship_type).convert()?;
+        Ok(self.controller.add_player(Player::new(id, ship, self.game.clone())))
+    }
+
+    pub fn add_step(&mut self, step: InputStep) {
+        self.controller.add_step(step);
+    }
+
+    pub fn get_game(&self) -> GC {
+        self.game.clone()
+    }
+
+    pub fn get_curr_gen(&self) -> u64 {
+        self.controller.get_curr_gen()
+    }
+
+    pub fn get_last_sync_state(&self) -> Option<SyncState> {
+        self.controller.get_last_sync_state()
+    }
+
+    pub fn save_state(&self) -> SimulationSnapshot {
+        self.controller.save_state(&self.world)
+    }
+
+    pub fn restore_state(&mut self, snapshot: &SimulationSnapshot) {
+        self.controller.restore_state(snapshot, &mut self.world);
+    }
+
+    // Advances the controller and world by one frame, if the next input step is there.
+    // Physics are stepped by the game container afterward.
+    pub fn update(&mut self) -> tetra::Result<bool> {
+        self.reconcile_predictions()?;
+        let is_next_frame_ready = self.controller.is_next_frame_ready();
+        self.game.borrow_mut().simulation_settings.run = is_next_frame_ready;
+        if is_next_frame_ready {
+            self.controller.update(&mut self.world)?;
+            self.world.update()?;
+        }
+        Ok(is_next_frame_ready)
+    }
+
+    // Headless counterpart to a game frame. Returns how many frames actually ran before
+    // the simulation ran out of input steps.
+    pub fn run_frames(&mut self, frames: u64) -> tetra::Result<u64> {
+        for frame in 0..frames {
+            if !self.update()? {
+                return Ok(frame)
+            }
+            self.game.borrow_mut().update_physics();
+        }
+        Ok(frames)
+    }
+
+    // On a misprediction, goes back to the frame the wrong step was applied at and simulates
+    // up to the current frame again, with the actual step
+    fn reconcile_predictions(&mut self) -> tetra::Result {
+        if let Some(snapshot) = self.controller.reconcile_predictions().convert()? {
+            let curr_frames = self.controller.input_buffer.curr_frames;
+            self.restore_state(&snapshot);
+            self.controller.set_resimulating(true);
+            while self.controller.input_buffer.curr_frames < curr_frames
+                && self.controller.is_next_frame_ready() {
+                self.controller.update(&mut self.world)?;
+                self.world.update()?;
+                self.game.borrow_mut().physics.update();
+            }
+            self.controller.set_resimulating(false);
+        }
+        Ok(())
+    }
+
+    fn init_computer_players(&mut self, count: u8, spawn_slot: u16, world_seed: u64,
+        patrol_area: V2) -> BbResult {
+        for i in 0..count as u16 {
+            let id = ID::new(COMPUTER_NAMES[i as usize % COMPUTER_NAMES.len()].to_owned(),
+                COMPUTER_ID_OFFSET + i);
+            let ship_type = match i % 3 {
+                0 => ShipType::Caravel,
+                1 => ShipType::Galleon,
+                _ => ShipType::Schooner
+            };
+            let ship = self.world.add_computer_ship(id.name.to_owned(), ship_type,
+                spawn_slot + i).convert()?;
+            let ai = ShipAI::new(world_seed, id.n, patrol_area);
+            self.controller.add_computer_player(Player::new(id, ship, self.game.clone()), ai);
+        }
+        Ok(())
+    }
+}
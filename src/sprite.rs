@@ -8,38 +8,39 @@ pub enum SpriteOrigin {
 }
 
 pub struct Sprite {
-    pub texture: Texture,
+    pub texture: Option<Texture>, // None in headless simulations, where only the size matters
+    pub size: V2,
     pub origin: V2,
     pub translation: Option<(V2, f32)>
 }
 
 impl Sprite {
-    pub fn new(texture: Texture, origin: SpriteOrigin,
+    pub fn new(texture: Option<Texture>, size: V2, origin: SpriteOrigin,
         translation: Option<(V2, f32)>) -> Sprite {
         Sprite {
-            texture: texture.clone(), origin: Self::resolve_origin(texture, origin),
+            texture, size, origin: Self::resolve_origin(size, origin),
             translation
         }
     }
 
-    pub fn resolve_origin(texture: Texture, origin: SpriteOrigin) -> V2 {
+    pub fn resolve_origin(size: V2, origin: SpriteOrigin) -> V2 {
         match origin {
             SpriteOrigin::TopLeft => V2::zero(),
-            SpriteOrigin::BottomRight => V2::new(texture.width() as f32,
-                texture.height() as f32),
-            SpriteOrigin::Centre => V2::new(texture.width() as f32 * 0.5,
-                texture.height() as f32 * 0.5)
+            SpriteOrigin::BottomRight => size,
+            SpriteOrigin::Centre => size * 0.5
         }
     }
 
     pub fn get_size(&self) -> V2 {
-        V2::new(self.texture.width() as f32, self.texture.height() as f32)
+        self.size
     }
 
     pub fn draw(&self, ctx: &mut Context, position: V2, rotation: f32) {
-        self.texture.draw(ctx, DrawParams {
-            position, rotation, origin: self.origin, scale: V2::one(), color: Color::WHITE
-        })
+        if let Some(texture) = self.texture.as_ref() {
+            texture.draw(ctx, DrawParams {
+                position, rotation, origin: self.origin, scale: V2::one(), color: Color::WHITE
+            })
+        }
     }
 
     pub fn draw2(&self, ctx: &mut Context, translation: (V2, f32)) {
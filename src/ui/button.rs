@@ -15,7 +15,7 @@ impl<T: UIReactor + 'static> Button<T> {
         game: GC) -> tetra::Result<Button<T>> {
         let mut game_ref = game.borrow_mut();
         let texture = game_ref.assets.load_texture(ctx,"UI/Button.png".to_owned(), true)?;
-        let font = game_ref.assets.font.clone();
+        let font = game_ref.assets.font();
         std::mem::drop(game_ref);
         
         let uniform_size = V2::new(10.0, 10.0);
@@ -27,6 +27,10 @@ impl<T: UIReactor + 'static> Button<T> {
         })
     }
 
+    pub fn set_text(&mut self, text: &str) {
+        self.text.set_content(text);
+    }
+
     pub fn is_pressed(&self) -> bool {
         self.reactor.get_state() == UIState::Focus
     }
@@ -12,24 +12,21 @@ pub struct HealthBar {
     max_health: u16,
     curr_health_rel: f32,
     label_color: Color,
-    label_rel_centre: V2
+    label_rel_centre: Option<V2> // Measuring the label needs a context, so it's done on first draw
 }
 
 impl HealthBar {
-    pub fn new(ctx: &mut Context, name: String, label_color: Color, max_health: u16,
-        game: GC) -> tetra::Result<HealthBar> {
-        let (life_tex, red_tex, font) = {
-            let game_ref = game.borrow();
-            (game_ref.assets.get_cached_texture("Green".to_owned()),
-                game_ref.assets.get_cached_texture("Red".to_owned()),
-                game_ref.assets.header2_font.clone())
-        };
-        let mut label = Text::new(name, font);
-        let label_rel_centre = label.get_bounds(ctx).unwrap().center();
-        Ok(HealthBar {
-            label, life_tex, red_tex,
+    // None for headless simulations, which have nothing to draw
+    pub fn new(name: String, label_color: Color, max_health: u16, game: GC)
+        -> Option<HealthBar> {
+        let game_ref = game.borrow();
+        let fonts = game_ref.assets.get_fonts()?;
+        Some(HealthBar {
+            label: Text::new(name, fonts.header2_font.clone()),
+            life_tex: game_ref.assets.get_cached_texture("Green".to_owned()),
+            red_tex: game_ref.assets.get_cached_texture("Red".to_owned()),
             curr_health: max_health, max_health, curr_health_rel: 1.0,
-            label_color, label_rel_centre
+            label_color, label_rel_centre: None
         })
     }
 
@@ -41,11 +38,19 @@ impl HealthBar {
 
     pub fn draw(&mut self, ctx: &mut Context, mut pos: V2) {
         pos -= V2::new(0.0, 30.0);
+        let label_rel_centre = match self.label_rel_centre {
+            Some(centre) => centre,
+            None => {
+                let centre = self.label.get_bounds(ctx).unwrap().center();
+                self.label_rel_centre = Some(centre);
+                centre
+            }
+        };
         self.label.draw(ctx, DrawParams {
-            position: pos - self.label_rel_centre, rotation: 0.0, scale: V2::one(),
+            position: pos - label_rel_centre, rotation: 0.0, scale: V2::one(),
             origin: V2::zero(), color: self.label_color
         });
-        pos += V2::new(-HEALTH_BAR_WIDTH * 0.5, self.label_rel_centre.y * 1.85);
+        pos += V2::new(-HEALTH_BAR_WIDTH * 0.5, label_rel_centre.y * 1.85);
         self.red_tex.draw(ctx, DrawParams {
             position: pos, rotation: 0.0, origin: V2::zero(),
             scale: V2::new(HEALTH_BAR_WIDTH, HEALTH_BAR_HEIGHT),
@@ -16,9 +16,9 @@ impl Label {
     pub fn new(ctx: &mut Context, text: &str, size: FontSize, padding: f32, game: GC)
         -> tetra::Result<Label> {
         let (font, font_size) = match size {
-            FontSize::Small => (game.borrow().assets.small_font.clone(), 17.0),
-            FontSize::Normal => (game.borrow().assets.font.clone(), 20.0),
-            FontSize::Header => (game.borrow().assets.header_font.clone(), 35.0),
+            FontSize::Small => (game.borrow().assets.small_font(), 17.0),
+            FontSize::Normal => (game.borrow().assets.font(), 20.0),
+            FontSize::Header => (game.borrow().assets.header_font(), 35.0),
         };
         let x_size = text.len() as f32 * font_size * 0.5;
         let text = Text::new(text, font);
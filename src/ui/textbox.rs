@@ -13,7 +13,7 @@ impl Textbox {
         -> tetra::Result<Textbox> {
         let mut game_ref = game.borrow_mut();
         let texture = game_ref.assets.load_texture(ctx, "UI/Textbox.png".to_owned(), true)?;
-        let font = game_ref.assets.font.clone();
+        let font = game_ref.assets.font();
         std::mem::drop(game_ref);
 
         Ok(Textbox {
@@ -4,7 +4,6 @@ use nalgebra::{ComplexField, RealField};
 use rand::{Rng, RngCore};
 use rand_xoshiro::{Xoshiro128Plus};
 use rapier2d::{math::{Isometry, Real, Vector}, na::{Point2}};
-use tetra::{Context};
 use crate::{DEFAULT_SIMULATION_TIMESTEP, V2};
 
 pub const UNIT_FRAMERATE_TIMESTEP: f32 = 1.0 / DEFAULT_SIMULATION_TIMESTEP as f32;
@@ -88,12 +87,7 @@ pub fn serialize_v2(stream: &mut BinaryStream, vec: V2) -> std::io::Result<()> {
     stream.write_f32(vec.y)
 }
 
-pub fn deserialize_v2(stream: &mut BinaryStream) -> V2 {
-    let x = stream.read_f32().unwrap();
-    let y = stream.read_f32().unwrap();
-    V2::new(x, y)
-}
-
+#[derive(Debug, Clone, Copy)]
 pub struct Timer {
     pub curr_time: f32,
     pub max: f32
@@ -112,7 +106,7 @@ impl Timer {
         }
     }
 
-    pub fn update(&mut self, ctx: &mut Context) {
+    pub fn update(&mut self) {
         // Timer needs to increment using const timesteps, as otherwise it
         // will not respond to frame rate acceleration (due to simulation catch-ups
         // of the lockstep model).
@@ -132,8 +126,8 @@ impl Timer {
         self.max - self.curr_time
     }
 
-    pub fn run(&mut self, ctx: &mut Context) -> bool {
-        self.update(ctx);
+    pub fn run(&mut self) -> bool {
+        self.update();
         self.is_over()
     }
 
@@ -0,0 +1,81 @@
+use std::f32::consts::PI;
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro128Plus;
+use crate::{V2, game_settings::Weather, polar_to_cartesian, rand_f32};
+
+// Wind turns towards a new target direction/strength every interval (~10 secs)
+pub const WIND_CHANGE_GEN_INTERVAL: u64 = 200;
+pub const MAX_WIND_STRENGTH: f32 = 90.0;
+// Sailing straight with/against the wind of maximum strength adds/removes this share of thrust
+pub const WIND_THRUST_FACTOR: f32 = 0.3;
+pub const RAIN_CANNON_RANGE_FACTOR: f32 = 0.75;
+pub const STORM_DAMAGE_GEN_INTERVAL: u64 = 100; // ~5 secs
+pub const STORM_DAMAGE: u16 = 4;
+
+// All weather effects are pure functions of the world seed and the generation, so every
+// client arrives at the same wind without exchanging any state.
+#[derive(Clone)]
+pub struct WeatherSystem {
+    pub weather: Weather,
+    pub wind: V2,
+    seed: u64
+}
+
+impl WeatherSystem {
+    pub fn new(weather: Weather, seed: u64) -> WeatherSystem {
+        let mut weather_system = WeatherSystem {
+            weather, wind: V2::zero(), seed
+        };
+        weather_system.update(0);
+        weather_system
+    }
+
+    pub fn calm() -> WeatherSystem {
+        Self::new(Weather::Sunny, 0)
+    }
+
+    pub fn update(&mut self, gen: u64) {
+        self.wind = self.calc_wind(gen);
+    }
+
+    pub fn calc_wind(&self, gen: u64) -> V2 {
+        let (min_strength, max_strength) = match self.weather {
+            Weather::Sunny => return V2::zero(),
+            Weather::Windy => (30.0, 60.0),
+            Weather::Rainy => (0.0, 20.0),
+            Weather::Stormy => (50.0, MAX_WIND_STRENGTH)
+        };
+        let interval = gen / WIND_CHANGE_GEN_INTERVAL;
+        let progress = (gen % WIND_CHANGE_GEN_INTERVAL) as f32 / WIND_CHANGE_GEN_INTERVAL as f32;
+        let curr_wind = self.gen_interval_wind(interval, min_strength, max_strength);
+        let next_wind = self.gen_interval_wind(interval + 1, min_strength, max_strength);
+        curr_wind + (next_wind - curr_wind) * progress
+    }
+
+    pub fn get_cannon_range_factor(&self) -> f32 {
+        match self.weather {
+            Weather::Rainy | Weather::Stormy => RAIN_CANNON_RANGE_FACTOR,
+            _ => 1.0
+        }
+    }
+
+    pub fn get_storm_damage(&self, gen: u64) -> Option<u16> {
+        match self.weather {
+            Weather::Stormy if gen > 0 && gen.is_multiple_of(STORM_DAMAGE_GEN_INTERVAL) =>
+                Some(STORM_DAMAGE),
+            _ => None
+        }
+    }
+
+    fn gen_interval_wind(&self, interval: u64, min_strength: f32, max_strength: f32) -> V2 {
+        let mut rng = Xoshiro128Plus::seed_from_u64(self.seed ^ interval.wrapping_mul(0x9E3779B97F4A7C15));
+        let angle = rand_f32(&mut rng) * PI * 2.0;
+        let strength = min_strength + rand_f32(&mut rng) * (max_strength - min_strength);
+        polar_to_cartesian(strength, angle)
+    }
+}
+
+// Expects a normalized facing direction
+pub fn calc_wind_thrust_factor(facing_dir: V2, wind: V2) -> f32 {
+    1.0 + facing_dir.dot(wind) / MAX_WIND_STRENGTH * WIND_THRUST_FACTOR
+}
@@ -2,10 +2,17 @@ use std::collections::HashMap;
 use indexmap::IndexMap;
 use rapier2d::{data::Index, prelude::ContactEvent};
 use tetra::{Context, Event, State};
-use crate::{CannonBall, GC, ID, Rcc, V2, entity::{Entity, EntityType}, harbour::Harbour, object::Object, ship::{Ship}, ship_data::{ShipID, ShipType}, wrap_rcc};
+use crate::{CannonBall, GC, ID, Rcc, V2, entity::{Entity, EntitySnapshot, EntityType}, harbour::Harbour, object::Object, ship::{Ship}, ship_data::{ShipID, ShipType}, state_digest::{DigestField, EntityDigest, StateDigest}, wrap_rcc};
 
 pub type EntityMap<T = dyn Entity + 'static> = HashMap<Index, Rcc<T>>;
 
+// Which entities existed at a frame, and the state each of them was in
+pub struct WorldSnapshot {
+    entities: IndexMap<Index, Rcc<dyn Entity>>,
+    ships: EntityMap<Ship>,
+    states: Vec<(Rcc<dyn Entity>, EntitySnapshot)>
+}
+
 pub struct World {
     entities: IndexMap<Index, Rcc<dyn Entity>>,
     sensors: EntityMap,
@@ -14,45 +21,51 @@ pub struct World {
 }
 
 impl World {
-    pub fn new(_: &mut Context, game: GC) -> World  {
+    pub fn new(game: GC) -> World  {
         World {
             entities: IndexMap::new(), sensors: HashMap::new(),
             ships: HashMap::new(), game
         }
     }
 
-    pub fn add_player_ship(&mut self, ctx: &mut Context, id: ID, ship_type: ShipType)
+    pub fn add_player_ship(&mut self, id: ID, ship_type: ShipType)
         -> tetra::Result<Rcc<Ship>> {
         let free_spawn_pos = {
             // This sometimes leads to immediate desync for one (usually the last) player
             // self.game.borrow().physics.check_for_space(V2::right() * id.n as f32 * 1500.0,
             //     V2::new(500.0, 200.0), V2::down())
-            V2::new(1000.0 + id.n as f32 * 1000.0, -450.0)
+            get_spawn_pos(id.n)
         };
-        self.add_ship(ctx, ship_type, ShipID::Player(id, false), free_spawn_pos, true)
+        self.add_ship(ship_type, ShipID::Player(id, false), free_spawn_pos, true)
+    }
+
+    // Computer ships spawn in line with the players, in the slot after the last player
+    pub fn add_computer_ship(&mut self, name: String, ship_type: ShipType,
+        spawn_slot: u16) -> tetra::Result<Rcc<Ship>> {
+        self.add_ship(ship_type, ShipID::Computer(name), get_spawn_pos(spawn_slot), true)
     }
 
-    pub fn add_island(&mut self, ctx: &mut Context, pos: V2, rot: f32, island_type: u32)
+    pub fn add_island(&mut self, pos: V2, rot: f32, island_type: u32)
         -> tetra::Result<Rcc<Object>> {
-        let island = Object::build_island(ctx, self.game.clone(), pos, rot, island_type)?;
+        let island = Object::build_island(self.game.clone(), pos, rot, island_type)?;
         Ok(self.add_entity(island).unwrap())
     }
 
-    pub fn add_ship_wreck(&mut self, ctx: &mut Context, pos: V2, rot: f32
+    pub fn add_ship_wreck(&mut self, pos: V2, rot: f32
         /* Ship Type */) -> tetra::Result<Rcc<Object>> {
-        let ship_wreck = Object::build_ship_wreck(ctx, self.game.clone(), pos, rot)?;
+        let ship_wreck = Object::build_ship_wreck(self.game.clone(), pos, rot)?;
         Ok(self.add_entity(ship_wreck).unwrap())
     }
 
-    pub fn add_reef(&mut self, ctx: &mut Context, pos: V2, rot: f32)
+    pub fn add_reef(&mut self, pos: V2, rot: f32)
         -> tetra::Result<Rcc<Object>> {
-        let reef = Object::build_reef(ctx, self.game.clone(), pos, rot)?;
+        let reef = Object::build_reef(self.game.clone(), pos, rot)?;
         Ok(self.add_entity(reef).unwrap())
     }
 
-    pub fn add_harbour(&mut self, ctx: &mut Context, name: &str, pos: V2, rot: f32)
+    pub fn add_harbour(&mut self, name: &str, pos: V2, rot: f32)
         -> tetra::Result<Rcc<Harbour>> {
-        let harbour = Harbour::new(ctx, name.to_owned(), pos, rot, self.game.clone())?;
+        let harbour = Harbour::new(name.to_owned(), pos, rot, self.game.clone())?;
         let index = harbour.get_index();
         let zone_index = harbour.zone_handle.0;
         let harbour_ref = wrap_rcc(harbour);
@@ -62,7 +75,7 @@ impl World {
         Ok(harbour_ref)
     }
 
-    pub fn add_cannon_ball(&mut self, _: &mut Context, cannon_ball: CannonBall) -> Rcc<CannonBall> {
+    pub fn add_cannon_ball(&mut self, cannon_ball: CannonBall) -> Rcc<CannonBall> {
         let index = cannon_ball.get_index();
         let cannon_ball_ref = wrap_rcc(cannon_ball);
         self.add_entity_unchecked(index, cannon_ball_ref.clone());
@@ -85,6 +98,15 @@ impl World {
         self.ships[&index].clone()
     }
 
+    // In insertion order, so it is the same across all clients
+    pub fn get_harbour_positions(&self) -> Vec<V2> {
+        self.entities.values()
+            .map(|e| e.borrow())
+            .filter(|e| e.get_type() == EntityType::Harbour)
+            .map(|e| e.get_transform().get_translation().0)
+            .collect()
+    }
+
     pub fn remove_entity(&mut self, index: Index) -> Option<Rcc<dyn Entity>> {
         if let Some(entity) = self.entities.remove(&index) {
             {
@@ -101,6 +123,46 @@ impl World {
         }
     }
 
+    pub fn gen_digest(&self, gen: u64) -> StateDigest {
+        let mut digest = StateDigest::new(gen);
+        for (index, entity) in self.entities.iter() {
+            let entity_ref = entity.borrow();
+            let mut entity_digest = EntityDigest::new(*index, entity_ref.get_type(),
+                entity_ref.get_name());
+            let transform = entity_ref.get_transform();
+            let (pos, rot) = transform.get_translation();
+            let vel = transform.get_lin_velocity();
+            entity_digest.add(DigestField::PosX, pos.x);
+            entity_digest.add(DigestField::PosY, pos.y);
+            entity_digest.add(DigestField::Rot, rot);
+            entity_digest.add(DigestField::VelX, vel.x);
+            entity_digest.add(DigestField::VelY, vel.y);
+            entity_ref.digest(&mut entity_digest);
+            digest.entities.push(entity_digest);
+        }
+        digest
+    }
+
+    // Entities removed after the snapshot are added back, newer ones are dropped. Their
+    // colliders are restored along with the physics.
+    pub fn save_state(&self) -> WorldSnapshot {
+        WorldSnapshot {
+            entities: self.entities.clone(),
+            ships: self.ships.clone(),
+            states: self.entities.values()
+                .filter_map(|e| e.borrow().save_state().map(|state| (e.clone(), state)))
+                .collect()
+        }
+    }
+
+    pub fn restore_state(&mut self, snapshot: &WorldSnapshot) {
+        self.entities = snapshot.entities.clone();
+        self.ships = snapshot.ships.clone();
+        for (entity, state) in snapshot.states.iter() {
+            entity.borrow_mut().restore_state(state);
+        }
+    }
+
     fn add_entity<T: Entity + 'static>(&mut self, entity: T) -> Option<Rcc<T>> {
         let index = entity.get_index();
         if self.entities.contains_key(&index) {
@@ -116,14 +178,14 @@ impl World {
         self.entities.insert(index, entity);
     }
 
-    fn add_ship(&mut self, ctx: &mut Context, ship_type: ShipType, id: ShipID,
+    fn add_ship(&mut self, ship_type: ShipType, id: ShipID,
         spawn: V2, respawn: bool) -> tetra::Result<Rcc<Ship>> {
         let ship = match ship_type {
-            ShipType::Caravel => Ship::caravel(ctx, self.game.clone(),
+            ShipType::Caravel => Ship::caravel(self.game.clone(),
                 id, spawn, respawn),
-            ShipType::Galleon => Ship::galleon(ctx, self.game.clone(),
+            ShipType::Galleon => Ship::galleon(self.game.clone(),
                 id, spawn, respawn),
-            ShipType::Schooner => Ship::schooner(ctx, self.game.clone(),
+            ShipType::Schooner => Ship::schooner(self.game.clone(),
                 id, spawn, respawn)
         }?;
         let index = ship.get_index();
@@ -132,8 +194,8 @@ impl World {
         Ok(ship_ref)
     }
 
-    fn handle_intersections(&mut self, ctx: &mut Context) -> tetra::Result {
-        let intersections = self.game.borrow().physics.get_intersections();
+    fn handle_intersections(&mut self) -> tetra::Result {
+        let intersections = self.game.borrow_mut().physics.get_intersections();
         for intersection in intersections.iter() {
             let coll1_sensor = self.game.borrow().physics
                     .get_coll(intersection.collider1).is_sensor();
@@ -164,16 +226,16 @@ impl World {
                 }
             };
 
-            entity1.borrow_mut().intersect_with_entity(ctx,
+            entity1.borrow_mut().intersect_with_entity(
                 intersection.intersecting, entity2.clone())?;
-            entity2.borrow_mut().intersect_with_entity(ctx,
+            entity2.borrow_mut().intersect_with_entity(
                 intersection.intersecting, entity1)?;
         }
         Ok(())
     }
 
-    fn handle_contacts(&mut self, ctx: &mut Context) -> tetra::Result {
-        let contacts = self.game.borrow().physics.get_contacts();
+    fn handle_contacts(&mut self) -> tetra::Result {
+        let contacts = self.game.borrow_mut().physics.get_contacts();
         for contact in contacts.iter() {
             match contact {
                 ContactEvent::Started(coll1_handle, coll2_handle) => {
@@ -197,16 +259,16 @@ impl World {
                                         e1_ref.get_name(), e1i, e2_ref.get_name(), e2i);
                                 }
                             }
-                            self.handle_contact_with(ctx, entity1.clone(), entity2.clone())?;
-                            self.handle_contact_with(ctx, entity2, entity1)?;
+                            self.handle_contact_with(entity1.clone(), entity2.clone())?;
+                            self.handle_contact_with(entity2, entity1)?;
                         }
                         else {
-                            entity1.borrow_mut().collide_with_neutral(ctx)?;
+                            entity1.borrow_mut().collide_with_neutral()?;
                         }
                     }
                     else {
                         if let Some(entity2) = entity2 {
-                            entity2.borrow_mut().collide_with_neutral(ctx)?;
+                            entity2.borrow_mut().collide_with_neutral()?;
                         }
                     }
                 },
@@ -216,7 +278,7 @@ impl World {
         Ok(())
     }
 
-    fn handle_contact_with(&mut self, ctx: &mut Context, a: Rcc<dyn Entity>, b: Rcc<dyn Entity>)
+    fn handle_contact_with(&mut self, a: Rcc<dyn Entity>, b: Rcc<dyn Entity>)
         -> tetra::Result {
         let b_ship = {
             let b_ref = b.borrow();
@@ -230,22 +292,26 @@ impl World {
             }
         };
         if let Some(b_ship) = b_ship { // b is a ship
-            a.borrow_mut().collide_with_ship(ctx, b_ship, self)
+            a.borrow_mut().collide_with_ship(b_ship, self)
         } else {
-            a.borrow_mut().collide_with_entity(ctx, b, self)
+            a.borrow_mut().collide_with_entity(b, self)
         }
     }
 }
 
-impl State for World {
-    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        self.handle_intersections(ctx)?;
-        self.handle_contacts(ctx)?;
+fn get_spawn_pos(slot: u16) -> V2 {
+    V2::new(1000.0 + slot as f32 * 1000.0, -450.0)
+}
+
+impl World {
+    pub fn update(&mut self) -> tetra::Result {
+        self.handle_intersections()?;
+        self.handle_contacts()?;
 
         let entities = &mut self.entities.clone(); // Performance?
         for entity in entities.values() {
             let mut entity_ref = entity.borrow_mut();
-            entity_ref.update(ctx, self)?;
+            entity_ref.update(self)?;
             if entity_ref.marked_destroy() {
                 let index = entity_ref.get_index();
                 std::mem::drop(entity_ref);
@@ -254,7 +320,9 @@ impl State for World {
         }
         Ok(())
     }
+}
 
+impl State for World {
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
         for entity in self.entities.values() {
             entity.borrow_mut().draw(ctx)?;
@@ -2,16 +2,15 @@ use std::f32::consts::PI;
 
 use rand::{SeedableRng};
 use rand_xoshiro::{Xoshiro128Plus};
-use tetra::Context;
 use worldgen::constraint;
 use worldgen::noisemap::{NoiseMapGenerator, Seed, Size, Step};
 use worldgen::world::tile::{Constraint, ConstraintType};
 use worldgen::{noise::perlin::PerlinNoise, noisemap::NoiseMap};
 use worldgen::world::{Tile, World as NoiseWorld};
-use crate::{V2, World, rand_f32};
+use crate::{GC, V2, World, rand_f32};
 
 pub struct WorldSettings {
-    events: Vec<WorldEvent>
+    events: Vec<(u64, WorldEvent)> // By the gen they happened in
 }
 
 impl WorldSettings {
@@ -21,15 +20,31 @@ impl WorldSettings {
         }
     }
 
-    pub fn add_event(&mut self, event: WorldEvent) {
-        self.events.push(event);
+    pub fn add_event(&mut self, gen: u64, event: WorldEvent) {
+        self.events.push((gen, event));
     }
 
-    pub fn flush_events(&mut self) -> Vec<WorldEvent> {
-        self.events.drain(0..).collect()
+    // Events of predicted gens are only announced once they are confirmed
+    pub fn flush_events(&mut self, confirmed_gen: u64) -> Vec<WorldEvent> {
+        let (confirmed, predicted) = self.events.drain(0..)
+            .partition(|(gen, _)| *gen <= confirmed_gen);
+        self.events = predicted;
+        confirmed.into_iter().map(|(_, event)| event).collect()
     }
+
+    // Gens after a rollback's snapshot are simulated again and add their events anew
+    pub fn discard_events_after(&mut self, gen: u64) {
+        self.events.retain(|(event_gen, _)| *event_gen <= gen);
+    }
+}
+
+pub fn add_world_event(game: GC, event: WorldEvent) {
+    let mut game_ref = game.borrow_mut();
+    let curr_gen = game_ref.simulation_settings.curr_gen;
+    game_ref.world.add_event(curr_gen, event);
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum WorldEvent {
     PlayerSunkByCannon(String, String),
     PlayerSunkByRamming(String, String),
@@ -44,7 +59,7 @@ enum WorldTile {
     Empty,
 }
 
-pub fn gen_world(ctx: &mut Context, width: i64, height: i64, base_tile_size: f32,
+pub fn gen_world(width: i64, height: i64, base_tile_size: f32,
     tile_padding: f32, seed: u64, scale: i64, world: &mut World) -> tetra::Result {
     let noise = PerlinNoise::new();
     let nm1 = NoiseMap::new(noise)
@@ -75,7 +90,7 @@ pub fn gen_world(ctx: &mut Context, width: i64, height: i64, base_tile_size: f32
     for row in noise_world.generate(0, 0).into_iter() {
         for (curr_col, col) in row.iter().enumerate() {
             for tile in col.iter() {
-                let y = add_procedural_element(ctx, &mut rng, curr_pos, base_tile_size,
+                let y = add_procedural_element(&mut rng, curr_pos, base_tile_size,
                     tile_padding, *tile, &mut harbours, &mut harbour_col,
                     col.len() as u32, curr_col as u32, world)?;
                 curr_pos.y += y;
@@ -87,27 +102,27 @@ pub fn gen_world(ctx: &mut Context, width: i64, height: i64, base_tile_size: f32
     Ok(())
 }
 
-fn add_procedural_element(ctx: &mut Context, rng: &mut Xoshiro128Plus, pos: V2,
+fn add_procedural_element(rng: &mut Xoshiro128Plus, pos: V2,
     base_tile_size: f32, tile_padding: f32, tile: WorldTile, harbours: &mut u32,
     harbour_col: &mut u32, total_cols: u32, curr_col: u32, world: &mut World) -> tetra::Result<f32> {
     let noise_pos = pos + V2::new(rand_f32(rng) - 0.5,
         rand_f32(rng) - 0.5) * 100.0;
     let noise_rot = rand_f32(rng) * PI;
     Ok(match tile {
-        WorldTile::Island(island_type) => world.add_island(ctx, noise_pos,
+        WorldTile::Island(island_type) => world.add_island(noise_pos,
             noise_rot, island_type)?.borrow().sprite.get_size().y * tile_padding,
         WorldTile::Harbour => {
             if *harbours >= 2 || (curr_col - *harbour_col) < (total_cols / 4) {
                 return Ok(base_tile_size * 0.25 * tile_padding)
             }
-            let harbour = world.add_harbour(ctx, "Port Royal", noise_pos,
+            let harbour = world.add_harbour("Port Royal", noise_pos,
                 noise_rot)?;
             let size_y = harbour.borrow().sprite.get_size().y;
             *harbours += 1;
             *harbour_col = curr_col;
             size_y * tile_padding
         },
-        WorldTile::Reef => world.add_reef(ctx, noise_pos, noise_rot)?
+        WorldTile::Reef => world.add_reef(noise_pos, noise_rot)?
             .borrow().sprite.get_size().y * tile_padding,
         WorldTile::Empty => base_tile_size * 0.3 * tile_padding,
     })
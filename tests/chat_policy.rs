@@ -0,0 +1,56 @@
+use std::time::Duration;
+use blackbeard::{chat_policy::{ChatPolicy, DEFAULT_HOST_MUTE_MINUTES, MAX_CHAT_MESSAGE_LENGTH,
+    MAX_HOST_MUTE_MINUTES}, moderation::HostCommand};
+
+#[test]
+fn violators_are_warned_and_muted() {
+    let mut policy = ChatPolicy::new(Vec::new());
+    assert!(policy.check("Blackbeard", &"☠".repeat(MAX_CHAT_MESSAGE_LENGTH)).is_ok());
+    assert!(policy.check("Blackbeard", &"☠".repeat(MAX_CHAT_MESSAGE_LENGTH + 1)).is_err());
+    assert!(policy.is_muted("Blackbeard"));
+    assert!(policy.check("Blackbeard", "Ahoy").is_err());
+    assert!(!policy.is_muted("AnneBonny"));
+}
+
+#[test]
+fn bursts_beyond_the_rate_limit_are_dropped() {
+    let mut policy = ChatPolicy::new(Vec::new());
+    let accepted = (0..10).filter(|i| policy.check("CalicoJack", &format!("Ahoy {}", i)).is_ok())
+        .count();
+    assert_eq!(accepted, 5);
+    assert!(policy.is_muted("CalicoJack"));
+    assert!(policy.check("AnneBonny", "Ahoy").is_ok()); // Limits are per sender
+}
+
+#[test]
+fn filter_matches_whole_words_only() {
+    let mut policy = ChatPolicy::new(vec!["Scallywag".to_owned()]);
+    assert!(policy.check("MaryRead", "Watch out for the assassin").is_ok());
+    assert!(policy.check("MaryRead", "Scallywags everywhere").is_ok());
+    assert!(policy.check("MaryRead", "You SCALLYWAG!").is_err());
+}
+
+#[test]
+fn host_mutes_can_be_lifted() {
+    let mut policy = ChatPolicy::new(Vec::new());
+    policy.mute("StedeBonnet", Duration::from_secs(60));
+    assert!(policy.check("StedeBonnet", "Ahoy").is_err());
+    assert!(policy.unmute("StedeBonnet"));
+    assert!(!policy.unmute("StedeBonnet"));
+    assert!(policy.check("StedeBonnet", "Ahoy").is_ok());
+
+    assert_eq!(HostCommand::parse("/mute StedeBonnet"), Ok(HostCommand::Mute {
+        name: "StedeBonnet".to_owned(), minutes: DEFAULT_HOST_MUTE_MINUTES
+    }));
+    assert_eq!(HostCommand::parse("/mute StedeBonnet 3"), Ok(HostCommand::Mute {
+        name: "StedeBonnet".to_owned(), minutes: 3
+    }));
+    assert!(HostCommand::parse("/mute StedeBonnet forever").is_err());
+    assert!(HostCommand::parse("/mute StedeBonnet 0").is_err());
+    assert!(HostCommand::parse(&format!("/mute StedeBonnet {}", MAX_HOST_MUTE_MINUTES + 1)).is_err());
+    assert!(HostCommand::parse(&format!("/mute StedeBonnet {}", u64::MAX)).is_err());
+
+    // Durations beyond what the host can type are capped instead of overflowing the deadline
+    policy.mute("StedeBonnet", Duration::MAX);
+    assert!(policy.is_muted("StedeBonnet"));
+}
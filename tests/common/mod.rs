@@ -0,0 +1,64 @@
+// Helpers shared by the networked tests. Every test binary compiles its own copy and only
+// uses some of them.
+#![allow(dead_code)]
+
+use std::{thread, time::{Duration, Instant}};
+use blackbeard::{client::{Client, ClientEvent}, net_conditions::NetConditions, net_settings::NetSettings, peer::DisconnectReason, server::{Server, ServerEvent}};
+
+// Long enough for any handshake over loopback, short enough to fail fast
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+// Discovery requests are broadcast to a known port. Every other socket binds port 0, so
+// tests running in parallel never collide.
+pub const DISCOVERY_TEST_PORT: u16 = 22280;
+
+// Hidden from discovery and without persistent bans
+pub fn settings() -> NetSettings {
+    let mut settings = NetSettings::default();
+    settings.discovery_port = None;
+    settings.bans_path = None;
+    settings
+}
+
+pub fn host(settings: NetSettings) -> Server {
+    Server::host(0, settings).unwrap()
+}
+
+pub fn get_endpoint(server: &Server) -> String {
+    format!("127.0.0.1:{}", server.get_local_port())
+}
+
+pub fn connect(endpoint: &str, name: &str) -> Client {
+    Client::connect(endpoint, name.to_owned(), false, None, None, NetConditions::default()).unwrap()
+}
+
+// Polls the server and every client until one of them gets the event
+pub fn poll_until<T>(server: &mut Server, clients: &mut [&mut Client],
+    mut check: impl FnMut(Option<ServerEvent>, Option<(usize, ClientEvent)>) -> Option<T>) -> T {
+    let start_time = Instant::now();
+    loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Expected event did not occur");
+        if let Some(result) = check(Some(server.poll_received_packets().unwrap()), None) {
+            return result
+        }
+        for (i, client) in clients.iter_mut().enumerate() {
+            if let Some(result) = check(None, Some((i, client.poll_received_packets().unwrap()))) {
+                return result
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+pub fn wait_for_connect(server: &mut Server, clients: &mut [&mut Client]) -> u16 {
+    poll_until(server, clients, |event, _| match event {
+        Some(ServerEvent::PlayerConnect(id, _)) => Some(id.n),
+        _ => None
+    })
+}
+
+pub fn wait_for_refusal(server: &mut Server, clients: &mut [&mut Client], client: usize) -> DisconnectReason {
+    poll_until(server, clients, |_, event| match event {
+        Some((i, ClientEvent::Disconnect(reason))) if i == client => Some(reason),
+        _ => None
+    })
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::{thread, time::{Duration, Instant}};
+use blackbeard::connection_quality::{ConnectionQuality, PING_TIMEOUT, QualityTracker};
+use common::{TIMEOUT, connect, get_endpoint, host, settings};
+
+#[test]
+fn tracker_measures_rtt_jitter_and_loss() {
+    let start = Instant::now();
+    let mut tracker = QualityTracker::new();
+    assert_eq!(tracker.get_quality(1, start), None);
+
+    // Round trips of 40, 60 and 50ms, then one ping that is never answered
+    for (i, rtt) in [40, 60, 50].iter().enumerate() {
+        let sent_at = start + Duration::from_secs(i as u64);
+        let seq = tracker.send_ping(sent_at);
+        assert!(tracker.on_pong(seq, sent_at + Duration::from_millis(*rtt)));
+        assert!(!tracker.on_pong(seq, sent_at + Duration::from_millis(*rtt + 5))); // Duplicate
+    }
+    let lost_at = start + Duration::from_secs(3);
+    tracker.send_ping(lost_at);
+    assert!(!tracker.on_pong(1234, lost_at));
+
+    let expected = ConnectionQuality { id: 1, rtt: 50, jitter: 15, loss: 0 };
+    assert_eq!(tracker.get_quality(1, lost_at), Some(expected)); // Not lost yet
+    let quality = tracker.get_quality(1, lost_at + Duration::from_secs_f32(PING_TIMEOUT)).unwrap();
+    assert_eq!(quality, ConnectionQuality { loss: 25, ..expected });
+    assert!(quality.is_lagging());
+    assert!(!expected.is_lagging());
+}
+
+#[test]
+fn clients_receive_the_quality_table() {
+    let mut server = host(settings());
+    let mut client = connect(&get_endpoint(&server), "Blackbeard");
+
+    // Pings go out every second, and the table with the next one
+    let start_time = Instant::now();
+    let quality = loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Quality table never arrived");
+        server.poll_received_packets().unwrap();
+        client.poll_received_packets().unwrap();
+        // The first player gets the host ID, replying to the handshake is up to the scenes
+        if let Some(quality) = client.get_connection_quality(0) {
+            break *quality
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!(server.get_quality_table(), vec![server.get_connection_quality(quality.id).unwrap()]);
+    assert!(quality.rtt < 500);
+    assert_eq!(quality.loss, 0);
+}
@@ -0,0 +1,114 @@
+use blackbeard::{desync_investigation::DesyncInvestigation, entity::EntityType, packet::Packet, state_digest::{DigestField, DigestValue, EntityDigest, StateDigest}, sync_checker::SyncState};
+use rapier2d::data::Index;
+
+fn ship(index: u32, name: &str, pos_x: f32, health: u16) -> EntityDigest {
+    let mut entity = EntityDigest::new(Index::from_raw_parts(index, 0), EntityType::Ship, name.to_owned());
+    entity.add(DigestField::PosX, pos_x);
+    entity.add(DigestField::Health, health);
+    entity
+}
+
+fn digest(gen: u64, entities: Vec<EntityDigest>) -> StateDigest {
+    let mut digest = StateDigest::new(gen);
+    digest.entities = entities;
+    digest
+}
+
+fn requested_gens(requests: &[(u16, Packet)]) -> Vec<(u16, Option<u64>)> {
+    requests.iter().map(|(id, packet)| match packet {
+        Packet::DigestRequest { gen } => (*id, *gen),
+        _ => panic!("Expected digest requests only")
+    }).collect()
+}
+
+#[test]
+fn suspects_without_diverging_digests_are_cleared() {
+    let mut investigation = DesyncInvestigation::new();
+    assert_eq!(investigation.investigate(0, vec![1]).len(), 2); // Summaries of both
+    assert!(investigation.is_suspect(1));
+
+    let states = vec![SyncState::new(4, 0x600D), SyncState::new(8, 0x600D)];
+    assert!(investigation.add_summary(0, states.clone()).is_empty());
+    assert!(investigation.add_summary(1, states).is_empty());
+    assert!(!investigation.is_suspect(1));
+    assert!(investigation.update().is_empty()); // Not timed out later on either
+}
+
+#[test]
+fn first_diverging_gen_is_investigated() {
+    let mut investigation = DesyncInvestigation::new();
+    assert_eq!(requested_gens(&investigation.investigate(0, vec![1])), vec![(1, None), (0, None)]);
+    let reference_states = vec![SyncState::new(4, 0x600D), SyncState::new(8, 0x600D), SyncState::new(12, 0x600D)];
+    let suspect_states = vec![SyncState::new(4, 0x600D), SyncState::new(8, 0xBAD), SyncState::new(12, 0xBAD)];
+    assert!(investigation.add_summary(0, reference_states).is_empty());
+    assert_eq!(requested_gens(&investigation.add_summary(1, suspect_states)), vec![(1, Some(8)), (0, Some(8))]);
+
+    // Both digests have to arrive before the suspect is done with
+    let reference_digest = digest(8, vec![ship(0, "Host", 100.0, 80)]);
+    let suspect_digest = digest(8, vec![ship(0, "Host", 100.5, 80)]);
+    for part in reference_digest.split().into_iter() {
+        assert!(investigation.add_digest_part(0, part).is_empty());
+    }
+    assert!(investigation.is_suspect(1));
+    let mut finished_suspects = Vec::new();
+    for part in suspect_digest.split().into_iter() {
+        finished_suspects.extend(investigation.add_digest_part(1, part));
+    }
+    assert_eq!(finished_suspects, vec![1]);
+    assert!(!investigation.is_suspect(1));
+}
+
+#[test]
+fn unrequested_digests_are_dropped() {
+    let mut investigation = DesyncInvestigation::new();
+    let states = vec![SyncState::new(4, 0x600D), SyncState::new(8, 0xBAD)];
+    // Nothing is being investigated
+    assert!(investigation.add_summary(2, states.clone()).is_empty());
+    for part in digest(8, vec![ship(0, "Host", 100.0, 80)]).split().into_iter() {
+        assert!(investigation.add_digest_part(2, part).is_empty());
+    }
+
+    investigation.investigate(0, vec![1]);
+    assert!(investigation.add_summary(0, vec![SyncState::new(4, 0x600D), SyncState::new(8, 0x600D)]).is_empty());
+    assert!(investigation.add_summary(2, states.clone()).is_empty()); // Nobody asked
+    assert_eq!(requested_gens(&investigation.add_summary(1, states)), vec![(1, Some(8)), (0, Some(8))]);
+
+    // Only the requested gen completes the investigation
+    for part in digest(4, vec![ship(0, "Host", 100.0, 80)]).split().into_iter() {
+        assert!(investigation.add_digest_part(0, part).is_empty());
+    }
+    for part in digest(8, vec![ship(0, "Host", 100.0, 80)]).split().into_iter() {
+        assert!(investigation.add_digest_part(0, part).is_empty());
+    }
+    let finished_suspects = digest(8, vec![ship(0, "Host", 100.0, 70)]).split().into_iter()
+        .flat_map(|part| investigation.add_digest_part(1, part))
+        .collect::<Vec<_>>();
+    assert_eq!(finished_suspects, vec![1]);
+}
+
+#[test]
+fn diff_names_the_diverging_entity_and_field() {
+    let reference = digest(8, vec![ship(0, "Host", 100.0, 80), ship(1, "Blackbeard", 250.0, 100)]);
+    let suspect = digest(8, vec![ship(0, "Host", 100.0, 80), ship(1, "Blackbeard", 250.0, 95)]);
+    assert!(reference.diff(&reference.clone()).is_none());
+
+    let mismatch = reference.diff(&suspect).unwrap();
+    assert_eq!(mismatch.gen, 8);
+    assert!(mismatch.entity.starts_with("Blackbeard"));
+    assert_eq!(mismatch.field, Some(DigestField::Health));
+    assert_eq!(mismatch.expected, Some(DigestValue::from(100u16)));
+    assert_eq!(mismatch.actual, Some(DigestValue::from(95u16)));
+}
+
+#[test]
+fn diff_finds_entities_only_one_side_has() {
+    let reference = digest(8, vec![ship(0, "Host", 100.0, 80)]);
+    let suspect = digest(8, vec![ship(0, "Host", 100.0, 80), ship(1, "Blackbeard", 250.0, 100)]);
+
+    // Either way round, the extra entity is named without a field
+    for mismatch in [reference.diff(&suspect).unwrap(), suspect.diff(&reference).unwrap()] {
+        assert!(mismatch.entity.starts_with("Blackbeard"));
+        assert_eq!(mismatch.field, None);
+        assert!(mismatch.to_string().contains("only exists for one client"));
+    }
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::{net::{Ipv4Addr, UdpSocket}, time::{Duration, Instant}};
+use blackbeard::{discovery::{DiscoveredLobby, DiscoveryBrowser, LobbyInfo, deserialize_lobby_info, gen_discovery_request,
+    serialize_lobby_info}, game_settings::GameMode, server::Server, version::GameVersion};
+use common::{DISCOVERY_TEST_PORT, TIMEOUT, host, settings};
+
+// Polls the server and browser until a lobby matching the check shows up
+fn discover(server: &mut Server, browser: &mut DiscoveryBrowser,
+    check: impl Fn(&DiscoveredLobby) -> bool) -> DiscoveredLobby {
+    let start_time = Instant::now();
+    loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Lobby was not discovered");
+        server.poll_received_packets().unwrap();
+        browser.update().unwrap();
+        if let Some(lobby) = browser.get_lobbies().into_iter().find(|lobby| check(lobby)) {
+            return lobby.clone()
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn lobby_info_survives_encoding() {
+    let info = LobbyInfo {
+        name: "Queen Anne's Revenge".to_owned(), port: 22081, version: GameVersion::local(),
+        mode: GameMode::Deathmatch(10), players: 3, max_players: 4, password: true, open: true
+    };
+    let bytes = serialize_lobby_info(&info);
+    assert_eq!(deserialize_lobby_info(&bytes).unwrap(), info);
+    assert!(info.is_joinable());
+    assert!(deserialize_lobby_info(&bytes[4..]).is_err());
+    assert!(deserialize_lobby_info(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn hosts_answer_discovery_requests() {
+    let mut settings = settings();
+    settings.lobby_name = "Nassau".to_owned();
+    settings.password = Some("rum".to_owned());
+    settings.discovery_port = Some(DISCOVERY_TEST_PORT);
+    let mut server = host(settings);
+    server.set_game_mode(GameMode::Deathmatch(5));
+    let mut browser = DiscoveryBrowser::new(DISCOVERY_TEST_PORT).unwrap();
+
+    let lobby = discover(&mut server, &mut browser, |lobby| lobby.info.name == "Nassau");
+    assert_eq!(lobby.addr.port(), server.get_local_port());
+    assert_eq!(lobby.info.mode, GameMode::Deathmatch(5));
+    assert_eq!((lobby.info.players, lobby.info.max_players), (0, 4));
+    assert!(lobby.info.password);
+    assert!(lobby.info.is_joinable());
+
+    // Locked lobbies are still listed, but can't be joined
+    server.set_locked(true);
+    let lobby = discover(&mut server, &mut browser, |lobby| !lobby.info.open);
+    assert!(!lobby.info.is_joinable());
+
+    // Short requests would get back more than they sent, so only padded ones are answered
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(1))).unwrap();
+    let mut buffer = [0; 512];
+    for (request, answered) in [(b"BBDQ".to_vec(), false), (gen_discovery_request(), true)] {
+        socket.send_to(&request, (Ipv4Addr::LOCALHOST, DISCOVERY_TEST_PORT)).unwrap();
+        let start_time = Instant::now();
+        let response = loop {
+            server.poll_received_packets().unwrap();
+            if let Ok(len) = socket.recv(&mut buffer) {
+                break Some(buffer[..len].to_vec())
+            }
+            if start_time.elapsed() > Duration::from_millis(200) {
+                break None
+            }
+        };
+        assert_eq!(response.is_some(), answered);
+        assert!(response.is_none_or(|response| response.len() <= request.len()));
+    }
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::{net::SocketAddr, thread, time::{Duration, Instant}};
+use laminar::SocketEvent;
+use blackbeard::{ID, flood_protection::{COOKIE_INTERVAL, FloodGuard, UNKNOWN_SENDER_BURST, UNKNOWN_SENDER_REFILL_RATE}, net_conditions::NetConditions, packet::{Packet, deserialize_packet, serialize_packet_unsigned}, peer::{CONTROL_STREAM, Delivery, DisconnectReason, Peer}, server::{Server, ServerEvent}, transport::TransportSetup, version::GameVersion};
+use common::{TIMEOUT, connect, get_endpoint, host, settings, wait_for_refusal};
+
+fn addr(ip: [u8; 4], port: u16) -> SocketAddr {
+    SocketAddr::from((ip, port))
+}
+
+#[test]
+fn unknown_senders_are_rate_limited_by_ip() {
+    let start = Instant::now();
+    let mut guard = FloodGuard::new();
+    let burst = UNKNOWN_SENDER_BURST as u64;
+    for port in 0..burst {
+        assert!(guard.allow(addr([10, 0, 0, 1], 4000 + port as u16), start)); // New ports share the bucket
+    }
+    assert!(!guard.allow(addr([10, 0, 0, 1], 4000), start));
+    assert!(guard.allow(addr([10, 0, 0, 2], 4000), start));
+
+    let refilled_at = start + Duration::from_secs_f32(1.0 / UNKNOWN_SENDER_REFILL_RATE);
+    assert!(guard.allow(addr([10, 0, 0, 1], 4000), refilled_at));
+    assert!(!guard.allow(addr([10, 0, 0, 1], 4000), refilled_at));
+    assert_eq!(guard.get_stats().rate_limited, 2);
+}
+
+#[test]
+fn cookies_are_bound_to_the_address_and_expire() {
+    let start = Instant::now();
+    let mut guard = FloodGuard::new();
+    let sender = addr([10, 0, 0, 1], 4000);
+    let cookie = guard.gen_cookie(sender, start);
+    assert!(guard.check_cookie(sender, Some(cookie), start));
+    assert!(!guard.check_cookie(sender, None, start)); // Only asked for one
+    assert!(!guard.check_cookie(addr([10, 0, 0, 1], 4001), Some(cookie), start));
+    assert!(!guard.check_cookie(addr([10, 0, 0, 2], 4000), Some(cookie), start));
+
+    let next_interval = start + Duration::from_secs_f32(COOKIE_INTERVAL * 1.5);
+    assert!(guard.check_cookie(sender, Some(cookie), next_interval));
+    let expired = start + Duration::from_secs_f32(COOKIE_INTERVAL * 2.5);
+    assert!(!guard.check_cookie(sender, Some(cookie), expired));
+    assert_eq!(guard.get_stats().invalid_cookies, 3);
+    assert_ne!(FloodGuard::new().gen_cookie(sender, start), cookie); // Secret per server
+}
+
+#[test]
+fn handshake_floods_create_no_connections() {
+    let mut server = host(settings());
+    let server_addr = addr([127, 0, 0, 1], server.get_local_port());
+
+    // Handshakes that never come back with their cookie
+    let mut flooder = Peer::setup(TransportSetup::Direct { port: None }, NetConditions::default()).unwrap();
+    for i in 0..(UNKNOWN_SENDER_BURST as usize * 4) {
+        flooder.send_raw_packet(serialize_packet_unsigned(Packet::Handshake {
+            version: GameVersion::local(), name: format!("Flooder {}", i), spectator: false,
+            cookie: Some(i as u64), rejoin_token: None
+        }), server_addr, Delivery::ReliableOrdered(CONTROL_STREAM)).unwrap();
+    }
+    let start_time = Instant::now();
+    while start_time.elapsed() < Duration::from_millis(500) {
+        if let ServerEvent::PlayerConnect(..) = server.poll_received_packets().unwrap() {
+            panic!("Flooder got a connection");
+        }
+        flooder.poll_received_packets().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    flooder.shutdown().unwrap();
+    let stats = server.get_flood_stats();
+    assert!(stats.invalid_cookies >= UNKNOWN_SENDER_BURST as u64);
+    assert!(stats.rate_limited > 0);
+
+    // Once the flooder's address calmed down, players from it get in with one extra round trip
+    thread::sleep(Duration::from_secs_f32(UNKNOWN_SENDER_BURST / UNKNOWN_SENDER_REFILL_RATE));
+    let mut client = connect(&get_endpoint(&server), "Blackbeard");
+    let start_time = Instant::now();
+    loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Player did not connect");
+        if let ServerEvent::PlayerConnect(id, _) = server.poll_received_packets().unwrap() {
+            assert_eq!(id.name, "Blackbeard");
+            break
+        }
+        client.poll_received_packets().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(server.get_connection_count(), 1);
+}
+
+#[test]
+fn no_connections_once_ids_ran_out() {
+    // The new host took over a match that already handed out the last ID
+    let peer = Peer::setup(TransportSetup::Direct { port: None }, NetConditions::default()).unwrap();
+    let mut server = Server::migrate(peer, settings(), 0, vec![ID::new("Blackbeard".to_owned(), u16::MAX)],
+        Vec::new(), Vec::new());
+    let mut client = connect(&get_endpoint(&server), "AnneBonny");
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut client], 0), DisconnectReason::ServerBusy);
+    assert_eq!(server.get_connection_count(), 0);
+}
+
+#[test]
+fn other_versions_prove_their_address_before_being_refused() {
+    let mut server = host(settings());
+    let server_addr = addr([127, 0, 0, 1], server.get_local_port());
+    let mut sender = Peer::setup(TransportSetup::Direct { port: None }, NetConditions::default()).unwrap();
+    let mut version = GameVersion::local();
+    version.protocol += 1;
+    let send_handshake = |sender: &mut Peer, cookie| sender.send_raw_packet(serialize_packet_unsigned(
+        Packet::Handshake { version, name: "Mary Read".to_owned(), spectator: false, cookie, rejoin_token: None }
+    ), server_addr, Delivery::ReliableOrdered(CONTROL_STREAM)).unwrap();
+    let receive = |server: &mut Server, sender: &mut Peer| {
+        let start_time = Instant::now();
+        loop {
+            assert!(start_time.elapsed() < TIMEOUT, "Server did not answer");
+            server.poll_received_packets().unwrap();
+            if let Some(SocketEvent::Packet(packet)) = sender.poll_received_packets().unwrap() {
+                return deserialize_packet(packet.payload().to_vec()).unwrap().0
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    send_handshake(&mut sender, None);
+    let cookie = match receive(&mut server, &mut sender) {
+        Packet::HandshakeCookie { cookie } => cookie,
+        packet => panic!("Expected a cookie, got {:?}", packet)
+    };
+    send_handshake(&mut sender, Some(cookie));
+    assert!(matches!(receive(&mut server, &mut sender), Packet::PlayerDisconnect {
+        reason: DisconnectReason::Incompatible(..)
+    }));
+    sender.shutdown().unwrap();
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::{thread, time::{Duration, Instant}};
+use laminar::SocketEvent;
+use blackbeard::{ID, PlayerParams, game_settings::GameSettings, host_migration::{HostCandidate, MIGRATION_STEP_WINDOW, MigrationToken, elect_host, gen_migration_packet}, input_pool::InputPool, net_conditions::NetConditions, packet::{InputState, InputStep, Packet, deserialize_packet, serialize_packet_unsigned}, peer::Peer, rejoin::hash_rejoin_token, replay::Replay, server::{Server, ServerEvent}, transport::TransportSetup};
+use common::{TIMEOUT, settings};
+
+fn history(total_gens: u64) -> Replay {
+    let players = vec![PlayerParams::new(ID::new("Host".to_owned(), 0)),
+        PlayerParams::new(ID::new("Calico Jack".to_owned(), 1)),
+        PlayerParams::new(ID::new("Mary Read".to_owned(), 2))];
+    let mut history = Replay::new(7, players, GameSettings::default());
+    for gen in 1..=total_gens {
+        history.add_step(step(gen, gen % 2 == 0));
+    }
+    history
+}
+
+fn step(gen: u64, shoot: bool) -> InputStep {
+    let mut step = InputStep::new(Vec::new(), gen);
+    step.add_state(1, InputState::new(false, false, false, shoot, false, false, None, None));
+    step
+}
+
+fn migration_steps(history: &Replay) -> Vec<InputStep> {
+    match gen_migration_packet(&ID::new("Mary Read".to_owned(), 2), 0x7E57, history) {
+        Packet::Migrate { steps, .. } => steps,
+        packet => panic!("Unexpected migration packet {:?}", packet)
+    }
+}
+
+#[test]
+fn every_peer_elects_the_same_host() {
+    let candidates = vec![
+        HostCandidate { id: 3, addr: "10.0.0.3:4000".parse().unwrap() },
+        HostCandidate { id: 1, addr: "10.0.0.1:4000".parse().unwrap() },
+        HostCandidate { id: 2, addr: "10.0.0.2:4000".parse().unwrap() }
+    ];
+    let mut reversed = candidates.clone();
+    reversed.reverse();
+    assert_eq!(elect_host(&candidates).unwrap().id, 1);
+    assert_eq!(elect_host(&reversed), elect_host(&candidates));
+    assert!(elect_host(&[]).is_none());
+}
+
+#[test]
+fn new_host_takes_on_steps_it_missed() {
+    // The peer received three more steps of the previous host than the new one
+    let mut input_pool = InputPool::from_history(history(100), vec![1, 2]);
+    let steps = migration_steps(&history(103));
+    assert_eq!(steps.len(), MIGRATION_STEP_WINDOW);
+
+    assert!(input_pool.merge_steps(steps.clone(), true));
+    assert_eq!(input_pool.curr_gen, 103);
+    assert_eq!(input_pool.get_history().get_total_gens(), 103);
+    // Reporting the same steps again changes nothing
+    assert!(input_pool.merge_steps(steps, true));
+    assert_eq!(input_pool.curr_gen, 103);
+}
+
+#[test]
+fn diverging_peers_are_detected() {
+    let mut input_pool = InputPool::from_history(history(100), vec![1, 2]);
+    // Too late to take on new steps
+    assert!(!input_pool.merge_steps(migration_steps(&history(101)), false));
+    // Steps the new host never saw, with a gap before them
+    assert!(!input_pool.merge_steps(vec![step(103, false)], true));
+    // A step that contradicts the history
+    assert!(!input_pool.merge_steps(vec![step(99, true)], true));
+    assert_eq!(input_pool.curr_gen, 100);
+}
+
+#[test]
+fn migrating_peers_prove_who_they_are() {
+    let mary_read = ID::new("Mary Read".to_owned(), 2);
+    let peer = Peer::setup(TransportSetup::Direct { port: None }, NetConditions::default()).unwrap();
+    let mut server = Server::migrate(peer, settings(), 1, vec![mary_read.clone()], Vec::new(),
+        vec![MigrationToken { id: 2, token_hash: hash_rejoin_token(0x7E57) }]);
+    let server_addr = format!("127.0.0.1:{}", server.get_local_port()).parse().unwrap();
+    let mut sender = Peer::setup(TransportSetup::Direct { port: None }, NetConditions::default()).unwrap();
+    let send_migration = |sender: &mut Peer, rejoin_token, cookie, gen| {
+        let packet = Packet::Migrate { id: 2, name: mary_read.name.clone(), rejoin_token, cookie, gen,
+            steps: Vec::new() };
+        let delivery = packet.get_delivery();
+        sender.send_raw_packet(serialize_packet_unsigned(packet), server_addr, delivery).unwrap();
+    };
+
+    // Without a cookie, the new host only answers with one
+    send_migration(&mut sender, 0x7E57, None, 100);
+    let start_time = Instant::now();
+    let cookie = loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Server did not answer");
+        assert!(matches!(server.poll_received_packets().unwrap(), ServerEvent::Empty));
+        if let Some(SocketEvent::Packet(packet)) = sender.poll_received_packets().unwrap() {
+            match deserialize_packet(packet.payload().to_vec()).unwrap().0 {
+                Packet::HandshakeCookie { cookie } => break cookie,
+                packet => panic!("Expected a cookie, got {:?}", packet)
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+
+    // Knowing the ID and name is not enough to take over the ship
+    send_migration(&mut sender, 0x0BAD, Some(cookie), 101);
+    send_migration(&mut sender, 0x7E57, Some(cookie), 102);
+    let start_time = Instant::now();
+    let (id, gen) = loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Peer did not migrate");
+        if let ServerEvent::PlayerMigrate(id, gen, _) = server.poll_received_packets().unwrap() {
+            break (id, gen)
+        }
+        sender.poll_received_packets().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert_eq!((id, gen), (mary_read, 102));
+    assert_eq!(server.get_connection_count(), 1);
+    // The token carries over to the new host
+    assert_eq!(server.get_rejoin_token(2), Some(0x7E57));
+    sender.shutdown().unwrap();
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use std::time::{Duration, Instant};
+use blackbeard::{ID, PlayerParams, V2, client::ClientEvent, game_settings::GameSettings, input_pool::InputPool, input_validation::{FLAG_INPUT_STRIKES, InputViolation, MAX_INPUT_STRIKES, MAX_PURCHASES_PER_STEP, MapBounds, validate_input_state}, packet::{InputState, Packet}, peer::DisconnectReason, server::ServerEvent, ship_mod::ShipModType};
+use common::{TIMEOUT, connect, get_endpoint, host, settings, wait_for_connect};
+
+fn steer(pos: V2) -> InputState {
+    InputState::new(true, false, false, false, false, false, None, Some(pos))
+}
+
+fn purchase() -> InputState {
+    InputState::new(false, false, false, false, true, false, Some(ShipModType::Repair), None)
+}
+
+#[test]
+fn states_are_checked_against_the_map() {
+    let bounds = MapBounds::of_match(2);
+    assert!(validate_input_state(&steer(V2::new(500.0, 1200.0)), &bounds, None).is_ok());
+    assert!(validate_input_state(&steer(-V2::one() * 400.0), &bounds, None).is_ok()); // Off the edge
+    assert_eq!(validate_input_state(&steer(V2::new(1.0e7, 0.0)), &bounds, None),
+        Err(InputViolation::OutOfBounds));
+    assert_eq!(validate_input_state(&steer(V2::new(f32::NAN, 0.0)), &bounds, None),
+        Err(InputViolation::InvalidPosition));
+
+    let leave = InputState { disconnect: true, ..Default::default() };
+    assert_eq!(validate_input_state(&leave, &bounds, None), Err(InputViolation::ForgedDisconnect));
+    assert!(validate_input_state(&purchase(), &bounds, None).is_ok()); // Harbour unknown
+    assert!(validate_input_state(&purchase(), &bounds, Some(true)).is_ok());
+    assert_eq!(validate_input_state(&purchase(), &bounds, Some(false)),
+        Err(InputViolation::PurchaseOutsideHarbour));
+
+    // Only violations honest clients can't cause count as strikes
+    assert!(InputViolation::OutOfBounds.is_forged());
+    assert!(InputViolation::ForgedDisconnect.is_forged());
+    assert!(!InputViolation::PurchaseOutsideHarbour.is_forged());
+    assert!(!InputViolation::TooManyPurchases.is_forged());
+}
+
+#[test]
+fn purchases_are_limited_per_step() {
+    let players = vec![PlayerParams::new(ID::new("Host".to_owned(), 0)),
+        PlayerParams::new(ID::new("Blackbeard".to_owned(), 1))];
+    let mut input_pool = InputPool::new(players, 0xC0FFEE, GameSettings::default());
+    for _ in 0..MAX_PURCHASES_PER_STEP {
+        assert!(input_pool.validate_state(1, &purchase(), Some(true)).is_ok());
+    }
+    assert_eq!(input_pool.validate_state(1, &purchase(), Some(true)), Err(InputViolation::TooManyPurchases));
+    assert!(input_pool.validate_state(0, &purchase(), Some(true)).is_ok()); // Limits are per player
+    input_pool.flush_states();
+    assert!(input_pool.validate_state(1, &purchase(), Some(true)).is_ok());
+}
+
+#[test]
+fn repeat_offenders_are_flagged_then_kicked() {
+    let mut server = host(settings());
+    let endpoint = get_endpoint(&server);
+    // The host connects first, so that they get the host ID
+    let mut host = connect(&endpoint, "Host");
+    assert_eq!(wait_for_connect(&mut server, &mut [&mut host]), 0);
+    let mut pirate = connect(&endpoint, "Blackbeard");
+    let pirate_id = wait_for_connect(&mut server, &mut [&mut host, &mut pirate]);
+
+    for strike in 1..MAX_INPUT_STRIKES {
+        assert!(!server.on_receive_invalid_input(pirate_id, InputViolation::OutOfBounds).unwrap());
+        assert_eq!(server.is_flagged(pirate_id), strike >= FLAG_INPUT_STRIKES);
+    }
+    assert!(server.on_receive_invalid_input(pirate_id, InputViolation::OutOfBounds).unwrap());
+    assert!(server.get_conn_by_id(pirate_id).is_none());
+
+    // The host heard about it first, the offender gets told why they were kicked
+    let (mut host_notice, mut reason) = (None, None);
+    let start_time = Instant::now();
+    while host_notice.is_none() || reason.is_none() {
+        assert!(start_time.elapsed() < TIMEOUT, "Expected packets did not arrive");
+        if let ServerEvent::PlayerConnect(..) = server.poll_received_packets().unwrap() {
+            panic!("Nobody else should connect");
+        }
+        if let ClientEvent::ReceivePacket(_, Packet::Notice { message }) = host.poll_received_packets().unwrap() {
+            host_notice = Some(message);
+        }
+        if let ClientEvent::Disconnect(disconnect_reason) = pirate.poll_received_packets().unwrap() {
+            reason = Some(disconnect_reason);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(host_notice.unwrap().starts_with("Blackbeard keeps sending invalid input"));
+    assert_eq!(reason, Some(DisconnectReason::InvalidInput));
+    assert!(pirate.describe_disconnect(DisconnectReason::InvalidInput).contains("outside the map"));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use blackbeard::{client::{Client, ClientEvent}, lobby_access::{check_password_proof, gen_password_proof}, net_conditions::NetConditions, net_settings::NetSettings, peer::DisconnectReason, server::ServerEvent};
+use common::{get_endpoint, host, poll_until, settings};
+
+// Polls both ends until the server admits the client or the client gets refused
+fn connect(settings: NetSettings, name: &str, password: Option<&str>) -> Result<(), DisconnectReason> {
+    let mut server = host(settings);
+    let mut client = Client::connect(&get_endpoint(&server), name.to_owned(), false,
+        password.map(|p| p.to_owned()), None, NetConditions::default()).unwrap();
+    let result = poll_until(&mut server, &mut [&mut client], |server_event, client_event| {
+        match (server_event, client_event) {
+            (Some(ServerEvent::PlayerConnect(id, _)), _) => {
+                assert_eq!(id.name, name);
+                Some(Ok(()))
+            },
+            (_, Some((_, ClientEvent::Disconnect(reason)))) => Some(Err(reason)),
+            _ => None
+        }
+    });
+    server.shutdown().unwrap();
+    result
+}
+
+fn protected_settings() -> NetSettings {
+    let mut settings = settings();
+    settings.password = Some("pieces of eight".to_owned());
+    settings
+}
+
+#[test]
+fn proofs_depend_on_salt_nonce_and_password() {
+    let proof = gen_password_proof(7, 3, "pieces of eight");
+    assert!(check_password_proof(7, 3, "pieces of eight", &proof));
+    assert!(!check_password_proof(8, 3, "pieces of eight", &proof));
+    assert!(!check_password_proof(7, 4, "pieces of eight", &proof));
+    assert!(!check_password_proof(7, 3, "pieces of nine", &proof));
+}
+
+#[test]
+fn allow_list_only_admits_invited_names() {
+    let mut settings = NetSettings::default();
+    assert!(settings.is_invited("BlackCaesar"));
+    settings.set_allow_list(" AnneBonny, MaryRead,,");
+    assert_eq!(settings.allow_list, vec!["AnneBonny".to_owned(), "MaryRead".to_owned()]);
+    assert!(settings.is_invited("MaryRead"));
+    assert!(!settings.is_invited("BlackCaesar"));
+}
+
+#[test]
+fn right_password_is_admitted() {
+    assert_eq!(connect(protected_settings(), "AnneBonny", Some("pieces of eight")), Ok(()));
+}
+
+#[test]
+fn wrong_or_missing_password_is_refused() {
+    assert_eq!(connect(protected_settings(), "AnneBonny", Some("pieces of nine")),
+        Err(DisconnectReason::WrongPassword));
+    assert_eq!(connect(protected_settings(), "AnneBonny", None),
+        Err(DisconnectReason::WrongPassword));
+}
+
+#[test]
+fn uninvited_names_are_refused() {
+    let mut settings = settings();
+    settings.set_allow_list("MaryRead");
+    assert_eq!(connect(settings, "AnneBonny", None), Err(DisconnectReason::NotInvited));
+}
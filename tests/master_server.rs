@@ -0,0 +1,142 @@
+mod common;
+
+use std::{net::{SocketAddr, UdpSocket}, time::{Duration, Instant}};
+use blackbeard::{discovery::LobbyInfo, game_settings::GameMode, master_server::{ListedServer, MasterMessage, MasterServer, ServerBrowser, answer_ping, deserialize_master_message, serialize_master_message}, server::Server, version::GameVersion};
+use common::{TIMEOUT, host, settings};
+
+fn lobby_info(name: &str, port: u16) -> LobbyInfo {
+    LobbyInfo {
+        name: name.to_owned(), port, version: GameVersion::local(), mode: GameMode::Raid(500),
+        players: 1, max_players: 4, password: false, open: true
+    }
+}
+
+// Runs everything on loopback until the check passes
+fn poll_until(master: &mut MasterServer, server: Option<&mut Server>, browser: Option<&mut ServerBrowser>,
+    check: impl Fn(&MasterServer, Option<&ServerBrowser>) -> bool) {
+    let (mut server, mut browser) = (server, browser);
+    let start_time = Instant::now();
+    loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Expected state was not reached");
+        master.poll().unwrap();
+        if let Some(server) = server.as_mut() {
+            server.poll_received_packets().unwrap();
+        }
+        if let Some(browser) = browser.as_mut() {
+            browser.update().unwrap();
+        }
+        if check(master, browser.as_deref()) {
+            return
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn master_messages_survive_encoding() {
+    let message = MasterMessage::ServerList { servers: vec![
+        ListedServer { addr: "192.168.0.17:22081".parse().unwrap(), info: lobby_info("Nassau", 22081) },
+        ListedServer { addr: "[2001:db8::1]:22082".parse().unwrap(), info: lobby_info("Tortuga", 22082) }
+    ]};
+    let bytes = serialize_master_message(&message);
+    assert_eq!(deserialize_master_message(&bytes).unwrap(), message);
+    assert!(deserialize_master_message(&bytes[..bytes.len() - 3]).is_err());
+    assert!(deserialize_master_message(b"BBDQ").is_err());
+}
+
+#[test]
+fn browsers_find_and_ping_registered_hosts() {
+    let mut master = MasterServer::bind(0).unwrap();
+    let master_addr = format!("127.0.0.1:{}", master.get_local_port().unwrap());
+    let mut settings = settings();
+    settings.lobby_name = "Port Royal".to_owned();
+    settings.master_server = Some(master_addr.clone());
+    let mut server = host(settings);
+    // Listed once the game port answered the master's ping
+    poll_until(&mut master, Some(&mut server), None, |master, _| master.get_servers().len() == 1);
+    let mut browser = ServerBrowser::new(&master_addr).unwrap();
+
+    poll_until(&mut master, Some(&mut server), Some(&mut browser), |_, browser| browser.unwrap()
+        .get_servers().iter().any(|server| server.ping.is_some()));
+    let listed = browser.get_servers()[0].clone();
+    assert_eq!(listed.addr, SocketAddr::from(([127, 0, 0, 1], server.get_local_port())));
+    assert_eq!(listed.info.name, "Port Royal");
+    assert_eq!(listed.get_free_slots(), 4);
+    assert!(listed.ping.unwrap() < Duration::from_secs(1));
+
+    server.shutdown().unwrap();
+    poll_until(&mut master, None, None, |master, _| master.get_servers().is_empty());
+}
+
+// Game sockets only answer the master's pings
+fn answer_pings(game_sockets: &mut [UdpSocket]) {
+    let mut buffer = [0; 64];
+    for socket in game_sockets.iter_mut() {
+        while let Ok((len, addr)) = socket.recv_from(&mut buffer) {
+            answer_ping(socket, &buffer[..len], addr);
+        }
+    }
+}
+
+#[test]
+fn master_limits_servers_per_ip() {
+    let mut master = MasterServer::bind(0).unwrap();
+    let master_addr = format!("127.0.0.1:{}", master.get_local_port().unwrap());
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut game_sockets = (0..10).map(|_| {
+        let game_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        game_socket.set_nonblocking(true).unwrap();
+        game_socket
+    }).collect::<Vec<_>>();
+    for game_socket in game_sockets.iter() {
+        let port = game_socket.local_addr().unwrap().port();
+        let name = format!("Lobby {} with a name far longer than anyone should need", port);
+        socket.send_to(&serialize_master_message(&MasterMessage::Heartbeat {
+            info: lobby_info(&name, port)
+        }), &master_addr).unwrap();
+    }
+    let start_time = Instant::now();
+    while master.get_servers().len() < 8 {
+        assert!(start_time.elapsed() < TIMEOUT, "Expected state was not reached");
+        master.poll().unwrap();
+        answer_pings(&mut game_sockets);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    master.poll().unwrap();
+    assert_eq!(master.get_servers().len(), 8);
+    assert!(master.get_servers().iter().all(|server| server.info.name.chars().count() == 32));
+}
+
+#[test]
+fn master_answers_unproven_addresses_with_one_datagram() {
+    let mut master = MasterServer::bind(0).unwrap();
+    let master_addr = format!("127.0.0.1:{}", master.get_local_port().unwrap());
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    // Nobody answers the pings for this port, so it is never listed
+    socket.send_to(&serialize_master_message(&MasterMessage::Heartbeat {
+        info: lobby_info("Spoofed", 9)
+    }), &master_addr).unwrap();
+    let start_time = Instant::now();
+    while start_time.elapsed() < Duration::from_millis(200) {
+        master.poll().unwrap();
+    }
+    assert!(master.get_servers().is_empty());
+
+    socket.send_to(&serialize_master_message(&MasterMessage::Query { cookie: None }),
+        &master_addr).unwrap();
+    master.poll().unwrap();
+    let mut buffer = [0; 2048];
+    let len = socket.recv(&mut buffer).unwrap();
+    let cookie = match deserialize_master_message(&buffer[..len]).unwrap() {
+        MasterMessage::QueryCookie { cookie } => cookie,
+        message => panic!("Expected a cookie, got {:?}", message)
+    };
+    socket.send_to(&serialize_master_message(&MasterMessage::Query { cookie: Some(cookie) }),
+        &master_addr).unwrap();
+    master.poll().unwrap();
+    let len = socket.recv(&mut buffer).unwrap();
+    assert_eq!(deserialize_master_message(&buffer[..len]).unwrap(),
+        MasterMessage::ServerList { servers: Vec::new() });
+}
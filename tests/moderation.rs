@@ -0,0 +1,97 @@
+mod common;
+
+use std::{net::IpAddr, time::Instant};
+use blackbeard::{client::Client, moderation::{Ban, BanList, HostCommand}, net_conditions::NetConditions, packet::Packet,
+    peer::DisconnectReason};
+use common::{TIMEOUT, connect, get_endpoint, host, settings, wait_for_connect, wait_for_refusal};
+
+#[test]
+fn host_commands_are_parsed() {
+    assert_eq!(HostCommand::parse("/kick Blackbeard Stop ramming"), Ok(HostCommand::Kick {
+        name: "Blackbeard".to_owned(), message: "Stop ramming".to_owned()
+    }));
+    assert_eq!(HostCommand::parse("/PERMABAN Blackbeard"), Ok(HostCommand::Ban {
+        name: "Blackbeard".to_owned(), message: String::new(), persistent: true
+    }));
+    assert_eq!(HostCommand::parse("/lock"), Ok(HostCommand::Lock(true)));
+    assert!(HostCommand::parse("/kick").is_err());
+    assert!(HostCommand::parse("/plunder Blackbeard").is_err());
+}
+
+#[test]
+fn persistent_bans_survive_reloads() {
+    let path = std::env::temp_dir().join(format!("blackbeard-bans-{}.cfg", std::process::id()));
+    let ip = "192.168.0.17".parse::<IpAddr>().unwrap();
+    let mut bans = BanList::load(Some(path.clone()));
+    bans.add(Ban { name: "Blackbeard".to_owned(), ip, persistent: true }).unwrap();
+    bans.add(Ban { name: "CalicoJack".to_owned(), ip: "10.0.0.2".parse().unwrap(), persistent: false })
+        .unwrap();
+
+    let mut reloaded = BanList::load(Some(path.clone()));
+    assert_eq!(reloaded.get_bans().len(), 1);
+    assert!(reloaded.is_banned("Blackbeard", "10.0.0.3".parse().unwrap()));
+    assert!(reloaded.is_banned("StedeBonnet", ip)); // Same address, other name
+    assert!(!reloaded.is_banned("CalicoJack", "10.0.0.2".parse().unwrap()));
+
+    assert!(reloaded.remove("Blackbeard").unwrap());
+    assert!(BanList::load(Some(path.clone())).get_bans().is_empty());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn banned_players_and_locked_lobbies_are_refused() {
+    let mut server = host(settings());
+    let endpoint = get_endpoint(&server);
+    let mut host = connect(&endpoint, "Host");
+    assert_eq!(wait_for_connect(&mut server, &mut [&mut host]), 0);
+    let mut pirate = connect(&endpoint, "Blackbeard");
+    let pirate_id = wait_for_connect(&mut server, &mut [&mut host, &mut pirate]);
+
+    host.send_packet(Packet::ChatMessage { message: "/ban Blackbeard Stop ramming".to_owned() })
+        .unwrap();
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut host, &mut pirate], 1),
+        DisconnectReason::Banned);
+    assert_eq!(pirate.describe_disconnect(DisconnectReason::Banned),
+        "Banned by the host (Stop ramming)");
+    assert!(server.get_conn_by_id(pirate_id).is_none());
+
+    let mut returning = connect(&endpoint, "Blackbeard");
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut host, &mut returning], 1),
+        DisconnectReason::Banned);
+
+    host.send_packet(Packet::ChatMessage { message: "/lock".to_owned() }).unwrap();
+    let start_time = Instant::now();
+    while !server.is_locked() {
+        assert!(start_time.elapsed() < TIMEOUT);
+        server.poll_received_packets().unwrap();
+    }
+    let mut newcomer = connect(&endpoint, "AnneBonny");
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut host, &mut newcomer], 1),
+        DisconnectReason::LobbyLocked);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn full_lobbies_and_running_matches_are_refused() {
+    let mut settings = settings();
+    settings.max_players = 1;
+    settings.max_spectators = 0;
+    let mut server = host(settings);
+    let endpoint = get_endpoint(&server);
+    let mut host = connect(&endpoint, "Host");
+    assert_eq!(wait_for_connect(&mut server, &mut [&mut host]), 0);
+
+    let mut pirate = connect(&endpoint, "Blackbeard");
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut host, &mut pirate], 1),
+        DisconnectReason::LobbyFull);
+    let mut spectator = Client::connect(&endpoint, "AnneBonny".to_owned(), true, None, None,
+        NetConditions::default()).unwrap();
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut host, &mut spectator], 1),
+        DisconnectReason::SpectatorsFull);
+
+    server.set_accepting_connections(false);
+    let mut latecomer = connect(&endpoint, "CalicoJack");
+    assert_eq!(wait_for_refusal(&mut server, &mut [&mut host, &mut latecomer], 1),
+        DisconnectReason::MatchInProgress);
+    server.shutdown().unwrap();
+}
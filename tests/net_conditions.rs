@@ -0,0 +1,74 @@
+use std::{io::ErrorKind, net::SocketAddr, thread, time::{Duration, Instant}};
+use blackbeard::net_conditions::{ConditionedSocket, NetConditions};
+use laminar::DatagramSocket;
+
+fn bind(conditions: NetConditions) -> ConditionedSocket {
+    ConditionedSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)), conditions).unwrap()
+}
+
+// Polls like the peer's poll thread until nothing arrived for a while
+fn receive_all(socket: &mut ConditionedSocket, idle_time: Duration) -> Vec<(Vec<u8>, Instant)> {
+    let mut received = Vec::new();
+    let mut buffer = [0; 1500];
+    let mut last_receive_time = Instant::now();
+    while last_receive_time.elapsed() < idle_time {
+        match socket.receive_packet(&mut buffer) {
+            Ok((payload, _)) => {
+                last_receive_time = Instant::now();
+                received.push((payload.to_vec(), last_receive_time));
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => panic!("{}", e)
+        }
+    }
+    received
+}
+
+#[test]
+fn conditions_are_inactive_by_default() {
+    assert!(!NetConditions::default().is_active());
+    assert!(NetConditions { loss: 0.05, ..Default::default() }.is_active());
+}
+
+#[test]
+fn delayed_datagrams_arrive_late_and_in_order() {
+    let mut sender = bind(NetConditions { delay: 100, ..Default::default() });
+    let mut receiver = bind(NetConditions::default());
+    let receiver_addr = receiver.local_addr().unwrap();
+    let send_time = Instant::now();
+    for i in 0..10u8 {
+        sender.send_packet(&receiver_addr, &[i]).unwrap();
+    }
+    // Delayed datagrams are only released while the sender is polled
+    let poll_thread = thread::spawn(move || {
+        let mut buffer = [0; 1500];
+        while send_time.elapsed() < Duration::from_millis(400) {
+            let _ = sender.receive_packet(&mut buffer);
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+    let received = receive_all(&mut receiver, Duration::from_millis(500));
+    poll_thread.join().unwrap();
+    assert_eq!(received.iter().map(|(payload, _)| payload[0]).collect::<Vec<_>>(),
+        (0..10).collect::<Vec<_>>());
+    assert!(received.iter().all(|(_, time)| *time - send_time >= Duration::from_millis(100)));
+}
+
+#[test]
+fn lost_and_duplicated_datagrams_follow_the_rates() {
+    let mut sender = bind(NetConditions::default());
+    let mut receiver = bind(NetConditions { loss: 0.2, duplication: 0.2, ..Default::default() });
+    let receiver_addr = receiver.local_addr().unwrap();
+    let mut received = Vec::new();
+    for i in 0..500u16 {
+        sender.send_packet(&receiver_addr, &i.to_le_bytes()).unwrap();
+        if i % 50 == 49 { // Don't overflow the receive buffer
+            received.extend(receive_all(&mut receiver, Duration::from_millis(20)));
+        }
+    }
+    let mut unique = received.iter().map(|(payload, _)| payload.clone()).collect::<Vec<_>>();
+    unique.dedup();
+    // 400 kept, of which 80 duplicated, with generous margins
+    assert!(unique.len() > 300 && unique.len() < 480, "{} unique", unique.len());
+    assert!(received.len() - unique.len() > 30, "{} duplicates", received.len() - unique.len());
+}
@@ -0,0 +1,241 @@
+use std::{collections::HashSet, net::SocketAddr};
+use blackbeard::{ID, PlayerParams, V2, connection_quality::ConnectionQuality, entity::EntityType, game_settings::{GameMode, GameSettings, Weather}, host_migration::{HostCandidate, MigrationToken}, packet::{GamePhase, InputState, InputStep, Packet, deserialize_packet, deserialize_packet_unsigned, peek_handshake_version, serialize_packet, serialize_packet_unsigned}, peer::DisconnectReason, rejoin::gen_rejoin_packets, replay::Replay, ship_data::ShipType, ship_mod::ShipModType, state_digest::{DigestField, DigestValue, EntityDigest, StateDigestPart}, sync_checker::SyncState, version::GameVersion};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro128Plus;
+
+const FUZZ_SEED: u64 = 0xF022_B1AC;
+const MUTATIONS_PER_PACKET: usize = 2000;
+const PACKET_TYPE_COUNT: u8 = 26;
+const MAX_PACKET_SIZE: usize = 16 * 1024;
+
+fn sample_packets() -> Vec<Packet> {
+    let mut player = PlayerParams::new(ID::new("Calico Jack".to_owned(), 1));
+    player.ship_type = ShipType::Schooner;
+    let players = vec![PlayerParams::new(ID::new("Host".to_owned(), 0)), player.clone()];
+    let mut step = InputStep::new(Vec::new(), 42);
+    step.add_state(0, InputState::new(true, false, true, false, false, false, None,
+        Some(V2::new(120.5, -300.0))));
+    step.add_state(1, InputState::new(false, false, false, false, true, false,
+        Some(ShipModType::CannonReloadUpgrade), None));
+    let mut settings = GameSettings::new(GameMode::Deathmatch(10), Weather::Stormy);
+    settings.bots = 2;
+    let entity = EntityDigest {
+        index: (3, 1), entity_type: EntityType::Ship, name: "Anne Bonny".to_owned(),
+        fields: vec![
+            (DigestField::PosX, DigestValue::F32(10.5)),
+            (DigestField::Destroy, DigestValue::Bool(false)),
+            (DigestField::CannonReload(2), DigestValue::U16(3)),
+            (DigestField::Balance, DigestValue::U32(250)),
+            (DigestField::Shooter, DigestValue::Index(7, 0))
+        ]
+    };
+
+    vec![
+        Packet::Handshake { version: GameVersion::local(), name: "Mary Read".to_owned(),
+            spectator: false, cookie: None, rejoin_token: Some(0x70CE) },
+        Packet::Handshake { version: GameVersion::local(), name: "Zheng Yi Sao".to_owned(),
+            spectator: true, cookie: Some(0x5EA_D06), rejoin_token: None },
+        Packet::HandshakeReply { players: vec![PlayerParams::new(ID::new("Host".to_owned(), 0)),
+            player], spectators: vec![ID::new("Zheng Yi Sao".to_owned(), 2)], host: Some(0), rejoin_token: u64::MAX },
+        Packet::HandshakeReply { players: Vec::new(), spectators: Vec::new(), host: None, rejoin_token: 0 },
+        Packet::PlayerConnect { name: "Ching Shih".to_owned(), spectator: false },
+        Packet::PlayerConnect { name: "Zheng Yi Sao".to_owned(), spectator: true },
+        Packet::PlayerDisconnect { reason: DisconnectReason::Incompatible(GameVersion::local()) },
+        Packet::PlayerDisconnect { reason: DisconnectReason::Timeout },
+        Packet::PlayerDisconnect { reason: DisconnectReason::WrongPassword },
+        Packet::ChatMessage { message: "Yo ho, yo ho! ☠".to_owned() },
+        Packet::Input { seq: 7, states: vec![InputState::default(), InputState::new(true, true, false,
+            true, false, false, None, Some(V2::new(-1.0, 2.0)))] },
+        Packet::Input { seq: 1, states: vec![InputState::default()] },
+        Packet::InputStep { steps: vec![InputStep::new(Vec::new(), 41), step.clone()] },
+        Packet::Game { phase: GamePhase::World(0xDEAD_BEEF) },
+        Packet::Game { phase: GamePhase::Score },
+        Packet::Sync { state: SyncState::new(30, 0x1234_5678_9ABC_DEF0) },
+        Packet::Selection { mode: true, ship: Some(ShipType::Galleon), settings: None },
+        Packet::Selection { mode: false, ship: None, settings: Some(settings) },
+        Packet::DigestRequest { gen: Some(120) },
+        Packet::DigestRequest { gen: None },
+        Packet::DigestSummary { states: vec![SyncState::new(10, 1), SyncState::new(20, 2)] },
+        Packet::Digest { part: StateDigestPart::new(120, 0, 1, vec![entity]) },
+        Packet::Rejoin { world_seed: 0xC0FFEE, settings, players, host: Some(3), gen: 42 },
+        Packet::RejoinSteps { steps: vec![step.clone(), InputStep::new(Vec::new(), 43)] },
+        Packet::HostCandidates { host: Some(0), candidates: vec![
+            HostCandidate { id: 1, addr: "192.168.0.17:50123".parse::<SocketAddr>().unwrap() },
+            HostCandidate { id: 2, addr: "[2001:db8::1]:22081".parse::<SocketAddr>().unwrap() }
+        ], tokens: vec![
+            MigrationToken { id: 1, token_hash: 0x7E57_AB1E },
+            MigrationToken { id: 5, token_hash: u64::MAX }
+        ] },
+        Packet::HostCandidates { host: None, candidates: Vec::new(), tokens: Vec::new() },
+        Packet::Migrate { id: 1, name: "Calico Jack".to_owned(), rejoin_token: 0x7E57, cookie: None, gen: 42,
+            steps: vec![step.clone()] },
+        Packet::Migrate { id: 1, name: "Calico Jack".to_owned(), rejoin_token: 0x7E57, cookie: Some(u64::MAX),
+            gen: 42, steps: vec![step] },
+        Packet::StepsRequest { gen: 40 },
+        Packet::Challenge { salt: 0x5EA5_0A17 },
+        Packet::ChallengeAnswer { nonce: 0x0DD_5EED, proof: [0xB1; 32] },
+        Packet::Kick { reason: DisconnectReason::Banned, message: "Stop ramming".to_owned() },
+        Packet::Kick { reason: DisconnectReason::InvalidInput, message: "Forged disconnect".to_owned() },
+        Packet::PlayerDisconnect { reason: DisconnectReason::ServerBusy },
+        Packet::PlayerDisconnect { reason: DisconnectReason::LobbyFull },
+        Packet::Notice { message: "You are muted for another 12s.".to_owned() },
+        Packet::Ping { seq: 17 },
+        Packet::Pong { seq: u32::MAX },
+        Packet::QualityTable { entries: vec![
+            ConnectionQuality { id: 0, rtt: 2, jitter: 0, loss: 0 },
+            ConnectionQuality { id: 3, rtt: 310, jitter: 45, loss: 12 }
+        ] },
+        Packet::HandshakeCookie { cookie: u64::MAX }
+    ]
+}
+
+#[test]
+fn samples_cover_every_packet_type() {
+    let types = sample_packets().iter().map(|p| p.to_num()).collect::<HashSet<_>>();
+    assert_eq!(types.len(), PACKET_TYPE_COUNT as usize);
+}
+
+#[test]
+fn every_packet_round_trips() {
+    for packet in sample_packets().into_iter() {
+        let bytes = serialize_packet(packet.clone(), 7);
+        let (decoded, sender) = deserialize_packet(bytes.clone())
+            .unwrap_or_else(|e| panic!("Failed to decode {:?}: {:?}", packet, e));
+        assert_eq!(sender, 7);
+        assert_eq!(serialize_packet(decoded, sender), bytes, "{:?} changed in transit", packet);
+
+        let bytes = serialize_packet_unsigned(packet.clone());
+        let decoded = deserialize_packet_unsigned(bytes.clone()).unwrap();
+        assert_eq!(serialize_packet_unsigned(decoded), bytes);
+    }
+}
+
+#[test]
+fn truncated_packets_are_rejected() {
+    for packet in sample_packets().into_iter() {
+        let bytes = serialize_packet_unsigned(packet.clone());
+        for len in 0..bytes.len() {
+            assert!(deserialize_packet_unsigned(bytes[..len].to_vec()).is_err(),
+                "{:?} truncated to {} bytes was accepted", packet, len);
+        }
+    }
+}
+
+#[test]
+fn trailing_bytes_are_rejected() {
+    for packet in sample_packets().into_iter() {
+        let mut bytes = serialize_packet_unsigned(packet.clone());
+        bytes.push(0);
+        assert!(deserialize_packet_unsigned(bytes).is_err(), "{:?} had a trailing byte", packet);
+    }
+}
+
+#[test]
+fn unknown_discriminants_are_rejected() {
+    for type_num in PACKET_TYPE_COUNT..=u8::MAX {
+        assert!(deserialize_packet_unsigned(vec![type_num, 0, 0, 0, 0]).is_err());
+    }
+    // Disconnect reason, game phase, ship type and weather out of range
+    assert!(deserialize_packet_unsigned(vec![3, 200]).is_err());
+    assert!(deserialize_packet_unsigned(vec![7, 2]).is_err());
+    assert!(deserialize_packet_unsigned(vec![9, 1, 3]).is_err());
+    assert!(deserialize_packet_unsigned(vec![9, 0, 0, 0, 0, 0, 0, 9, 0]).is_err());
+}
+
+#[test]
+fn oversized_lengths_are_rejected() {
+    // Chat message and digest summary claiming u32::MAX elements in a tiny datagram
+    assert!(deserialize_packet_unsigned(vec![4, 0xFF, 0xFF, 0xFF, 0xFF, b'a']).is_err());
+    assert!(deserialize_packet_unsigned(vec![11, 0xFF, 0xFF, 0xFF, 0xFF, 0]).is_err());
+    // Invalid UTF-8
+    assert!(deserialize_packet_unsigned(vec![2, 2, 0, 0, 0, 0xC3, 0x28]).is_err());
+}
+
+#[test]
+fn handshake_version_survives_layout_changes() {
+    let mut old_version = GameVersion::local();
+    old_version.protocol -= 1;
+    let mut bytes = serialize_packet_unsigned(Packet::Handshake {
+        version: old_version, name: "Mary Read".to_owned(), spectator: false, cookie: None,
+        rejoin_token: None
+    });
+    // An older handshake without the rejoin flag
+    bytes.pop();
+    assert!(deserialize_packet_unsigned(bytes.clone()).is_err());
+    assert!(peek_handshake_version(&bytes) == Some(old_version));
+    assert!(peek_handshake_version(&[4, 0, 0, 0, 0]).is_none());
+}
+
+#[test]
+fn mutated_packets_never_panic() {
+    let mut rng = Xoshiro128Plus::seed_from_u64(FUZZ_SEED);
+    for packet in sample_packets().into_iter() {
+        let bytes = serialize_packet(packet, 1);
+        for _ in 0..MUTATIONS_PER_PACKET {
+            let mut mutated = bytes.clone();
+            for _ in 0..rng.gen_range(1..=4) {
+                let i = rng.gen_range(0..mutated.len());
+                match rng.gen_range(0..3) {
+                    0 => mutated[i] = rng.gen(),
+                    1 => { mutated.remove(i); },
+                    _ => mutated.insert(i, rng.gen())
+                }
+                if mutated.is_empty() {
+                    break
+                }
+            }
+            // Either outcome is fine, as long as decoding returns
+            let _ = deserialize_packet(mutated);
+        }
+    }
+}
+
+#[test]
+fn random_datagrams_never_panic() {
+    let mut rng = Xoshiro128Plus::seed_from_u64(FUZZ_SEED);
+    for _ in 0..20000 {
+        let len = rng.gen_range(0..64);
+        let mut bytes = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+        // Point most datagrams at a valid packet type, so decoding gets past the first byte
+        if let Some(type_num) = bytes.first_mut() {
+            *type_num %= PACKET_TYPE_COUNT + 1;
+        }
+        let _ = deserialize_packet_unsigned(bytes);
+    }
+}
+
+#[test]
+fn rejoin_packets_carry_every_step() {
+    let players = vec![PlayerParams::new(ID::new("Host".to_owned(), 0)),
+        PlayerParams::new(ID::new("Calico Jack".to_owned(), 1))];
+    let mut history = Replay::new(7, players, GameSettings::default());
+    for gen in 1..=5000 {
+        let mut step = InputStep::new(Vec::new(), gen);
+        for id in 0..2 {
+            step.add_state(id, InputState::new(true, false, true, true, false, false, None,
+                Some(V2::new(gen as f32, -(gen as f32)))));
+        }
+        history.add_step(step);
+    }
+
+    let mut next_gen = 1;
+    for (i, packet) in gen_rejoin_packets(&history, Some(2)).into_iter().enumerate() {
+        let bytes = serialize_packet(packet, 1);
+        assert!(bytes.len() < MAX_PACKET_SIZE, "Rejoin packet {} has {} bytes", i, bytes.len());
+        match deserialize_packet(bytes).unwrap().0 {
+            Packet::Rejoin { world_seed, host, gen, .. } if i == 0 => {
+                assert_eq!(world_seed, 7);
+                assert_eq!(host, Some(2));
+                assert_eq!(gen, 5000);
+            },
+            Packet::RejoinSteps { steps } if i > 0 => {
+                for step in steps.iter() {
+                    assert_eq!(step.gen, next_gen);
+                    next_gen += 1;
+                }
+            },
+            packet => panic!("Unexpected rejoin packet {:?}", packet)
+        }
+    }
+    assert_eq!(next_gen, 5001);
+}
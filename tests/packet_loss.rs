@@ -0,0 +1,73 @@
+use blackbeard::{ID, PlayerParams, game_settings::GameSettings, input_pool::{InputPool, REDUNDANT_INPUT_STEPS}, packet::{GamePhase, InputState, InputStep, Packet}, peer::Delivery, rejoin::gen_missed_steps_packets, replay::Replay, ship_mod::ShipModType};
+
+fn input_pool() -> InputPool {
+    let players = vec![PlayerParams::new(ID::new("Host".to_owned(), 0)),
+        PlayerParams::new(ID::new("Calico Jack".to_owned(), 1))];
+    InputPool::new(players, 7, GameSettings::default())
+}
+
+#[test]
+fn only_lockstep_packets_are_sent_unreliably() {
+    let unreliable = [
+        Packet::Input { seq: 1, states: vec![InputState::default()] },
+        Packet::InputStep { steps: vec![InputStep::new(Vec::new(), 1)] }
+    ];
+    for packet in unreliable.iter() {
+        assert!(matches!(packet.get_delivery(), Delivery::UnreliableSequenced(..)), "{:?}", packet);
+    }
+    let reliable = [
+        Packet::ChatMessage { message: "Ahoy".to_owned() },
+        Packet::Game { phase: GamePhase::Score },
+        Packet::RejoinSteps { steps: vec![InputStep::new(Vec::new(), 1)] },
+        Packet::StepsRequest { gen: 1 }
+    ];
+    for packet in reliable.iter() {
+        assert!(matches!(packet.get_delivery(), Delivery::ReliableOrdered(..)), "{:?}", packet);
+    }
+    // Lost steps must not hold back inputs, and the other way round
+    assert_ne!(unreliable[0].get_delivery(), unreliable[1].get_delivery());
+}
+
+#[test]
+fn purchases_survive_redundant_states() {
+    let mut input_pool = input_pool();
+    // The packet carrying the purchase got lost, the next one repeats it along with a later state
+    input_pool.add_state(1, InputState::new(false, false, false, false, true, false,
+        Some(ShipModType::CannonReloadUpgrade), None));
+    input_pool.add_state(1, InputState::new(true, false, false, false, false, false, None, None));
+    let step = input_pool.flush_states();
+    let (_, state) = step.states.iter().find(|(id, _)| *id == 1).unwrap();
+    assert!(state.buy_mod && state.rmb);
+    assert!(state.mod_type == Some(ShipModType::CannonReloadUpgrade));
+}
+
+#[test]
+fn step_packets_repeat_the_latest_steps() {
+    let mut input_pool = input_pool();
+    input_pool.flush_states();
+    assert_eq!(input_pool.get_latest_steps(REDUNDANT_INPUT_STEPS).len(), 1);
+    for _ in 0..10 {
+        input_pool.flush_states();
+    }
+    let gens = input_pool.get_latest_steps(REDUNDANT_INPUT_STEPS).iter()
+        .map(|step| step.gen)
+        .collect::<Vec<_>>();
+    assert_eq!(gens, vec![9, 10, 11]);
+}
+
+#[test]
+fn requested_steps_follow_the_last_received_gen() {
+    let mut history = Replay::new(7, Vec::new(), GameSettings::default());
+    for gen in 1..=50 {
+        history.add_step(InputStep::new(Vec::new(), gen));
+    }
+    let gens = gen_missed_steps_packets(&history, 46).into_iter()
+        .flat_map(|packet| match packet {
+            Packet::RejoinSteps { steps } => steps,
+            packet => panic!("Unexpected steps packet {:?}", packet)
+        })
+        .map(|step| step.gen)
+        .collect::<Vec<_>>();
+    assert_eq!(gens, vec![47, 48, 49, 50]);
+    assert!(gen_missed_steps_packets(&history, 50).is_empty());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::{thread, time::{Duration, Instant}};
+use blackbeard::{client::Client, net_conditions::NetConditions, packet::{Packet, serialize_packet}, server::{Server, ServerEvent}};
+use common::{TIMEOUT, get_endpoint, host, settings};
+
+// Replies to handshakes the way the lobby does, returns the latest event of the server
+fn poll(server: &mut Server, clients: &mut [&mut Client]) -> ServerEvent {
+    let event = server.poll_received_packets().unwrap();
+    if let ServerEvent::PlayerConnect(id, addr) = &event {
+        server.send_raw_unicast(serialize_packet(Packet::HandshakeReply {
+            players: Vec::new(), spectators: Vec::new(), host: server.get_host_id(),
+            rejoin_token: server.get_rejoin_token(id.n).unwrap()
+        }, id.n), *addr).unwrap();
+    }
+    for client in clients.iter_mut() {
+        client.poll_received_packets().unwrap();
+    }
+    thread::sleep(Duration::from_millis(1));
+    event
+}
+
+#[test]
+fn only_the_token_takes_back_a_reserved_slot() {
+    let mut server = host(settings());
+    let endpoint = get_endpoint(&server);
+    let connect = |name: &str, token| Client::connect(&endpoint, name.to_owned(), false, None, token,
+        NetConditions::default()).unwrap();
+    let mut host = connect("Host", None);
+    let mut pirate = connect("Blackbeard", None);
+    let start_time = Instant::now();
+    while pirate.get_rejoin_token().is_none() {
+        assert!(start_time.elapsed() < TIMEOUT, "Players did not connect");
+        poll(&mut server, &mut [&mut host, &mut pirate]);
+    }
+    let token = pirate.get_rejoin_token();
+    let pirate_id = pirate.get_local_id().unwrap();
+
+    // The pirate goes silent mid-match, until the server gives up on them
+    server.set_accepting_connections(false);
+    pirate.hand_over_peer().unwrap().shutdown().unwrap();
+    let start_time = Instant::now();
+    while !server.is_slot_reserved(pirate_id.n) {
+        assert!(start_time.elapsed() < Duration::from_secs(30), "Pirate did not time out");
+        poll(&mut server, &mut [&mut host]);
+    }
+
+    // Knowing the name is not enough
+    let mut impostor = connect("Blackbeard", None);
+    let start_time = Instant::now();
+    while start_time.elapsed() < Duration::from_millis(500) {
+        if let ServerEvent::PlayerConnect(..) | ServerEvent::PlayerRejoin(..) =
+            poll(&mut server, &mut [&mut host, &mut impostor]) {
+            panic!("Impostor took the slot");
+        }
+    }
+    let mut pirate = connect("Blackbeard", token);
+    let start_time = Instant::now();
+    let id = loop {
+        assert!(start_time.elapsed() < TIMEOUT, "Pirate did not rejoin");
+        if let ServerEvent::PlayerRejoin(id, _) = poll(&mut server, &mut [&mut host, &mut pirate]) {
+            break id
+        }
+    };
+    assert_eq!(id, pirate_id);
+    assert_eq!(server.get_rejoin_token(id.n), token); // Good for the next timeout as well
+}
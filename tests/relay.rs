@@ -0,0 +1,96 @@
+mod common;
+
+use std::{net::UdpSocket, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use blackbeard::{client::Client, net_conditions::NetConditions, packet::Packet, relay::{MAX_RELAY_SESSIONS_PER_IP, RelayMessage, RelayServer, deserialize_relay_message, get_virtual_addr, get_virtual_peer, serialize_relay_message}, server::ServerEvent};
+use common::{connect, host, poll_until, settings};
+
+// Peers block while they join a session, so the relay runs on its own
+fn spawn_relay() -> (u16, Arc<AtomicBool>, JoinHandle<RelayServer>) {
+    let mut relay = RelayServer::bind(0).unwrap();
+    let port = relay.get_local_port().unwrap();
+    let running = Arc::new(AtomicBool::new(true));
+    let running_ref = running.clone();
+    (port, running, thread::spawn(move || {
+        while running_ref.load(Ordering::Relaxed) {
+            relay.poll().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        relay
+    }))
+}
+
+#[test]
+fn relay_messages_survive_encoding() {
+    let message = RelayMessage::Data { session: 1234567, peer: 3, payload: vec![1, 2, 3] };
+    assert_eq!(deserialize_relay_message(&serialize_relay_message(&message)).unwrap(), message);
+    let message = RelayMessage::Joined { session: 1234567, peer: 300 };
+    assert_eq!(deserialize_relay_message(&serialize_relay_message(&message)).unwrap(), message);
+    assert!(deserialize_relay_message(b"BBRL").is_err());
+
+    assert_eq!(get_virtual_peer(get_virtual_addr(300)), Some(300));
+    assert_eq!(get_virtual_peer("127.0.0.1:22081".parse().unwrap()), None);
+}
+
+#[test]
+fn players_connect_and_chat_through_relay() {
+    let (relay_port, running, relay_thread) = spawn_relay();
+    let mut settings = settings();
+    settings.relay = Some(format!("127.0.0.1:{}", relay_port));
+    let mut server = host(settings);
+    let endpoint = server.get_relay_endpoint().unwrap().to_owned();
+    assert!(endpoint.starts_with(&format!("relay://127.0.0.1:{}/", relay_port)));
+
+    let mut host = connect(&endpoint, "Host");
+    let mut pirate = connect(&endpoint, "Blackbeard");
+    for _ in 0..2 {
+        poll_until(&mut server, &mut [&mut host, &mut pirate], |event, _| match event {
+            Some(ServerEvent::PlayerConnect(..)) => Some(()),
+            _ => None
+        });
+    }
+    assert_eq!(server.get_connection_count(), 2);
+
+    pirate.send_packet(Packet::ChatMessage { message: "Ahoy".to_owned() }).unwrap();
+    let message = poll_until(&mut server, &mut [&mut host, &mut pirate], |event, _| match event {
+        Some(ServerEvent::ReceivePacket(_, Packet::ChatMessage { message })) => Some(message),
+        _ => None
+    });
+    assert_eq!(message, "Ahoy");
+
+    // Unknown sessions are refused right away
+    let unknown_endpoint = format!("relay://127.0.0.1:{}/{}", relay_port,
+        endpoint.rsplit('/').next().unwrap().parse::<u32>().unwrap().wrapping_add(1));
+    assert!(Client::connect(&unknown_endpoint, "Stowaway".to_owned(), false, None, None,
+        NetConditions::default()).is_err());
+
+    running.store(false, Ordering::Relaxed);
+    let relay = relay_thread.join().unwrap();
+    assert_eq!(relay.get_session_count(), 1);
+    assert_eq!(relay.get_peer_count(), 3);
+}
+
+#[test]
+fn relay_caps_sessions_per_ip() {
+    let mut relay = RelayServer::bind(0).unwrap();
+    let relay_addr = format!("127.0.0.1:{}", relay.get_local_port().unwrap());
+    let hosts = (0..=MAX_RELAY_SESSIONS_PER_IP).map(|_| {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        socket.send_to(&serialize_relay_message(&RelayMessage::Host), &relay_addr).unwrap();
+        socket
+    }).collect::<Vec<_>>();
+    let start_time = Instant::now();
+    while start_time.elapsed() < Duration::from_millis(200) {
+        relay.poll().unwrap();
+    }
+
+    let mut buffer = [0; 64];
+    let answers = hosts.iter().map(|socket| {
+        let len = socket.recv(&mut buffer).unwrap();
+        deserialize_relay_message(&buffer[..len]).unwrap()
+    }).collect::<Vec<_>>();
+    assert_eq!(answers.iter().filter(|answer| matches!(answer, RelayMessage::Hosting { .. })).count(),
+        MAX_RELAY_SESSIONS_PER_IP);
+    assert!(answers.contains(&RelayMessage::Refused { session: 0 }));
+    assert_eq!(relay.get_session_count(), MAX_RELAY_SESSIONS_PER_IP);
+}
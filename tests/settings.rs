@@ -0,0 +1,56 @@
+use blackbeard::{CAM_MAX_ZOOM, CAM_MIN_ZOOM, settings::{DEFAULT_WINDOW_SIZE_HEIGHT, DEFAULT_WINDOW_SIZE_WIDTH, Settings}};
+use tetra::input::Key;
+
+#[test]
+fn settings_survive_saving_and_loading() {
+    let mut settings = Settings::new();
+    settings.parse("# Comment\n\
+        name = Blackbeard\n\
+        window_width = 1600\n\
+        fullscreen = true\n\
+        cam_speed = 650.5\n\
+        rollback = true\n\
+        relay = relay.example.org:22090\n\
+        last_endpoint = 192.168.0.17:22081\n\
+        last_endpoint = 10.0.0.2:22081\n\
+        key_rotate = F\n\
+        key_shoot_all = leftshift\n");
+    assert_eq!(settings.name, "Blackbeard");
+    assert_eq!((settings.window_width, settings.window_height), (1600, DEFAULT_WINDOW_SIZE_HEIGHT));
+    assert!(settings.fullscreen && settings.rollback);
+    assert_eq!(settings.cam_speed, 650.5);
+    assert_eq!(settings.relay, "relay.example.org:22090");
+    assert_eq!(settings.get_last_endpoint(), Some("192.168.0.17:22081"));
+    assert_eq!((settings.key_bindings.rotate, settings.key_bindings.shoot_all), (Key::F, Key::LeftShift));
+
+    let saved = settings.to_string();
+    let mut loaded = Settings::new();
+    loaded.parse(&saved);
+    assert_eq!(loaded.to_string(), saved);
+    assert_eq!(loaded.last_endpoints, settings.last_endpoints);
+    assert_eq!(loaded.key_bindings.rotate, Key::F);
+}
+
+#[test]
+fn invalid_entries_fall_back_to_the_defaults() {
+    let mut settings = Settings::new();
+    settings.parse("name = Blackbeard the Pirate\n\
+        window_width = 0\n\
+        window_height = -640\n\
+        cam_speed = fast\n\
+        fullscreen = maybe\n\
+        key_rotate = Mouse1\n\
+        key_unknown = R\n\
+        no separator\n\
+        cam_min_zoom = 4\n\
+        cam_max_zoom = 2\n");
+    let defaults = Settings::new();
+    assert_eq!(settings.name, "");
+    assert_eq!((settings.window_width, settings.window_height),
+        (DEFAULT_WINDOW_SIZE_WIDTH, DEFAULT_WINDOW_SIZE_HEIGHT));
+    assert_eq!(settings.cam_speed, defaults.cam_speed);
+    assert!(!settings.fullscreen);
+    assert_eq!(settings.key_bindings.rotate, Key::R);
+    assert_eq!((settings.cam_min_zoom, settings.cam_max_zoom), (CAM_MIN_ZOOM, CAM_MAX_ZOOM));
+    assert_eq!(settings.to_string(), defaults.to_string());
+}
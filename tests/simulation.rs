@@ -0,0 +1,174 @@
+use blackbeard::{ID, PlayerParams, V2, game_settings::{GameMode, GameSettings, Weather}, input_pool::STEP_PHASE_FRAME_LENGTH, packet::{InputState, InputStep}, ship_data::ShipType, ship_mod::ShipModType, simulation::Simulation, sync_checker::{SYNC_STATE_GEN_INTERVAL, SyncState}};
+
+const WORLD_SEED: u64 = 0xB1AC_BEA2D;
+const TOTAL_GENS: u64 = 240;
+
+fn players() -> Vec<PlayerParams> {
+    let mut player1 = PlayerParams::new(ID::new("Blackbeard".to_owned(), 0));
+    player1.ship_type = ShipType::Galleon;
+    let mut player2 = PlayerParams::new(ID::new("Barbarossa".to_owned(), 1));
+    player2.ship_type = ShipType::Schooner;
+    vec![player1, player2]
+}
+
+fn settings(weather: Weather, bots: u8) -> GameSettings {
+    let mut settings = GameSettings::new(GameMode::Deathmatch(50), weather);
+    settings.bots = bots;
+    settings
+}
+
+// Player 0 chases and rams player 1, who circles while firing broadsides and tries to buy a mod
+fn scripted_steps(total_gens: u64) -> Vec<InputStep> {
+    (1..=total_gens).map(|gen| {
+        let mut step = InputStep::new(Vec::new(), gen);
+        let player1 = match gen % 15 {
+            1 => InputState::new(true, false, false, false, false, false, None,
+                Some(V2::new(2000.0, -450.0))),
+            _ => InputState::default()
+        };
+        let player2 = match gen % 20 {
+            2 => InputState::new(true, false, false, false, false, false, None,
+                Some(V2::new(1000.0 + (gen as f32 * 0.1).cos() * 800.0,
+                    -450.0 + (gen as f32 * 0.1).sin() * 800.0))),
+            7 => InputState::new(false, false, true, false, false, false, None, None),
+            12 => InputState::new(false, true, false, true, false, false, None,
+                Some(V2::new(1000.0, -450.0))),
+            17 => InputState::new(false, false, false, false, true, false,
+                Some(ShipModType::CannonRangeUpgrade), None),
+            _ => InputState::default()
+        };
+        step.add_state(0, player1);
+        step.add_state(1, player2);
+        step
+    }).collect()
+}
+
+// Feeds all steps up front and collects the sync state of every sync generation
+fn run(players: Vec<PlayerParams>, settings: GameSettings, steps: Vec<InputStep>) -> Vec<SyncState> {
+    let mut simulation = Simulation::headless(players, WORLD_SEED, settings)
+        .expect("Failed to build headless simulation");
+    let total_gens = steps.len() as u64;
+    for step in steps.into_iter() {
+        simulation.add_step(step);
+    }
+
+    let mut sync_states = Vec::new();
+    while simulation.get_curr_gen() < total_gens {
+        let frames = simulation.run_frames(STEP_PHASE_FRAME_LENGTH as u64).unwrap();
+        assert!(frames > 0, "Simulation stalled at gen {}", simulation.get_curr_gen());
+        if let Some(state) = simulation.get_last_sync_state() {
+            if sync_states.last().is_none_or(|last: &SyncState| last.t < state.t) {
+                sync_states.push(state);
+            }
+        }
+    }
+    assert_eq!(sync_states.len() as u64, total_gens / SYNC_STATE_GEN_INTERVAL);
+    sync_states
+}
+
+fn assert_in_sync(a: &[SyncState], b: &[SyncState]) {
+    assert_eq!(a.len(), b.len());
+    for (a, b) in a.iter().zip(b.iter()) {
+        assert_eq!(a.t, b.t);
+        assert_eq!(a.hash, b.hash, "Simulations diverged at gen {}", a.t);
+    }
+}
+
+#[test]
+fn identical_inputs_stay_in_sync() {
+    let a = run(players(), settings(Weather::Sunny, 0), scripted_steps(TOTAL_GENS));
+    let b = run(players(), settings(Weather::Sunny, 0), scripted_steps(TOTAL_GENS));
+    assert_in_sync(&a, &b);
+}
+
+#[test]
+fn stormy_matches_with_bots_stay_in_sync() {
+    let a = run(players(), settings(Weather::Stormy, 3), scripted_steps(TOTAL_GENS));
+    let b = run(players(), settings(Weather::Stormy, 3), scripted_steps(TOTAL_GENS));
+    assert_in_sync(&a, &b);
+}
+
+#[test]
+fn player_order_does_not_matter() {
+    let mut reversed_players = players();
+    reversed_players.reverse();
+    let a = run(players(), settings(Weather::Windy, 1), scripted_steps(TOTAL_GENS));
+    let b = run(reversed_players, settings(Weather::Windy, 1), scripted_steps(TOTAL_GENS));
+    assert_in_sync(&a, &b);
+}
+
+#[test]
+fn different_inputs_diverge() {
+    let mut steps = scripted_steps(TOTAL_GENS);
+    steps[0].states[0].1 = InputState::new(true, false, false, false, false, false, None,
+        Some(V2::new(1000.0, 1000.0)));
+    let a = run(players(), settings(Weather::Sunny, 0), scripted_steps(TOTAL_GENS));
+    let b = run(players(), settings(Weather::Sunny, 0), steps);
+    assert_ne!(a.last().unwrap().hash, b.last().unwrap().hash);
+}
+
+// Player 0 heads somewhere else entirely and keeps firing
+fn diverging_steps(steps: &[InputStep]) -> Vec<InputStep> {
+    steps.iter().map(|step| {
+        let mut step = step.clone();
+        step.states[0].1 = InputState::new(true, false, true, true, false, false, None,
+            Some(V2::new(0.0, 2000.0)));
+        step
+    }).collect()
+}
+
+fn run_to_gen(simulation: &mut Simulation, gen: u64) {
+    while simulation.get_curr_gen() < gen {
+        let frames = simulation.run_frames(1).unwrap();
+        assert!(frames > 0, "Simulation stalled at gen {}", simulation.get_curr_gen());
+    }
+}
+
+#[test]
+fn restored_snapshots_continue_the_same_match() {
+    let expected = run(players(), settings(Weather::Stormy, 2), scripted_steps(TOTAL_GENS));
+    let steps = scripted_steps(TOTAL_GENS);
+    let (first_half, second_half) = steps.split_at(TOTAL_GENS as usize / 2);
+    let mut simulation = Simulation::headless(players(), WORLD_SEED, settings(Weather::Stormy, 2))
+        .expect("Failed to build headless simulation");
+    first_half.iter().for_each(|step| simulation.add_step(step.clone()));
+    run_to_gen(&mut simulation, first_half.len() as u64);
+    let snapshot = simulation.save_state();
+
+    diverging_steps(second_half).into_iter().for_each(|step| simulation.add_step(step));
+    run_to_gen(&mut simulation, TOTAL_GENS);
+    assert_ne!(simulation.get_last_sync_state().unwrap().hash, expected.last().unwrap().hash);
+
+    simulation.restore_state(&snapshot);
+    second_half.iter().for_each(|step| simulation.add_step(step.clone()));
+    run_to_gen(&mut simulation, TOTAL_GENS);
+    let state = simulation.get_last_sync_state().unwrap();
+    assert_eq!(state.t, TOTAL_GENS);
+    assert_eq!(state.hash, expected.last().unwrap().hash);
+}
+
+#[test]
+fn rolled_back_predictions_end_up_in_sync() {
+    let expected = run(players(), settings(Weather::Windy, 1), scripted_steps(TOTAL_GENS));
+    let mut simulation = Simulation::headless(players(), WORLD_SEED, settings(Weather::Windy, 1))
+        .expect("Failed to build headless simulation");
+    simulation.controller.enable_rollback();
+
+    // Steps arrive at half the rate they are simulated at, so most of them are predicted first
+    let mut steps = scripted_steps(TOTAL_GENS).into_iter();
+    let mut phase = 0;
+    while simulation.controller.get_confirmed_gen() < TOTAL_GENS {
+        if phase % 2 == 0 {
+            if let Some(step) = steps.next() {
+                simulation.add_step(step);
+            }
+        }
+        simulation.run_frames(STEP_PHASE_FRAME_LENGTH as u64).unwrap();
+        phase += 1;
+        assert!(phase < TOTAL_GENS * 4, "Simulation stalled at gen {}", simulation.get_curr_gen());
+    }
+    assert!(simulation.controller.get_rollback().unwrap().rollbacks > 0);
+    let state = simulation.get_last_sync_state().unwrap();
+    assert_eq!(state.t, TOTAL_GENS);
+    assert_eq!(state.hash, expected.last().unwrap().hash);
+}
@@ -0,0 +1,36 @@
+use blackbeard::sync_checker::{SyncChecker, SyncReview, SyncState};
+
+#[test]
+fn gens_are_only_reviewed_once_every_player_reported() {
+    let mut sync_checker = SyncChecker::new();
+    let players = [1, 2, 3];
+    sync_checker.add_state(1, SyncState::new(4, 0xBAD)); // Desynced, but reports first
+    sync_checker.add_state(2, SyncState::new(4, 0x600D));
+    assert!(sync_checker.take_reviews(&players, None).is_empty());
+
+    sync_checker.add_state(3, SyncState::new(4, 0x600D));
+    assert_eq!(sync_checker.take_reviews(&players, None), vec![SyncReview {
+        gen: 4, reference_id: 2, desynced_players: vec![1]
+    }]);
+    assert!(sync_checker.take_reviews(&players, None).is_empty()); // Reviewed once
+}
+
+#[test]
+fn no_reference_without_a_strict_majority() {
+    let mut sync_checker = SyncChecker::new();
+    sync_checker.add_state(1, SyncState::new(4, 0xBAD));
+    sync_checker.add_state(2, SyncState::new(4, 0x600D));
+    assert!(sync_checker.take_reviews(&[1, 2], None).is_empty());
+
+    // The host is the reference whenever it plays, also after taking over from another host
+    sync_checker.add_state(0, SyncState::new(8, 0x600D));
+    sync_checker.add_state(1, SyncState::new(8, 0xBAD));
+    assert_eq!(sync_checker.take_reviews(&[0, 1], Some(0)), vec![SyncReview {
+        gen: 8, reference_id: 0, desynced_players: vec![1]
+    }]);
+    sync_checker.add_state(1, SyncState::new(12, 0x600D));
+    sync_checker.add_state(2, SyncState::new(12, 0xBAD));
+    assert_eq!(sync_checker.take_reviews(&[1, 2], Some(2)), vec![SyncReview {
+        gen: 12, reference_id: 2, desynced_players: vec![1]
+    }]);
+}
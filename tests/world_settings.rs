@@ -0,0 +1,15 @@
+use blackbeard::{WorldEvent, WorldSettings};
+
+#[test]
+fn predicted_events_are_announced_once_confirmed() {
+    let mut world = WorldSettings::new();
+    world.add_event(4, WorldEvent::PlayerSunkByAccident("Blackbeard".to_owned()));
+    world.add_event(5, WorldEvent::PlayerSunkByCannon("Anne".to_owned(), "Calico".to_owned()));
+    assert_eq!(world.flush_events(4), vec![WorldEvent::PlayerSunkByAccident("Blackbeard".to_owned())]);
+
+    // Gen 5 was mispredicted and simulated again
+    world.discard_events_after(4);
+    world.add_event(5, WorldEvent::PlayerSunkByRamming("Anne".to_owned(), "Calico".to_owned()));
+    assert_eq!(world.flush_events(5), vec![WorldEvent::PlayerSunkByRamming("Anne".to_owned(), "Calico".to_owned())]);
+    assert!(world.flush_events(5).is_empty());
+}